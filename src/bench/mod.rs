@@ -0,0 +1,156 @@
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy::utils::{Duration, Instant};
+use bevy_spatial::SpatialSet;
+use crate::boids_2d::{self, resources::BoidSettings2D};
+use crate::boids_3d::{self, resources::BoidSettings3D};
+use crate::cli::{apply_cli_count, apply_cli_overrides, Cli, CliMode};
+use crate::kd_tree_2d::KDTree2DPlugin;
+use crate::kd_tree_3d::KDTree3DPlugin;
+use crate::rules::FlockingRules;
+use crate::metrics::{sample_metrics_2d, sample_metrics_3d, FlockMetrics, Metric, MetricsSettings};
+use crate::sky::birds::{self, FlightModelSettings, SkyBoidSettings};
+use crate::sky::murmuration::MurmurationSettings;
+use crate::underwater::fish::{self, components::SpeciesSettings, ecology::{EcologySettings, SpeciesInteractions}};
+
+pub mod resources;
+pub mod systems;
+
+pub use resources::*;
+pub use systems::*;
+
+/// Runs one mode's simulation systems without rendering, input or UI, each stage closed by a
+/// timing system.
+pub struct BenchPlugin {
+    pub mode: CliMode
+}
+
+impl Plugin for BenchPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BenchTimings>()
+           .init_resource::<FlockMetrics>()
+           .insert_resource(MetricsSettings { capacity: usize::MAX, ..default() })
+           .init_resource::<BoidSettings2D>()
+           .init_resource::<BoidSettings3D>()
+           .init_resource::<SkyBoidSettings>()
+           .init_resource::<SpeciesSettings>()
+           .init_resource::<FlockingRules>()
+           .insert_resource(TimeUpdateStrategy::ManualDuration(BENCH_STEP))
+           .configure_sets(Update, (SpatialSet, BenchSet::Flocking, BenchSet::Integration, BenchSet::Confinement).chain())
+           .add_systems(PreStartup, (apply_cli_count, apply_cli_overrides).chain())
+           .add_systems(Update, (
+                start_step.before(SpatialSet),
+                lap(Stage::KdTree).after(SpatialSet).before(BenchSet::Flocking)
+           ));
+
+        match self.mode {
+            CliMode::Mode2D => {
+                app.add_plugins(KDTree2DPlugin::default())
+                   .init_resource::<boids_2d::resources::GroupsTargets>()
+                   .add_event::<boids_2d::events::ApplyForceEvent>()
+                   .add_systems(Startup, spawn_bench_boids_2d)
+                   .add_systems(Update, (
+                        (boids_2d::systems::flocking, lap(Stage::Flocking)).chain().in_set(BenchSet::Flocking),
+                        (boids_2d::systems::apply_forces_system, boids_2d::systems::update_boid_position, lap(Stage::Integration)).chain().in_set(BenchSet::Integration),
+                        (boids_2d::systems::confine_movement, lap(Stage::Confinement)).chain().in_set(BenchSet::Confinement)
+                   ));
+            }
+            CliMode::Mode3D => {
+                app.add_plugins(KDTree3DPlugin::default())
+                   .init_resource::<boids_3d::resources::GroupsTargets>()
+                   .add_event::<boids_3d::events::ApplyForceEvent>()
+                   .add_systems(Startup, spawn_bench_boids_3d)
+                   .add_systems(Update, (
+                        (boids_3d::systems::flocking, lap(Stage::Flocking)).chain().in_set(BenchSet::Flocking),
+                        (boids_3d::systems::apply_forces_system, boids_3d::systems::update_boid_position, lap(Stage::Integration)).chain().in_set(BenchSet::Integration),
+                        (boids_3d::systems::confine_movement, lap(Stage::Confinement)).chain().in_set(BenchSet::Confinement)
+                   ));
+            }
+            CliMode::Sky => {
+                app.add_plugins(KDTree3DPlugin::default())
+                   .init_resource::<boids_3d::resources::GroupsTargets>()
+                   .init_resource::<FlightModelSettings>()
+                   .init_resource::<MurmurationSettings>()
+                   .add_event::<boids_3d::events::ApplyForceEvent>()
+                   .add_systems(Startup, spawn_bench_birds)
+                   .add_systems(Update, (
+                        (birds::apply_sky_flocking, lap(Stage::Flocking)).chain().in_set(BenchSet::Flocking),
+                        (boids_3d::systems::apply_forces_system, birds::update_birds_position, lap(Stage::Integration)).chain().in_set(BenchSet::Integration),
+                        (boids_3d::systems::confine_movement, birds::confine_birds_movement, lap(Stage::Confinement)).chain().in_set(BenchSet::Confinement)
+                   ));
+            }
+            CliMode::Underwater => {
+                app.add_plugins(KDTree3DPlugin::default())
+                   .init_resource::<SpeciesInteractions>()
+                   .init_resource::<EcologySettings>()
+                   .add_event::<boids_3d::events::ApplyForceEvent>()
+                   .add_systems(Startup, spawn_bench_fish)
+                   .add_systems(Update, (
+                        (fish::apply_underwater_flocking, lap(Stage::Flocking)).chain().in_set(BenchSet::Flocking),
+                        (boids_3d::systems::apply_forces_system, fish::update_fish_positions, lap(Stage::Integration)).chain().in_set(BenchSet::Integration),
+                        (fish::confine_fishes_movement, lap(Stage::Confinement)).chain().in_set(BenchSet::Confinement)
+                   ));
+            }
+        }
+    }
+}
+
+fn mode_settings(world: &World, mode: CliMode) -> serde_json::Value {
+    let settings = match mode {
+        CliMode::Mode2D => serde_json::to_value(world.resource::<BoidSettings2D>()),
+        CliMode::Mode3D => serde_json::to_value(world.resource::<BoidSettings3D>()),
+        CliMode::Sky => serde_json::to_value(world.resource::<SkyBoidSettings>()),
+        CliMode::Underwater => serde_json::to_value(world.resource::<SpeciesSettings>())
+    };
+    settings.unwrap_or_default()
+}
+
+/// Runs the warm-up and `cli.steps` timed steps of the chosen mode. Metrics are sampled between
+/// steps so that they stay out of the timings.
+pub fn run(cli: &Cli) -> BenchResult {
+    if let Some(seed) = cli.seed {
+        crate::random::seed(seed);
+    }
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, BenchPlugin { mode: cli.mode }))
+       .insert_resource(cli.clone());
+    app.finish();
+    app.cleanup();
+
+    // The first update runs the startup systems and has no elapsed time, so it counts as warm-up.
+    for _ in 0..cli.warmup.max(1) {
+        app.update();
+    }
+    let sample = match cli.mode {
+        CliMode::Mode2D => app.world.register_system(sample_metrics_2d),
+        _ => app.world.register_system(sample_metrics_3d)
+    };
+    app.world.insert_resource(BenchTimings::default());
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..cli.steps {
+        let started = Instant::now();
+        app.update();
+        elapsed += started.elapsed();
+        let _ = app.world.run_system(sample);
+    }
+
+    let boids = app.world.query_filtered::<(), Or<(With<boids_2d::components::Boid>, With<boids_3d::components::Boid>)>>().iter(&app.world).count();
+    let timings = app.world.resource::<BenchTimings>();
+    let metrics = app.world.resource::<FlockMetrics>();
+    BenchResult {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        mode: cli.mode,
+        seed: cli.seed,
+        boids,
+        warmup: cli.warmup,
+        steps: cli.steps,
+        elapsed_ms: elapsed.as_secs_f64() * 1000.0,
+        steps_per_second: cli.steps as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        stages: Stage::ALL.iter().map(|stage| StageTiming {
+            stage: stage.name().to_string(),
+            total_ms: timings.total(*stage).as_secs_f64() * 1000.0
+        }).collect(),
+        metrics: Metric::ALL.iter().map(|metric| (metric.key().to_string(), metrics.mean(*metric))).collect(),
+        settings: mode_settings(&app.world, cli.mode)
+    }
+}
@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+use bevy::utils::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use crate::cli::CliMode;
+
+/// Simulation time advanced by every headless step, so that runs are repeatable.
+pub const BENCH_STEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemSet)]
+pub enum BenchSet {
+    Flocking,
+    Integration,
+    Confinement
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    KdTree,
+    Flocking,
+    Integration,
+    Confinement
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [Stage::KdTree, Stage::Flocking, Stage::Integration, Stage::Confinement];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::KdTree => "KD-tree update",
+            Stage::Flocking => "Flocking",
+            Stage::Integration => "Integration",
+            Stage::Confinement => "Confinement"
+        }
+    }
+}
+
+/// Wall-clock time spent in each stage, measured between the timing systems that close them.
+#[derive(Resource, Default)]
+pub struct BenchTimings {
+    lap_started: Option<Instant>,
+    totals: [Duration; 4]
+}
+
+impl BenchTimings {
+    pub fn start_lap(&mut self) {
+        self.lap_started = Some(Instant::now());
+    }
+
+    pub fn lap(&mut self, stage: Stage) {
+        let now = Instant::now();
+        if let Some(started) = self.lap_started {
+            self.totals[stage as usize] += now - started;
+        }
+        self.lap_started = Some(now);
+    }
+
+    pub fn total(&self, stage: Stage) -> Duration {
+        self.totals[stage as usize]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StageTiming {
+    pub stage: String,
+    pub total_ms: f64
+}
+
+/// Outcome of a headless run, with what is needed to repeat it.
+#[derive(Serialize, Deserialize)]
+pub struct BenchResult {
+    pub version: String,
+    pub mode: CliMode,
+    pub seed: Option<u64>,
+    pub boids: usize,
+    pub warmup: u32,
+    pub steps: u32,
+    pub elapsed_ms: f64,
+    pub steps_per_second: f64,
+    pub stages: Vec<StageTiming>,
+    /// Means over the steps after warm-up, by metric key.
+    pub metrics: BTreeMap<String, f64>,
+    pub settings: serde_json::Value
+}
+
+impl BenchResult {
+    pub fn report(&self) -> String {
+        let mode = self.mode.name();
+        let seed = self.seed.map_or("random".to_string(), |seed| seed.to_string());
+        let steps = self.steps.max(1) as f64;
+        let mut report = format!(
+            "Headless benchmark, mode {mode}, seed {seed}\n{} boids, {} steps after {} warm-up steps\n\n{:<16}{:>12}{:>14}{:>9}\n",
+            self.boids, self.steps, self.warmup, "Stage", "Total (ms)", "Mean (ms)", "Share"
+        );
+        let mut row = |name: &str, total_ms: f64| {
+            let share = 100.0 * total_ms / self.elapsed_ms.max(f64::EPSILON);
+            report += &format!("{name:<16}{total_ms:>12.2}{:>14.4}{share:>8.1}%\n", total_ms / steps);
+        };
+        for timing in self.stages.iter() {
+            row(&timing.stage, timing.total_ms);
+        }
+        // Scheduling, events and time updates that fall outside the measured stages.
+        row("Other", (self.elapsed_ms - self.stages.iter().map(|timing| timing.total_ms).sum::<f64>()).max(0.0));
+        row("Step", self.elapsed_ms);
+        report += &format!("\n{:.1} steps per second\n", self.steps_per_second);
+        for (metric, value) in self.metrics.iter() {
+            report += &format!("\n{metric:<28}{value:>10.3}");
+        }
+        report
+    }
+}
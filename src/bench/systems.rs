@@ -0,0 +1,126 @@
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, WindowResolution};
+use rand::Rng;
+use std::f32::consts::PI;
+use crate::boids_2d::components as boids_2d;
+use crate::boids_2d::resources::BoidSettings2D;
+use crate::boids_3d::components::{Acceleration, Boid, Velocity};
+use crate::boids_3d::resources::BoidSettings3D;
+use crate::cli::Cli;
+use crate::kd_tree_2d::components::TrackedByKDTree2D;
+use crate::kd_tree_3d::components::TrackedByKDTree3D;
+use crate::random::SystemRng;
+use crate::sky::birds::{Flight, SkyBoidSettings};
+use crate::sky::SkySceneMarker;
+use crate::underwater::fish::components::{FishType, SpeciesSettings};
+use crate::underwater::fish::ecology::ALL_SPECIES;
+use crate::underwater::terrain::{GROUND_Y_POSITION, TERRAIN_SIZE};
+use crate::underwater::UnderwaterMarker;
+use super::resources::*;
+
+pub fn start_step(mut timings: ResMut<BenchTimings>) {
+    timings.start_lap();
+}
+
+/// Closes the given stage, to be ordered right after its systems.
+pub fn lap(stage: Stage) -> impl FnMut(ResMut<BenchTimings>) {
+    move |mut timings: ResMut<BenchTimings>| timings.lap(stage)
+}
+
+fn random_direction(rng: &mut impl Rng) -> Vec3 {
+    let theta = rng.gen_range(0.0..2.0 * PI);
+    let phi = rng.gen_range(0.0..PI);
+    Vec3::new(f32::sin(phi) * f32::cos(theta), f32::sin(phi) * f32::sin(theta), f32::cos(phi))
+}
+
+/// Headless boids only carry the components the simulation systems read, nothing to render.
+fn spawn_boid_3d(commands: &mut Commands, group: u8, transform: Transform, velocity: Vec3) -> Entity {
+    commands.spawn((
+        Boid { group },
+        Velocity { velocity },
+        Acceleration { acceleration: Vec3::ZERO },
+        transform,
+        TrackedByKDTree3D
+    )).id()
+}
+
+/// The 2D confinement reads the primary window size, so a window component stands in for it.
+pub fn spawn_bench_boids_2d(
+    mut commands: Commands,
+    cli: Res<Cli>,
+    boid_settings: Res<BoidSettings2D>,
+    mut rng: Local<SystemRng>
+) {
+    commands.spawn((
+        Window {
+            resolution: WindowResolution::new(cli.width, cli.height),
+            ..default()
+        },
+        PrimaryWindow
+    ));
+    let rng = rng.get("spawn_bench_boids_2d");
+    for _ in 0..boid_settings.count {
+        let angle: f32 = rng.gen_range(0.0..2.0 * PI);
+        commands.spawn((
+            boids_2d::Boid { group: rng.gen_range(0..2) },
+            boids_2d::Velocity { velocity: Vec2::new(angle.cos(), angle.sin()) },
+            boids_2d::Acceleration { acceleration: Vec2::ZERO },
+            Transform::from_xyz(rng.gen_range(0.0..cli.width), rng.gen_range(0.0..cli.height), 0.0),
+            TrackedByKDTree2D
+        ));
+    }
+}
+
+pub fn spawn_bench_boids_3d(
+    mut commands: Commands,
+    boid_settings: Res<BoidSettings3D>,
+    mut rng: Local<SystemRng>
+) {
+    let rng = rng.get("spawn_bench_boids_3d");
+    for _ in 0..boid_settings.count {
+        let position = Vec3::new(rng.gen_range(-50.0..50.0), rng.gen_range(-50.0..50.0), rng.gen_range(-50.0..50.0));
+        let transform = Transform::from_translation(position).with_scale(Vec3::splat(boid_settings.size * 2.0));
+        let velocity = random_direction(rng);
+        spawn_boid_3d(&mut commands, rng.gen_range(0..2), transform, velocity);
+    }
+}
+
+pub fn spawn_bench_birds(
+    mut commands: Commands,
+    boid_settings: Res<SkyBoidSettings>,
+    mut rng: Local<SystemRng>
+) {
+    let rng = rng.get("spawn_bench_birds");
+    for _ in 0..boid_settings.count {
+        let position = Vec3::new(rng.gen_range(-50.0..50.0), rng.gen_range(20.0..100.0), rng.gen_range(-50.0..50.0));
+        let transform = Transform::from_translation(position).with_scale(Vec3::splat(boid_settings.size * 0.3));
+        let bird = spawn_boid_3d(&mut commands, rng.gen_range(0..2), transform, Vec3::X);
+        commands.entity(bird).insert((Flight::default(), SkySceneMarker));
+    }
+}
+
+/// Same school layout as the underwater scene.
+pub fn spawn_bench_fish(
+    mut commands: Commands,
+    species_settings: Res<SpeciesSettings>,
+    mut rng: Local<SystemRng>
+) {
+    let rng = rng.get("spawn_bench_fish");
+    for species in ALL_SPECIES {
+        let settings = &species_settings.get(species).boids;
+        for school_id in 0..species_settings.get(species).schools {
+            let center = Vec3::new(
+                rng.gen_range(-TERRAIN_SIZE/2.0..TERRAIN_SIZE/2.0),
+                rng.gen_range(GROUND_Y_POSITION..-10.0),
+                rng.gen_range(-TERRAIN_SIZE/2.0..TERRAIN_SIZE/2.0)
+            );
+            for _ in 0..settings.count {
+                let offset = Vec3::new(rng.gen_range(-20.0..20.0), rng.gen_range(-10.0..10.0), rng.gen_range(-20.0..20.0));
+                let velocity = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-0.5..0.5), rng.gen_range(-1.0..1.0)).normalize() * settings.min_speed;
+                let transform = Transform::from_translation(center + offset).with_scale(Vec3::splat(settings.size));
+                let fish = spawn_boid_3d(&mut commands, school_id as u8, transform, velocity);
+                commands.entity(fish).insert((FishType { species, school_id }, UnderwaterMarker));
+            }
+        }
+    }
+}
@@ -21,5 +21,12 @@ pub struct Mode2DMarker;
 #[derive(Component)]
 pub struct ObstacleTag;
 
+/// Obstacles placed by the user, as opposed to the ones that are part of a scene.
+#[derive(Component)]
+pub struct PlacedObstacle {
+    /// Radius the obstacle was placed with, which is kept when the shape settings change.
+    pub radius: f32
+}
+
 #[derive(Component)]
 pub struct Shark;
\ No newline at end of file
@@ -1,7 +1,9 @@
 use bevy::prelude::*;
+use crate::rules::ForceSource;
 
 #[derive(Event)]
 pub struct ApplyForceEvent {
     pub entity: Entity,
-    pub force: Vec2
-}
\ No newline at end of file
+    pub force: Vec2,
+    pub source: ForceSource
+}
@@ -9,15 +9,18 @@ pub mod events;
 use events::ApplyForceEvent;
 use resources::*;
 use systems::*;
-use crate::SimulationState;
+use crate::ui::resources::SimulationState;
+use crate::ui::events::CursorVisibilityEvent;
 
 pub struct Boids2DPlugin;
 
 impl Plugin for Boids2DPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(BoidSettings2D::default())
+        .register_type::<BoidSettings2D>()
         .insert_resource(GroupsTargets::default())
         .add_event::<ApplyForceEvent>()
+        .add_event::<CursorVisibilityEvent>()
         .add_systems(Startup, spawn_boids)
         .add_systems(Update, (
             flocking,
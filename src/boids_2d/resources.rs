@@ -1,6 +1,10 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::settings::{FieldHints, ReflectSettingsHints, SettingsHints};
+use crate::ui::resources::SimulationState;
 
-#[derive(Resource,Debug)]
+#[derive(Resource, Debug, Clone, Serialize, Deserialize, Reflect)]
+#[reflect(Resource, SettingsHints)]
 pub struct BoidSettings2D {
     pub count: usize,
     pub previous_count: usize,
@@ -41,6 +45,42 @@ impl Default for BoidSettings2D {
     }
 }
 
+/// Also the hints of the fields the 3D settings share with the 2D ones.
+impl SettingsHints for BoidSettings2D {
+    fn field_hints(field: &str) -> Option<FieldHints> {
+        Some(match field {
+            "count" => FieldHints::new("Boids count").range(0.0, 15000.0),
+            "previous_count" => FieldHints::hidden(),
+            "cohesion_range" => FieldHints::new("Cohesion range").range(0.0, 100.0)
+                .tooltip("Neighbours closer than this pull the boid towards their centre"),
+            "alignment_range" => FieldHints::new("Alignment range").range(0.0, 100.0).capped_by("cohesion_range")
+                .tooltip("Neighbours closer than this are matched in velocity, at most the cohesion range"),
+            "separation_range" => FieldHints::new("Separation range").range(0.0, 100.0).capped_by("alignment_range")
+                .tooltip("Neighbours closer than this push the boid away, at most the alignment range"),
+            "min_distance_between_boids" => FieldHints::new("Minimum distance between boids").range(0.0, 50.0)
+                .tooltip("Below this distance, separation is boosted by the collision coefficient"),
+            "cohesion_coeff" => FieldHints::new("Cohesion").range(0.0, 50.0),
+            "alignment_coeff" => FieldHints::new("Alignment").range(0.0, 50.0),
+            "separation_coeff" => FieldHints::new("Separation").range(0.0, 50.0),
+            "collision_coeff" => FieldHints::new("Collision").range(0.0, 50.0)
+                .tooltip("Extra separation from neighbours under the minimum distance"),
+            "min_speed" => FieldHints::new("Min speed").range(0.0, 500.0),
+            "max_speed" => FieldHints::new("Max speed").range(0.0, 1000.0),
+            "bounce_against_walls" => FieldHints::new("Boids bounce against walls")
+                .tooltip("Otherwise boids leaving one side come back on the other"),
+            "attraction_coeff" => FieldHints::new("Attraction to target").range(0.0, 100.0)
+                .tooltip("Pull towards the target of the boid's group"),
+            "field_of_view" => FieldHints::new("Field of view").range(0.0, 360.0).unit("°")
+                .tooltip("Angle around the heading in which neighbours are seen"),
+            _ => return None
+        })
+    }
+
+    fn mode() -> Option<SimulationState> {
+        Some(SimulationState::Mode2D)
+    }
+}
+
 #[derive(Resource)]
 pub struct GroupsTargets {
     pub targets: Vec<Vec2>
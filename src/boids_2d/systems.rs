@@ -8,10 +8,12 @@ use crate::boids_2d::resources::*;
 use crate::boids_2d::bundles::*;
 use crate::boids_2d::events::*;
 use crate::ui::resources::SimulationState;
+use crate::boids_3d::systems::{Confinement, OBSTACLE_AVOIDANCE_RANGE};
+use crate::rules::{FlockingRules, ForceSource, Neighbour, NeighbourContext, RuleCoefficients};
+use crate::random::SystemRng;
 
 use bevy::sprite::MaterialMesh2dBundle;
 use crate::kd_tree_2d::components::*;
-use crate::input::resources::ShapeSettings;
 use crate::input::systems::*;
 use crate::ui::events::CursorVisibilityEvent;
 
@@ -20,48 +22,59 @@ pub const SPRITE_SIZE: f32 = 32.0;
 pub fn spawn_boid_entity(
     commands: &mut Commands,
     window: &Window,
-    asset_server: &Res<AssetServer>
+    asset_server: &Res<AssetServer>,
+    rng: &mut impl Rng
 ) {
-    let texture_path = "../assets/fish.png";
-    let mut rng = rand::thread_rng();
     let random_x: f32 = rng.gen_range(0.0..window.width());
     let random_y: f32 = rng.gen_range(0.0..window.height());
     let random_group: u8 = rng.gen_range(0..2);
     let random_angle: f32 = ((rng.gen_range(0.0..1.0) * 360.0) as f32).to_radians();
+    spawn_boid_at(
+        commands,
+        asset_server,
+        random_group,
+        Transform::from_xyz(random_x, random_y, 0.0),
+        Vec2::new(f32::cos(random_angle), f32::sin(random_angle))
+    );
+}
+
+pub fn spawn_boid_at(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    group: u8,
+    transform: Transform,
+    velocity: Vec2
+) -> Entity {
+    let texture_path = "../assets/fish.png";
     commands.spawn(
         BoidBundle {
-            boid: Boid {
-                group: random_group
-            },
-            velocity: Velocity {
-                velocity: Vec2::new(f32::cos(random_angle), f32::sin(random_angle))
-            },
+            boid: Boid { group },
+            velocity: Velocity { velocity },
             acceleration: Acceleration {
                 acceleration: Vec2::new(0.0,0.0)
             },
             sprite_bundle: SpriteBundle {
-                transform: Transform {
-                    translation: Vec3::new(random_x, random_y, 0.0),
-                    ..default()
-                },
+                transform,
                 texture: asset_server.load(texture_path),
                 ..default()
             },
             mode_2d_marker: Mode2DMarker,
             tracked_by_kdtree: TrackedByKDTree2D
         }
-    );
+    ).id()
 }
 
 pub fn spawn_boids(
     mut commands: Commands,
     window_query: Query<&Window, With<PrimaryWindow>>,
     asset_server: Res<AssetServer>,
-    boid_settings: Res<BoidSettings2D>) {
+    boid_settings: Res<BoidSettings2D>,
+    mut rng: Local<SystemRng>) {
     info!("Spawning {} boids", boid_settings.count);
     let window = window_query.get_single().unwrap();
+    let rng = rng.get("spawn_boids_2d");
     for _ in 0..boid_settings.count {
-        spawn_boid_entity(&mut commands, &window, &asset_server);
+        spawn_boid_entity(&mut commands, &window, &asset_server, rng);
     }
 }
 
@@ -70,30 +83,37 @@ pub fn flocking(
     event_writer: EventWriter<ApplyForceEvent>,
     boid_settings: Res<BoidSettings2D>,
     groups_targets: Res<GroupsTargets>,
+    rules: Res<FlockingRules>,
     kd_tree: Res<NNTree2D>
 ) {
     let cohesion_range = boid_settings.cohesion_range;
     let alignment_range = boid_settings.alignment_range;
     let separation_range = boid_settings.separation_range;
+    let coefficients = RuleCoefficients::from(&*boid_settings);
 
     let event_writer = Mutex::new(event_writer);
 
     boid_query.par_iter().for_each(|(entity, transform, velocity, boid)| {
         let position = transform.translation.truncate();
-        let mut cohesion_neighbors: Vec<Vec2> = Vec::new();
-        let mut repulsion_neighbors: Vec<(Vec2, f32)> = Vec::new();
-        let mut alignment_neighbors: Vec<Vec2> = Vec::new();
+        let mut context = NeighbourContext::new(position.extend(0.0), velocity.velocity.extend(0.0), boid.group, coefficients);
         for (_, neighbor_entity) in kd_tree.within_distance(position, cohesion_range) {
             if let Some(neighbor_entity) = neighbor_entity {
-                if let Ok((_, neighbor_transform, neighbor_velocity, _)) = boid_query.get(neighbor_entity) {
+                if neighbor_entity == entity { continue; }
+                if let Ok((_, neighbor_transform, neighbor_velocity, neighbor_boid)) = boid_query.get(neighbor_entity) {
                     let neighbor_position = neighbor_transform.translation.truncate();
                     if let Some(distance) = is_in_field_of_view(&position, &neighbor_position, &boid_settings.field_of_view, &boid_settings.cohesion_range) {
+                        context.neighbours.push(Neighbour {
+                            position: neighbor_position.extend(0.0),
+                            velocity: neighbor_velocity.velocity.extend(0.0),
+                            group: neighbor_boid.group,
+                            distance
+                        });
                         if distance < separation_range {
-                            repulsion_neighbors.push((neighbor_position, distance));
+                            context.separation_neighbours.push((neighbor_position.extend(0.0), distance));
                         } else if distance < alignment_range {
-                            alignment_neighbors.push(neighbor_velocity.velocity);
+                            context.alignment_neighbours.push((neighbor_position.extend(0.0), neighbor_velocity.velocity.extend(0.0)));
                         } else if distance < cohesion_range {
-                            cohesion_neighbors.push(neighbor_position);
+                            context.cohesion_neighbours.push(neighbor_position.extend(0.0));
                         }
                     }
                 } else {
@@ -101,81 +121,32 @@ pub fn flocking(
                 }
             }
         }  
-        let cohesion_force: Vec2 = cohesion(&position, &cohesion_neighbors, &boid_settings.cohesion_coeff);
-        let avoidance_force: Vec2 = avoidance(&position, &repulsion_neighbors, &boid_settings.separation_coeff, &boid_settings.min_distance_between_boids, &boid_settings.collision_coeff);
-        let alignment_force: Vec2 = alignment(&velocity, &alignment_neighbors, &boid_settings.alignment_coeff);
-        let target = groups_targets.targets[boid.group as usize];
-        let attraction_force: Vec2 = attraction_to_target(&position, &target, &boid_settings.attraction_coeff);
-        let total_force = cohesion_force + avoidance_force + alignment_force + attraction_force;
+        context.target = Some(groups_targets.targets[boid.group as usize].extend(0.0));
+        rules.trace(entity, &context);
+        let total_force = rules.force(&SimulationState::Mode2D, &context).truncate();
         let mut event_writer = event_writer.lock().unwrap();
         event_writer.send(ApplyForceEvent {
             entity: entity,
-            force: total_force
+            force: total_force,
+            source: ForceSource::Flocking
         });
     });
 }
 
-pub fn cohesion(position: &Vec2, cohesion_neighbors: &Vec<Vec2>, cohesion_coeff: &f32) -> Vec2 {
-    let mut cohesion_force: Vec2 = Vec2::ZERO;
-    let nb_neighbors = cohesion_neighbors.len();
-    if nb_neighbors == 0 {
-        return Vec2::ZERO;
-    }
-    for other_position in cohesion_neighbors.iter() {
-        cohesion_force += *other_position;
-    }
-    cohesion_force /= nb_neighbors as f32;
-    cohesion_force = cohesion_force - *position;
-    cohesion_force * *cohesion_coeff
-}
-
-pub fn avoidance(position: &Vec2, repulsion_neighbors: &Vec<(Vec2, f32)>, separation_coeff: &f32, min_distance_between_boids: &f32, collision_coeff: &f32) -> Vec2 {
-    let mut avoidance_force: Vec2 = Vec2::ZERO;
-    for (other_position, distance) in repulsion_neighbors.iter() {
-        if distance < min_distance_between_boids {
-            let interpolation_factor = (*min_distance_between_boids - distance) / *min_distance_between_boids;
-            avoidance_force += (*position - *other_position) * *collision_coeff * interpolation_factor;
-        }
-        else {
-            avoidance_force += *position - *other_position;
-        }
-    }
-    avoidance_force * *separation_coeff
-}
-
-pub fn alignment(velocity: &Velocity, alignment_neighbors: &Vec<Vec2>, alignment_coeff: &f32) -> Vec2 {
-    let mut alignment_force: Vec2 = Vec2::ZERO;
-    let nb_neighbors = alignment_neighbors.len();
-    if nb_neighbors == 0 {
-        return Vec2::ZERO;
-    }
-    for other_velocity in alignment_neighbors.iter() {
-        alignment_force += *other_velocity;
-    }
-    alignment_force /= nb_neighbors as f32;
-    alignment_force = alignment_force - velocity.velocity;
-    alignment_force * *alignment_coeff
-}
-
-pub fn attraction_to_target(position: &Vec2, target: &Vec2, attraction_coeff: &f32) -> Vec2 {
-    (*target - *position) * *attraction_coeff
-}
-
 pub fn avoid_obstacles(
     mut boid_query: Query<(Entity, &Transform, &mut Velocity), With<Boid>>,
     mut event_writer: EventWriter<ApplyForceEvent>,
-    obstacles_query: Query<(&Transform, &ObstacleTag)>,
-    shape_settings: Res<ShapeSettings>
+    obstacles_query: Query<(&Transform, &PlacedObstacle), With<ObstacleTag>>
 ) {
     for (entity, transform, mut velocity) in boid_query.iter_mut() {
         let position = transform.translation.truncate();
         let mut avoidance_force: Vec2 = Vec2::ZERO;
-        let obstacle_avoidance_range = 50.0;
+        let obstacle_avoidance_range = OBSTACLE_AVOIDANCE_RANGE;
         let obstacle_avoidance_coeff = 10.0;
         let turn_factor: f32 = 20.0;
-        for (obstacle_transform, _) in obstacles_query.iter() {
+        for (obstacle_transform, obstacle) in obstacles_query.iter() {
             let obstacle_position = obstacle_transform.translation.truncate();
-            let distance = position.distance(obstacle_position) - shape_settings.radius;
+            let distance = position.distance(obstacle_position) - obstacle.radius;
             if distance < obstacle_avoidance_range {
                 let direction = (position - obstacle_position).normalize();
                 let interpolation_factor = (obstacle_avoidance_range - distance) / obstacle_avoidance_range;
@@ -186,11 +157,13 @@ pub fn avoid_obstacles(
         }
         event_writer.send(ApplyForceEvent {
             entity: entity,
-            force: avoidance_force
+            force: avoidance_force,
+            source: ForceSource::Obstacle
         });
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn scare_with_cursor(
     mut commands: Commands,
     mut boid_query: Query<(Entity, &Transform, &mut Velocity), With<Boid>>,
@@ -242,6 +215,7 @@ pub fn scare_with_cursor(
                         event_writer.send(ApplyForceEvent {
                             entity: entity,
                             force: avoidance_force,
+                            source: ForceSource::Fear
                         });
                     }
                 }
@@ -265,7 +239,7 @@ pub fn apply_forces_system(
     mut forces: EventReader<ApplyForceEvent>,
     mut boid_query: Query<&mut Acceleration, With<Boid>>
 ) {
-    for ApplyForceEvent{entity, force} in forces.read() {
+    for ApplyForceEvent{entity, force, ..} in forces.read() {
         if let Ok(mut acceleration) = boid_query.get_mut(*entity) {
             acceleration.acceleration += *force;
         }
@@ -292,7 +266,7 @@ pub fn update_boid_position(
     }
 }
 
-fn is_in_field_of_view(position: &Vec2, other_position: &Vec2, fov: &f32, cohesion_range: &f32) -> Option<f32> {
+pub fn is_in_field_of_view(position: &Vec2, other_position: &Vec2, fov: &f32, cohesion_range: &f32) -> Option<f32> {
     let to_other = *other_position - *position;
     let distance_squared = to_other.length_squared();
     let cohesion_range_squared = cohesion_range * cohesion_range;
@@ -305,21 +279,26 @@ fn is_in_field_of_view(position: &Vec2, other_position: &Vec2, fov: &f32, cohesi
     None
 }
 
+pub fn confinement_2d(window: &Window) -> Confinement {
+    let half_sprite_size = SPRITE_SIZE / 2.0;
+    Confinement {
+        min: Vec3::new(half_sprite_size, half_sprite_size, 0.0),
+        max: Vec3::new(window.width() - half_sprite_size, window.height() - half_sprite_size, 0.0),
+        margin: 100.0
+    }
+}
+
 pub fn confine_movement (
     mut boid_query: Query<(&mut Transform, &mut Velocity, &mut Acceleration), With<Boid>>,
     window_query: Query<&Window, With<PrimaryWindow>>,
     boid_settings: Res<BoidSettings2D>
 ) {
     let window = window_query.get_single().unwrap();
-    let half_sprite_size = SPRITE_SIZE / 2.0;
-    let x_min = 0.0 + half_sprite_size;
-    let y_min = 0.0 + half_sprite_size;
-    let x_max = window.width() - half_sprite_size;
-    let y_max = window.height() - half_sprite_size;
+    let Confinement { min, max, margin } = confinement_2d(window);
+    let (x_min, y_min, x_max, y_max) = (min.x, min.y, max.x, max.y);
     for (mut transform, mut velocity, _) in boid_query.iter_mut() {
         if boid_settings.bounce_against_walls {
             let turn_factor: f32 = 20.0;
-            let margin = 100.0;
             if transform.translation.x > x_max - margin {
                 velocity.velocity.x -= turn_factor;
             }
@@ -353,7 +332,8 @@ pub fn adjust_population(
     mut commands: Commands,
     mut boid_settings: ResMut<BoidSettings2D>,
     window_query: Query<&Window, With<PrimaryWindow>>,
-    asset_server: Res<AssetServer>
+    asset_server: Res<AssetServer>,
+    mut rng: Local<SystemRng>
 ) {
     let current_count = boid_settings.count;
     let previous_count = boid_settings.previous_count;
@@ -363,8 +343,9 @@ pub fn adjust_population(
         return;
     }
     else if current_count > previous_count {
+        let rng = rng.get("adjust_population_2d");
         for _ in 0..(current_count - previous_count) {
-            spawn_boid_entity(&mut commands, &window, &asset_server);
+            spawn_boid_entity(&mut commands, &window, &asset_server, rng);
         }
     }
     else {
@@ -410,6 +391,7 @@ pub fn spawn_obstacle_2d(
             },
         },
         ObstacleTag,
+        PlacedObstacle { radius }
     ));
 }
 
@@ -1,12 +1,14 @@
 use bevy::prelude::*;
+use crate::rules::ForceSource;
 
 #[derive(Event)]
 pub struct ApplyForceEvent {
     pub entity: Entity,
-    pub force: Vec3
+    pub force: Vec3,
+    pub source: ForceSource
 }
 
 #[derive(Event)]
 pub struct ResizeEvent {
     pub scale: f32
-}
\ No newline at end of file
+}
@@ -19,6 +19,7 @@ impl Plugin for Boids3DPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<CameraControlState>()
         .insert_resource(BoidSettings3D::default())
+        .register_type::<BoidSettings3D>()
         .insert_resource(GroupsTargets::default())
         .add_event::<ApplyForceEvent>()
         .add_event::<ResizeEvent>()
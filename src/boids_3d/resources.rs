@@ -1,7 +1,12 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::boids_2d::resources::BoidSettings2D;
+use crate::settings::{FieldHints, ReflectSettingsHints, SettingsHints};
+use crate::ui::resources::SimulationState;
 use super::BOUNDS_SIZE;
 
-#[derive(Resource)]
+#[derive(Resource, Clone, Serialize, Deserialize, Reflect)]
+#[reflect(Resource, SettingsHints)]
 pub struct BoidSettings3D {
     pub count: usize,
     pub previous_count: usize,
@@ -18,7 +23,11 @@ pub struct BoidSettings3D {
     pub max_speed: f32,
     pub bounce_against_walls: bool,
     pub attraction_coeff: f32,
-    pub field_of_view: f32
+    pub field_of_view: f32,
+    pub max_force: f32,
+    pub max_angular_velocity: f32,
+    pub banking_coeff: f32,
+    pub max_bank_angle: f32
 }
 
 impl Default for BoidSettings3D {
@@ -39,11 +48,34 @@ impl Default for BoidSettings3D {
             max_speed: 300.0,
             bounce_against_walls: true,
             attraction_coeff: 1.0,
-            field_of_view: 90.0
+            field_of_view: 90.0,
+            max_force: 2000.0,
+            max_angular_velocity: 360.0,
+            banking_coeff: 0.002,
+            max_bank_angle: 60.0
         }
     }
 }
 
+impl SettingsHints for BoidSettings3D {
+    fn field_hints(field: &str) -> Option<FieldHints> {
+        Some(match field {
+            "size" => FieldHints::new("Boids size").range(0.1, 20.0),
+            "max_force" => FieldHints::new("Max steering force").range(0.0, 5000.0)
+                .tooltip("Bound on the sum of the flocking forces"),
+            "max_angular_velocity" => FieldHints::new("Max turn rate").range(10.0, 720.0).unit("°/s"),
+            "banking_coeff" => FieldHints::new("Banking").range(0.0, 0.02)
+                .tooltip("How far boids roll into their turns"),
+            "max_bank_angle" => FieldHints::new("Max bank angle").range(0.0, 90.0).unit("°"),
+            _ => return BoidSettings2D::field_hints(field)
+        })
+    }
+
+    fn mode() -> Option<SimulationState> {
+        Some(SimulationState::Mode3D)
+    }
+}
+
 #[derive(Resource)]
 pub struct GroupsTargets {
     pub targets: Vec<Vec3>
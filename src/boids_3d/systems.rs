@@ -10,15 +10,23 @@ use crate::boids_3d::events::*;
 use crate::boids_3d::cone::Cone;
 use std::sync::Mutex;
 use crate::kd_tree_3d::components::*;
-use crate::boids_2d::components::ObstacleTag;
+use crate::boids_2d::components::{ObstacleTag, PlacedObstacle};
+use crate::rules::{FlockingRules, ForceSource, Neighbour, NeighbourContext, RuleCoefficients};
+use crate::random::SystemRng;
+use crate::ui::resources::SimulationState;
 
 pub const BOUNDS_SIZE: f32 = 350.0;
 
-const GROUP_COLORS: [Color; 2] = [
+/// Distance from an obstacle's surface under which boids steer away from it.
+pub const OBSTACLE_AVOIDANCE_RANGE: f32 = 50.0;
+/// Radius avoidance takes sky and underwater obstacles to have.
+pub const OBSTACLE_RADIUS: f32 = 10.0;
+
+pub const GROUP_COLORS: [Color; 2] = [
     Color::rgb(0.9, 0.3, 0.3),
     Color::rgb(0.3, 0.3, 0.9)
 ];
-const GROUP_EMISSIVE: [Color; 2] = [
+pub const GROUP_EMISSIVE: [Color; 2] = [
     Color::rgba(0.5, 0.0, 0.0, 0.5),
     Color::rgba(0.0, 0.0, 0.5, 0.5)
 ];
@@ -27,9 +35,9 @@ pub fn spawn_boid_entity(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<StandardMaterial>>,
-    boid_settings: &BoidSettings3D
+    boid_settings: &BoidSettings3D,
+    rng: &mut impl Rng
 ) {
-    let mut rng = rand::thread_rng();
     let group = rng.gen_range(0..2);
     
     let random_pos = Vec3::new(
@@ -45,11 +53,27 @@ pub fn spawn_boid_entity(
         f32::sin(phi) * f32::sin(theta),
         f32::cos(phi)
     );
+    let transform = Transform {
+        translation: random_pos,
+        scale: Vec3::splat(boid_settings.size * 2.0),
+        ..default()
+    };
+    spawn_boid_at(commands, meshes, materials, boid_settings, group, transform, initial_velocity);
+}
 
+pub fn spawn_boid_at(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    boid_settings: &BoidSettings3D,
+    group: u8,
+    transform: Transform,
+    velocity: Vec3
+) -> Entity {
     commands.spawn((
         BoidBundle {
             boid: Boid { group },
-            velocity: Velocity { velocity: initial_velocity },
+            velocity: Velocity { velocity },
             acceleration: Acceleration { acceleration: Vec3::ZERO },
             pbr_bundle: PbrBundle {
                 mesh: meshes.add(Mesh::from(Cone {
@@ -61,16 +85,12 @@ pub fn spawn_boid_entity(
                     emissive: GROUP_EMISSIVE[group as usize],
                     ..default()
                 }),
-                transform: Transform {
-                    translation: random_pos,
-                    scale: Vec3::splat(boid_settings.size * 2.0),
-                    ..default()
-                },
+                transform,
                 ..default()
             },
             tracked_by_kdtree: TrackedByKDTree3D
         },
-        Mode3DMarker));
+        Mode3DMarker)).id()
 }
 
 pub fn spawn_boids(
@@ -78,9 +98,11 @@ pub fn spawn_boids(
     boid_settings: Res<BoidSettings3D>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut rng: Local<SystemRng>
 ) {
+    let rng = rng.get("spawn_boids_3d");
     for _ in 0..boid_settings.count {
-        spawn_boid_entity(&mut commands, &mut meshes, &mut materials, &boid_settings);
+        spawn_boid_entity(&mut commands, &mut meshes, &mut materials, &boid_settings, rng);
     }
 }
 
@@ -89,48 +111,53 @@ pub fn flocking(
     event_writer: EventWriter<ApplyForceEvent>,
     boid_settings: Res<BoidSettings3D>,
     groups_targets: Res<GroupsTargets>,
+    rules: Res<FlockingRules>,
     kd_tree: Res<NNTree3D>,
 ) {
     let cohesion_range = boid_settings.cohesion_range;
     let alignment_range = boid_settings.alignment_range;
     let separation_range = boid_settings.separation_range;
+    let coefficients = RuleCoefficients::from(&*boid_settings);
 
     let event_writer = Mutex::new(event_writer);
 
     boid_query.par_iter().for_each(|(entity, transform, velocity, boid)| {
         let position = transform.translation;
-        let mut cohesion_neighbors: Vec<Vec3> = Vec::new();
-        let mut repulsion_neighbors: Vec<(Vec3, f32)> = Vec::new();
-        let mut alignment_neighbors: Vec<Vec3> = Vec::new();
+        let mut context = NeighbourContext::new(position, velocity.velocity, boid.group, coefficients);
 
         for (_, neighbor_entity) in kd_tree.within_distance(position, cohesion_range) {
             let neighbor_entity = neighbor_entity.unwrap();
             if neighbor_entity == entity { continue; }
 
-            if let Ok((_, neighbor_transform, neighbor_velocity, _)) = boid_query.get(neighbor_entity) {
+            if let Ok((_, neighbor_transform, neighbor_velocity, neighbor_boid)) = boid_query.get(neighbor_entity) {
                 let neighbor_pos = neighbor_transform.translation;
                 if let Some(distance) = is_in_field_of_view(&position, &velocity.velocity, &neighbor_pos, &boid_settings.field_of_view) {
+                    context.neighbours.push(Neighbour {
+                        position: neighbor_pos,
+                        velocity: neighbor_velocity.velocity,
+                        group: neighbor_boid.group,
+                        distance
+                    });
                     if distance < separation_range {
-                        repulsion_neighbors.push((neighbor_pos, distance));
+                        context.separation_neighbours.push((neighbor_pos, distance));
                     } else if distance < alignment_range {
-                        alignment_neighbors.push(neighbor_velocity.velocity);
+                        context.alignment_neighbours.push((neighbor_pos, neighbor_velocity.velocity));
                     } else if distance < cohesion_range {
-                        cohesion_neighbors.push(neighbor_pos);
+                        context.cohesion_neighbours.push(neighbor_pos);
                     }
                 }
             }
         }
 
-        let cohesion_force = cohesion(&position, &cohesion_neighbors, &boid_settings.cohesion_coeff);
-        let separation_force = separation(&position, &repulsion_neighbors, &boid_settings.separation_coeff);
-        let alignment_force = alignment(&velocity.velocity, &alignment_neighbors, &boid_settings.alignment_coeff);
-        let target = groups_targets.targets[boid.group as usize];
-        let attraction_force = attraction_to_target(&position, &target, &boid_settings.attraction_coeff);
-        let total_force = cohesion_force + separation_force + alignment_force + attraction_force;
+        context.target = Some(groups_targets.targets[boid.group as usize]);
+        rules.trace(entity, &context);
+        let total_force = rules.force(&SimulationState::Mode3D, &context);
         let mut event_writer = event_writer.lock().unwrap();
         event_writer.send(ApplyForceEvent {
             entity: entity,
-            force: total_force });
+            force: total_force,
+            source: ForceSource::Flocking
+        });
     });
 }
 
@@ -173,13 +200,13 @@ pub fn separation(position: &Vec3, repulsion_neighbors: &Vec<(Vec3, f32)>, separ
     separation_force * *separation_coeff
 }
 
-pub fn alignment(velocity: &Vec3, alignment_neighbors: &Vec<Vec3>, alignment_coeff: &f32) -> Vec3 {
+pub fn alignment(velocity: &Vec3, alignment_neighbors: &Vec<(Vec3, Vec3)>, alignment_coeff: &f32) -> Vec3 {
     let mut alignment_force: Vec3 = Vec3::ZERO;
     let nb_neighbors = alignment_neighbors.len();
     if nb_neighbors == 0 {
         return Vec3::ZERO;
     }
-    for other_velocity in alignment_neighbors.iter() {
+    for (_, other_velocity) in alignment_neighbors.iter() {
         alignment_force += *other_velocity;
     }
     alignment_force /= nb_neighbors as f32;
@@ -191,11 +218,20 @@ pub fn attraction_to_target(position: &Vec3, target: &Vec3, attraction_coeff: &f
     (*target - *position) * *attraction_coeff
 }
 
+pub fn roost_attraction(position: &Vec3, roost: &Vec3, roost_radius: &f32, roost_coeff: &f32) -> Vec3 {
+    let to_roost = *roost - *position;
+    let distance = to_roost.length();
+    if distance <= *roost_radius {
+        return Vec3::ZERO;
+    }
+    to_roost / distance * (distance - *roost_radius) * *roost_coeff
+}
+
 pub fn apply_forces_system(
     mut forces: EventReader<ApplyForceEvent>,
     mut boid_query: Query<&mut Acceleration, With<Boid>>
 ) {
-    for ApplyForceEvent{entity, force} in forces.read() {
+    for ApplyForceEvent{entity, force, ..} in forces.read() {
         if let Ok(mut acceleration) = boid_query.get_mut(*entity) {
             acceleration.acceleration += *force;
         }
@@ -208,23 +244,118 @@ pub fn update_boid_position(
     time: Res<Time>
 ) {
     for (mut transform, mut velocity, mut acceleration) in boid_query.iter_mut() {
-        velocity.velocity += acceleration.acceleration * time.delta_seconds();
-        
-        let speed = velocity.velocity.length();
-        if speed < boid_settings.min_speed {
-            velocity.velocity = velocity.velocity.normalize() * boid_settings.min_speed;
-        } else if speed > boid_settings.max_speed {
-            velocity.velocity = velocity.velocity.normalize() * boid_settings.max_speed;
-        }
-
-        transform.translation += velocity.velocity * time.delta_seconds();
-        
-        if velocity.velocity.length_squared() > 0.0 {
-            let forward = -velocity.velocity.normalize();
-            transform.rotation = Quat::from_rotation_arc(Vec3::Z, forward);
-        }
+        integrate_boid(&mut transform, &mut velocity, &mut acceleration, &boid_settings, time.delta_seconds());
+    }
+}
 
+/// Integrates one boid for a frame: the steering force is clamped to `max_force`, the heading
+/// may only turn by `max_angular_velocity` degrees per second, and the body slerps towards its
+/// new heading while banking into the turn.
+pub fn integrate_boid(
+    transform: &mut Transform,
+    velocity: &mut Velocity,
+    acceleration: &mut Acceleration,
+    boid_settings: &BoidSettings3D,
+    delta_seconds: f32
+) {
+    if delta_seconds <= 0.0 {
         acceleration.acceleration = Vec3::ZERO;
+        return;
+    }
+
+    let previous_velocity = velocity.velocity;
+    let steering = acceleration.acceleration.clamp_length_max(boid_settings.max_force);
+    velocity.velocity += steering * delta_seconds;
+
+    let max_turn = boid_settings.max_angular_velocity.to_radians() * delta_seconds;
+    velocity.velocity = limit_turn(previous_velocity, velocity.velocity, max_turn);
+
+    let speed = velocity.velocity.length();
+    if speed < boid_settings.min_speed {
+        velocity.velocity = velocity.velocity.normalize_or_zero() * boid_settings.min_speed;
+    } else if speed > boid_settings.max_speed {
+        velocity.velocity = velocity.velocity.normalize() * boid_settings.max_speed;
+    }
+
+    transform.translation += velocity.velocity * delta_seconds;
+    orient_along_velocity(transform, previous_velocity, velocity.velocity, boid_settings, delta_seconds);
+
+    acceleration.acceleration = Vec3::ZERO;
+}
+
+/// Turns the body towards `velocity` at the limited angular rate, banking with the change of
+/// velocity since `previous_velocity`.
+pub fn orient_along_velocity(
+    transform: &mut Transform,
+    previous_velocity: Vec3,
+    velocity: Vec3,
+    boid_settings: &BoidSettings3D,
+    delta_seconds: f32
+) {
+    if velocity.length_squared() > 0.0 {
+        let max_turn = boid_settings.max_angular_velocity.to_radians() * delta_seconds;
+        let lateral_acceleration = (velocity - previous_velocity) / delta_seconds;
+        let target = banked_orientation(velocity, lateral_acceleration, boid_settings);
+        transform.rotation = rotate_towards(transform.rotation, target, max_turn);
+    }
+}
+
+/// Rotates `from` towards `to` by at most `max_angle` radians, keeping the magnitude of `to`.
+pub fn limit_turn(from: Vec3, to: Vec3, max_angle: f32) -> Vec3 {
+    let (Some(from_dir), Some(to_dir)) = (from.try_normalize(), to.try_normalize()) else {
+        return to;
+    };
+    let angle = from_dir.angle_between(to_dir);
+    if angle <= max_angle {
+        return to;
+    }
+    let turn = Quat::from_rotation_arc(from_dir, to_dir);
+    let limited = Quat::IDENTITY.slerp(turn, max_angle / angle);
+    limited * from_dir * to.length()
+}
+
+/// Slerps `from` towards `to`, turning by at most `max_angle` radians.
+pub fn rotate_towards(from: Quat, to: Quat, max_angle: f32) -> Quat {
+    let angle = from.angle_between(to);
+    if angle <= max_angle || angle <= f32::EPSILON {
+        to
+    } else {
+        from.slerp(to, max_angle / angle)
+    }
+}
+
+/// Orientation facing `velocity` (the model's nose is -Z) and rolled around its forward axis in
+/// proportion to the sideways component of `acceleration`.
+pub fn banked_orientation(velocity: Vec3, acceleration: Vec3, boid_settings: &BoidSettings3D) -> Quat {
+    let forward = velocity.normalize();
+    let right = forward.cross(Vec3::Y);
+    if right.length_squared() < 1e-6 {
+        return Quat::from_rotation_arc(Vec3::Z, -forward);
+    }
+    let right = right.normalize();
+    let heading = Transform::IDENTITY.looking_to(forward, Vec3::Y).rotation;
+
+    let lateral = acceleration.dot(right);
+    let max_bank = boid_settings.max_bank_angle.to_radians();
+    let bank = (lateral * boid_settings.banking_coeff).atan().clamp(-max_bank, max_bank);
+    heading * Quat::from_rotation_z(-bank)
+}
+
+/// Box the boids of a mode are kept in: they wrap around at its faces, or are turned back once
+/// within `margin` of them.
+#[derive(Clone, Copy)]
+pub struct Confinement {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub margin: f32
+}
+
+pub fn confinement_3d() -> Confinement {
+    let margin = BOUNDS_SIZE * 0.2;
+    Confinement {
+        min: Vec3::splat(-BOUNDS_SIZE + margin),
+        max: Vec3::splat(BOUNDS_SIZE - margin),
+        margin
     }
 }
 
@@ -232,13 +363,9 @@ pub fn confine_movement (
     mut boid_query: Query<(&mut Transform, &mut Velocity, &mut Acceleration), With<Boid>>,
     boid_settings: Res<BoidSettings3D>
 ) {
-    let margin = BOUNDS_SIZE * 0.2;
-    let x_min = -BOUNDS_SIZE + margin;
-    let y_min = -BOUNDS_SIZE + margin;
-    let z_min = -BOUNDS_SIZE + margin;
-    let x_max = BOUNDS_SIZE - margin;
-    let y_max = BOUNDS_SIZE - margin;
-    let z_max = BOUNDS_SIZE - margin;
+    let Confinement { min, max, margin } = confinement_3d();
+    let (x_min, y_min, z_min) = min.into();
+    let (x_max, y_max, z_max) = max.into();
     for (mut transform, mut velocity, _) in boid_query.iter_mut() {
         if boid_settings.bounce_against_walls {
             let turn_factor: f32 = 10.0;
@@ -287,6 +414,7 @@ pub fn adjust_population(
     mut boid_settings: ResMut<BoidSettings3D>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut rng: Local<SystemRng>
 ) {
     let current_count = boid_settings.count;
     let previous_count = boid_settings.previous_count;
@@ -295,8 +423,9 @@ pub fn adjust_population(
         return;
     } 
     else if current_count > previous_count {
+        let rng = rng.get("adjust_population_3d");
         for _ in 0..(current_count - previous_count) {
-            spawn_boid_entity(&mut commands, &mut meshes, &mut materials, &boid_settings);
+            spawn_boid_entity(&mut commands, &mut meshes, &mut materials, &boid_settings, rng);
         }
     }
     else {
@@ -347,7 +476,8 @@ pub fn spawn_obstacle_3d(
             },
         },
         ObstacleTag,
-         ));
+        PlacedObstacle { radius }
+    ));
 }
 
 pub fn setup_3d_scene(
@@ -0,0 +1,36 @@
+use bevy::prelude::*;
+use crate::osc::{OscConfig, OscPlugin};
+use crate::remote::RemotePlugin;
+
+pub mod resources;
+pub mod systems;
+
+pub use resources::*;
+pub use systems::*;
+
+pub struct CliPlugin {
+    pub cli: Cli
+}
+
+impl Plugin for CliPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(seed) = self.cli.seed {
+            crate::random::seed(seed);
+        }
+        app.insert_resource(self.cli.clone())
+           .add_systems(PreStartup, (apply_cli_count, apply_cli_overrides).chain())
+           .add_systems(Startup, apply_cli_preset)
+           .add_systems(Update, exit_after_duration.run_if(has_duration));
+        if let Some(port) = self.cli.remote {
+            app.add_plugins(RemotePlugin { port });
+        }
+        if let Some(path) = self.cli.osc.as_ref() {
+            match OscConfig::load(path) {
+                Ok(config) => {
+                    app.add_plugins(OscPlugin { config });
+                }
+                Err(error) => error!("Could not load the OSC config: {error}")
+            }
+        }
+    }
+}
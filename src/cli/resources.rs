@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use crate::ui::resources::SimulationState;
+use crate::{WINDOW_HEIGHT, WINDOW_WIDTH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CliMode {
+    #[value(name = "2d")]
+    #[serde(rename = "2d")]
+    Mode2D,
+    #[value(name = "3d")]
+    #[serde(rename = "3d")]
+    Mode3D,
+    Underwater,
+    Sky
+}
+
+impl CliMode {
+    /// Name as written on the command line.
+    pub fn name(&self) -> String {
+        self.to_possible_value().map_or(String::new(), |value| value.get_name().to_string())
+    }
+}
+
+impl From<CliMode> for SimulationState {
+    fn from(mode: CliMode) -> Self {
+        match mode {
+            CliMode::Mode2D => SimulationState::Mode2D,
+            CliMode::Mode3D => SimulationState::Mode3D,
+            CliMode::Underwater => SimulationState::Underwater,
+            CliMode::Sky => SimulationState::Sky
+        }
+    }
+}
+
+impl From<SimulationState> for CliMode {
+    fn from(mode: SimulationState) -> Self {
+        match mode {
+            SimulationState::Mode2D => CliMode::Mode2D,
+            SimulationState::Mode3D => CliMode::Mode3D,
+            SimulationState::Underwater => CliMode::Underwater,
+            SimulationState::Sky => CliMode::Sky
+        }
+    }
+}
+
+#[derive(Parser, Resource, Debug, Clone)]
+#[command(version, about = "Real-time 2D and 3D boid simulation")]
+pub struct Cli {
+    /// Mode to start in
+    #[arg(long, value_enum, default_value_t = CliMode::Mode3D)]
+    pub mode: CliMode,
+    /// Number of boids in the starting mode, spread over the species underwater
+    #[arg(long)]
+    pub count: Option<usize>,
+    /// Seed for every random draw of the run
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Preset file applied on startup, see the presets directory
+    #[arg(long)]
+    pub preset: Option<PathBuf>,
+    /// Window width in logical pixels
+    #[arg(long, default_value_t = WINDOW_WIDTH)]
+    pub width: f32,
+    /// Window height in logical pixels
+    #[arg(long, default_value_t = WINDOW_HEIGHT)]
+    pub height: f32,
+    /// Start in borderless fullscreen
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// Exit after this many seconds
+    #[arg(long)]
+    pub duration: Option<f32>,
+    /// Run the simulation without a window and print a performance report
+    #[arg(long)]
+    pub headless: bool,
+    /// Number of simulation steps in a headless run
+    #[arg(long, default_value_t = 1000)]
+    pub steps: u32,
+    /// Headless steps run before timings and metrics are recorded
+    #[arg(long, default_value_t = 0)]
+    pub warmup: u32,
+    /// Print the headless report as JSON
+    #[arg(long)]
+    pub json: bool,
+    /// Override a boid settings field of the starting mode, such as `--set cohesion_coeff=12`
+    #[arg(long = "set", value_name = "FIELD=VALUE", value_parser = parse_override)]
+    pub overrides: Vec<(String, serde_json::Value)>,
+    /// Run every combination of an experiment manifest headless and write a results table
+    #[arg(long, value_name = "MANIFEST")]
+    pub sweep: Option<PathBuf>,
+    /// Search boid settings against an objective in headless runs and save the best as presets
+    #[arg(long, value_name = "SPEC")]
+    pub optimise: Option<PathBuf>,
+    /// Accept JSON commands over a WebSocket on localhost, 9001 unless a port is given
+    #[arg(long, value_name = "PORT", num_args = 0..=1, default_missing_value = "9001")]
+    pub remote: Option<u16>,
+    /// Send flock state over OSC and read settings from it, as configured in a RON file
+    #[arg(long, value_name = "CONFIG", num_args = 0..=1, default_missing_value = "osc/default.ron")]
+    pub osc: Option<PathBuf>
+}
+
+/// Values are read as JSON, anything that is not valid JSON is taken as a string.
+fn parse_override(text: &str) -> Result<(String, serde_json::Value), String> {
+    let (field, value) = text.split_once('=').ok_or_else(|| format!("expected FIELD=VALUE, got \"{text}\""))?;
+    let value = serde_json::from_str(value).unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+    Ok((field.trim().to_string(), value))
+}
@@ -0,0 +1,203 @@
+use bevy::app::AppExit;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::boids_2d::resources::BoidSettings2D;
+use crate::boids_3d::resources::BoidSettings3D;
+use crate::presets::{PresetRequest, Presets};
+use crate::sky::birds::SkyBoidSettings;
+use crate::ui::resources::SimulationState;
+use crate::underwater::fish::components::SpeciesSettings;
+use crate::underwater::fish::ecology::ALL_SPECIES;
+use super::resources::*;
+
+/// Keeps the ratio between species and only changes the number of fish per school.
+fn spread_over_species(species_settings: &mut SpeciesSettings, total: usize) {
+    let current_total = ALL_SPECIES.iter().map(|species| species_settings.get(*species).population()).sum::<usize>().max(1);
+    for species in ALL_SPECIES {
+        let school_settings = species_settings.get_mut(species);
+        let share = total * school_settings.population() / current_total;
+        school_settings.boids.count = (share / school_settings.schools.max(1)).max(1);
+        school_settings.boids.previous_count = school_settings.boids.count;
+    }
+}
+
+/// Runs before the starting mode spawns its boids.
+pub fn apply_cli_count(
+    cli: Res<Cli>,
+    mut boid_settings_2d: ResMut<BoidSettings2D>,
+    mut boid_settings_3d: ResMut<BoidSettings3D>,
+    mut sky_boid_settings: ResMut<SkyBoidSettings>,
+    mut species_settings: ResMut<SpeciesSettings>
+) {
+    let Some(count) = cli.count else { return };
+    match cli.mode {
+        CliMode::Mode2D => {
+            boid_settings_2d.count = count;
+            boid_settings_2d.previous_count = count;
+        }
+        CliMode::Mode3D => {
+            boid_settings_3d.count = count;
+            boid_settings_3d.previous_count = count;
+        }
+        CliMode::Sky => {
+            sky_boid_settings.count = count;
+            sky_boid_settings.previous_count = count;
+        }
+        CliMode::Underwater => spread_over_species(&mut species_settings, count)
+    }
+}
+
+/// Goes through the serialized form so that any field can be set by name, and so that a value of
+/// the wrong type is refused rather than silently converted.
+fn override_fields<T: Serialize + DeserializeOwned>(settings: &mut T, overrides: &[(String, serde_json::Value)]) -> Result<(), String> {
+    let mut value = serde_json::to_value(&*settings).map_err(|error| error.to_string())?;
+    let fields = value.as_object_mut().ok_or("settings are not a map of fields")?;
+    for (field, new_value) in overrides {
+        let slot = fields.get_mut(field).ok_or_else(|| format!("unknown settings field \"{field}\""))?;
+        *slot = new_value.clone();
+    }
+    *settings = serde_json::from_value(value).map_err(|error| error.to_string())?;
+    Ok(())
+}
+
+/// Underwater, the overrides apply to the fish of every species.
+pub fn override_mode_settings(
+    mode: CliMode,
+    overrides: &[(String, serde_json::Value)],
+    boid_settings_2d: &mut BoidSettings2D,
+    boid_settings_3d: &mut BoidSettings3D,
+    sky_boid_settings: &mut SkyBoidSettings,
+    species_settings: &mut SpeciesSettings
+) -> Result<(), String> {
+    match mode {
+        CliMode::Mode2D => override_fields(boid_settings_2d, overrides),
+        CliMode::Mode3D => override_fields(boid_settings_3d, overrides),
+        CliMode::Sky => override_fields(sky_boid_settings, overrides),
+        CliMode::Underwater => ALL_SPECIES.iter().try_for_each(|species| override_fields(&mut species_settings.get_mut(*species).boids, overrides))
+    }
+}
+
+/// Boid settings of every mode, for fields set by name while the simulation runs.
+#[derive(SystemParam)]
+pub struct LiveSettings<'w> {
+    boids_2d: ResMut<'w, BoidSettings2D>,
+    boids_3d: ResMut<'w, BoidSettings3D>,
+    sky_boids: ResMut<'w, SkyBoidSettings>,
+    species: ResMut<'w, SpeciesSettings>
+}
+
+impl LiveSettings<'_> {
+    /// Only the mode's resource is marked as changed, so that other modes do not react.
+    pub fn set(&mut self, mode: &SimulationState, field: String, value: serde_json::Value) -> Result<(), String> {
+        override_mode_settings(
+            CliMode::from(mode.clone()),
+            &[(field, value)],
+            self.boids_2d.bypass_change_detection(),
+            self.boids_3d.bypass_change_detection(),
+            self.sky_boids.bypass_change_detection(),
+            self.species.bypass_change_detection()
+        )?;
+        match mode {
+            SimulationState::Mode2D => self.boids_2d.set_changed(),
+            SimulationState::Mode3D => self.boids_3d.set_changed(),
+            SimulationState::Sky => self.sky_boids.set_changed(),
+            SimulationState::Underwater => self.species.set_changed()
+        }
+        Ok(())
+    }
+}
+
+/// Tries the overrides on the default settings, so that a typo fails before anything starts.
+pub fn check_cli_overrides(cli: &Cli) -> Result<(), String> {
+    override_mode_settings(cli.mode, &cli.overrides, &mut default(), &mut default(), &mut default(), &mut default())
+}
+
+/// Runs after the count, which an override of `count` then wins over.
+pub fn apply_cli_overrides(
+    cli: Res<Cli>,
+    mut boid_settings_2d: ResMut<BoidSettings2D>,
+    mut boid_settings_3d: ResMut<BoidSettings3D>,
+    mut sky_boid_settings: ResMut<SkyBoidSettings>,
+    mut species_settings: ResMut<SpeciesSettings>
+) {
+    if cli.overrides.is_empty() {
+        return;
+    }
+    let result = override_mode_settings(cli.mode, &cli.overrides, &mut boid_settings_2d, &mut boid_settings_3d, &mut sky_boid_settings, &mut species_settings);
+    if let Err(error) = result {
+        error!("Could not apply --set: {error}");
+    }
+}
+
+pub fn apply_cli_preset(
+    cli: Res<Cli>,
+    mut presets: ResMut<Presets>
+) {
+    if let Some(path) = cli.preset.clone() {
+        presets.requests.push(PresetRequest::LoadFile(path));
+    }
+}
+
+pub fn has_duration(cli: Res<Cli>) -> bool {
+    cli.duration.is_some()
+}
+
+/// Wall-clock time, so that pausing does not keep a scripted run open.
+pub fn exit_after_duration(
+    cli: Res<Cli>,
+    time: Res<Time<Real>>,
+    mut exit: EventWriter<AppExit>
+) {
+    if cli.duration.is_some_and(|duration| time.elapsed_seconds() >= duration) {
+        info!("Run duration reached, exiting");
+        exit.send(AppExit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn overrides(fields: &[(&str, serde_json::Value)]) -> Vec<(String, serde_json::Value)> {
+        fields.iter().map(|(field, value)| (field.to_string(), value.clone())).collect()
+    }
+
+    #[test]
+    fn fields_are_set_by_name() {
+        let mut settings = BoidSettings2D::default();
+        override_fields(&mut settings, &overrides(&[("cohesion_coeff", json!(3.5)), ("bounce_against_walls", json!(false))])).unwrap();
+        assert_eq!(settings.cohesion_coeff, 3.5);
+        assert!(!settings.bounce_against_walls);
+        assert_eq!(settings.alignment_coeff, BoidSettings2D::default().alignment_coeff);
+    }
+
+    #[test]
+    fn unknown_fields_are_refused() {
+        let mut settings = BoidSettings2D::default();
+        let error = override_fields(&mut settings, &overrides(&[("cohesion", json!(1.0))])).unwrap_err();
+        assert!(error.contains("\"cohesion\""));
+    }
+
+    #[test]
+    fn values_of_the_wrong_type_leave_the_settings_as_they_were() {
+        let mut settings = BoidSettings2D::default();
+        let result = override_fields(&mut settings, &overrides(&[("max_speed", json!(10.0)), ("count", json!("many"))]));
+        assert!(result.is_err());
+        assert_eq!(settings.max_speed, BoidSettings2D::default().max_speed);
+    }
+
+    #[test]
+    fn sky_and_underwater_overrides_reach_their_boid_settings() {
+        let mut sky_boid_settings = SkyBoidSettings::default();
+        let mut species_settings = SpeciesSettings::default();
+        let fields = overrides(&[("max_force", json!(42.0))]);
+        for mode in [CliMode::Sky, CliMode::Underwater] {
+            override_mode_settings(mode, &fields, &mut BoidSettings2D::default(), &mut BoidSettings3D::default(), &mut sky_boid_settings, &mut species_settings).unwrap();
+        }
+        assert_eq!(sky_boid_settings.max_force, 42.0);
+        assert!(ALL_SPECIES.iter().all(|species| species_settings.get(*species).boids.max_force == 42.0));
+    }
+}
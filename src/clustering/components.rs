@@ -0,0 +1,7 @@
+use bevy::prelude::*;
+
+/// Spatial flock a boid belongs to, kept stable across frames by overlap matching.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlockId {
+    pub id: u32
+}
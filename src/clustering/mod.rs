@@ -0,0 +1,32 @@
+use bevy::prelude::*;
+use crate::ui::resources::SimulationState;
+
+pub mod components;
+pub mod resources;
+pub mod systems;
+
+pub use resources::*;
+pub use systems::*;
+
+pub struct ClusteringPlugin;
+
+impl Plugin for ClusteringPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ClusteringSettings>()
+           .init_resource::<FlockClusters>()
+           .add_systems(OnEnter(SimulationState::Mode2D), reset_flock_clusters)
+           .add_systems(OnEnter(SimulationState::Mode3D), reset_flock_clusters)
+           .add_systems(OnEnter(SimulationState::Underwater), reset_flock_clusters)
+           .add_systems(OnEnter(SimulationState::Sky), reset_flock_clusters)
+           .add_systems(Update, (
+                cluster_flocks_2d,
+                apply_deferred,
+                tint_boids_by_flock_2d
+           ).chain().run_if(in_state(SimulationState::Mode2D)))
+           .add_systems(Update, (
+                cluster_flocks_3d,
+                apply_deferred,
+                tint_boids_by_flock_3d.run_if(in_state(SimulationState::Mode3D))
+           ).chain().run_if(not(in_state(SimulationState::Mode2D))));
+    }
+}
@@ -0,0 +1,57 @@
+use bevy::prelude::*;
+
+#[derive(Resource)]
+pub struct ClusteringSettings {
+    /// Boids closer than this are linked, a flock is a connected component of these links.
+    pub link_distance: f32,
+    /// Smaller components still get a `FlockId`, but are not counted as flocks nor logged in events.
+    pub min_flock_size: usize,
+    pub interval: u32,
+    pub tint_by_flock: bool
+}
+
+impl Default for ClusteringSettings {
+    fn default() -> Self {
+        Self {
+            link_distance: 25.0,
+            min_flock_size: 3,
+            interval: 10,
+            tint_by_flock: false
+        }
+    }
+}
+
+pub struct Flock {
+    pub id: u32,
+    pub size: usize,
+    pub centroid: Vec3
+}
+
+#[derive(Debug, Clone)]
+pub enum FlockEventKind {
+    Split { parent: u32, children: Vec<u32> },
+    Merge { parents: Vec<u32>, child: u32 }
+}
+
+pub struct FlockEvent {
+    pub time: f32,
+    pub kind: FlockEventKind
+}
+
+impl FlockEvent {
+    pub fn describe(&self) -> String {
+        match &self.kind {
+            FlockEventKind::Split { parent, children } => format!("{:.1}s  flock {parent} split into {children:?}", self.time),
+            FlockEventKind::Merge { parents, child } => format!("{:.1}s  flocks {parents:?} merged into {child}", self.time)
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct FlockClusters {
+    /// Flocks of at least `min_flock_size` boids, largest first.
+    pub flocks: Vec<Flock>,
+    pub events: Vec<FlockEvent>,
+    pub frame: u32,
+    pub(super) next_id: u32
+}
@@ -0,0 +1,236 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_spatial::SpatialAccess;
+use crate::boids_2d::components as boids_2d;
+use crate::boids_3d::components as boids_3d;
+use crate::boids_3d::systems::{GROUP_COLORS, GROUP_EMISSIVE};
+use crate::kd_tree_2d::components::NNTree2D;
+use crate::kd_tree_3d::components::NNTree3D;
+use crate::metrics::find_root;
+use super::components::*;
+use super::resources::*;
+
+const MAX_LOGGED_EVENTS: usize = 100;
+
+pub fn reset_flock_clusters(mut clusters: ResMut<FlockClusters>) {
+    *clusters = FlockClusters::default();
+}
+
+fn should_cluster(clusters: &mut FlockClusters, settings: &ClusteringSettings) -> bool {
+    clusters.frame += 1;
+    if clusters.frame < settings.interval.max(1) {
+        return false;
+    }
+    clusters.frame = 0;
+    true
+}
+
+/// Groups boid indices by the root of their union-find tree.
+fn components(parents: &mut [usize]) -> Vec<Vec<usize>> {
+    let mut by_root: HashMap<usize, Vec<usize>> = HashMap::new();
+    for index in 0..parents.len() {
+        by_root.entry(find_root(parents, index)).or_default().push(index);
+    }
+    by_root.into_values().collect()
+}
+
+/// Gives every component the previous flock ID it shares the most boids with, each previous ID
+/// going to at most one component, and records splits and merges between sizeable flocks.
+fn assign_flocks(
+    commands: &mut Commands,
+    clusters: &mut FlockClusters,
+    settings: &ClusteringSettings,
+    boids: &[(Entity, Vec3, Option<u32>)],
+    components: Vec<Vec<usize>>,
+    now: f32
+) {
+    let overlaps: Vec<HashMap<u32, usize>> = components.iter().map(|members| {
+        let mut overlap: HashMap<u32, usize> = HashMap::new();
+        for previous in members.iter().filter_map(|index| boids[*index].2) {
+            *overlap.entry(previous).or_default() += 1;
+        }
+        overlap
+    }).collect();
+
+    let mut candidates: Vec<(usize, usize, u32)> = overlaps.iter()
+        .enumerate()
+        .flat_map(|(component, overlap)| overlap.iter().map(move |(previous, count)| (*count, component, *previous)))
+        .collect();
+    candidates.sort_by(|a, b| b.cmp(a));
+
+    let mut ids: Vec<Option<u32>> = vec![None; components.len()];
+    let mut taken: HashSet<u32> = HashSet::new();
+    for (_, component, previous) in candidates {
+        if ids[component].is_none() && taken.insert(previous) {
+            ids[component] = Some(previous);
+        }
+    }
+    let ids: Vec<u32> = ids.into_iter().map(|id| id.unwrap_or_else(|| {
+        clusters.next_id += 1;
+        clusters.next_id
+    })).collect();
+
+    let sizeable = |component: usize| components[component].len() >= settings.min_flock_size;
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (component, overlap) in overlaps.iter().enumerate() {
+        if !sizeable(component) {
+            continue;
+        }
+        let mut parents: Vec<u32> = overlap.iter()
+            .filter(|(_, count)| **count >= settings.min_flock_size)
+            .map(|(previous, _)| *previous)
+            .collect();
+        for parent in parents.iter() {
+            children.entry(*parent).or_default().push(ids[component]);
+        }
+        if parents.len() > 1 {
+            parents.sort();
+            let event = FlockEvent { time: now, kind: FlockEventKind::Merge { parents, child: ids[component] } };
+            info!("{}", event.describe());
+            clusters.events.push(event);
+        }
+    }
+    for (parent, mut children) in children {
+        if children.len() > 1 {
+            children.sort();
+            let event = FlockEvent { time: now, kind: FlockEventKind::Split { parent, children } };
+            info!("{}", event.describe());
+            clusters.events.push(event);
+        }
+    }
+    let overflow = clusters.events.len().saturating_sub(MAX_LOGGED_EVENTS);
+    clusters.events.drain(..overflow);
+
+    let mut flocks = Vec::new();
+    for (component, members) in components.iter().enumerate() {
+        for index in members.iter() {
+            let (entity, _, previous) = boids[*index];
+            if previous != Some(ids[component]) {
+                commands.entity(entity).try_insert(FlockId { id: ids[component] });
+            }
+        }
+        if sizeable(component) {
+            flocks.push(Flock {
+                id: ids[component],
+                size: members.len(),
+                centroid: members.iter().map(|index| boids[*index].1).sum::<Vec3>() / members.len() as f32
+            });
+        }
+    }
+    flocks.sort_by(|a, b| b.size.cmp(&a.size).then(a.id.cmp(&b.id)));
+    clusters.flocks = flocks;
+}
+
+pub fn cluster_flocks_2d(
+    mut commands: Commands,
+    boid_query: Query<(Entity, &Transform, Option<&FlockId>), With<boids_2d::Boid>>,
+    kd_tree: Res<NNTree2D>,
+    settings: Res<ClusteringSettings>,
+    mut clusters: ResMut<FlockClusters>,
+    time: Res<Time>
+) {
+    if !should_cluster(clusters.bypass_change_detection(), &settings) {
+        return;
+    }
+    let boids: Vec<(Entity, Vec3, Option<u32>)> = boid_query.iter()
+        .map(|(entity, transform, flock_id)| (entity, transform.translation, flock_id.map(|flock_id| flock_id.id)))
+        .collect();
+    let indices: HashMap<Entity, usize> = boids.iter().enumerate().map(|(index, (entity, _, _))| (*entity, index)).collect();
+
+    let mut parents: Vec<usize> = (0..boids.len()).collect();
+    for (index, (_, position, _)) in boids.iter().enumerate() {
+        for (_, neighbour) in kd_tree.within_distance(position.truncate(), settings.link_distance) {
+            if let Some(other) = neighbour.and_then(|neighbour| indices.get(&neighbour)) {
+                let (root, other_root) = (find_root(&mut parents, index), find_root(&mut parents, *other));
+                parents[root] = other_root;
+            }
+        }
+    }
+    let components = components(&mut parents);
+    assign_flocks(&mut commands, &mut clusters, &settings, &boids, components, time.elapsed_seconds());
+}
+
+pub fn cluster_flocks_3d(
+    mut commands: Commands,
+    boid_query: Query<(Entity, &Transform, Option<&FlockId>), With<boids_3d::Boid>>,
+    kd_tree: Res<NNTree3D>,
+    settings: Res<ClusteringSettings>,
+    mut clusters: ResMut<FlockClusters>,
+    time: Res<Time>
+) {
+    if !should_cluster(clusters.bypass_change_detection(), &settings) {
+        return;
+    }
+    let boids: Vec<(Entity, Vec3, Option<u32>)> = boid_query.iter()
+        .map(|(entity, transform, flock_id)| (entity, transform.translation, flock_id.map(|flock_id| flock_id.id)))
+        .collect();
+    let indices: HashMap<Entity, usize> = boids.iter().enumerate().map(|(index, (entity, _, _))| (*entity, index)).collect();
+
+    let mut parents: Vec<usize> = (0..boids.len()).collect();
+    for (index, (_, position, _)) in boids.iter().enumerate() {
+        for (_, neighbour) in kd_tree.within_distance(*position, settings.link_distance) {
+            if let Some(other) = neighbour.and_then(|neighbour| indices.get(&neighbour)) {
+                let (root, other_root) = (find_root(&mut parents, index), find_root(&mut parents, *other));
+                parents[root] = other_root;
+            }
+        }
+    }
+    let components = components(&mut parents);
+    assign_flocks(&mut commands, &mut clusters, &settings, &boids, components, time.elapsed_seconds());
+}
+
+/// Well spread hues for consecutive flock IDs.
+pub fn flock_color(id: u32) -> Color {
+    Color::hsl((id as f32 * 137.508) % 360.0, 0.75, 0.55)
+}
+
+pub fn tint_boids_by_flock_2d(
+    mut boid_query: Query<(&mut Sprite, Option<&FlockId>), With<boids_2d::Boid>>,
+    settings: Res<ClusteringSettings>,
+    clusters: Res<FlockClusters>,
+    mut tinted: Local<bool>
+) {
+    if settings.tint_by_flock {
+        if *tinted && !clusters.is_changed() {
+            return;
+        }
+        for (mut sprite, flock_id) in boid_query.iter_mut() {
+            sprite.color = flock_id.map_or(Color::WHITE, |flock_id| flock_color(flock_id.id));
+        }
+        *tinted = true;
+    } else if *tinted {
+        for (mut sprite, _) in boid_query.iter_mut() {
+            sprite.color = Color::WHITE;
+        }
+        *tinted = false;
+    }
+}
+
+/// Boids in the 3D mode each own their material, so it can be recoloured in place.
+pub fn tint_boids_by_flock_3d(
+    boid_query: Query<(&Handle<StandardMaterial>, &boids_3d::Boid, Option<&FlockId>)>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    settings: Res<ClusteringSettings>,
+    clusters: Res<FlockClusters>,
+    mut tinted: Local<bool>
+) {
+    if settings.tint_by_flock {
+        if *tinted && !clusters.is_changed() {
+            return;
+        }
+        for (handle, boid, flock_id) in boid_query.iter() {
+            let Some(material) = materials.get_mut(handle) else { continue };
+            let color = flock_id.map_or(GROUP_COLORS[boid.group as usize], |flock_id| flock_color(flock_id.id));
+            material.base_color = color;
+            material.emissive = color * 0.5;
+        }
+        *tinted = true;
+    } else if *tinted {
+        for (handle, boid, _) in boid_query.iter() {
+            let Some(material) = materials.get_mut(handle) else { continue };
+            material.base_color = GROUP_COLORS[boid.group as usize];
+            material.emissive = GROUP_EMISSIVE[boid.group as usize];
+        }
+        *tinted = false;
+    }
+}
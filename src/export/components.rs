@@ -0,0 +1,7 @@
+use bevy::prelude::*;
+
+/// Identifier that is never reused, unlike entity IDs which are recycled on despawn.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BoidId {
+    pub id: u64
+}
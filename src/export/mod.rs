@@ -0,0 +1,34 @@
+use bevy::prelude::*;
+use crate::ui::resources::SimulationState;
+
+pub mod components;
+pub mod resources;
+pub mod systems;
+
+pub use resources::*;
+pub use systems::*;
+
+pub struct ExportPlugin;
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NextBoidId>()
+           .init_resource::<TrajectoryRecorder>()
+           .add_systems(OnExit(SimulationState::Mode2D), stop_recording)
+           .add_systems(OnExit(SimulationState::Mode3D), stop_recording)
+           .add_systems(OnExit(SimulationState::Underwater), stop_recording)
+           .add_systems(OnExit(SimulationState::Sky), stop_recording)
+           .add_systems(Update, (
+                assign_boid_ids_2d,
+                assign_boid_ids_3d,
+                toggle_recording_hotkey,
+                handle_recording_toggle
+           ).chain())
+           .add_systems(Update, record_trajectories_2d
+                .after(handle_recording_toggle)
+                .run_if(in_state(SimulationState::Mode2D)))
+           .add_systems(Update, record_trajectories_3d
+                .after(handle_recording_toggle)
+                .run_if(not(in_state(SimulationState::Mode2D))));
+    }
+}
@@ -0,0 +1,61 @@
+use bevy::prelude::*;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+#[derive(Resource, Default)]
+pub struct NextBoidId {
+    pub next: u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    JsonLines
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::JsonLines => "jsonl"
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct TrajectoryRecorder {
+    pub format: ExportFormat,
+    /// A row is written for every boid once every `every_k_steps` frames.
+    pub every_k_steps: u32,
+    pub directory: PathBuf,
+    pub toggle_requested: bool,
+    pub path: Option<PathBuf>,
+    pub step: u64,
+    pub rows: u64,
+    pub error: Option<String>,
+    pub(super) writer: Option<BufWriter<File>>
+}
+
+impl Default for TrajectoryRecorder {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Csv,
+            every_k_steps: 1,
+            directory: PathBuf::from("recordings"),
+            toggle_requested: false,
+            path: None,
+            step: 0,
+            rows: 0,
+            error: None,
+            writer: None
+        }
+    }
+}
+
+impl TrajectoryRecorder {
+    pub fn is_recording(&self) -> bool {
+        self.writer.is_some()
+    }
+}
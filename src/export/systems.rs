@@ -0,0 +1,228 @@
+use bevy::prelude::*;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::boids_2d::components as boids_2d;
+use crate::boids_2d::resources::BoidSettings2D;
+use crate::boids_3d::components as boids_3d;
+use crate::boids_3d::resources::BoidSettings3D;
+use crate::sky::birds::SkyBoidSettings;
+use crate::ui::resources::SimulationState;
+use crate::underwater::fish::components::{FishType, SpeciesSettings};
+use super::components::*;
+use super::resources::*;
+
+const CSV_COLUMNS: &str = "step,time,id,group,species,x,y,z,vx,vy,vz";
+
+#[derive(Serialize)]
+struct TrajectoryRow<'a> {
+    step: u64,
+    time: f64,
+    id: u64,
+    group: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    species: Option<&'a str>,
+    position: [f32; 3],
+    velocity: [f32; 3]
+}
+
+pub fn assign_boid_ids_2d(
+    mut commands: Commands,
+    boid_query: Query<Entity, (With<boids_2d::Boid>, Without<BoidId>)>,
+    mut next_id: ResMut<NextBoidId>
+) {
+    for entity in boid_query.iter() {
+        commands.entity(entity).try_insert(BoidId { id: next_id.next });
+        next_id.next += 1;
+    }
+}
+
+pub fn assign_boid_ids_3d(
+    mut commands: Commands,
+    boid_query: Query<Entity, (With<boids_3d::Boid>, Without<BoidId>)>,
+    mut next_id: ResMut<NextBoidId>
+) {
+    for entity in boid_query.iter() {
+        commands.entity(entity).try_insert(BoidId { id: next_id.next });
+        next_id.next += 1;
+    }
+}
+
+pub fn toggle_recording_hotkey(
+    keys: Res<Input<KeyCode>>,
+    mut recorder: ResMut<TrajectoryRecorder>
+) {
+    if keys.just_pressed(KeyCode::F9) {
+        recorder.toggle_requested = true;
+    }
+}
+
+/// Settings of the current mode, written in the header of every export.
+pub fn settings_header(
+    state: &SimulationState,
+    boid_settings_2d: &BoidSettings2D,
+    boid_settings_3d: &BoidSettings3D,
+    sky_boid_settings: &SkyBoidSettings,
+    species_settings: &SpeciesSettings
+) -> serde_json::Value {
+    let settings = match state {
+        SimulationState::Mode2D => serde_json::to_value(boid_settings_2d),
+        SimulationState::Mode3D => serde_json::to_value(boid_settings_3d),
+        SimulationState::Underwater => serde_json::to_value(species_settings),
+        SimulationState::Sky => serde_json::to_value(sky_boid_settings)
+    };
+    serde_json::json!({
+        "mode": format!("{state:?}"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "settings": settings.unwrap_or_default()
+    })
+}
+
+fn start_recording(recorder: &mut TrajectoryRecorder, header: serde_json::Value) -> std::io::Result<()> {
+    fs::create_dir_all(&recorder.directory)?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let mode = header["mode"].as_str().unwrap_or("unknown").to_lowercase();
+    let path = recorder.directory.join(format!("trajectories_{mode}_{timestamp}.{}", recorder.format.extension()));
+
+    let mut writer = BufWriter::new(File::create(&path)?);
+    match recorder.format {
+        ExportFormat::Csv => {
+            writeln!(writer, "# boidsy trajectory export")?;
+            writeln!(writer, "# mode: {}", header["mode"].as_str().unwrap_or_default())?;
+            writeln!(writer, "# version: {}", header["version"].as_str().unwrap_or_default())?;
+            writeln!(writer, "# settings: {}", header["settings"])?;
+            writeln!(writer, "{CSV_COLUMNS}")?;
+        }
+        ExportFormat::JsonLines => {
+            writeln!(writer, "{}", serde_json::json!({ "header": header }))?;
+        }
+    }
+
+    info!("Recording trajectories to {}", path.display());
+    recorder.writer = Some(writer);
+    recorder.path = Some(path);
+    recorder.step = 0;
+    recorder.rows = 0;
+    Ok(())
+}
+
+pub fn stop_recording(mut recorder: ResMut<TrajectoryRecorder>) {
+    if let Some(mut writer) = recorder.writer.take() {
+        if let Err(error) = writer.flush() {
+            recorder.error = Some(error.to_string());
+        }
+        info!("Stopped recording after {} rows", recorder.rows);
+    }
+}
+
+pub fn handle_recording_toggle(
+    mut recorder: ResMut<TrajectoryRecorder>,
+    state: Res<State<SimulationState>>,
+    boid_settings_2d: Res<BoidSettings2D>,
+    boid_settings_3d: Res<BoidSettings3D>,
+    sky_boid_settings: Res<SkyBoidSettings>,
+    species_settings: Res<SpeciesSettings>
+) {
+    if !recorder.toggle_requested {
+        return;
+    }
+    recorder.toggle_requested = false;
+
+    if recorder.is_recording() {
+        stop_recording(recorder);
+        return;
+    }
+    let header = settings_header(state.get(), &boid_settings_2d, &boid_settings_3d, &sky_boid_settings, &species_settings);
+    recorder.error = start_recording(&mut recorder, header).err().map(|error| error.to_string());
+}
+
+fn write_row(recorder: &mut TrajectoryRecorder, row: &TrajectoryRow) -> std::io::Result<()> {
+    let format = recorder.format;
+    let Some(writer) = recorder.writer.as_mut() else {
+        return Ok(());
+    };
+    match format {
+        ExportFormat::Csv => writeln!(
+            writer,
+            "{},{:.4},{},{},{},{},{},{},{},{},{}",
+            row.step, row.time, row.id, row.group, row.species.unwrap_or_default(),
+            row.position[0], row.position[1], row.position[2],
+            row.velocity[0], row.velocity[1], row.velocity[2]
+        )?,
+        ExportFormat::JsonLines => {
+            serde_json::to_writer(&mut *writer, row)?;
+            writeln!(writer)?;
+        }
+    }
+    recorder.rows += 1;
+    Ok(())
+}
+
+/// Advances the step counter and tells whether this step should be written.
+fn sample_step(recorder: &mut TrajectoryRecorder) -> Option<u64> {
+    if !recorder.is_recording() {
+        return None;
+    }
+    let step = recorder.step;
+    recorder.step += 1;
+    (step % recorder.every_k_steps.max(1) as u64 == 0).then_some(step)
+}
+
+fn fail(recorder: &mut TrajectoryRecorder, error: std::io::Error) {
+    error!("Trajectory export failed: {error}");
+    recorder.error = Some(error.to_string());
+    recorder.writer = None;
+}
+
+pub fn record_trajectories_2d(
+    boid_query: Query<(&Transform, &boids_2d::Velocity, &boids_2d::Boid, &BoidId)>,
+    mut recorder: ResMut<TrajectoryRecorder>,
+    time: Res<Time>
+) {
+    let Some(step) = sample_step(&mut recorder) else { return };
+    let mut boids: Vec<_> = boid_query.iter().collect();
+    boids.sort_by_key(|(_, _, _, boid_id)| boid_id.id);
+
+    for (transform, velocity, boid, boid_id) in boids {
+        let row = TrajectoryRow {
+            step,
+            time: time.elapsed_seconds_f64(),
+            id: boid_id.id,
+            group: boid.group,
+            species: None,
+            position: [transform.translation.x, transform.translation.y, 0.0],
+            velocity: [velocity.velocity.x, velocity.velocity.y, 0.0]
+        };
+        if let Err(error) = write_row(&mut recorder, &row) {
+            fail(&mut recorder, error);
+            return;
+        }
+    }
+}
+
+pub fn record_trajectories_3d(
+    boid_query: Query<(&Transform, &boids_3d::Velocity, &boids_3d::Boid, &BoidId, Option<&FishType>)>,
+    mut recorder: ResMut<TrajectoryRecorder>,
+    time: Res<Time>
+) {
+    let Some(step) = sample_step(&mut recorder) else { return };
+    let mut boids: Vec<_> = boid_query.iter().collect();
+    boids.sort_by_key(|(_, _, _, boid_id, _)| boid_id.id);
+
+    for (transform, velocity, boid, boid_id, fish_type) in boids {
+        let row = TrajectoryRow {
+            step,
+            time: time.elapsed_seconds_f64(),
+            id: boid_id.id,
+            group: boid.group,
+            species: fish_type.map(|fish_type| fish_type.species.name()),
+            position: transform.translation.to_array(),
+            velocity: velocity.velocity.to_array()
+        };
+        if let Err(error) = write_row(&mut recorder, &row) {
+            fail(&mut recorder, error);
+            return;
+        }
+    }
+}
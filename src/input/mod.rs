@@ -7,7 +7,10 @@ pub use resources::*;
 
 pub const RADIUS: f32 = 10.0;
 
-pub struct InputPlugin;
+pub struct InputPlugin {
+    /// Whether E toggles the free-flying camera of the 3D modes.
+    pub camera_control: bool
+}
 
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
@@ -15,8 +18,10 @@ impl Plugin for InputPlugin {
         .insert_resource(MouseSettings::default())
         .add_systems(Update, (
             mouse_buttons_input,
-            scroll_events,
-            handle_camera_control
+            scroll_events
         ));
+        if self.camera_control {
+            app.add_systems(Update, handle_camera_control);
+        }
     }
 }
\ No newline at end of file
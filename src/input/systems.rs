@@ -12,6 +12,7 @@ use crate::ui::resources::SimulationState;
 use bevy::input::mouse::MouseWheel;
 use bevy::input::mouse::MouseMotion;
 
+#[allow(clippy::too_many_arguments)]
 pub fn mouse_buttons_input(
     mouse_buttons: Res<Input<MouseButton>>,
     mut commands: Commands,
@@ -126,7 +127,7 @@ pub fn handle_camera_control(
 }
 
 pub fn handle_camera_movement(
-    time: Res<Time>,
+    time: Res<Time<Real>>,
     keyboard_input: Res<Input<KeyCode>>,
     mut mouse_motion: EventReader<MouseMotion>,
     mut settings: ResMut<MouseSettings>,
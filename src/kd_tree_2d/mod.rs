@@ -5,13 +5,26 @@ use components::TrackedByKDTree2D;
 
 pub mod components;
 
-pub struct KDTree2DPlugin;
+pub struct KDTree2DPlugin {
+    /// Time between rebuilds of the tree.
+    pub frequency: Duration,
+    pub transform: TransformMode
+}
+
+impl Default for KDTree2DPlugin {
+    fn default() -> Self {
+        Self {
+            frequency: Duration::from_millis(1),
+            transform: TransformMode::Transform
+        }
+    }
+}
 
 impl Plugin for KDTree2DPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(AutomaticUpdate::<TrackedByKDTree2D>::new()
         .with_spatial_ds(SpatialStructure::KDTree2)
-        .with_frequency(Duration::from_millis(1))
-        .with_transform(TransformMode::Transform));
+        .with_frequency(self.frequency)
+        .with_transform(self.transform));
     }
-}
\ No newline at end of file
+}
@@ -5,13 +5,26 @@ use components::TrackedByKDTree3D;
 
 pub mod components;
 
-pub struct KDTree3DPlugin;
+pub struct KDTree3DPlugin {
+    /// Time between rebuilds of the tree.
+    pub frequency: Duration,
+    pub transform: TransformMode
+}
+
+impl Default for KDTree3DPlugin {
+    fn default() -> Self {
+        Self {
+            frequency: Duration::from_millis(1),
+            transform: TransformMode::Transform
+        }
+    }
+}
 
 impl Plugin for KDTree3DPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(AutomaticUpdate::<TrackedByKDTree3D>::new()
         .with_spatial_ds(SpatialStructure::KDTree3)
-        .with_frequency(Duration::from_millis(1))
-        .with_transform(TransformMode::Transform));
+        .with_frequency(self.frequency)
+        .with_transform(self.transform));
     }
-}
\ No newline at end of file
+}
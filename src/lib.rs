@@ -0,0 +1,35 @@
+//! Boid flocking simulations for Bevy, in 2D, 3D, underwater and in the sky. Add `BoidsPlugin`
+//! to an app, or pick the modules' own plugins for finer control.
+
+pub mod boids_2d;
+pub mod ui;
+pub mod input;
+pub mod kd_tree_2d;
+pub mod boids_3d;
+pub mod kd_tree_3d;
+pub mod underwater;
+pub mod sky;
+pub mod metrics;
+pub mod export;
+pub mod clustering;
+pub mod replay;
+pub mod snapshot;
+pub mod presets;
+pub mod cli;
+pub mod random;
+pub mod bench;
+pub mod sweep;
+pub mod optimise;
+pub mod osc;
+pub mod overlay;
+pub mod scripting;
+pub mod rules;
+pub mod remote;
+pub mod selection;
+pub mod settings;
+pub mod plugin;
+
+pub use plugin::{BoidsPlugin, Dimensions, SpatialBackend};
+
+pub const WINDOW_WIDTH: f32 = 1920.0;
+pub const WINDOW_HEIGHT: f32 = 1080.0;
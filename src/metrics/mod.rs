@@ -0,0 +1,23 @@
+use bevy::prelude::*;
+use crate::ui::resources::SimulationState;
+
+pub mod resources;
+pub mod systems;
+
+pub use resources::*;
+pub use systems::*;
+
+pub struct MetricsPlugin;
+
+impl Plugin for MetricsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MetricsSettings>()
+           .init_resource::<FlockMetrics>()
+           .add_systems(OnEnter(SimulationState::Mode2D), reset_metrics)
+           .add_systems(OnEnter(SimulationState::Mode3D), reset_metrics)
+           .add_systems(OnEnter(SimulationState::Underwater), reset_metrics)
+           .add_systems(OnEnter(SimulationState::Sky), reset_metrics)
+           .add_systems(Update, sample_metrics_2d.run_if(in_state(SimulationState::Mode2D)))
+           .add_systems(Update, sample_metrics_3d.run_if(not(in_state(SimulationState::Mode2D))));
+    }
+}
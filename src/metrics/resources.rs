@@ -0,0 +1,158 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::collections::VecDeque;
+
+#[derive(Resource)]
+pub struct MetricsSettings {
+    /// Metrics are computed every `sample_interval` frames.
+    pub sample_interval: u32,
+    /// Two boids closer than this are neighbours, and belong to the same cluster.
+    pub neighbour_radius: f32,
+    /// A boid whose nearest neighbour is closer than this is colliding.
+    pub collision_distance: f32,
+    pub capacity: usize
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            sample_interval: 10,
+            neighbour_radius: 30.0,
+            collision_distance: 2.0,
+            capacity: 600
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GroupMetrics {
+    pub boids: usize,
+    pub polarisation: f32,
+    pub angular_momentum: f32,
+    pub nearest_neighbour_distance: f32,
+    pub neighbour_count: f32,
+    pub average_speed: f32,
+    pub radius_of_gyration: f32,
+    pub clusters: usize,
+    /// Fraction of boids closer than the collision distance to another one.
+    pub collisions: f32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Metric {
+    #[default]
+    Polarisation,
+    AngularMomentum,
+    NearestNeighbourDistance,
+    NeighbourCount,
+    AverageSpeed,
+    RadiusOfGyration,
+    Clusters,
+    Collisions
+}
+
+impl Metric {
+    pub const ALL: [Metric; 8] = [
+        Metric::Polarisation,
+        Metric::AngularMomentum,
+        Metric::NearestNeighbourDistance,
+        Metric::NeighbourCount,
+        Metric::AverageSpeed,
+        Metric::RadiusOfGyration,
+        Metric::Clusters,
+        Metric::Collisions
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Metric::Polarisation => "Polarisation",
+            Metric::AngularMomentum => "Angular momentum",
+            Metric::NearestNeighbourDistance => "Nearest neighbour distance",
+            Metric::NeighbourCount => "Neighbour count",
+            Metric::AverageSpeed => "Average speed",
+            Metric::RadiusOfGyration => "Radius of gyration",
+            Metric::Clusters => "Clusters",
+            Metric::Collisions => "Collisions"
+        }
+    }
+
+    /// Name used in result tables and experiment files.
+    pub fn key(&self) -> &'static str {
+        match self {
+            Metric::Polarisation => "polarisation",
+            Metric::AngularMomentum => "angular_momentum",
+            Metric::NearestNeighbourDistance => "nearest_neighbour_distance",
+            Metric::NeighbourCount => "neighbour_count",
+            Metric::AverageSpeed => "average_speed",
+            Metric::RadiusOfGyration => "radius_of_gyration",
+            Metric::Clusters => "clusters",
+            Metric::Collisions => "collisions"
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Metric> {
+        Metric::ALL.into_iter().find(|metric| metric.key() == key)
+    }
+
+    pub fn value(&self, metrics: &GroupMetrics) -> f64 {
+        match self {
+            Metric::Polarisation => metrics.polarisation as f64,
+            Metric::AngularMomentum => metrics.angular_momentum as f64,
+            Metric::NearestNeighbourDistance => metrics.nearest_neighbour_distance as f64,
+            Metric::NeighbourCount => metrics.neighbour_count as f64,
+            Metric::AverageSpeed => metrics.average_speed as f64,
+            Metric::RadiusOfGyration => metrics.radius_of_gyration as f64,
+            Metric::Clusters => metrics.clusters as f64,
+            Metric::Collisions => metrics.collisions as f64
+        }
+    }
+}
+
+/// Fixed-capacity time series, the oldest samples are dropped first.
+#[derive(Default)]
+pub struct TimeSeries {
+    pub samples: VecDeque<(f64, GroupMetrics)>
+}
+
+impl TimeSeries {
+    pub fn push(&mut self, time: f64, metrics: GroupMetrics, capacity: usize) {
+        self.samples.push_back((time, metrics));
+        while self.samples.len() > capacity {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn latest(&self) -> Option<&GroupMetrics> {
+        self.samples.back().map(|(_, metrics)| metrics)
+    }
+
+    pub fn points(&self, metric: Metric) -> Vec<[f64; 2]> {
+        self.samples.iter().map(|(time, metrics)| [*time, metric.value(metrics)]).collect()
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct FlockMetrics {
+    pub groups: HashMap<String, TimeSeries>,
+    pub frame: u32,
+    pub plotted: Metric
+}
+
+impl FlockMetrics {
+    /// Mean of a metric over every recorded sample, each group weighted by its number of boids.
+    pub fn mean(&self, metric: Metric) -> f64 {
+        let (mut total, mut weight) = (0.0, 0.0);
+        for (_, metrics) in self.groups.values().flat_map(|series| series.samples.iter()) {
+            total += metric.value(metrics) * metrics.boids as f64;
+            weight += metrics.boids as f64;
+        }
+        if weight > 0.0 { total / weight } else { 0.0 }
+    }
+
+
+    pub fn sorted_groups(&self) -> Vec<(&String, &TimeSeries)> {
+        let mut groups: Vec<_> = self.groups.iter().collect();
+        groups.sort_by_key(|(group, _)| *group);
+        groups
+    }
+}
@@ -0,0 +1,294 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use crate::boids_2d::components as boids_2d;
+use crate::boids_3d::components as boids_3d;
+use crate::underwater::fish::components::FishType;
+use super::resources::*;
+
+/// Norm of the mean heading, 1 when every velocity points the same way.
+pub fn polarisation(velocities: &[Vec3]) -> f32 {
+    if velocities.is_empty() {
+        return 0.0;
+    }
+    let sum: Vec3 = velocities.iter().map(|velocity| velocity.normalize_or_zero()).sum();
+    sum.length() / velocities.len() as f32
+}
+
+/// Norm of the mean normalised angular momentum around the centroid, 1 for a perfect mill.
+pub fn angular_momentum(positions: &[Vec3], velocities: &[Vec3]) -> f32 {
+    if positions.is_empty() {
+        return 0.0;
+    }
+    let centroid = positions.iter().copied().sum::<Vec3>() / positions.len() as f32;
+    let sum: Vec3 = positions.iter()
+        .zip(velocities.iter())
+        .map(|(position, velocity)| (*position - centroid).normalize_or_zero().cross(velocity.normalize_or_zero()))
+        .sum();
+    sum.length() / positions.len() as f32
+}
+
+/// Uniform grid with cells as large as the neighbour radius, so a neighbourhood query only
+/// visits the 27 surrounding cells.
+struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<IVec3, Vec<usize>>
+}
+
+impl SpatialGrid {
+    fn new(positions: &[Vec3], cell_size: f32) -> Self {
+        let mut cells: HashMap<IVec3, Vec<usize>> = HashMap::new();
+        for (index, position) in positions.iter().enumerate() {
+            cells.entry((*position / cell_size).floor().as_ivec3()).or_default().push(index);
+        }
+        Self { cell_size, cells }
+    }
+
+    fn cell(&self, position: Vec3) -> IVec3 {
+        (position / self.cell_size).floor().as_ivec3()
+    }
+
+    /// Calls `visit` for every point in the cells exactly `ring` cells away from `cell`.
+    fn visit_ring(&self, cell: IVec3, ring: i32, mut visit: impl FnMut(usize)) {
+        for x in -ring..=ring {
+            for y in -ring..=ring {
+                for z in -ring..=ring {
+                    if x.abs().max(y.abs()).max(z.abs()) != ring {
+                        continue;
+                    }
+                    if let Some(indices) = self.cells.get(&(cell + IVec3::new(x, y, z))) {
+                        indices.iter().for_each(|index| visit(*index));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Calls `visit` for every point in the cells within one cell of `position`.
+    fn visit(&self, position: Vec3, mut visit: impl FnMut(usize)) {
+        let cell = self.cell(position);
+        self.visit_ring(cell, 0, &mut visit);
+        self.visit_ring(cell, 1, &mut visit);
+    }
+
+    /// Distance from the point at `index` to its nearest other point, given the `nearest` found
+    /// within one cell. Points outside the first `ring` rings are more than `ring` cells away, so
+    /// rings are searched outwards until none can be closer. Once a ring would hold more cells
+    /// than the grid has occupied, every point is checked instead.
+    fn nearest_distance(&self, positions: &[Vec3], index: usize, mut nearest: f32) -> f32 {
+        let position = positions[index];
+        let cell = self.cell(position);
+        let mut ring = 1;
+        while nearest > ring as f32 * self.cell_size {
+            ring += 1;
+            if (2 * ring as usize + 1).pow(3) > self.cells.len() {
+                return positions.iter()
+                    .enumerate()
+                    .filter(|(other, _)| *other != index)
+                    .fold(nearest, |nearest, (_, other)| nearest.min(position.distance(*other)));
+            }
+            self.visit_ring(cell, ring, |other| {
+                if other != index {
+                    nearest = nearest.min(position.distance(positions[other]));
+                }
+            });
+        }
+        nearest
+    }
+}
+
+pub fn find_root(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+    index
+}
+
+pub fn compute_group_metrics(positions: &[Vec3], velocities: &[Vec3], neighbour_radius: f32, collision_distance: f32) -> GroupMetrics {
+    let count = positions.len();
+    if count == 0 {
+        return GroupMetrics::default();
+    }
+    let neighbour_radius = neighbour_radius.max(f32::EPSILON);
+    let centroid = positions.iter().copied().sum::<Vec3>() / count as f32;
+    let radius_of_gyration = (positions.iter().map(|position| position.distance_squared(centroid)).sum::<f32>() / count as f32).sqrt();
+    let average_speed = velocities.iter().map(|velocity| velocity.length()).sum::<f32>() / count as f32;
+
+    let grid = SpatialGrid::new(positions, neighbour_radius);
+    let mut parents: Vec<usize> = (0..count).collect();
+    let mut neighbour_total = 0;
+    let mut nearest_total = 0.0;
+    let mut nearest_found = 0;
+    let mut colliding = 0;
+
+    for (index, position) in positions.iter().enumerate() {
+        let mut nearest = f32::MAX;
+        grid.visit(*position, |other| {
+            if other == index {
+                return;
+            }
+            let distance = position.distance(positions[other]);
+            nearest = nearest.min(distance);
+            if distance < neighbour_radius {
+                neighbour_total += 1;
+                let (root, other_root) = (find_root(&mut parents, index), find_root(&mut parents, other));
+                parents[root] = other_root;
+            }
+        });
+        let nearest = grid.nearest_distance(positions, index, nearest);
+        if nearest < f32::MAX {
+            nearest_total += nearest;
+            nearest_found += 1;
+        }
+        if nearest < collision_distance {
+            colliding += 1;
+        }
+    }
+
+    let clusters = (0..count).filter(|index| find_root(&mut parents, *index) == *index).count();
+
+    GroupMetrics {
+        boids: count,
+        polarisation: polarisation(velocities),
+        angular_momentum: angular_momentum(positions, velocities),
+        nearest_neighbour_distance: if nearest_found > 0 { nearest_total / nearest_found as f32 } else { 0.0 },
+        neighbour_count: neighbour_total as f32 / count as f32,
+        average_speed,
+        radius_of_gyration,
+        clusters,
+        collisions: colliding as f32 / count as f32
+    }
+}
+
+pub fn reset_metrics(mut metrics: ResMut<FlockMetrics>) {
+    metrics.groups.clear();
+    metrics.frame = 0;
+}
+
+fn should_sample(metrics: &mut FlockMetrics, settings: &MetricsSettings) -> bool {
+    metrics.frame += 1;
+    if metrics.frame < settings.sample_interval.max(1) {
+        return false;
+    }
+    metrics.frame = 0;
+    true
+}
+
+fn record_groups(
+    metrics: &mut FlockMetrics,
+    settings: &MetricsSettings,
+    members: HashMap<String, (Vec<Vec3>, Vec<Vec3>)>,
+    time: f64
+) {
+    metrics.groups.retain(|group, _| members.contains_key(group));
+    for (group, (positions, velocities)) in members {
+        let group_metrics = compute_group_metrics(&positions, &velocities, settings.neighbour_radius, settings.collision_distance);
+        metrics.groups.entry(group).or_default().push(time, group_metrics, settings.capacity);
+    }
+}
+
+/// Name metrics are recorded under. Fish are grouped by species rather than by school.
+pub fn group_name(group: u8, fish_type: Option<&FishType>) -> String {
+    match fish_type {
+        Some(fish_type) => fish_type.species.name().to_string(),
+        None => format!("Group {group}")
+    }
+}
+
+pub fn sample_metrics_2d(
+    boid_query: Query<(&Transform, &boids_2d::Velocity, &boids_2d::Boid)>,
+    mut metrics: ResMut<FlockMetrics>,
+    settings: Res<MetricsSettings>,
+    time: Res<Time>
+) {
+    if !should_sample(&mut metrics, &settings) {
+        return;
+    }
+    let mut members: HashMap<String, (Vec<Vec3>, Vec<Vec3>)> = HashMap::new();
+    for (transform, velocity, boid) in boid_query.iter() {
+        let (positions, velocities) = members.entry(group_name(boid.group, None)).or_default();
+        positions.push(transform.translation.truncate().extend(0.0));
+        velocities.push(velocity.velocity.extend(0.0));
+    }
+    record_groups(&mut metrics, &settings, members, time.elapsed_seconds_f64());
+}
+
+/// Used by the 3D, sky and underwater modes.
+pub fn sample_metrics_3d(
+    boid_query: Query<(&Transform, &boids_3d::Velocity, &boids_3d::Boid, Option<&FishType>)>,
+    mut metrics: ResMut<FlockMetrics>,
+    settings: Res<MetricsSettings>,
+    time: Res<Time>
+) {
+    if !should_sample(&mut metrics, &settings) {
+        return;
+    }
+    let mut members: HashMap<String, (Vec<Vec3>, Vec<Vec3>)> = HashMap::new();
+    for (transform, velocity, boid, fish_type) in boid_query.iter() {
+        let (positions, velocities) = members.entry(group_name(boid.group, fish_type)).or_default();
+        positions.push(transform.translation);
+        velocities.push(velocity.velocity);
+    }
+    record_groups(&mut metrics, &settings, members, time.elapsed_seconds_f64());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Boids evenly spread on a circle of the given radius in the XZ plane, flying around it.
+    fn mill(count: usize, radius: f32) -> (Vec<Vec3>, Vec<Vec3>) {
+        (0..count).map(|index| {
+            let angle = index as f32 / count as f32 * std::f32::consts::TAU;
+            let (sin, cos) = angle.sin_cos();
+            (Vec3::new(cos, 0.0, sin) * radius, Vec3::new(-sin, 0.0, cos))
+        }).unzip()
+    }
+
+    #[test]
+    fn polarisation_measures_shared_headings() {
+        assert_eq!(polarisation(&[Vec3::X, Vec3::X * 3.0, Vec3::X * 0.5]), 1.0);
+        assert_eq!(polarisation(&[Vec3::X, -Vec3::X]), 0.0);
+        assert!((polarisation(&[Vec3::X, Vec3::Y]) - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        assert_eq!(polarisation(&[]), 0.0);
+    }
+
+    #[test]
+    fn angular_momentum_measures_milling() {
+        let (positions, velocities) = mill(12, 20.0);
+        assert!((angular_momentum(&positions, &velocities) - 1.0).abs() < 1e-5);
+        assert!(polarisation(&velocities) < 1e-5);
+        let aligned = vec![Vec3::X; positions.len()];
+        assert!(angular_momentum(&positions, &aligned) < 1e-5);
+    }
+
+    #[test]
+    fn nearest_neighbour_distance_looks_past_the_first_hit() {
+        // Cells are 10 wide: the first boid's neighbour in the next cell is 19.4 away, while the
+        // one two cells away is only 11 away.
+        let positions = [Vec3::new(9.5, 5.0, 5.0), Vec3::new(-9.9, 5.0, 5.0), Vec3::new(20.5, 5.0, 5.0)];
+        let metrics = compute_group_metrics(&positions, &[Vec3::X; 3], 10.0, 1.0);
+        assert!((metrics.nearest_neighbour_distance - (11.0 + 19.4 + 11.0) / 3.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn isolated_boids_count_towards_the_nearest_neighbour_distance() {
+        let positions = [Vec3::ZERO, Vec3::new(100.0, 0.0, 0.0), Vec3::new(100.0, 3.0, 0.0)];
+        let metrics = compute_group_metrics(&positions, &[Vec3::X; 3], 10.0, 1.0);
+        assert!((metrics.nearest_neighbour_distance - (100.0 + 3.0 + 3.0) / 3.0).abs() < 1e-4);
+        assert_eq!(compute_group_metrics(&[Vec3::ZERO], &[Vec3::X], 10.0, 1.0).nearest_neighbour_distance, 0.0);
+    }
+
+    #[test]
+    fn clusters_are_linked_by_the_neighbour_radius() {
+        let positions = [
+            Vec3::ZERO, Vec3::new(6.0, 0.0, 0.0), Vec3::new(12.0, 0.0, 0.0),
+            Vec3::new(100.0, 0.0, 0.0), Vec3::new(100.0, 0.5, 0.0),
+            Vec3::new(-200.0, 0.0, 0.0)
+        ];
+        let metrics = compute_group_metrics(&positions, &[Vec3::X; 6], 8.0, 1.0);
+        assert_eq!(metrics.boids, 6);
+        assert_eq!(metrics.clusters, 3);
+        assert!((metrics.collisions - 2.0 / 6.0).abs() < 1e-6);
+    }
+}
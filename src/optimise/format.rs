@@ -0,0 +1,186 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use crate::cli::CliMode;
+use crate::metrics::Metric;
+use crate::sweep::format::{default_seeds, default_steps, mode_from_name};
+
+#[derive(Deserialize)]
+pub struct Bounds {
+    pub min: f64,
+    pub max: f64
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+/// One term of the objective. Terms are summed, and the sum is minimised.
+#[derive(Deserialize)]
+pub enum Term {
+    /// Squared distance to `value`, relative to it unless it is zero.
+    Target {
+        metric: String,
+        value: f64,
+        #[serde(default = "one")]
+        weight: f64
+    },
+    Minimise {
+        metric: String,
+        #[serde(default = "one")]
+        weight: f64
+    },
+    Maximise {
+        metric: String,
+        #[serde(default = "one")]
+        weight: f64
+    }
+}
+
+impl Term {
+    pub fn metric(&self) -> &str {
+        match self {
+            Term::Target { metric, .. } | Term::Minimise { metric, .. } | Term::Maximise { metric, .. } => metric
+        }
+    }
+
+    pub fn cost(&self, value: f64) -> f64 {
+        match self {
+            Term::Target { value: target, weight, .. } => {
+                let scale = if *target == 0.0 { 1.0 } else { target.abs() };
+                weight * ((value - target) / scale).powi(2)
+            }
+            Term::Minimise { weight, .. } => weight * value,
+            Term::Maximise { weight, .. } => -weight * value
+        }
+    }
+}
+
+fn default_population() -> usize {
+    16
+}
+
+fn default_generations() -> usize {
+    20
+}
+
+fn default_elite() -> usize {
+    2
+}
+
+fn default_mutation() -> f64 {
+    0.1
+}
+
+fn default_export() -> usize {
+    3
+}
+
+/// Optimisation run, read from a RON file. Parameters are boid settings fields of the mode, as
+/// with `--set`, searched within their bounds.
+#[derive(Deserialize)]
+pub struct OptimiserSpec {
+    #[serde(deserialize_with = "mode_from_name")]
+    pub mode: CliMode,
+    /// Total number of boids, the mode default when unset.
+    #[serde(default)]
+    pub count: Option<usize>,
+    /// Every candidate is run once per seed and scored on the mean cost.
+    #[serde(default = "default_seeds")]
+    pub seeds: Vec<u64>,
+    #[serde(default)]
+    pub warmup: u32,
+    #[serde(default = "default_steps")]
+    pub steps: u32,
+    pub parameters: BTreeMap<String, Bounds>,
+    pub objective: Vec<Term>,
+    #[serde(default = "default_population")]
+    pub population: usize,
+    #[serde(default = "default_generations")]
+    pub generations: usize,
+    /// Best candidates carried over unchanged to the next generation.
+    #[serde(default = "default_elite")]
+    pub elite: usize,
+    /// Standard deviation of mutations, as a fraction of each parameter's range.
+    #[serde(default = "default_mutation")]
+    pub mutation: f64,
+    /// Seed of the search itself.
+    #[serde(default)]
+    pub seed: u64,
+    /// Number of best parameter sets saved as presets.
+    #[serde(default = "default_export")]
+    pub export: usize,
+    /// Preset name prefix, the file name of the spec by default.
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub jobs: Option<usize>
+}
+
+impl OptimiserSpec {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.parameters.is_empty() || self.objective.is_empty() || self.seeds.is_empty() {
+            return Err("the spec needs at least one parameter, objective term and seed".to_string());
+        }
+        if let Some((name, _)) = self.parameters.iter().find(|(_, bounds)| bounds.min > bounds.max) {
+            return Err(format!("{name}: min is above max"));
+        }
+        if let Some(term) = self.objective.iter().find(|term| Metric::from_key(term.metric()).is_none()) {
+            return Err(format!("unknown metric \"{}\"", term.metric()));
+        }
+        Ok(())
+    }
+
+    /// Sum of the terms for a run's metrics.
+    pub fn cost(&self, metrics: &BTreeMap<String, f64>) -> f64 {
+        self.objective.iter()
+            .map(|term| term.cost(metrics.get(term.metric()).copied().unwrap_or(f64::NAN)))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(value: f64, weight: f64) -> Term {
+        Term::Target { metric: "polarisation".to_string(), value, weight }
+    }
+
+    #[test]
+    fn target_cost_is_relative_to_the_target() {
+        assert_eq!(target(2.0, 1.0).cost(2.0), 0.0);
+        assert!((target(2.0, 1.0).cost(3.0) - 0.25).abs() < 1e-12);
+        assert!((target(-2.0, 1.0).cost(-3.0) - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn target_cost_of_zero_is_absolute() {
+        assert!((target(0.0, 1.0).cost(0.5) - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn costs_are_weighted() {
+        assert!((target(2.0, 4.0).cost(3.0) - 1.0).abs() < 1e-12);
+        assert_eq!(Term::Minimise { metric: "collisions".to_string(), weight: 2.0 }.cost(3.0), 6.0);
+        assert_eq!(Term::Maximise { metric: "polarisation".to_string(), weight: 2.0 }.cost(3.0), -6.0);
+    }
+
+    #[test]
+    fn weight_defaults_to_one() {
+        let term: Term = ron::from_str("Minimise(metric: \"collisions\")").unwrap();
+        assert_eq!(term.cost(5.0), 5.0);
+    }
+
+    #[test]
+    fn spec_cost_sums_the_terms_and_is_nan_for_missing_metrics() {
+        let spec: OptimiserSpec = ron::from_str(r#"(
+            mode: "2d",
+            parameters: { "cohesion_coeff": (min: 0.0, max: 50.0) },
+            objective: [Target(metric: "polarisation", value: 1.0), Minimise(metric: "collisions", weight: 0.5)]
+        )"#).unwrap();
+        assert!(spec.validate().is_ok());
+        let metrics = BTreeMap::from([("polarisation".to_string(), 0.5), ("collisions".to_string(), 4.0)]);
+        assert!((spec.cost(&metrics) - 2.25).abs() < 1e-12);
+        assert!(spec.cost(&BTreeMap::from([("polarisation".to_string(), 0.5)])).is_nan());
+    }
+}
@@ -0,0 +1,157 @@
+use bevy::prelude::default;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use ron::ser::PrettyConfig;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::boids_2d::resources::BoidSettings2D;
+use crate::boids_3d::resources::BoidSettings3D;
+use crate::cli::{override_mode_settings, CliMode};
+use crate::presets::Presets;
+use crate::sky::birds::SkyBoidSettings;
+use crate::sweep::format::read_ron;
+use crate::sweep::{default_jobs, headless_arguments, parameter_value, run_headless};
+use crate::ui::resources::SimulationState;
+use crate::underwater::fish::components::SpeciesSettings;
+use crate::underwater::fish::ecology::ALL_SPECIES;
+use super::format::*;
+
+/// A parameter set and its mean cost over the seeds, with the settings its runs ended with.
+#[derive(Clone)]
+pub struct Candidate {
+    pub genes: Vec<f64>,
+    pub cost: f64,
+    pub settings: Option<serde_json::Value>
+}
+
+fn overrides(spec: &OptimiserSpec, genes: &[f64]) -> Vec<(String, serde_json::Value)> {
+    spec.parameters.keys().zip(genes.iter()).map(|(name, gene)| (name.clone(), parameter_value(*gene))).collect()
+}
+
+fn describe(spec: &OptimiserSpec, genes: &[f64]) -> String {
+    spec.parameters.keys().zip(genes.iter()).map(|(name, gene)| format!("{name}={gene:.3}")).collect::<Vec<_>>().join(" ")
+}
+
+/// Runs every parameter set on every seed. Failed runs cost infinitely much.
+fn evaluate(spec: &OptimiserSpec, population: Vec<Vec<f64>>, jobs: usize) -> Vec<Candidate> {
+    let runs: Vec<Vec<String>> = population.iter()
+        .flat_map(|genes| spec.seeds.iter().map(move |seed| {
+            headless_arguments(spec.mode, *seed, spec.warmup, spec.steps, spec.count, &overrides(spec, genes))
+        }))
+        .collect();
+    let results = run_headless(&runs, jobs, |_, result| {
+        if let Err(error) = result {
+            eprintln!("Run failed: {error}");
+        }
+    });
+    let seeds = spec.seeds.len().max(1);
+    population.into_iter().zip(results.chunks(seeds)).map(|(genes, results)| {
+        let cost = results.iter()
+            .map(|result| result.as_ref().map_or(f64::INFINITY, |result| spec.cost(&result.metrics)))
+            .sum::<f64>() / seeds as f64;
+        let settings = results.iter().find_map(|result| result.as_ref().ok().map(|result| result.settings.clone()));
+        Candidate { genes, cost: if cost.is_nan() { f64::INFINITY } else { cost }, settings }
+    }).collect()
+}
+
+fn gaussian(rng: &mut StdRng) -> f64 {
+    // Box-Muller transform.
+    let (u, v): (f64, f64) = (rng.gen_range(f64::EPSILON..1.0), rng.gen());
+    (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+}
+
+fn random_genes(spec: &OptimiserSpec, rng: &mut StdRng) -> Vec<f64> {
+    spec.parameters.values().map(|bounds| rng.gen_range(bounds.min..=bounds.max)).collect()
+}
+
+/// Best of three random candidates.
+fn tournament<'a>(ranked: &'a [Candidate], rng: &mut StdRng) -> &'a Candidate {
+    (0..3).map(|_| &ranked[rng.gen_range(0..ranked.len())])
+        .min_by(|a, b| a.cost.total_cmp(&b.cost))
+        .unwrap()
+}
+
+/// Blend crossover of two parents, then a Gaussian mutation of each gene.
+fn offspring(spec: &OptimiserSpec, ranked: &[Candidate], rng: &mut StdRng) -> Vec<f64> {
+    let (first, second) = (tournament(ranked, rng), tournament(ranked, rng));
+    spec.parameters.values().enumerate().map(|(index, bounds)| {
+        let blend = rng.gen_range(-0.25..1.25);
+        let gene = first.genes[index] + blend * (second.genes[index] - first.genes[index]);
+        let mutated = gene + gaussian(rng) * spec.mutation * (bounds.max - bounds.min);
+        mutated.clamp(bounds.min, bounds.max)
+    }).collect()
+}
+
+fn to_ron<T: serde::Serialize + serde::de::DeserializeOwned>(settings: &serde_json::Value) -> Result<String, String> {
+    let settings: T = serde_json::from_value(settings.clone()).map_err(|error| error.to_string())?;
+    ron::ser::to_string_pretty(&settings, PrettyConfig::default()).map_err(|error| error.to_string())
+}
+
+/// Writes the settings a candidate ran with in the preset format of its mode. Underwater presets
+/// hold one species, so every species gets its own file.
+fn export_preset(mode: CliMode, directory: &Path, name: &str, settings: &serde_json::Value) -> Result<Vec<PathBuf>, String> {
+    let files = match mode {
+        CliMode::Mode2D => vec![(name.to_string(), to_ron::<BoidSettings2D>(settings)?)],
+        CliMode::Mode3D => vec![(name.to_string(), to_ron::<BoidSettings3D>(settings)?)],
+        CliMode::Sky => vec![(name.to_string(), to_ron::<SkyBoidSettings>(settings)?)],
+        CliMode::Underwater => {
+            let species_settings: SpeciesSettings = serde_json::from_value(settings.clone()).map_err(|error| error.to_string())?;
+            ALL_SPECIES.iter().map(|species| {
+                let file = format!("{name}_{}", species.name().to_lowercase().replace(' ', "_"));
+                let text = ron::ser::to_string_pretty(&species_settings.get(*species).boids, PrettyConfig::default()).map_err(|error| error.to_string())?;
+                Ok((file, text))
+            }).collect::<Result<Vec<_>, String>>()?
+        }
+    };
+    fs::create_dir_all(directory).map_err(|error| error.to_string())?;
+    files.into_iter().map(|(file, text)| {
+        let path = directory.join(format!("{file}.ron"));
+        fs::write(&path, text).map_err(|error| format!("{}: {error}", path.display()))?;
+        Ok(path)
+    }).collect()
+}
+
+/// Genetic search over the spec's parameters, then exports the best parameter sets as presets.
+pub fn run(spec_path: &Path) -> Result<(), String> {
+    let spec: OptimiserSpec = read_ron(spec_path)?;
+    spec.validate()?;
+    let midpoint: Vec<f64> = spec.parameters.values().map(|bounds| (bounds.min + bounds.max) / 2.0).collect();
+    override_mode_settings(spec.mode, &overrides(&spec, &midpoint), &mut default(), &mut default(), &mut default(), &mut default())?;
+
+    let jobs = spec.jobs.unwrap_or_else(default_jobs);
+    let population_size = spec.population.max(2);
+    let elite = spec.elite.min(population_size - 1);
+    let mut rng = StdRng::seed_from_u64(spec.seed);
+    eprintln!(
+        "Optimising {} parameters of {} mode, {population_size} candidates over {} generations",
+        spec.parameters.len(), spec.mode.name(), spec.generations
+    );
+
+    let mut ranked = evaluate(&spec, (0..population_size).map(|_| random_genes(&spec, &mut rng)).collect(), jobs);
+    let mut history: Vec<Candidate> = ranked.clone();
+    for generation in 0..spec.generations.max(1) {
+        ranked.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+        eprintln!("Generation {}: best cost {:.5} with {}", generation + 1, ranked[0].cost, describe(&spec, &ranked[0].genes));
+        if generation + 1 == spec.generations.max(1) {
+            break;
+        }
+        // Elites keep their cost, since the same seeds would give the same runs.
+        let children = (elite..population_size).map(|_| offspring(&spec, &ranked, &mut rng)).collect();
+        let children = evaluate(&spec, children, jobs);
+        history.extend(children.iter().cloned());
+        ranked.truncate(elite);
+        ranked.extend(children);
+    }
+
+    history.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+    history.dedup_by(|a, b| a.genes == b.genes);
+    let name = spec.name.clone().unwrap_or_else(|| spec_path.file_stem().map_or("optimised".to_string(), |stem| stem.to_string_lossy().to_string()));
+    let directory = Presets::default().mode_directory(&SimulationState::from(spec.mode));
+    for (rank, candidate) in history.iter().filter(|candidate| candidate.cost.is_finite()).take(spec.export).enumerate() {
+        let Some(settings) = candidate.settings.as_ref() else { continue };
+        for path in export_preset(spec.mode, &directory, &format!("{name}_{}", rank + 1), settings)? {
+            eprintln!("Saved {} (cost {:.5}, {})", path.display(), candidate.cost, describe(&spec, &candidate.genes));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use crate::metrics::Metric;
+use crate::sweep::format::read_ron;
+
+/// What an output address carries.
+#[derive(Clone, Debug, Deserialize)]
+pub enum OscValue {
+    /// Mean position, three floats.
+    Centroid,
+    /// Latest value of a metric, by key such as `"polarisation"`.
+    Metric(String),
+    /// Distance from the sky predator to the nearest bird, only sent while it hunts.
+    PredatorDistance,
+    /// One message per boid for the first `boids` boids: group, position and velocity.
+    Boids
+}
+
+/// An address and its value. `{group}` in the address sends one message per group, named as
+/// in the metrics window in lower case with underscores; without it, the value is taken over
+/// every boid. `{index}` numbers the messages of `Boids`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OscOutput {
+    pub address: String,
+    pub value: OscValue
+}
+
+fn default_target() -> String {
+    "127.0.0.1:9000".to_string()
+}
+
+fn default_rate() -> f32 {
+    30.0
+}
+
+fn default_boids() -> usize {
+    16
+}
+
+fn default_outputs() -> Vec<OscOutput> {
+    vec![
+        OscOutput { address: "/flock/{group}/centroid".to_string(), value: OscValue::Centroid },
+        OscOutput { address: "/polarisation".to_string(), value: OscValue::Metric("polarisation".to_string()) },
+        OscOutput { address: "/predator/distance".to_string(), value: OscValue::PredatorDistance },
+        OscOutput { address: "/boid/{index}".to_string(), value: OscValue::Boids }
+    ]
+}
+
+/// OSC sender and receiver, read from a RON file such as `osc/default.ron`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OscConfig {
+    /// Host and port messages are sent to.
+    #[serde(default = "default_target")]
+    pub target: String,
+    /// Times a second every output is sent.
+    #[serde(default = "default_rate")]
+    pub rate: f32,
+    /// Number of boids `Boids` outputs report on, the oldest first.
+    #[serde(default = "default_boids")]
+    pub boids: usize,
+    #[serde(default = "default_outputs")]
+    pub outputs: Vec<OscOutput>,
+    /// Host and port incoming messages are read on, nothing is read without it.
+    #[serde(default)]
+    pub listen: Option<String>,
+    /// Boid settings field of the current mode set by the first argument of each address.
+    #[serde(default)]
+    pub inputs: BTreeMap<String, String>
+}
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        Self {
+            target: default_target(),
+            rate: default_rate(),
+            boids: default_boids(),
+            outputs: default_outputs(),
+            listen: None,
+            inputs: BTreeMap::new()
+        }
+    }
+}
+
+impl OscConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let config: Self = read_ron(path)?;
+        if config.rate <= 0.0 {
+            return Err(format!("{}: the rate must be positive", path.display()));
+        }
+        for output in config.outputs.iter() {
+            if let OscValue::Metric(key) = &output.value {
+                if Metric::from_key(key).is_none() {
+                    return Err(format!("{}: unknown metric \"{key}\" for {}", path.display(), output.address));
+                }
+            }
+        }
+        Ok(config)
+    }
+}
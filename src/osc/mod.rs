@@ -0,0 +1,32 @@
+use bevy::prelude::*;
+
+pub mod format;
+pub mod resources;
+pub mod systems;
+
+pub use format::*;
+pub use resources::*;
+pub use systems::*;
+
+/// Sends flock state as OSC messages over UDP, and sets boid settings from incoming ones.
+pub struct OscPlugin {
+    pub config: OscConfig
+}
+
+impl Plugin for OscPlugin {
+    fn build(&self, app: &mut App) {
+        let link = match OscLink::open(self.config.clone()) {
+            Ok(link) => link,
+            Err(error) => {
+                error!("Could not open OSC sockets: {error}");
+                return;
+            }
+        };
+        info!("Sending OSC to {}", link.config.target);
+        if let Some(address) = link.local_address() {
+            info!("Reading OSC on {address}");
+        }
+        app.insert_resource(link)
+           .add_systems(Update, (receive_osc, send_osc));
+    }
+}
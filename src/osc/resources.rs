@@ -0,0 +1,42 @@
+use bevy::prelude::*;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use super::format::OscConfig;
+
+fn resolve(address: &str) -> Result<SocketAddr, String> {
+    address.to_socket_addrs()
+        .map_err(|error| format!("{address}: {error}"))?
+        .next()
+        .ok_or_else(|| format!("{address} does not resolve to an address"))
+}
+
+/// Sockets of the OSC sender and receiver.
+#[derive(Resource)]
+pub struct OscLink {
+    pub config: OscConfig,
+    pub(super) target: SocketAddr,
+    pub(super) sender: UdpSocket,
+    pub(super) receiver: Option<UdpSocket>,
+    pub(super) send_timer: Timer
+}
+
+impl OscLink {
+    pub fn open(config: OscConfig) -> Result<Self, String> {
+        let target = resolve(&config.target)?;
+        let local: SocketAddr = if target.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+        let sender = UdpSocket::bind(local).map_err(|error| error.to_string())?;
+        let receiver = match config.listen.as_deref() {
+            Some(listen) => {
+                let socket = UdpSocket::bind(resolve(listen)?).map_err(|error| format!("{listen}: {error}"))?;
+                socket.set_nonblocking(true).map_err(|error| error.to_string())?;
+                Some(socket)
+            }
+            None => None
+        };
+        let send_timer = Timer::from_seconds(1.0 / config.rate, TimerMode::Repeating);
+        Ok(Self { config, target, sender, receiver, send_timer })
+    }
+
+    pub fn local_address(&self) -> Option<SocketAddr> {
+        self.receiver.as_ref().and_then(|receiver| receiver.local_addr().ok())
+    }
+}
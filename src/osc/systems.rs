@@ -0,0 +1,359 @@
+use bevy::prelude::*;
+use rosc::{OscMessage, OscPacket, OscType};
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use crate::boids_2d::components as boids_2d;
+use crate::boids_3d::components as boids_3d;
+use crate::cli::LiveSettings;
+use crate::export::components::BoidId;
+use crate::metrics::{group_name, FlockMetrics, Metric};
+use crate::sky::perching::SkyPredator;
+use crate::ui::resources::SimulationState;
+use crate::underwater::fish::components::FishType;
+use super::format::*;
+use super::resources::*;
+
+/// A boid as the outputs see it, in either mode.
+struct OscBoid {
+    id: u64,
+    group: u8,
+    group_name: String,
+    position: Vec3,
+    velocity: Vec3
+}
+
+fn address_for_group(address: &str, group: &str) -> String {
+    address.replace("{group}", &group.to_lowercase().replace(' ', "_"))
+}
+
+fn vec3_arguments(value: Vec3) -> Vec<OscType> {
+    vec![OscType::Float(value.x), OscType::Float(value.y), OscType::Float(value.z)]
+}
+
+fn centroid<'a>(positions: impl Iterator<Item = &'a Vec3>) -> Vec3 {
+    let (total, count) = positions.fold((Vec3::ZERO, 0), |(total, count), position| (total + *position, count + 1));
+    if count > 0 { total / count as f32 } else { Vec3::ZERO }
+}
+
+fn output_messages(
+    output: &OscOutput,
+    boids: &[OscBoid],
+    boid_limit: usize,
+    metrics: &FlockMetrics,
+    predator: Option<Vec3>
+) -> Vec<OscMessage> {
+    let per_group = output.address.contains("{group}");
+    let mut groups: BTreeMap<&str, Vec<&OscBoid>> = BTreeMap::new();
+    for boid in boids.iter() {
+        groups.entry(&boid.group_name).or_default().push(boid);
+    }
+    let message = |address: String, args: Vec<OscType>| OscMessage { addr: address, args };
+
+    match &output.value {
+        OscValue::Centroid if per_group => groups.iter().map(|(name, members)| {
+            let value = centroid(members.iter().map(|boid| &boid.position));
+            message(address_for_group(&output.address, name), vec3_arguments(value))
+        }).collect(),
+        OscValue::Centroid => {
+            let value = centroid(boids.iter().map(|boid| &boid.position));
+            vec![message(output.address.clone(), vec3_arguments(value))]
+        }
+        OscValue::Metric(key) => {
+            let Some(metric) = Metric::from_key(key) else { return Vec::new() };
+            let latest = metrics.groups.iter().filter_map(|(name, series)| Some((name, series.latest()?)));
+            if per_group {
+                latest.map(|(name, sample)| {
+                    message(address_for_group(&output.address, name), vec![OscType::Float(metric.value(sample) as f32)])
+                }).collect()
+            } else {
+                // Weighted by group size, like the means of the metrics window.
+                let (total, weight) = latest.fold((0.0, 0.0), |(total, weight), (_, sample)| {
+                    (total + metric.value(sample) * sample.boids as f64, weight + sample.boids as f64)
+                });
+                if weight == 0.0 {
+                    return Vec::new();
+                }
+                vec![message(output.address.clone(), vec![OscType::Float((total / weight) as f32)])]
+            }
+        }
+        OscValue::PredatorDistance => {
+            let Some(predator) = predator else { return Vec::new() };
+            let nearest = boids.iter().map(|boid| boid.position.distance(predator)).reduce(f32::min);
+            nearest.map(|distance| message(output.address.clone(), vec![OscType::Float(distance)])).into_iter().collect()
+        }
+        OscValue::Boids => boids.iter().take(boid_limit).enumerate().map(|(index, boid)| {
+            let mut args = vec![OscType::Int(boid.group as i32)];
+            args.extend(vec3_arguments(boid.position));
+            args.extend(vec3_arguments(boid.velocity));
+            message(output.address.replace("{index}", &index.to_string()), args)
+        }).collect()
+    }
+}
+
+/// Sends every output at the configured rate, on real time so that a paused simulation keeps
+/// reporting its state.
+#[allow(clippy::type_complexity)]
+pub fn send_osc(
+    mut link: ResMut<OscLink>,
+    real_time: Res<Time<Real>>,
+    state: Res<State<SimulationState>>,
+    metrics: Res<FlockMetrics>,
+    boid_query_2d: Query<(&Transform, &boids_2d::Velocity, &boids_2d::Boid, Option<&BoidId>)>,
+    boid_query_3d: Query<(&Transform, &boids_3d::Velocity, &boids_3d::Boid, Option<&BoidId>, Option<&FishType>)>,
+    predator_query: Query<&Transform, With<SkyPredator>>
+) {
+    if !link.send_timer.tick(real_time.delta()).just_finished() {
+        return;
+    }
+    // Boids get their ID a frame after they spawn, they come last until then.
+    let mut boids: Vec<OscBoid> = match state.get() {
+        SimulationState::Mode2D => boid_query_2d.iter().map(|(transform, velocity, boid, id)| OscBoid {
+            id: id.map_or(u64::MAX, |id| id.id),
+            group: boid.group,
+            group_name: group_name(boid.group, None),
+            position: transform.translation.truncate().extend(0.0),
+            velocity: velocity.velocity.extend(0.0)
+        }).collect(),
+        _ => boid_query_3d.iter().map(|(transform, velocity, boid, id, fish_type)| OscBoid {
+            id: id.map_or(u64::MAX, |id| id.id),
+            group: boid.group,
+            group_name: group_name(boid.group, fish_type),
+            position: transform.translation,
+            velocity: velocity.velocity
+        }).collect()
+    };
+    boids.sort_by_key(|boid| boid.id);
+    let predator = match state.get() {
+        SimulationState::Sky => predator_query.iter().next().map(|transform| transform.translation),
+        _ => None
+    };
+
+    for output in link.config.outputs.iter() {
+        for message in output_messages(output, &boids, link.config.boids, &metrics, predator) {
+            let Ok(packet) = rosc::encoder::encode(&OscPacket::Message(message)) else { continue };
+            // UDP has no receiver to wait for, a send only fails when the target is unreachable.
+            let _ = link.sender.send_to(&packet, link.target);
+        }
+    }
+}
+
+fn argument_value(argument: &OscType) -> Option<serde_json::Value> {
+    match argument {
+        // Sliders send floats, whole ones are also accepted by integer fields such as `count`.
+        OscType::Float(value) if value.fract() == 0.0 => Some(serde_json::Value::from(*value as i64)),
+        OscType::Float(value) => serde_json::Number::from_f64(*value as f64).map(serde_json::Value::Number),
+        OscType::Double(value) if value.fract() == 0.0 => Some(serde_json::Value::from(*value as i64)),
+        OscType::Double(value) => serde_json::Number::from_f64(*value).map(serde_json::Value::Number),
+        OscType::Int(value) => Some(serde_json::Value::from(*value)),
+        OscType::Long(value) => Some(serde_json::Value::from(*value)),
+        OscType::Bool(value) => Some(serde_json::Value::from(*value)),
+        OscType::String(value) => Some(serde_json::Value::from(value.as_str())),
+        _ => None
+    }
+}
+
+fn collect_messages(packet: OscPacket, messages: &mut Vec<OscMessage>) {
+    match packet {
+        OscPacket::Message(message) => messages.push(message),
+        OscPacket::Bundle(bundle) => {
+            for packet in bundle.content {
+                collect_messages(packet, messages);
+            }
+        }
+    }
+}
+
+/// Sets the settings field mapped to each incoming address to the message's first argument.
+pub fn receive_osc(
+    link: Res<OscLink>,
+    mut settings: LiveSettings,
+    state: Res<State<SimulationState>>
+) {
+    let Some(receiver) = link.receiver.as_ref() else { return };
+    let mut buffer = [0; rosc::decoder::MTU];
+    let mut messages = Vec::new();
+    loop {
+        match receiver.recv(&mut buffer) {
+            Ok(size) => match rosc::decoder::decode_udp(&buffer[..size]) {
+                Ok((_, packet)) => collect_messages(packet, &mut messages),
+                Err(error) => warn!("Could not decode OSC packet: {error}")
+            },
+            Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+            Err(error) => {
+                warn!("Could not read OSC: {error}");
+                break;
+            }
+        }
+    }
+
+    for message in messages {
+        let Some(field) = link.config.inputs.get(&message.addr) else { continue };
+        let Some(value) = message.args.first().and_then(argument_value) else {
+            warn!("OSC {}: expected a number, boolean or string", message.addr);
+            continue;
+        };
+        if let Err(error) = settings.set(state.get(), field.clone(), value) {
+            warn!("OSC {}: {error}", message.addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::utils::{Duration, Instant};
+    use std::net::UdpSocket;
+    use crate::boids_2d::resources::BoidSettings2D;
+    use crate::boids_3d::resources::BoidSettings3D;
+    use crate::metrics::{GroupMetrics, TimeSeries};
+    use crate::sky::birds::SkyBoidSettings;
+    use crate::underwater::fish::components::SpeciesSettings;
+
+    fn boid(id: u64, group: u8, position: Vec3) -> OscBoid {
+        OscBoid { id, group, group_name: group_name(group, None), position, velocity: Vec3::X }
+    }
+
+    fn output(address: &str, value: OscValue) -> OscOutput {
+        OscOutput { address: address.to_string(), value }
+    }
+
+    fn addresses(messages: &[OscMessage]) -> Vec<&str> {
+        messages.iter().map(|message| message.addr.as_str()).collect()
+    }
+
+    #[test]
+    fn centroids_are_sent_per_group_or_over_every_boid() {
+        let boids = [boid(0, 0, Vec3::ZERO), boid(1, 0, Vec3::new(2.0, 0.0, 0.0)), boid(2, 1, Vec3::new(0.0, 4.0, 0.0))];
+        let metrics = FlockMetrics::default();
+
+        let messages = output_messages(&output("/flock/{group}/centroid", OscValue::Centroid), &boids, 16, &metrics, None);
+        assert_eq!(addresses(&messages), ["/flock/group_0/centroid", "/flock/group_1/centroid"]);
+        assert_eq!(messages[0].args, vec3_arguments(Vec3::new(1.0, 0.0, 0.0)));
+
+        let messages = output_messages(&output("/centroid", OscValue::Centroid), &boids, 16, &metrics, None);
+        assert_eq!(messages[0].args, vec3_arguments(Vec3::new(2.0 / 3.0, 4.0 / 3.0, 0.0)));
+    }
+
+    #[test]
+    fn metrics_are_averaged_over_groups_by_size() {
+        let mut metrics = FlockMetrics::default();
+        for (group, boids, polarisation) in [("Group 0", 1, 1.0), ("Group 1", 3, 0.0)] {
+            let mut series = TimeSeries::default();
+            series.push(0.0, GroupMetrics { boids, polarisation, ..default() }, 10);
+            metrics.groups.insert(group.to_string(), series);
+        }
+        let polarisation = || OscValue::Metric("polarisation".to_string());
+
+        let messages = output_messages(&output("/polarisation", polarisation()), &[], 16, &metrics, None);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].args, vec![OscType::Float(0.25)]);
+
+        let mut messages = output_messages(&output("/{group}/polarisation", polarisation()), &[], 16, &metrics, None);
+        messages.sort_by(|first, second| first.addr.cmp(&second.addr));
+        assert_eq!(addresses(&messages), ["/group_0/polarisation", "/group_1/polarisation"]);
+        assert_eq!(messages[0].args, vec![OscType::Float(1.0)]);
+    }
+
+    #[test]
+    fn boid_messages_are_numbered_up_to_the_limit() {
+        let boids: Vec<_> = (0..5).map(|id| boid(id, 1, Vec3::splat(id as f32))).collect();
+        let messages = output_messages(&output("/boid/{index}", OscValue::Boids), &boids, 2, &FlockMetrics::default(), None);
+        assert_eq!(addresses(&messages), ["/boid/0", "/boid/1"]);
+        assert_eq!(messages[1].args[0], OscType::Int(1));
+        assert_eq!(messages[1].args[1..4], vec3_arguments(Vec3::ONE));
+    }
+
+    #[test]
+    fn predator_distance_is_only_sent_during_a_hunt() {
+        let boids = [boid(0, 0, Vec3::new(3.0, 4.0, 0.0)), boid(1, 0, Vec3::new(30.0, 0.0, 0.0))];
+        let predator_distance = output("/predator/distance", OscValue::PredatorDistance);
+        assert!(output_messages(&predator_distance, &boids, 16, &FlockMetrics::default(), None).is_empty());
+        let messages = output_messages(&predator_distance, &boids, 16, &FlockMetrics::default(), Some(Vec3::ZERO));
+        assert_eq!(messages[0].args, vec![OscType::Float(5.0)]);
+    }
+
+    #[test]
+    fn whole_floats_become_integers() {
+        assert_eq!(argument_value(&OscType::Float(120.0)), Some(serde_json::json!(120)));
+        assert_eq!(argument_value(&OscType::Float(0.5)), Some(serde_json::json!(0.5)));
+        assert_eq!(argument_value(&OscType::Double(-2.0)), Some(serde_json::json!(-2)));
+        assert_eq!(argument_value(&OscType::Bool(true)), Some(serde_json::json!(true)));
+        assert_eq!(argument_value(&OscType::Nil), None);
+    }
+
+    fn world(mode: SimulationState) -> World {
+        let mut world = World::new();
+        world.insert_resource(State::new(mode));
+        world.init_resource::<FlockMetrics>();
+        world.init_resource::<BoidSettings2D>();
+        world.init_resource::<BoidSettings3D>();
+        world.init_resource::<SkyBoidSettings>();
+        world.init_resource::<SpeciesSettings>();
+        world
+    }
+
+    #[test]
+    fn outputs_reach_a_local_socket() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let config = OscConfig {
+            target: receiver.local_addr().unwrap().to_string(),
+            outputs: vec![output("/flock/{group}/centroid", OscValue::Centroid), output("/boid/{index}", OscValue::Boids)],
+            ..default()
+        };
+
+        let mut world = world(SimulationState::Mode3D);
+        world.insert_resource(OscLink::open(config).unwrap());
+        // The first send is due once a period of real time has passed.
+        let start = Instant::now();
+        let mut real_time = Time::<Real>::new(start);
+        real_time.update_with_instant(start);
+        real_time.update_with_instant(start + Duration::from_secs(1));
+        world.insert_resource(real_time);
+        for (group, position) in [(0, Vec3::X), (1, Vec3::Y)] {
+            world.spawn((Transform::from_translation(position), boids_3d::Velocity { velocity: Vec3::Z }, boids_3d::Boid { group }));
+        }
+        world.run_system_once(send_osc);
+
+        let mut buffer = [0; rosc::decoder::MTU];
+        let mut received = Vec::new();
+        for _ in 0..4 {
+            let size = receiver.recv(&mut buffer).unwrap();
+            let (_, packet) = rosc::decoder::decode_udp(&buffer[..size]).unwrap();
+            collect_messages(packet, &mut received);
+        }
+        received.sort_by(|first, second| first.addr.cmp(&second.addr));
+        assert_eq!(addresses(&received), ["/boid/0", "/boid/1", "/flock/group_0/centroid", "/flock/group_1/centroid"]);
+        assert_eq!(received[3].args, vec3_arguments(Vec3::Y));
+    }
+
+    #[test]
+    fn inputs_set_the_mapped_settings_field() {
+        let config = OscConfig {
+            listen: Some("127.0.0.1:0".to_string()),
+            outputs: Vec::new(),
+            inputs: [("/cohesion".to_string(), "cohesion_coeff".to_string())].into(),
+            ..default()
+        };
+        let link = OscLink::open(config).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for (address, value) in [("/unmapped", 1.0), ("/cohesion", 7.5)] {
+            let message = OscPacket::Message(OscMessage { addr: address.to_string(), args: vec![OscType::Float(value)] });
+            sender.send_to(&rosc::encoder::encode(&message).unwrap(), link.local_address().unwrap()).unwrap();
+        }
+
+        let mut world = world(SimulationState::Mode2D);
+        world.insert_resource(link);
+        // Loopback delivery is fast but not synchronous.
+        for _ in 0..50 {
+            world.run_system_once(receive_osc);
+            if world.resource::<BoidSettings2D>().cohesion_coeff == 7.5 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(world.resource::<BoidSettings2D>().cohesion_coeff, 7.5);
+        assert_eq!(world.resource::<BoidSettings3D>().cohesion_coeff, BoidSettings3D::default().cohesion_coeff);
+    }
+}
@@ -0,0 +1,22 @@
+use bevy::prelude::*;
+use crate::ui::resources::SimulationState;
+
+pub mod resources;
+pub mod systems;
+
+pub use resources::*;
+pub use systems::*;
+
+/// Debug gizmos of the flocking internals, in layers toggled from the "Debug overlay" window.
+pub struct OverlayPlugin;
+
+impl Plugin for OverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DebugOverlay>()
+           // After every force of the frame has been sent.
+           .add_systems(PostUpdate, (
+                draw_overlay_2d.run_if(in_state(SimulationState::Mode2D)),
+                draw_overlay_3d.run_if(not(in_state(SimulationState::Mode2D)))
+           ));
+    }
+}
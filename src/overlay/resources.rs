@@ -0,0 +1,99 @@
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use crate::boids_3d::systems::GROUP_COLORS;
+use crate::rules::{ALIGNMENT_COLOR, COHESION_COLOR, SEPARATION_COLOR};
+
+pub const VELOCITY_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+pub const ACCELERATION_COLOR: Color = Color::rgb(1.0, 0.6, 0.1);
+pub const FIELD_OF_VIEW_COLOR: Color = Color::rgb(0.8, 0.5, 1.0);
+pub const ROOST_COLOR: Color = Color::rgb(0.2, 1.0, 0.4);
+pub const OBSTACLE_COLOR: Color = Color::rgb(1.0, 0.4, 0.2);
+pub const BOUNDS_COLOR: Color = Color::rgb(0.6, 0.6, 0.6);
+pub const MARGIN_COLOR: Color = Color::rgb(1.0, 1.0, 0.4);
+
+/// A kind of debug gizmo, drawn while its layer is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OverlayLayer {
+    Velocity,
+    Acceleration,
+    Perception,
+    FieldOfView,
+    Targets,
+    Obstacles,
+    Confinement
+}
+
+impl OverlayLayer {
+    pub const ALL: [OverlayLayer; 7] = [
+        OverlayLayer::Velocity,
+        OverlayLayer::Acceleration,
+        OverlayLayer::Perception,
+        OverlayLayer::FieldOfView,
+        OverlayLayer::Targets,
+        OverlayLayer::Obstacles,
+        OverlayLayer::Confinement
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OverlayLayer::Velocity => "Velocity",
+            OverlayLayer::Acceleration => "Acceleration",
+            OverlayLayer::Perception => "Perception radii",
+            OverlayLayer::FieldOfView => "Field of view",
+            OverlayLayer::Targets => "Group targets",
+            OverlayLayer::Obstacles => "Obstacle avoidance",
+            OverlayLayer::Confinement => "Confinement"
+        }
+    }
+
+    /// Colours of the layer's gizmos and what each one marks, for the legend.
+    pub fn legend(&self) -> Vec<(Color, &'static str)> {
+        match self {
+            OverlayLayer::Velocity => vec![(VELOCITY_COLOR, "Velocity of each boid")],
+            OverlayLayer::Acceleration => vec![(ACCELERATION_COLOR, "Sum of the forces, capped at the max steering force except in 2D, drawn at most as long as the max speed")],
+            OverlayLayer::Perception => vec![
+                (SEPARATION_COLOR, "Separation range"),
+                (ALIGNMENT_COLOR, "Alignment range"),
+                (COHESION_COLOR, "Cohesion range")
+            ],
+            OverlayLayer::FieldOfView => vec![(FIELD_OF_VIEW_COLOR, "Where neighbours are seen, up to the cohesion range")],
+            OverlayLayer::Targets => vec![
+                (GROUP_COLORS[0], "Target of group 0"),
+                (GROUP_COLORS[1], "Target of group 1"),
+                (ROOST_COLOR, "Roost of a murmuration, and its radius")
+            ],
+            OverlayLayer::Obstacles => vec![(OBSTACLE_COLOR, "Boids inside steer away from the obstacle")],
+            OverlayLayer::Confinement => vec![
+                (BOUNDS_COLOR, "Bounds, boids wrap around when they do not bounce"),
+                (MARGIN_COLOR, "Boids outside are turned back")
+            ]
+        }
+    }
+}
+
+/// Debug gizmo layers that are on, and how they are drawn.
+#[derive(Resource)]
+pub struct DebugOverlay {
+    pub layers: HashSet<OverlayLayer>,
+    /// Whether the per-boid layers are only drawn for the boid selected with the middle mouse
+    /// button, rather than for every boid.
+    pub selected_only: bool,
+    /// Length of the velocity and acceleration vectors for each unit of their magnitude.
+    pub vector_scale: f32
+}
+
+impl Default for DebugOverlay {
+    fn default() -> Self {
+        Self {
+            layers: HashSet::default(),
+            selected_only: false,
+            vector_scale: 0.1
+        }
+    }
+}
+
+impl DebugOverlay {
+    pub fn shows(&self, layer: OverlayLayer) -> bool {
+        self.layers.contains(&layer)
+    }
+}
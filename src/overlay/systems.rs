@@ -0,0 +1,239 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy::window::PrimaryWindow;
+use std::f32::consts::{PI, TAU};
+use crate::boids_2d::components as boids_2d;
+use crate::boids_2d::components::{ObstacleTag, PlacedObstacle};
+use crate::boids_2d::events::ApplyForceEvent as ApplyForceEvent2D;
+use crate::boids_2d::resources::{BoidSettings2D, GroupsTargets as GroupsTargets2D};
+use crate::boids_2d::systems::{confinement_2d, is_in_field_of_view};
+use crate::boids_3d::components as boids_3d;
+use crate::boids_3d::events::ApplyForceEvent as ApplyForceEvent3D;
+use crate::boids_3d::resources::{BoidSettings3D, GroupsTargets as GroupsTargets3D};
+use crate::boids_3d::systems::{confinement_3d, Confinement, GROUP_COLORS, OBSTACLE_AVOIDANCE_RANGE, OBSTACLE_RADIUS};
+use crate::rules::{ALIGNMENT_COLOR, COHESION_COLOR, SEPARATION_COLOR};
+use crate::selection::SelectedBoid;
+use crate::sky::birds::{sky_confinement, SkyBoidSettings};
+use crate::sky::murmuration::MurmurationSettings;
+use crate::ui::resources::SimulationState;
+use crate::underwater::fish::components::{FishType, SpeciesSettings};
+use crate::underwater::fish::underwater_confinement;
+use super::resources::*;
+
+const FIELD_OF_VIEW_SAMPLES: usize = 48;
+const CONE_LINES: usize = 8;
+
+/// Whether the per-boid layers are drawn for this boid.
+fn draws_boid(overlay: &DebugOverlay, selected: &SelectedBoid, entity: Entity) -> bool {
+    !overlay.selected_only || selected.entity == Some(entity)
+}
+
+/// Sum of the forces sent to each boid this frame, which is the acceleration it is integrated with.
+fn steering_by_boid(forces: impl Iterator<Item = (Entity, Vec3)>) -> HashMap<Entity, Vec3> {
+    let mut steering: HashMap<Entity, Vec3> = HashMap::default();
+    for (entity, force) in forces {
+        *steering.entry(entity).or_default() += force;
+    }
+    steering
+}
+
+fn draw_vectors(gizmos: &mut Gizmos, overlay: &DebugOverlay, position: Vec3, velocity: Vec3, steering: Option<Vec3>, max_speed: f32) {
+    if overlay.shows(OverlayLayer::Velocity) {
+        gizmos.ray(position, velocity * overlay.vector_scale, VELOCITY_COLOR);
+    }
+    if let Some(steering) = steering {
+        // Only the arrow is cut short: forces such as fear are far larger than any speed.
+        gizmos.ray(position, (steering * overlay.vector_scale).clamp_length_max(max_speed * overlay.vector_scale), ACCELERATION_COLOR);
+    }
+}
+
+fn draw_confinement_2d(gizmos: &mut Gizmos, confinement: &Confinement, bounce: bool) {
+    let centre = ((confinement.min + confinement.max) / 2.0).truncate();
+    let size = (confinement.max - confinement.min).truncate();
+    gizmos.rect_2d(centre, 0.0, size, BOUNDS_COLOR);
+    if bounce {
+        gizmos.rect_2d(centre, 0.0, (size - Vec2::splat(confinement.margin * 2.0)).abs(), MARGIN_COLOR);
+    }
+}
+
+fn draw_confinement_3d(gizmos: &mut Gizmos, confinement: &Confinement, bounce: bool) {
+    let centre = (confinement.min + confinement.max) / 2.0;
+    let size = (confinement.max - confinement.min).abs();
+    gizmos.cuboid(Transform::from_translation(centre).with_scale(size), BOUNDS_COLOR);
+    if bounce {
+        // The sky's box is thinner than its margins: along y, both faces push and cancel out
+        // between the two turning planes, whichever way round they are.
+        let (start, end) = (confinement.min + confinement.margin, confinement.max - confinement.margin);
+        gizmos.cuboid(Transform::from_translation((start + end) / 2.0).with_scale((end - start).abs()), MARGIN_COLOR);
+    }
+}
+
+/// Samples `is_in_field_of_view` around the cohesion circle, drawing the arcs where neighbours
+/// would be seen and the edges of the visible sectors.
+fn draw_field_of_view_2d(gizmos: &mut Gizmos, position: Vec2, settings: &BoidSettings2D) {
+    // Neighbours are only seen strictly within the cohesion range.
+    let range = settings.cohesion_range * 0.999;
+    let samples: Vec<(Vec2, bool)> = (0..=FIELD_OF_VIEW_SAMPLES).map(|index| {
+        let point = position + Vec2::from_angle(TAU * index as f32 / FIELD_OF_VIEW_SAMPLES as f32) * range;
+        (point, is_in_field_of_view(&position, &point, &settings.field_of_view, &settings.cohesion_range).is_some())
+    }).collect();
+    for pair in samples.windows(2) {
+        let ((start, start_seen), (end, end_seen)) = (pair[0], pair[1]);
+        if start_seen && end_seen {
+            gizmos.line_2d(start, end, FIELD_OF_VIEW_COLOR);
+        } else if start_seen != end_seen {
+            gizmos.line_2d(position, if start_seen { start } else { end }, FIELD_OF_VIEW_COLOR);
+        }
+    }
+}
+
+/// The cone `is_in_field_of_view` accepts neighbours in, around the velocity and as long as the
+/// cohesion range.
+fn draw_field_of_view_3d(gizmos: &mut Gizmos, position: Vec3, velocity: Vec3, settings: &BoidSettings3D) {
+    let Some(axis) = velocity.try_normalize() else { return };
+    let half_angle = (settings.field_of_view / 2.0).to_radians();
+    if half_angle >= PI {
+        return;
+    }
+    let centre = position + axis * settings.cohesion_range * half_angle.cos();
+    let radius = settings.cohesion_range * half_angle.sin();
+    gizmos.circle(centre, axis, radius, FIELD_OF_VIEW_COLOR);
+    let side = axis.any_orthonormal_vector();
+    for index in 0..CONE_LINES {
+        let rim = Quat::from_axis_angle(axis, TAU * index as f32 / CONE_LINES as f32) * side * radius;
+        gizmos.line(position, centre + rim, FIELD_OF_VIEW_COLOR);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn draw_overlay_2d(
+    mut gizmos: Gizmos,
+    overlay: Res<DebugOverlay>,
+    selected: Res<SelectedBoid>,
+    mut force_events: EventReader<ApplyForceEvent2D>,
+    boid_query: Query<(Entity, &Transform, &boids_2d::Velocity), With<boids_2d::Boid>>,
+    obstacle_query: Query<(&Transform, &PlacedObstacle), With<ObstacleTag>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    boid_settings: Res<BoidSettings2D>,
+    groups_targets: Res<GroupsTargets2D>
+) {
+    let steering = if overlay.shows(OverlayLayer::Acceleration) {
+        Some(steering_by_boid(force_events.read().map(|event| (event.entity, event.force.extend(0.0)))))
+    } else {
+        force_events.clear();
+        None
+    };
+
+    for (entity, transform, velocity) in boid_query.iter() {
+        if !draws_boid(&overlay, &selected, entity) {
+            continue;
+        }
+        let position = transform.translation.truncate();
+        // Unlike in 3D, the sum is integrated as it is, with no steering force limit.
+        let boid_steering = steering.as_ref().map(|steering| steering.get(&entity).copied().unwrap_or_default());
+        draw_vectors(&mut gizmos, &overlay, position.extend(0.0), velocity.velocity.extend(0.0), boid_steering, boid_settings.max_speed);
+        if overlay.shows(OverlayLayer::Perception) {
+            gizmos.circle_2d(position, boid_settings.separation_range, SEPARATION_COLOR);
+            gizmos.circle_2d(position, boid_settings.alignment_range, ALIGNMENT_COLOR);
+            gizmos.circle_2d(position, boid_settings.cohesion_range, COHESION_COLOR);
+        }
+        if overlay.shows(OverlayLayer::FieldOfView) {
+            draw_field_of_view_2d(&mut gizmos, position, &boid_settings);
+        }
+    }
+
+    if overlay.shows(OverlayLayer::Targets) {
+        for (target, color) in groups_targets.targets.iter().zip(GROUP_COLORS) {
+            gizmos.circle_2d(*target, 8.0, color);
+        }
+    }
+    if overlay.shows(OverlayLayer::Obstacles) {
+        for (transform, obstacle) in obstacle_query.iter() {
+            gizmos.circle_2d(transform.translation.truncate(), obstacle.radius + OBSTACLE_AVOIDANCE_RANGE, OBSTACLE_COLOR);
+        }
+    }
+    if overlay.shows(OverlayLayer::Confinement) {
+        if let Ok(window) = window_query.get_single() {
+            draw_confinement_2d(&mut gizmos, &confinement_2d(window), boid_settings.bounce_against_walls);
+        }
+    }
+}
+
+/// Shared by the 3D, sky and underwater modes, with the settings of the mode or of each fish's
+/// species.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_overlay_3d(
+    mut gizmos: Gizmos,
+    overlay: Res<DebugOverlay>,
+    selected: Res<SelectedBoid>,
+    mut force_events: EventReader<ApplyForceEvent3D>,
+    boid_query: Query<(Entity, &Transform, &boids_3d::Velocity, Option<&FishType>), With<boids_3d::Boid>>,
+    obstacle_query: Query<(&Transform, Option<&PlacedObstacle>), With<ObstacleTag>>,
+    state: Res<State<SimulationState>>,
+    settings_3d: Res<BoidSettings3D>,
+    sky_settings: Res<SkyBoidSettings>,
+    species_settings: Res<SpeciesSettings>,
+    murmuration: Res<MurmurationSettings>,
+    groups_targets: Res<GroupsTargets3D>
+) {
+    let steering = if overlay.shows(OverlayLayer::Acceleration) {
+        Some(steering_by_boid(force_events.read().map(|event| (event.entity, event.force))))
+    } else {
+        force_events.clear();
+        None
+    };
+    let mode = state.get();
+
+    for (entity, transform, velocity, fish_type) in boid_query.iter() {
+        if !draws_boid(&overlay, &selected, entity) {
+            continue;
+        }
+        let settings: &BoidSettings3D = match (mode, fish_type) {
+            (SimulationState::Underwater, Some(fish_type)) => &species_settings.get(fish_type.species).boids,
+            (SimulationState::Sky, _) => &sky_settings,
+            _ => &settings_3d
+        };
+        let position = transform.translation;
+        let boid_steering = steering.as_ref().map(|steering| {
+            steering.get(&entity).copied().unwrap_or_default().clamp_length_max(settings.max_force)
+        });
+        draw_vectors(&mut gizmos, &overlay, position, velocity.velocity, boid_steering, settings.max_speed);
+        if overlay.shows(OverlayLayer::Perception) {
+            gizmos.sphere(position, Quat::IDENTITY, settings.separation_range, SEPARATION_COLOR);
+            gizmos.sphere(position, Quat::IDENTITY, settings.alignment_range, ALIGNMENT_COLOR);
+            gizmos.sphere(position, Quat::IDENTITY, settings.cohesion_range, COHESION_COLOR);
+        }
+        if overlay.shows(OverlayLayer::FieldOfView) {
+            draw_field_of_view_3d(&mut gizmos, position, velocity.velocity, settings);
+        }
+    }
+
+    if overlay.shows(OverlayLayer::Targets) {
+        match mode {
+            // Fish have no group targets.
+            SimulationState::Underwater => {}
+            SimulationState::Sky if murmuration.enabled => {
+                gizmos.sphere(murmuration.roost, Quat::IDENTITY, murmuration.roost_radius.max(5.0), ROOST_COLOR);
+            }
+            _ => {
+                for (target, color) in groups_targets.targets.iter().zip(GROUP_COLORS) {
+                    gizmos.sphere(*target, Quat::IDENTITY, 5.0, color);
+                }
+            }
+        }
+    }
+    // Boids of the 3D mode do not avoid obstacles.
+    if overlay.shows(OverlayLayer::Obstacles) && mode != &SimulationState::Mode3D {
+        for (transform, placed) in obstacle_query.iter() {
+            let radius = placed.map_or(OBSTACLE_RADIUS, |placed| placed.radius);
+            gizmos.sphere(transform.translation, Quat::IDENTITY, radius + OBSTACLE_AVOIDANCE_RANGE, OBSTACLE_COLOR);
+        }
+    }
+    if overlay.shows(OverlayLayer::Confinement) {
+        match mode {
+            SimulationState::Sky => draw_confinement_3d(&mut gizmos, &sky_confinement(), true),
+            SimulationState::Underwater => draw_confinement_3d(&mut gizmos, &underwater_confinement(), true),
+            _ => draw_confinement_3d(&mut gizmos, &confinement_3d(), settings_3d.bounce_against_walls)
+        }
+    }
+}
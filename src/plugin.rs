@@ -0,0 +1,304 @@
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use bevy::window::PrimaryWindow;
+use bevy::core_pipeline::clear_color::ClearColorConfig;
+use bevy_egui::EguiPlugin;
+use bevy_spatial::TransformMode;
+
+use crate::boids_2d::{self, Boids2DPlugin};
+use crate::boids_2d::components::Mode2DMarker;
+use crate::boids_2d::resources::BoidSettings2D;
+use crate::boids_3d::{self, Boids3DPlugin};
+use crate::boids_3d::components::Mode3DMarker;
+use crate::boids_3d::resources::BoidSettings3D;
+use crate::boids_3d::systems::BOUNDS_SIZE;
+use crate::clustering::ClusteringPlugin;
+use crate::export::ExportPlugin;
+use crate::input::InputPlugin;
+use crate::kd_tree_2d::KDTree2DPlugin;
+use crate::kd_tree_3d::KDTree3DPlugin;
+use crate::metrics::MetricsPlugin;
+use crate::overlay::OverlayPlugin;
+use crate::presets::PresetsPlugin;
+use crate::random::SystemRng;
+use crate::replay::ReplayPlugin;
+use crate::rules::RulesPlugin;
+use crate::scripting::ScriptingPlugin;
+use crate::selection::SelectionPlugin;
+use crate::sky::SkyPlugin;
+use crate::sky::birds::SkyBoidSettings;
+use crate::snapshot::SnapshotPlugin;
+use crate::ui::UiPlugin;
+use crate::ui::resources::{EnabledModes, SimulationState};
+use crate::underwater::UnderwaterPlugin;
+use crate::underwater::fish::components::SpeciesSettings;
+
+/// Which kinds of modes an app runs: the 2D mode, the 3D ones (3D, underwater and sky) or all.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Dimensions {
+    Two,
+    Three,
+    Both
+}
+
+impl Dimensions {
+    pub fn modes(&self) -> Vec<SimulationState> {
+        match self {
+            Dimensions::Two => vec![SimulationState::Mode2D],
+            Dimensions::Three => vec![SimulationState::Mode3D, SimulationState::Underwater, SimulationState::Sky],
+            Dimensions::Both => EnabledModes::default().modes
+        }
+    }
+}
+
+/// How neighbours are found. bevy_spatial only provides k-d trees, rebuilt from the boids'
+/// transforms every `frequency`; a longer period is cheaper but boids see older positions.
+#[derive(Clone, Copy)]
+pub struct SpatialBackend {
+    pub frequency: Duration,
+    pub transform: TransformMode
+}
+
+impl Default for SpatialBackend {
+    fn default() -> Self {
+        let tree = KDTree3DPlugin::default();
+        Self {
+            frequency: tree.frequency,
+            transform: tree.transform
+        }
+    }
+}
+
+/// The whole simulation: every mode, the neighbour search, metrics, recording, presets and,
+/// unless turned off, the egui windows and camera controls. Built with `BoidsPlugin::default()`
+/// and the `with_*` methods:
+///
+/// ```no_run
+/// use bevy::prelude::*;
+/// use boidsy::{BoidsPlugin, Dimensions};
+///
+/// App::new()
+///     .add_plugins(DefaultPlugins)
+///     .add_plugins(BoidsPlugin::default().with_dimensions(Dimensions::Three).with_ui(false))
+///     .run();
+/// ```
+pub struct BoidsPlugin {
+    modes: Vec<SimulationState>,
+    initial_mode: SimulationState,
+    spatial_backend: SpatialBackend,
+    settings_2d: Option<BoidSettings2D>,
+    settings_3d: Option<BoidSettings3D>,
+    sky_settings: Option<SkyBoidSettings>,
+    species_settings: Option<SpeciesSettings>,
+    ui: bool,
+    camera_input: bool
+}
+
+impl Default for BoidsPlugin {
+    fn default() -> Self {
+        Self {
+            modes: Dimensions::Both.modes(),
+            initial_mode: SimulationState::default(),
+            spatial_backend: SpatialBackend::default(),
+            settings_2d: None,
+            settings_3d: None,
+            sky_settings: None,
+            species_settings: None,
+            ui: true,
+            camera_input: true
+        }
+    }
+}
+
+impl BoidsPlugin {
+    /// Modes the simulation can be switched to, in the order the UI lists them.
+    pub fn with_modes(mut self, modes: impl IntoIterator<Item = SimulationState>) -> Self {
+        self.modes = modes.into_iter().collect();
+        self
+    }
+
+    pub fn with_dimensions(self, dimensions: Dimensions) -> Self {
+        self.with_modes(dimensions.modes())
+    }
+
+    /// Mode the app starts in. The first enabled mode is used if this one is not enabled.
+    pub fn with_initial_mode(mut self, mode: SimulationState) -> Self {
+        self.initial_mode = mode;
+        self
+    }
+
+    pub fn with_spatial_backend(mut self, backend: SpatialBackend) -> Self {
+        self.spatial_backend = backend;
+        self
+    }
+
+    /// `count` is the population the mode starts with.
+    pub fn with_settings_2d(mut self, mut settings: BoidSettings2D) -> Self {
+        settings.previous_count = settings.count;
+        self.settings_2d = Some(settings);
+        self
+    }
+
+    pub fn with_settings_3d(mut self, mut settings: BoidSettings3D) -> Self {
+        settings.previous_count = settings.count;
+        self.settings_3d = Some(settings);
+        self
+    }
+
+    pub fn with_sky_settings(mut self, mut settings: SkyBoidSettings) -> Self {
+        settings.previous_count = settings.count;
+        self.sky_settings = Some(settings);
+        self
+    }
+
+    pub fn with_species_settings(mut self, settings: SpeciesSettings) -> Self {
+        self.species_settings = Some(settings);
+        self
+    }
+
+    /// Whether to add the egui windows, the boid inspector, the debug overlay and the FPS counter.
+    pub fn with_ui(mut self, ui: bool) -> Self {
+        self.ui = ui;
+        self
+    }
+
+    /// Whether E toggles the free-flying camera of the 3D modes.
+    pub fn with_camera_input(mut self, camera_input: bool) -> Self {
+        self.camera_input = camera_input;
+        self
+    }
+}
+
+impl Plugin for BoidsPlugin {
+    fn build(&self, app: &mut App) {
+        let modes = EnabledModes { modes: self.modes.clone() };
+        let initial_mode = if modes.contains(&self.initial_mode) {
+            self.initial_mode.clone()
+        } else {
+            modes.modes.first().cloned().unwrap_or_default()
+        };
+        // Mode systems only run in their state, so a tree nobody searches is not kept up to date.
+        let has_2d = modes.contains(&SimulationState::Mode2D);
+        let has_3d = modes.modes.iter().any(|mode| mode != &SimulationState::Mode2D);
+
+        app.insert_resource(State::new(initial_mode))
+           .add_state::<SimulationState>()
+           .insert_resource(modes)
+           .add_plugins(RulesPlugin);
+        let SpatialBackend { frequency, transform } = self.spatial_backend;
+        if has_2d {
+            app.add_plugins(KDTree2DPlugin { frequency, transform });
+        }
+        if has_3d {
+            app.add_plugins(KDTree3DPlugin { frequency, transform });
+        }
+        app.add_plugins((
+            Boids2DPlugin,
+            Boids3DPlugin,
+            UnderwaterPlugin,
+            SkyPlugin,
+            MetricsPlugin,
+            ExportPlugin,
+            ClusteringPlugin,
+            ReplayPlugin,
+            SnapshotPlugin,
+            PresetsPlugin,
+            ScriptingPlugin
+        ));
+        if self.ui {
+            if !app.is_plugin_added::<EguiPlugin>() {
+                app.add_plugins(EguiPlugin);
+            }
+            app.add_plugins((UiPlugin, SelectionPlugin, OverlayPlugin));
+        }
+        app.add_plugins(InputPlugin { camera_control: self.camera_input })
+           .add_systems(OnEnter(SimulationState::Mode2D), setup_2d_mode)
+           .add_systems(OnEnter(SimulationState::Mode3D), setup_3d_mode)
+           .add_systems(OnExit(SimulationState::Mode2D), cleanup_2d_mode)
+           .add_systems(OnExit(SimulationState::Mode3D), cleanup_3d_mode);
+
+        // Replaces the defaults the mode plugins inserted.
+        if let Some(settings) = self.settings_2d.as_ref() {
+            app.insert_resource(settings.clone());
+        }
+        if let Some(settings) = self.settings_3d.as_ref() {
+            app.insert_resource(settings.clone());
+        }
+        if let Some(settings) = self.sky_settings.as_ref() {
+            app.insert_resource(settings.clone());
+        }
+        if let Some(settings) = self.species_settings.as_ref() {
+            app.insert_resource(settings.clone());
+        }
+    }
+}
+
+fn setup_2d_mode(
+    mut commands: Commands,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    asset_server: Res<AssetServer>,
+    boid_settings: Res<BoidSettings2D>,
+    rng: Local<SystemRng>
+) {
+    let window = window_query.get_single().unwrap();
+    commands.spawn((
+        Camera2dBundle {
+            transform: Transform::from_xyz(window.width() / 2.0, window.height() / 2.0, 0.0),
+            ..default()
+        },
+        Mode2DMarker,
+    ));
+    boids_2d::systems::spawn_boids(commands, window_query, asset_server, boid_settings, rng);
+}
+
+fn setup_3d_mode(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    boid_settings: Res<BoidSettings3D>,
+    rng: Local<SystemRng>
+) {
+    commands.spawn((
+        Camera3dBundle {
+            transform: Transform::from_xyz(-100.0, BOUNDS_SIZE * 2.0, -100.0)
+                .looking_at(Vec3::ZERO, Vec3::Y),
+            camera: Camera {
+                order: 0,
+                ..default()
+            },
+            camera_3d: Camera3d {
+                clear_color: ClearColorConfig::Custom(Color::rgb(0.1, 0.1, 0.5)),
+                ..default()
+            },
+            ..default()
+        },
+        Mode3DMarker
+    ));
+
+    commands.insert_resource(AmbientLight {
+        color: Color::WHITE,
+        brightness: 1.0,
+    }
+    );
+
+    crate::boids_3d::systems::setup_3d_scene(&mut commands, &mut meshes, &mut materials);
+    boids_3d::systems::spawn_boids(commands, boid_settings, meshes, materials, rng);
+}
+
+fn cleanup_2d_mode(
+    mut commands: Commands,
+    entities: Query<Entity, With<Mode2DMarker>>,
+) {
+    for entity in entities.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+pub fn cleanup_3d_mode(
+    mut commands: Commands,
+    entities: Query<Entity, With<Mode3DMarker>>,
+) {
+    for entity in entities.iter() {
+        commands.entity(entity).despawn();
+    }
+}
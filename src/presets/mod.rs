@@ -0,0 +1,16 @@
+use bevy::prelude::*;
+
+pub mod resources;
+pub mod systems;
+
+pub use resources::*;
+pub use systems::*;
+
+pub struct PresetsPlugin;
+
+impl Plugin for PresetsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Presets>()
+           .add_systems(Update, (handle_preset_requests, poll_presets).chain());
+    }
+}
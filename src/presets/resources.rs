@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::ui::resources::SimulationState;
+use crate::underwater::fish::components::Species;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltInPreset {
+    TightSchool,
+    LooseSwarm,
+    MillingTorus
+}
+
+impl BuiltInPreset {
+    pub const ALL: [BuiltInPreset; 3] = [BuiltInPreset::TightSchool, BuiltInPreset::LooseSwarm, BuiltInPreset::MillingTorus];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuiltInPreset::TightSchool => "Tight school",
+            BuiltInPreset::LooseSwarm => "Loose swarm",
+            BuiltInPreset::MillingTorus => "Milling torus"
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PresetRequest {
+    SaveAs(String),
+    LoadFile(PathBuf),
+    LoadBuiltIn(BuiltInPreset),
+    Delete(PathBuf),
+    Reset
+}
+
+/// The preset file last loaded or saved, reloaded whenever it changes on disk.
+pub struct ActivePreset {
+    pub path: PathBuf,
+    pub mode: SimulationState,
+    pub species: Species,
+    pub(super) modified: Option<SystemTime>
+}
+
+#[derive(Resource)]
+pub struct Presets {
+    pub directory: PathBuf,
+    /// Preset files of the current mode, listed by the UI.
+    pub files: Vec<PathBuf>,
+    pub new_name: String,
+    /// Species whose settings presets apply to in the underwater mode.
+    pub species: Species,
+    pub requests: Vec<PresetRequest>,
+    pub active: Option<ActivePreset>,
+    pub error: Option<String>,
+    pub(super) listed_mode: Option<SimulationState>,
+    pub(super) poll_timer: Timer
+}
+
+impl Default for Presets {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("presets"),
+            files: Vec::new(),
+            new_name: String::new(),
+            species: Species::SmallFish,
+            requests: Vec::new(),
+            active: None,
+            error: None,
+            listed_mode: None,
+            poll_timer: Timer::from_seconds(0.5, TimerMode::Repeating)
+        }
+    }
+}
+
+impl Presets {
+    /// Presets are stored per mode, as each mode has its own settings type.
+    pub fn mode_directory(&self, mode: &SimulationState) -> PathBuf {
+        self.directory.join(format!("{mode:?}").to_lowercase())
+    }
+
+    pub fn refresh_files(&mut self, mode: &SimulationState) {
+        self.files = fs::read_dir(self.mode_directory(mode))
+            .map(|entries| entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+                .collect())
+            .unwrap_or_default();
+        self.files.sort();
+        self.listed_mode = Some(mode.clone());
+    }
+
+    pub fn is_active(&self, path: &Path) -> bool {
+        self.active.as_ref().is_some_and(|active| active.path == path)
+    }
+}
@@ -0,0 +1,312 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use ron::ser::PrettyConfig;
+use std::fs;
+use std::path::Path;
+use crate::boids_2d::resources::BoidSettings2D;
+use crate::boids_3d::resources::BoidSettings3D;
+use crate::sky::birds::SkyBoidSettings;
+use crate::ui::resources::SimulationState;
+use crate::underwater::fish::components::{Species, SpeciesSettings, UnderwaterBoidSettings};
+use super::resources::*;
+
+/// Fields of the 2D and 3D boid settings that built-in presets tune.
+struct Tunable<'a> {
+    cohesion_range: &'a mut f32,
+    alignment_range: &'a mut f32,
+    separation_range: &'a mut f32,
+    min_distance_between_boids: &'a mut f32,
+    cohesion_coeff: &'a mut f32,
+    alignment_coeff: &'a mut f32,
+    separation_coeff: &'a mut f32,
+    attraction_coeff: &'a mut f32,
+    field_of_view: &'a mut f32,
+    min_speed: &'a mut f32,
+    max_speed: f32
+}
+
+impl<'a> From<&'a mut BoidSettings2D> for Tunable<'a> {
+    fn from(settings: &'a mut BoidSettings2D) -> Self {
+        let BoidSettings2D {
+            cohesion_range, alignment_range, separation_range, min_distance_between_boids, cohesion_coeff,
+            alignment_coeff, separation_coeff, attraction_coeff, field_of_view, min_speed, max_speed, ..
+        } = settings;
+        Self {
+            cohesion_range, alignment_range, separation_range, min_distance_between_boids, cohesion_coeff,
+            alignment_coeff, separation_coeff, attraction_coeff, field_of_view, min_speed, max_speed: *max_speed
+        }
+    }
+}
+
+impl<'a> From<&'a mut BoidSettings3D> for Tunable<'a> {
+    fn from(settings: &'a mut BoidSettings3D) -> Self {
+        let BoidSettings3D {
+            cohesion_range, alignment_range, separation_range, min_distance_between_boids, cohesion_coeff,
+            alignment_coeff, separation_coeff, attraction_coeff, field_of_view, min_speed, max_speed, ..
+        } = settings;
+        Self {
+            cohesion_range, alignment_range, separation_range, min_distance_between_boids, cohesion_coeff,
+            alignment_coeff, separation_coeff, attraction_coeff, field_of_view, min_speed, max_speed: *max_speed
+        }
+    }
+}
+
+/// Built-in presets scale the mode defaults rather than set absolute values, since ranges and
+/// speeds differ a lot between modes.
+fn tune<'a>(settings: impl Into<Tunable<'a>>, preset: BuiltInPreset) {
+    let settings = settings.into();
+    match preset {
+        BuiltInPreset::TightSchool => {
+            *settings.cohesion_coeff *= 2.0;
+            *settings.alignment_coeff *= 2.0;
+            *settings.separation_range *= 0.5;
+            *settings.min_distance_between_boids *= 0.5;
+            *settings.field_of_view = 300.0;
+        }
+        BuiltInPreset::LooseSwarm => {
+            *settings.cohesion_coeff *= 0.3;
+            *settings.alignment_coeff *= 0.2;
+            *settings.separation_range *= 1.5;
+            *settings.separation_coeff *= 1.5;
+            *settings.min_speed = settings.max_speed * 0.3;
+        }
+        BuiltInPreset::MillingTorus => {
+            // A narrow alignment band and strong long-range cohesion without targets make the
+            // group circle around its own centre.
+            *settings.alignment_range = *settings.separation_range * 1.2;
+            *settings.alignment_coeff *= 0.5;
+            *settings.cohesion_range *= 2.0;
+            *settings.cohesion_coeff *= 1.5;
+            *settings.attraction_coeff = 0.0;
+            *settings.field_of_view = 270.0;
+            *settings.min_speed = settings.max_speed * 0.8;
+        }
+    }
+}
+
+/// Presets describe behaviour, so the population and boid size are left as they are.
+fn keep_population_3d(current: &BoidSettings3D, new: &mut BoidSettings3D) {
+    new.count = current.count;
+    new.previous_count = current.previous_count;
+    new.size = current.size;
+}
+
+/// Boid settings of every mode, the ones presets read and write.
+#[derive(SystemParam)]
+pub struct PresetTargets<'w> {
+    boids_2d: ResMut<'w, BoidSettings2D>,
+    boids_3d: ResMut<'w, BoidSettings3D>,
+    sky_boids: ResMut<'w, SkyBoidSettings>,
+    species: ResMut<'w, SpeciesSettings>
+}
+
+impl PresetTargets<'_> {
+    fn to_ron(&self, mode: &SimulationState, species: Species) -> Result<String, ron::Error> {
+        let config = PrettyConfig::default();
+        match mode {
+            SimulationState::Mode2D => ron::ser::to_string_pretty(&*self.boids_2d, config),
+            SimulationState::Mode3D => ron::ser::to_string_pretty(&*self.boids_3d, config),
+            SimulationState::Underwater => ron::ser::to_string_pretty(&self.species.get(species).boids, config),
+            SimulationState::Sky => ron::ser::to_string_pretty(&*self.sky_boids, config)
+        }
+    }
+
+    fn apply_ron(&mut self, mode: &SimulationState, species: Species, text: &str) -> Result<(), ron::error::SpannedError> {
+        match mode {
+            SimulationState::Mode2D => {
+                let mut settings: BoidSettings2D = ron::from_str(text)?;
+                settings.count = self.boids_2d.count;
+                settings.previous_count = self.boids_2d.previous_count;
+                *self.boids_2d = settings;
+            }
+            SimulationState::Mode3D => {
+                let mut settings: BoidSettings3D = ron::from_str(text)?;
+                keep_population_3d(&self.boids_3d, &mut settings);
+                *self.boids_3d = settings;
+            }
+            SimulationState::Underwater => {
+                let mut settings: UnderwaterBoidSettings = ron::from_str(text)?;
+                let current = &mut self.species.get_mut(species).boids;
+                keep_population_3d(current, &mut settings);
+                *current = settings;
+            }
+            SimulationState::Sky => {
+                let mut settings: SkyBoidSettings = ron::from_str(text)?;
+                keep_population_3d(&self.sky_boids, &mut settings);
+                *self.sky_boids = settings;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resets to the code defaults, then applies the built-in preset if any.
+    fn reset(&mut self, mode: &SimulationState, species: Species, preset: Option<BuiltInPreset>) {
+        match mode {
+            SimulationState::Mode2D => {
+                let mut settings = BoidSettings2D {
+                    count: self.boids_2d.count,
+                    previous_count: self.boids_2d.previous_count,
+                    ..default()
+                };
+                if let Some(preset) = preset {
+                    tune(&mut settings, preset);
+                }
+                *self.boids_2d = settings;
+            }
+            SimulationState::Mode3D => {
+                let mut settings = BoidSettings3D::default();
+                keep_population_3d(&self.boids_3d, &mut settings);
+                if let Some(preset) = preset {
+                    tune(&mut settings, preset);
+                }
+                *self.boids_3d = settings;
+            }
+            SimulationState::Underwater => {
+                let mut settings = species.default_settings().boids;
+                let current = &mut self.species.get_mut(species).boids;
+                keep_population_3d(current, &mut settings);
+                if let Some(preset) = preset {
+                    tune(&mut *settings, preset);
+                }
+                *current = settings;
+            }
+            SimulationState::Sky => {
+                let mut settings = SkyBoidSettings::default();
+                keep_population_3d(&self.sky_boids, &mut settings);
+                if let Some(preset) = preset {
+                    tune(&mut *settings, preset);
+                }
+                *self.sky_boids = settings;
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<std::time::SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Names become file names, so anything that could leave the presets directory is refused.
+fn valid_name(name: &str) -> Option<&str> {
+    let name = name.trim();
+    let valid = !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\', ':']);
+    valid.then_some(name)
+}
+
+fn handle_request(
+    presets: &mut Presets,
+    targets: &mut PresetTargets,
+    mode: &SimulationState,
+    request: PresetRequest
+) -> Result<(), String> {
+    let species = presets.species;
+    match request {
+        PresetRequest::SaveAs(name) => {
+            let name = valid_name(&name).ok_or_else(|| format!("\"{name}\" is not a valid preset name"))?;
+            let directory = presets.mode_directory(mode);
+            let path = directory.join(format!("{name}.ron"));
+            let text = targets.to_ron(mode, species).map_err(|error| error.to_string())?;
+            fs::create_dir_all(&directory).and_then(|_| fs::write(&path, text)).map_err(|error| error.to_string())?;
+            info!("Saved preset {}", path.display());
+            presets.active = Some(ActivePreset { modified: modified(&path), path, mode: mode.clone(), species });
+        }
+        PresetRequest::LoadFile(path) => {
+            let text = fs::read_to_string(&path).map_err(|error| error.to_string())?;
+            targets.apply_ron(mode, species, &text).map_err(|error| format!("{}: {error}", path.display()))?;
+            info!("Loaded preset {}", path.display());
+            presets.active = Some(ActivePreset { modified: modified(&path), path, mode: mode.clone(), species });
+        }
+        PresetRequest::LoadBuiltIn(preset) => {
+            targets.reset(mode, species, Some(preset));
+            presets.active = None;
+        }
+        PresetRequest::Delete(path) => {
+            fs::remove_file(&path).map_err(|error| error.to_string())?;
+            if presets.is_active(&path) {
+                presets.active = None;
+            }
+        }
+        PresetRequest::Reset => {
+            targets.reset(mode, species, None);
+            presets.active = None;
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_preset_requests(
+    mut presets: ResMut<Presets>,
+    mut targets: PresetTargets,
+    state: Res<State<SimulationState>>
+) {
+    if presets.requests.is_empty() {
+        return;
+    }
+    for request in std::mem::take(&mut presets.requests) {
+        if let Err(error) = handle_request(&mut presets, &mut targets, state.get(), request) {
+            presets.error = Some(error);
+        }
+    }
+    presets.refresh_files(state.get());
+}
+
+/// Polls the preset directory and the active preset file. A file that fails to parse, which is
+/// common halfway through an edit, leaves the settings untouched until it is fixed.
+pub fn poll_presets(
+    mut presets: ResMut<Presets>,
+    mut targets: PresetTargets,
+    state: Res<State<SimulationState>>,
+    time: Res<Time<Real>>
+) {
+    let presets = presets.bypass_change_detection();
+    let mode_changed = presets.listed_mode.as_ref() != Some(state.get());
+    if !presets.poll_timer.tick(time.delta()).just_finished() && !mode_changed {
+        return;
+    }
+    presets.refresh_files(state.get());
+
+    let Some(active) = presets.active.as_mut() else { return };
+    if &active.mode != state.get() {
+        return;
+    }
+    let modified = modified(&active.path);
+    if modified.is_none() || modified == active.modified {
+        return;
+    }
+    active.modified = modified;
+    let (path, species) = (active.path.clone(), active.species);
+    let result = fs::read_to_string(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| targets.apply_ron(state.get(), species, &text).map_err(|error| format!("{}: {error}", path.display())));
+    match result {
+        Ok(()) => {
+            info!("Reloaded preset {}", path.display());
+            presets.error = None;
+        }
+        Err(error) => presets.error = Some(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_trimmed() {
+        assert_eq!(valid_name("  calm flock "), Some("calm flock"));
+        assert_eq!(valid_name("v1.2"), Some("v1.2"));
+    }
+
+    #[test]
+    fn empty_names_are_refused() {
+        assert_eq!(valid_name(""), None);
+        assert_eq!(valid_name("   "), None);
+    }
+
+    #[test]
+    fn names_that_leave_the_directory_are_refused() {
+        for name in ["../escape", "sub/preset", "sub\\preset", "C:preset", ".hidden", " ..", "/etc/passwd"] {
+            assert_eq!(valid_name(name), None, "{name}");
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! Randomness of the simulation. Every system that draws random numbers owns its generator as a
+//! `Local<SystemRng>`, seeded from the seed given on the command line and the system's name, so
+//! that a seeded run draws the same numbers whichever order parallel systems run in. Without a
+//! seed the generators are seeded from entropy.
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::Mutex;
+
+static SEED: Mutex<Option<u64>> = Mutex::new(None);
+
+pub fn seed(seed: u64) {
+    *SEED.lock().unwrap() = Some(seed);
+}
+
+/// Random number generator of a single system.
+#[derive(Default)]
+pub struct SystemRng(Option<StdRng>);
+
+impl SystemRng {
+    /// The generator, seeded on first use for the system named `system`.
+    pub fn get(&mut self, system: &str) -> &mut StdRng {
+        self.0.get_or_insert_with(|| match *SEED.lock().unwrap() {
+            Some(seed) => StdRng::seed_from_u64(seed ^ name_hash(system)),
+            None => StdRng::from_entropy()
+        })
+    }
+}
+
+/// FNV-1a, as the seeds must not change between builds the way `DefaultHasher` may.
+fn name_hash(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
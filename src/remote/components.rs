@@ -0,0 +1,23 @@
+use bevy::prelude::*;
+use crate::ui::resources::SimulationState;
+use crate::underwater::fish::components::Species;
+
+/// Spawns boids at a point at a steady rate until `remaining` runs out. Dropped when the
+/// simulation leaves its mode.
+#[derive(Component)]
+pub struct Emitter {
+    pub mode: SimulationState,
+    pub position: Vec3,
+    pub group: u8,
+    pub species: Species,
+    pub rate: f32,
+    pub remaining: u32,
+    /// Boids owed since the last spawn, the fraction carries over to the next frame.
+    pub(super) due: f32
+}
+
+impl Emitter {
+    pub fn new(mode: SimulationState, position: Vec3, group: u8, species: Species, rate: f32, count: u32) -> Self {
+        Self { mode, position, group, species, rate, remaining: count, due: 0.0 }
+    }
+}
@@ -0,0 +1,175 @@
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::cli::CliMode;
+use crate::underwater::fish::components::Species;
+
+/// A request from a client, one JSON object per WebSocket text message, such as
+/// `{"command": "set", "field": "cohesion_coeff", "value": 12}`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Sets a boid settings field of the current mode, as `--set` does.
+    Set {
+        field: String,
+        value: serde_json::Value
+    },
+    Mode {
+        mode: CliMode
+    },
+    /// `z` is ignored in 2D.
+    Obstacle {
+        position: Vec3
+    },
+    /// Spawns `count` boids at `position`, `rate` a second. Underwater, `group` is the school.
+    Emitter {
+        position: Vec3,
+        count: u32,
+        #[serde(default = "default_emitter_rate")]
+        rate: f32,
+        #[serde(default)]
+        group: u8,
+        #[serde(default = "default_species")]
+        species: Species
+    },
+    Pause,
+    Resume,
+    /// Runs the paused simulation for a number of frames.
+    Step {
+        #[serde(default = "default_steps")]
+        frames: u32
+    },
+    /// Chooses what the server streams to this client.
+    Subscribe {
+        #[serde(default)]
+        metrics: bool,
+        #[serde(default)]
+        positions: bool
+    }
+}
+
+fn default_emitter_rate() -> f32 {
+    20.0
+}
+
+fn default_species() -> Species {
+    Species::SmallFish
+}
+
+fn default_steps() -> u32 {
+    1
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Set { .. } => "set",
+            Command::Mode { .. } => "mode",
+            Command::Obstacle { .. } => "obstacle",
+            Command::Emitter { .. } => "emitter",
+            Command::Pause => "pause",
+            Command::Resume => "resume",
+            Command::Step { .. } => "step",
+            Command::Subscribe { .. } => "subscribe"
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct BoidState {
+    pub group: u8,
+    pub position: Vec3,
+    pub velocity: Vec3
+}
+
+/// What the server sends: a reply to every command, then the subscribed streams.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Ok {
+        command: &'static str
+    },
+    Error {
+        message: String
+    },
+    /// Latest sample of every group, by metric key.
+    Metrics {
+        time: f64,
+        mode: CliMode,
+        groups: BTreeMap<String, BTreeMap<&'static str, f64>>
+    },
+    Positions {
+        time: f64,
+        mode: CliMode,
+        boids: Vec<BoidState>
+    }
+}
+
+impl ServerMessage {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn command(value: serde_json::Value) -> Result<Command, serde_json::Error> {
+        serde_json::from_value(value)
+    }
+
+    #[test]
+    fn commands_are_tagged_by_name() {
+        let Command::Set { field, value } = command(json!({ "command": "set", "field": "cohesion_coeff", "value": 12 })).unwrap() else { panic!() };
+        assert_eq!((field.as_str(), value), ("cohesion_coeff", json!(12)));
+        assert!(matches!(command(json!({ "command": "mode", "mode": "2d" })).unwrap(), Command::Mode { mode: CliMode::Mode2D }));
+        assert!(matches!(command(json!({ "command": "mode", "mode": "sky" })).unwrap(), Command::Mode { mode: CliMode::Sky }));
+        let Command::Obstacle { position } = command(json!({ "command": "obstacle", "position": [1.0, 2.0, 3.0] })).unwrap() else { panic!() };
+        assert_eq!(position, Vec3::new(1.0, 2.0, 3.0));
+        assert!(matches!(command(json!({ "command": "pause" })).unwrap(), Command::Pause));
+        assert!(matches!(command(json!({ "command": "resume" })).unwrap(), Command::Resume));
+        for name in ["set", "mode", "obstacle", "emitter", "pause", "resume", "step", "subscribe"] {
+            if let Ok(command) = command(json!({ "command": name })) {
+                assert_eq!(command.name(), name);
+            }
+        }
+    }
+
+    #[test]
+    fn optional_fields_have_defaults() {
+        let Command::Emitter { position, count, rate, group, species } = command(json!({ "command": "emitter", "position": [0.0, 5.0, 0.0], "count": 10 })).unwrap() else { panic!() };
+        assert_eq!((position, count, rate, group, species), (Vec3::new(0.0, 5.0, 0.0), 10, 20.0, 0, Species::SmallFish));
+        let Command::Emitter { species, .. } = command(json!({ "command": "emitter", "position": [0.0, 0.0, 0.0], "count": 1, "species": "LargeFish" })).unwrap() else { panic!() };
+        assert_eq!(species, Species::LargeFish);
+        assert!(matches!(command(json!({ "command": "step" })).unwrap(), Command::Step { frames: 1 }));
+        assert!(matches!(command(json!({ "command": "subscribe", "metrics": true })).unwrap(), Command::Subscribe { metrics: true, positions: false }));
+    }
+
+    #[test]
+    fn malformed_commands_are_refused() {
+        assert!(command(json!({ "command": "teleport" })).is_err());
+        assert!(command(json!({ "field": "count", "value": 3 })).is_err());
+        assert!(command(json!({ "command": "set", "field": "count" })).is_err());
+        assert!(command(json!({ "command": "mode", "mode": "4d" })).is_err());
+    }
+
+    #[test]
+    fn server_messages_are_tagged_by_type() {
+        let reply = |message: ServerMessage| serde_json::from_str::<serde_json::Value>(&message.to_json()).unwrap();
+        assert_eq!(reply(ServerMessage::Ok { command: "set" }), json!({ "type": "ok", "command": "set" }));
+        assert_eq!(reply(ServerMessage::Error { message: "no".to_string() }), json!({ "type": "error", "message": "no" }));
+
+        let groups = BTreeMap::from([("Group 0".to_string(), BTreeMap::from([("polarisation", 0.5)]))]);
+        assert_eq!(
+            reply(ServerMessage::Metrics { time: 1.5, mode: CliMode::Mode3D, groups }),
+            json!({ "type": "metrics", "time": 1.5, "mode": "3d", "groups": { "Group 0": { "polarisation": 0.5 } } })
+        );
+        let boids = vec![BoidState { group: 1, position: Vec3::new(1.0, 2.0, 3.0), velocity: Vec3::X }];
+        assert_eq!(
+            reply(ServerMessage::Positions { time: 2.0, mode: CliMode::Underwater, boids }),
+            json!({ "type": "positions", "time": 2.0, "mode": "underwater", "boids": [{ "group": 1, "position": [1.0, 2.0, 3.0], "velocity": [1.0, 0.0, 0.0] }] })
+        );
+    }
+}
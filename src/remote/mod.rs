@@ -0,0 +1,44 @@
+use bevy::prelude::*;
+
+pub mod components;
+pub mod format;
+pub mod resources;
+pub mod server;
+pub mod systems;
+
+pub use components::*;
+pub use resources::*;
+pub use systems::*;
+
+/// Lets other programs drive the simulation over a WebSocket on localhost: set settings fields,
+/// switch modes, place obstacles and emitters, pause and step, and subscribe to metrics and
+/// boid positions. See `format::Command` for the messages.
+pub struct RemotePlugin {
+    pub port: u16
+}
+
+impl Plugin for RemotePlugin {
+    fn build(&self, app: &mut App) {
+        let inbound = match server::listen(self.port) {
+            Ok(inbound) => {
+                info!("Remote control listening on ws://127.0.0.1:{}", self.port);
+                Some(inbound)
+            }
+            Err(error) => {
+                error!("Remote control cannot listen on port {}: {error}", self.port);
+                None
+            }
+        };
+        app.insert_resource(RemoteControl::new(self.port, inbound))
+           .add_systems(Update, (
+                receive_remote_commands,
+                apply_remote_stepping,
+                run_emitters,
+                stream_to_clients
+           ).chain().run_if(remote_listening));
+    }
+}
+
+pub fn remote_listening(remote: Res<RemoteControl>) -> bool {
+    remote.is_listening()
+}
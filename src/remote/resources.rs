@@ -0,0 +1,49 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, Sender};
+use super::server::Inbound;
+
+/// A connected client and the streams it subscribed to.
+pub struct RemoteClient {
+    pub(super) outbox: Sender<String>,
+    pub metrics: bool,
+    pub positions: bool
+}
+
+/// WebSocket server that other programs drive the simulation through.
+#[derive(Resource)]
+pub struct RemoteControl {
+    pub port: u16,
+    pub clients: HashMap<u32, RemoteClient>,
+    /// Paused by a client, as opposed to by a replay.
+    pub paused: bool,
+    /// Frames left to run while paused.
+    pub steps: u32,
+    pub(super) inbound: Option<Mutex<Receiver<Inbound>>>,
+    pub(super) stream_timer: Timer
+}
+
+impl RemoteControl {
+    /// Without `inbound`, the server could not start and no client ever connects.
+    pub fn new(port: u16, inbound: Option<Receiver<Inbound>>) -> Self {
+        Self {
+            port,
+            clients: HashMap::default(),
+            paused: false,
+            steps: 0,
+            inbound: inbound.map(Mutex::new),
+            stream_timer: Timer::from_seconds(0.1, TimerMode::Repeating)
+        }
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.inbound.is_some()
+    }
+
+    pub(super) fn send(&self, client: u32, message: String) {
+        if let Some(remote_client) = self.clients.get(&client) {
+            let _ = remote_client.outbox.send(message);
+        }
+    }
+}
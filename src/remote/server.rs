@@ -0,0 +1,70 @@
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use tungstenite::Message;
+use super::format::Command;
+
+/// How long a client's thread waits for a command before sending what is queued for it.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What the connection threads pass on to the app.
+pub enum Inbound {
+    Connected {
+        client: u32,
+        outbox: Sender<String>
+    },
+    Command {
+        client: u32,
+        command: Result<Command, String>
+    },
+    Disconnected {
+        client: u32
+    }
+}
+
+/// Accepts WebSocket connections on localhost, each served by its own thread.
+pub fn listen(port: u16) -> std::io::Result<Receiver<Inbound>> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let (inbox, inbound) = mpsc::channel();
+    thread::spawn(move || {
+        for (client, stream) in (0..).zip(listener.incoming()) {
+            let Ok(stream) = stream else { continue };
+            let inbox = inbox.clone();
+            thread::spawn(move || serve(client, stream, inbox));
+        }
+    });
+    Ok(inbound)
+}
+
+fn serve(client: u32, stream: TcpStream, inbox: Sender<Inbound>) {
+    let Ok(mut socket) = tungstenite::accept(stream) else { return };
+    if socket.get_ref().set_read_timeout(Some(POLL_INTERVAL)).is_err() {
+        return;
+    }
+    let (outbox, outgoing) = mpsc::channel();
+    if inbox.send(Inbound::Connected { client, outbox }).is_err() {
+        return;
+    }
+    'serve: loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let command = serde_json::from_str(&text).map_err(|error| error.to_string());
+                if inbox.send(Inbound::Command { client, command }).is_err() {
+                    break;
+                }
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(error)) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break
+        }
+        for text in outgoing.try_iter() {
+            if socket.send(Message::Text(text)).is_err() {
+                break 'serve;
+            }
+        }
+    }
+    let _ = inbox.send(Inbound::Disconnected { client });
+}
@@ -0,0 +1,326 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use rand::Rng;
+use std::f32::consts::PI;
+use crate::boids_2d::components as boids_2d;
+use crate::boids_2d::systems::{spawn_boid_at as spawn_boid_2d, spawn_obstacle_2d};
+use crate::boids_3d::components as boids_3d;
+use crate::boids_3d::resources::BoidSettings3D;
+use crate::boids_3d::systems::{spawn_boid_at as spawn_boid_3d, spawn_obstacle_3d, OBSTACLE_RADIUS};
+use crate::cli::{CliMode, LiveSettings};
+use crate::input::resources::ShapeSettings;
+use crate::metrics::{FlockMetrics, Metric};
+use crate::random::SystemRng;
+use crate::replay::Replay;
+use crate::sky::birds::{spawn_bird, spawn_sky_obstacle, BirdModel, SkyBoidSettings};
+use crate::ui::resources::{EnabledModes, SimulationState};
+use crate::underwater::fish::components::{FishModels, SpeciesSettings};
+use crate::underwater::fish::spawn_fish;
+use super::components::*;
+use super::format::*;
+use super::resources::*;
+use super::server::Inbound;
+
+#[derive(SystemParam)]
+pub struct ObstacleAssets<'w> {
+    shape: Res<'w, ShapeSettings>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    color_materials: ResMut<'w, Assets<ColorMaterial>>,
+    standard_materials: ResMut<'w, Assets<StandardMaterial>>
+}
+
+/// Places an obstacle the way a click does in the mode.
+fn spawn_obstacle(commands: &mut Commands, assets: &mut ObstacleAssets, mode: &SimulationState, position: Vec3) -> Result<(), String> {
+    let radius = assets.shape.radius;
+    match mode {
+        SimulationState::Mode2D => {
+            spawn_obstacle_2d(commands, position.truncate(), Vec3::new(0.0, 0.0, 0.5), radius, &mut assets.meshes, &mut assets.color_materials);
+        }
+        SimulationState::Mode3D => {
+            spawn_obstacle_3d(commands, position, Vec3::new(0.0, 0.0, 0.5), radius, &mut assets.meshes, &mut assets.standard_materials);
+        }
+        SimulationState::Sky => {
+            spawn_sky_obstacle(commands, position, OBSTACLE_RADIUS, &mut assets.meshes, &mut assets.standard_materials);
+        }
+        SimulationState::Underwater => return Err("There are no obstacles underwater".to_string())
+    }
+    Ok(())
+}
+
+/// Handles connections and runs the commands clients sent since the last frame, answering
+/// each with `ok` or `error`.
+#[allow(clippy::too_many_arguments)]
+pub fn receive_remote_commands(
+    mut commands: Commands,
+    mut remote: ResMut<RemoteControl>,
+    mut settings: LiveSettings,
+    mut obstacle_assets: ObstacleAssets,
+    state: Res<State<SimulationState>>,
+    mut next_state: ResMut<NextState<SimulationState>>,
+    enabled_modes: Res<EnabledModes>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    replay: Res<Replay>
+) {
+    let Some(inbound) = remote.inbound.as_ref() else { return };
+    let received: Vec<Inbound> = inbound.lock().unwrap().try_iter().collect();
+
+    for message in received {
+        let (client, command) = match message {
+            Inbound::Connected { client, outbox } => {
+                info!("Remote client {client} connected");
+                remote.clients.insert(client, RemoteClient { outbox, metrics: false, positions: false });
+                continue;
+            }
+            Inbound::Disconnected { client } => {
+                info!("Remote client {client} disconnected");
+                remote.clients.remove(&client);
+                continue;
+            }
+            Inbound::Command { client, command } => (client, command)
+        };
+        let result = command.and_then(|command| {
+            let name = command.name();
+            let mode = state.get();
+            match command {
+                Command::Set { field, value } => settings.set(mode, field, value)?,
+                Command::Mode { mode } => {
+                    let mode = SimulationState::from(mode);
+                    if !enabled_modes.contains(&mode) {
+                        return Err(format!("{mode:?} is not enabled"));
+                    }
+                    next_state.set(mode);
+                }
+                Command::Obstacle { position } => spawn_obstacle(&mut commands, &mut obstacle_assets, mode, position)?,
+                Command::Emitter { position, count, rate, group, species } => {
+                    if rate <= 0.0 {
+                        return Err("The rate must be positive".to_string());
+                    }
+                    commands.spawn(Emitter::new(mode.clone(), position, group, species, rate, count));
+                }
+                Command::Pause | Command::Resume | Command::Step { .. } if replay.is_active() => {
+                    return Err("Exit the replay first".to_string());
+                }
+                Command::Pause => {
+                    remote.paused = true;
+                    virtual_time.pause();
+                }
+                Command::Resume => {
+                    remote.paused = false;
+                    remote.steps = 0;
+                    virtual_time.unpause();
+                }
+                Command::Step { frames } => {
+                    remote.paused = true;
+                    remote.steps += frames;
+                }
+                Command::Subscribe { metrics, positions } => {
+                    if let Some(remote_client) = remote.clients.get_mut(&client) {
+                        remote_client.metrics = metrics;
+                        remote_client.positions = positions;
+                    }
+                }
+            }
+            Ok(name)
+        });
+        let reply = match result {
+            Ok(command) => ServerMessage::Ok { command },
+            Err(message) => ServerMessage::Error { message }
+        };
+        remote.send(client, reply.to_json());
+    }
+}
+
+/// While a client has the simulation paused, lets virtual time run for one frame per step.
+pub fn apply_remote_stepping(
+    mut remote: ResMut<RemoteControl>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    replay: Res<Replay>
+) {
+    if !remote.paused || replay.is_active() {
+        return;
+    }
+    if remote.steps > 0 {
+        remote.steps -= 1;
+        virtual_time.unpause();
+    } else {
+        virtual_time.pause();
+    }
+}
+
+/// What emitters need to spawn boids in any mode.
+#[derive(SystemParam)]
+pub struct BoidAssets<'w> {
+    asset_server: Res<'w, AssetServer>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    standard_materials: ResMut<'w, Assets<StandardMaterial>>,
+    boids_3d: Res<'w, BoidSettings3D>,
+    sky_boids: Res<'w, SkyBoidSettings>,
+    species: Res<'w, SpeciesSettings>,
+    bird_model: Res<'w, BirdModel>,
+    fish_models: Res<'w, FishModels>
+}
+
+fn spawn_emitted_boid(commands: &mut Commands, assets: &mut BoidAssets, emitter: &Emitter, rng: &mut impl Rng) {
+    let theta = rng.gen_range(0.0..2.0 * PI);
+    let phi = rng.gen_range(0.0..PI);
+    let direction = Vec3::new(phi.sin() * theta.cos(), phi.sin() * theta.sin(), phi.cos());
+    let position = emitter.position;
+    match emitter.mode {
+        SimulationState::Mode2D => {
+            let transform = Transform::from_translation(position.truncate().extend(0.0));
+            spawn_boid_2d(commands, &assets.asset_server, emitter.group, transform, Vec2::from_angle(theta));
+        }
+        SimulationState::Mode3D => {
+            let transform = Transform::from_translation(position).with_scale(Vec3::splat(assets.boids_3d.size * 2.0));
+            spawn_boid_3d(commands, &mut assets.meshes, &mut assets.standard_materials, &assets.boids_3d, emitter.group, transform, direction);
+        }
+        SimulationState::Sky => {
+            let transform = Transform::from_translation(position).with_scale(Vec3::splat(assets.sky_boids.size * 0.3));
+            spawn_bird(commands, &assets.bird_model, emitter.group, transform, direction);
+        }
+        SimulationState::Underwater => {
+            let school_settings = &assets.species.get(emitter.species).boids;
+            spawn_fish(commands, &assets.fish_models, &emitter.species, emitter.group as usize, position, direction, school_settings);
+        }
+    }
+}
+
+/// Emitters run on virtual time, so they stop while the simulation is paused.
+pub fn run_emitters(
+    mut commands: Commands,
+    mut emitters: Query<(Entity, &mut Emitter)>,
+    mut assets: BoidAssets,
+    state: Res<State<SimulationState>>,
+    time: Res<Time>,
+    mut rng: Local<SystemRng>
+) {
+    let rng = rng.get("run_emitters");
+    for (entity, mut emitter) in emitters.iter_mut() {
+        if &emitter.mode != state.get() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        emitter.due += emitter.rate * time.delta_seconds();
+        while emitter.due >= 1.0 && emitter.remaining > 0 {
+            spawn_emitted_boid(&mut commands, &mut assets, &emitter, rng);
+            emitter.due -= 1.0;
+            emitter.remaining -= 1;
+        }
+        if emitter.remaining == 0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Sends the latest metrics and every boid's position and velocity to the clients subscribed
+/// to them, ten times a second.
+pub fn stream_to_clients(
+    mut remote: ResMut<RemoteControl>,
+    real_time: Res<Time<Real>>,
+    time: Res<Time>,
+    state: Res<State<SimulationState>>,
+    metrics: Res<FlockMetrics>,
+    boid_query_2d: Query<(&Transform, &boids_2d::Velocity, &boids_2d::Boid)>,
+    boid_query_3d: Query<(&Transform, &boids_3d::Velocity, &boids_3d::Boid)>
+) {
+    if !remote.stream_timer.tick(real_time.delta()).just_finished() {
+        return;
+    }
+    let wants_metrics = remote.clients.values().any(|client| client.metrics);
+    let wants_positions = remote.clients.values().any(|client| client.positions);
+    let time = time.elapsed_seconds_f64();
+    let mode = CliMode::from(state.get().clone());
+
+    let metrics_message = wants_metrics.then(|| ServerMessage::Metrics {
+        time,
+        mode,
+        groups: metrics.groups.iter().filter_map(|(name, series)| {
+            let latest = series.latest()?;
+            Some((name.clone(), Metric::ALL.iter().map(|metric| (metric.key(), metric.value(latest))).collect()))
+        }).collect()
+    }.to_json());
+    let positions_message = wants_positions.then(|| {
+        let boids = match state.get() {
+            SimulationState::Mode2D => boid_query_2d.iter().map(|(transform, velocity, boid)| BoidState {
+                group: boid.group,
+                position: transform.translation,
+                velocity: velocity.velocity.extend(0.0)
+            }).collect(),
+            _ => boid_query_3d.iter().map(|(transform, velocity, boid)| BoidState {
+                group: boid.group,
+                position: transform.translation,
+                velocity: velocity.velocity
+            }).collect()
+        };
+        ServerMessage::Positions { time, mode, boids }.to_json()
+    });
+
+    for client in remote.clients.values() {
+        if let (true, Some(message)) = (client.metrics, metrics_message.as_ref()) {
+            let _ = client.outbox.send(message.clone());
+        }
+        if let (true, Some(message)) = (client.positions, positions_message.as_ref()) {
+            let _ = client.outbox.send(message.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use std::net::TcpListener;
+    use std::time::Duration;
+    use tungstenite::stream::MaybeTlsStream;
+    use tungstenite::Message;
+    use crate::boids_2d::resources::BoidSettings2D;
+    use super::super::server;
+
+    fn world(remote: RemoteControl) -> World {
+        let mut world = World::new();
+        world.insert_resource(remote);
+        world.insert_resource(State::new(SimulationState::Mode2D));
+        world.init_resource::<NextState<SimulationState>>();
+        world.init_resource::<EnabledModes>();
+        world.init_resource::<Time<Virtual>>();
+        world.init_resource::<Replay>();
+        world.init_resource::<ShapeSettings>();
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Assets<ColorMaterial>>();
+        world.init_resource::<Assets<StandardMaterial>>();
+        world.init_resource::<BoidSettings2D>();
+        world.init_resource::<BoidSettings3D>();
+        world.init_resource::<SkyBoidSettings>();
+        world.init_resource::<SpeciesSettings>();
+        world
+    }
+
+    #[test]
+    fn a_local_client_sets_a_field() {
+        // Finds a free port, which the server binds again.
+        let port = TcpListener::bind(("127.0.0.1", 0)).unwrap().local_addr().unwrap().port();
+        let inbound = server::listen(port).unwrap();
+        let mut world = world(RemoteControl::new(port, Some(inbound)));
+
+        let (mut socket, _) = tungstenite::connect(format!("ws://127.0.0.1:{port}")).unwrap();
+        if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        }
+        socket.send(Message::Text(r#"{"command": "set", "field": "cohesion_coeff", "value": 12}"#.to_string())).unwrap();
+
+        // The connection thread passes the command on in its own time.
+        for _ in 0..500 {
+            world.run_system_once(receive_remote_commands);
+            if world.resource::<BoidSettings2D>().cohesion_coeff == 12.0 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(world.resource::<BoidSettings2D>().cohesion_coeff, 12.0);
+        assert_eq!(world.resource::<RemoteControl>().clients.len(), 1);
+
+        let reply = socket.read().unwrap().into_text().unwrap();
+        let reply: serde_json::Value = serde_json::from_str(&reply).unwrap();
+        assert_eq!(reply, serde_json::json!({ "type": "ok", "command": "set" }));
+    }
+}
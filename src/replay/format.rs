@@ -0,0 +1,358 @@
+//! Compact binary recording format, little-endian:
+//!
+//! ```text
+//! b"BOIDREC2"  u32 header length  JSON header (mode, version, settings)
+//! per frame:   u8 1  f32 time  u32 agent count
+//!              per agent: u32 id  u8 kind  u8 group  3 x f32 position  4 x i16 rotation  f32 scale
+//!              u8 flags  [u32 count  4 x f32 position and radius per obstacle]  [u32 count  3 x f32 per target]
+//! ```
+//!
+//! Obstacles (flag bit 0) and group targets (flag bit 1) are only written on frames where they changed.
+//! Lengths and counts are not trusted when reading: nothing is allocated for them up front.
+
+use bevy::prelude::*;
+use std::io::{self, Read, Write};
+use crate::ui::resources::SimulationState;
+
+const MAGIC: &[u8; 8] = b"BOIDREC2";
+const FRAME_TAG: u8 = 1;
+const OBSTACLES_FLAG: u8 = 1;
+const TARGETS_FLAG: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AgentKind {
+    Boid2D,
+    Boid3D,
+    Bird,
+    SmallFish,
+    MediumFish,
+    LargeFish
+}
+
+impl AgentKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            AgentKind::Boid2D => 0,
+            AgentKind::Boid3D => 1,
+            AgentKind::Bird => 2,
+            AgentKind::SmallFish => 3,
+            AgentKind::MediumFish => 4,
+            AgentKind::LargeFish => 5
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        Ok(match byte {
+            0 => AgentKind::Boid2D,
+            1 => AgentKind::Boid3D,
+            2 => AgentKind::Bird,
+            3 => AgentKind::SmallFish,
+            4 => AgentKind::MediumFish,
+            5 => AgentKind::LargeFish,
+            _ => return Err(invalid_data(format!("unknown agent kind {byte}")))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AgentSample {
+    pub id: u32,
+    pub kind: AgentKind,
+    pub group: u8,
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub scale: f32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObstacleSample {
+    pub position: Vec3,
+    pub radius: f32
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RecordedFrame {
+    pub time: f32,
+    pub agents: Vec<AgentSample>,
+    pub obstacles: Option<Vec<ObstacleSample>>,
+    pub targets: Option<Vec<Vec3>>
+}
+
+pub struct Recording {
+    pub mode: SimulationState,
+    pub header: serde_json::Value,
+    pub frames: Vec<RecordedFrame>
+}
+
+impl Recording {
+    pub fn duration(&self) -> f32 {
+        self.frames.last().map_or(0.0, |frame| frame.time)
+    }
+
+    /// Index of the last frame recorded at or before `time`.
+    pub fn frame_at(&self, time: f32) -> usize {
+        self.frames.partition_point(|frame| frame.time <= time).saturating_sub(1)
+    }
+
+    /// Obstacles and targets as they were at `frame`, found by walking back to their last change.
+    pub fn obstacles_at(&self, frame: usize) -> &[ObstacleSample] {
+        self.frames[..=frame].iter().rev().find_map(|frame| frame.obstacles.as_deref()).unwrap_or(&[])
+    }
+
+    pub fn targets_at(&self, frame: usize) -> &[Vec3] {
+        self.frames[..=frame].iter().rev().find_map(|frame| frame.targets.as_deref()).unwrap_or(&[])
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn mode_from_name(name: &str) -> io::Result<SimulationState> {
+    Ok(match name {
+        "Mode2D" => SimulationState::Mode2D,
+        "Mode3D" => SimulationState::Mode3D,
+        "Underwater" => SimulationState::Underwater,
+        "Sky" => SimulationState::Sky,
+        _ => return Err(invalid_data(format!("unknown mode {name}")))
+    })
+}
+
+pub fn write_header(writer: &mut impl Write, header: &serde_json::Value) -> io::Result<()> {
+    let header = serde_json::to_vec(header)?;
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(header.len() as u32).to_le_bytes())?;
+    writer.write_all(&header)
+}
+
+fn write_vec3(writer: &mut impl Write, value: Vec3) -> io::Result<()> {
+    for component in value.to_array() {
+        writer.write_all(&component.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_points(writer: &mut impl Write, points: &[Vec3]) -> io::Result<()> {
+    writer.write_all(&(points.len() as u32).to_le_bytes())?;
+    points.iter().try_for_each(|point| write_vec3(writer, *point))
+}
+
+pub fn write_frame(writer: &mut impl Write, frame: &RecordedFrame) -> io::Result<()> {
+    writer.write_all(&[FRAME_TAG])?;
+    writer.write_all(&frame.time.to_le_bytes())?;
+    writer.write_all(&(frame.agents.len() as u32).to_le_bytes())?;
+    for agent in frame.agents.iter() {
+        writer.write_all(&agent.id.to_le_bytes())?;
+        writer.write_all(&[agent.kind.to_byte(), agent.group])?;
+        write_vec3(writer, agent.position)?;
+        for component in agent.rotation.normalize().to_array() {
+            writer.write_all(&((component * i16::MAX as f32).round() as i16).to_le_bytes())?;
+        }
+        writer.write_all(&agent.scale.to_le_bytes())?;
+    }
+    let mut flags = 0;
+    if frame.obstacles.is_some() {
+        flags |= OBSTACLES_FLAG;
+    }
+    if frame.targets.is_some() {
+        flags |= TARGETS_FLAG;
+    }
+    writer.write_all(&[flags])?;
+    if let Some(obstacles) = frame.obstacles.as_ref() {
+        writer.write_all(&(obstacles.len() as u32).to_le_bytes())?;
+        for obstacle in obstacles.iter() {
+            write_vec3(writer, obstacle.position)?;
+            writer.write_all(&obstacle.radius.to_le_bytes())?;
+        }
+    }
+    if let Some(targets) = frame.targets.as_ref() {
+        write_points(writer, targets)?;
+    }
+    Ok(())
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    Ok(f32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_vec3(reader: &mut impl Read) -> io::Result<Vec3> {
+    Ok(Vec3::new(read_f32(reader)?, read_f32(reader)?, read_f32(reader)?))
+}
+
+fn read_points(reader: &mut impl Read) -> io::Result<Vec<Vec3>> {
+    let count = read_u32(reader)?;
+    (0..count).map(|_| read_vec3(reader)).collect()
+}
+
+fn read_obstacles(reader: &mut impl Read) -> io::Result<Vec<ObstacleSample>> {
+    let count = read_u32(reader)?;
+    (0..count).map(|_| Ok(ObstacleSample { position: read_vec3(reader)?, radius: read_f32(reader)? })).collect()
+}
+
+fn read_frame(reader: &mut impl Read) -> io::Result<RecordedFrame> {
+    let time = read_f32(reader)?;
+    let count = read_u32(reader)?;
+    let mut agents = Vec::new();
+    for _ in 0..count {
+        let id = read_u32(reader)?;
+        let [kind, group] = read_bytes(reader)?;
+        let position = read_vec3(reader)?;
+        let mut rotation = [0.0; 4];
+        for component in rotation.iter_mut() {
+            *component = i16::from_le_bytes(read_bytes(reader)?) as f32 / i16::MAX as f32;
+        }
+        agents.push(AgentSample {
+            id,
+            kind: AgentKind::from_byte(kind)?,
+            group,
+            position,
+            rotation: Quat::from_array(rotation).normalize(),
+            scale: read_f32(reader)?
+        });
+    }
+    let [flags] = read_bytes(reader)?;
+    let obstacles = if flags & OBSTACLES_FLAG != 0 { Some(read_obstacles(reader)?) } else { None };
+    let targets = if flags & TARGETS_FLAG != 0 { Some(read_points(reader)?) } else { None };
+    Ok(RecordedFrame { time, agents, obstacles, targets })
+}
+
+/// Reads a whole recording. A truncated last frame, left by a crash while recording, is dropped.
+pub fn read_recording(reader: &mut impl Read) -> io::Result<Recording> {
+    if &read_bytes::<8>(reader)? != MAGIC {
+        return Err(invalid_data("not a boidsy recording".to_string()));
+    }
+    let header_length = read_u32(reader)?;
+    let mut header = Vec::new();
+    if reader.take(header_length as u64).read_to_end(&mut header)? < header_length as usize {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated header"));
+    }
+    let header: serde_json::Value = serde_json::from_slice(&header)?;
+    let mode = mode_from_name(header["mode"].as_str().unwrap_or_default())?;
+
+    let mut frames = Vec::new();
+    loop {
+        let mut tag = [0];
+        if reader.read(&mut tag)? == 0 {
+            break;
+        }
+        if tag[0] != FRAME_TAG {
+            return Err(invalid_data(format!("unexpected tag {}", tag[0])));
+        }
+        match read_frame(reader) {
+            Ok(frame) => frames.push(frame),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(error)
+        }
+    }
+    Ok(Recording { mode, header, frames })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(id: u32, kind: AgentKind, rotation: Quat) -> AgentSample {
+        AgentSample { id, kind, group: 1, position: Vec3::new(1.5, -2.0, 3.25), rotation, scale: 0.5 }
+    }
+
+    fn encode(mode: &str, frames: &[RecordedFrame]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, &serde_json::json!({ "mode": mode, "version": "test" })).unwrap();
+        for frame in frames {
+            write_frame(&mut bytes, frame).unwrap();
+        }
+        bytes
+    }
+
+    fn frames() -> Vec<RecordedFrame> {
+        vec![
+            RecordedFrame {
+                time: 0.0,
+                agents: vec![agent(7, AgentKind::Bird, Quat::from_rotation_y(1.0)), agent(8, AgentKind::LargeFish, Quat::IDENTITY)],
+                obstacles: Some(vec![ObstacleSample { position: Vec3::X, radius: 12.5 }]),
+                targets: Some(vec![Vec3::Y, Vec3::Z])
+            },
+            RecordedFrame { time: 0.5, agents: vec![agent(7, AgentKind::Bird, Quat::from_rotation_x(-0.3))], obstacles: None, targets: None },
+            RecordedFrame { time: 1.0, agents: Vec::new(), obstacles: Some(Vec::new()), targets: None }
+        ]
+    }
+
+    #[test]
+    fn frames_survive_a_round_trip() {
+        let written = frames();
+        let recording = read_recording(&mut encode("Sky", &written).as_slice()).unwrap();
+        assert_eq!(recording.mode, SimulationState::Sky);
+        assert_eq!(recording.header["version"], "test");
+        assert_eq!(recording.frames.len(), written.len());
+        for (read, written) in recording.frames.iter().zip(written.iter()) {
+            assert_eq!(read.time, written.time);
+            assert_eq!(read.obstacles, written.obstacles);
+            assert_eq!(read.targets, written.targets);
+            assert_eq!(read.agents.len(), written.agents.len());
+            for (read, written) in read.agents.iter().zip(written.agents.iter()) {
+                assert_eq!((read.id, read.kind, read.group, read.position, read.scale), (written.id, written.kind, written.group, written.position, written.scale));
+                // Rotations are quantised to 16 bits per component.
+                assert!(read.rotation.angle_between(written.rotation) < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn obstacles_and_targets_carry_over_until_they_change() {
+        let recording = read_recording(&mut encode("Mode3D", &frames()).as_slice()).unwrap();
+        assert_eq!(recording.obstacles_at(1), &[ObstacleSample { position: Vec3::X, radius: 12.5 }]);
+        assert_eq!(recording.targets_at(2), &[Vec3::Y, Vec3::Z]);
+        assert!(recording.obstacles_at(2).is_empty());
+    }
+
+    #[test]
+    fn frames_are_found_by_time() {
+        let recording = read_recording(&mut encode("Mode2D", &frames()).as_slice()).unwrap();
+        assert_eq!(recording.duration(), 1.0);
+        assert_eq!(recording.frame_at(-1.0), 0);
+        assert_eq!(recording.frame_at(0.75), 1);
+        assert_eq!(recording.frame_at(5.0), 2);
+    }
+
+    #[test]
+    fn a_truncated_last_frame_is_dropped() {
+        let bytes = encode("Underwater", &frames());
+        let recording = read_recording(&mut &bytes[..bytes.len() - 3]).unwrap();
+        assert_eq!(recording.frames.len(), 2);
+    }
+
+    #[test]
+    fn other_files_are_refused() {
+        assert!(read_recording(&mut b"BOIDREC0\0\0\0\0".as_slice()).is_err());
+        let mut bytes = encode("Mode2D", &[]);
+        bytes.push(9);
+        assert!(read_recording(&mut bytes.as_slice()).is_err());
+        assert!(read_recording(&mut encode("Space", &[]).as_slice()).is_err());
+    }
+
+    #[test]
+    fn garbage_lengths_fail_without_allocating_them() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(u32::MAX.to_le_bytes());
+        bytes.extend(b"{}");
+        assert_eq!(read_recording(&mut bytes.as_slice()).err().map(|error| error.kind()), Some(io::ErrorKind::UnexpectedEof));
+
+        let mut bytes = encode("Mode3D", &frames()[..1]);
+        bytes.push(FRAME_TAG);
+        bytes.extend(0.5f32.to_le_bytes());
+        bytes.extend(u32::MAX.to_le_bytes());
+        bytes.extend([0; 16]);
+        let recording = read_recording(&mut bytes.as_slice()).unwrap();
+        assert_eq!(recording.frames.len(), 1);
+    }
+}
@@ -0,0 +1,40 @@
+use bevy::prelude::*;
+use crate::ui::resources::SimulationState;
+
+pub mod format;
+pub mod resources;
+pub mod systems;
+
+pub use resources::*;
+pub use systems::*;
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimulationRecorder>()
+           .init_resource::<Replay>()
+           .add_systems(OnExit(SimulationState::Mode2D), (stop_simulation_recording, exit_replay))
+           .add_systems(OnExit(SimulationState::Mode3D), (stop_simulation_recording, exit_replay))
+           .add_systems(OnExit(SimulationState::Underwater), (stop_simulation_recording, exit_replay))
+           .add_systems(OnExit(SimulationState::Sky), (stop_simulation_recording, exit_replay))
+           .add_systems(Update, (
+                toggle_simulation_recording_hotkey,
+                handle_simulation_recording_toggle
+           ).chain())
+           .add_systems(Update, record_frame_2d
+                .after(handle_simulation_recording_toggle)
+                .run_if(in_state(SimulationState::Mode2D)))
+           .add_systems(Update, record_frame_3d
+                .after(handle_simulation_recording_toggle)
+                .run_if(not(in_state(SimulationState::Mode2D))))
+           .add_systems(Update, (
+                load_replay,
+                start_replay,
+                exit_replay.run_if(exit_requested),
+                advance_replay,
+                apply_replay_frame,
+                draw_replay_markers
+           ).chain());
+    }
+}
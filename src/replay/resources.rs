@@ -0,0 +1,97 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use super::format::{ObstacleSample, Recording};
+
+#[derive(Resource)]
+pub struct SimulationRecorder {
+    pub directory: PathBuf,
+    pub toggle_requested: bool,
+    pub path: Option<PathBuf>,
+    pub frames: u64,
+    pub started_at: f32,
+    pub error: Option<String>,
+    pub(super) writer: Option<BufWriter<File>>,
+    pub(super) last_obstacles: Option<Vec<ObstacleSample>>,
+    pub(super) last_targets: Option<Vec<Vec3>>
+}
+
+impl Default for SimulationRecorder {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("recordings"),
+            toggle_requested: false,
+            path: None,
+            frames: 0,
+            started_at: 0.0,
+            error: None,
+            writer: None,
+            last_obstacles: None,
+            last_targets: None
+        }
+    }
+}
+
+impl SimulationRecorder {
+    pub fn is_recording(&self) -> bool {
+        self.writer.is_some()
+    }
+}
+
+#[derive(Resource)]
+pub struct Replay {
+    pub recording: Option<Recording>,
+    /// Recordings found in the recordings directory, listed by the UI.
+    pub files: Vec<PathBuf>,
+    /// Set by the UI, loaded once the recording's mode is active.
+    pub load_requested: Option<PathBuf>,
+    pub exit_requested: bool,
+    pub playing: bool,
+    pub speed: f32,
+    /// Playback position, in seconds since the start of the recording.
+    pub cursor: f32,
+    pub frame: usize,
+    pub step_requested: i32,
+    pub error: Option<String>,
+    pub(super) pending: Option<Recording>,
+    pub(super) proxies: HashMap<u32, Entity>,
+    pub(super) cone_mesh: Option<Handle<Mesh>>
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Self {
+            recording: None,
+            files: Vec::new(),
+            load_requested: None,
+            exit_requested: false,
+            playing: true,
+            speed: 1.0,
+            cursor: 0.0,
+            frame: 0,
+            step_requested: 0,
+            error: None,
+            pending: None,
+            proxies: HashMap::new(),
+            cone_mesh: None
+        }
+    }
+}
+
+impl Replay {
+    pub fn is_active(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn refresh_files(&mut self, directory: &Path) {
+        self.files = fs::read_dir(directory)
+            .map(|entries| entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|extension| extension == "boidrec"))
+                .collect())
+            .unwrap_or_default();
+        self.files.sort();
+    }
+}
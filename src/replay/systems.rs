@@ -0,0 +1,401 @@
+use bevy::prelude::*;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::boids_2d::components::{self as boids_2d, PlacedObstacle};
+use crate::boids_2d::resources::{BoidSettings2D, GroupsTargets as GroupsTargets2D};
+use crate::boids_3d::components as boids_3d;
+use crate::boids_3d::cone::Cone;
+use crate::boids_3d::resources::{BoidSettings3D, GroupsTargets as GroupsTargets3D};
+use crate::boids_3d::systems::{GROUP_COLORS, GROUP_EMISSIVE};
+use crate::export::components::BoidId;
+use crate::export::settings_header;
+use crate::sky::birds::{BirdModel, SkyBoidSettings};
+use crate::sky::SkySceneMarker;
+use crate::ui::resources::SimulationState;
+use crate::underwater::fish::components::{FishModels, FishType, Species, SpeciesSettings};
+use super::format::*;
+use super::resources::*;
+
+#[derive(Component)]
+pub struct ReplayProxy;
+
+pub fn toggle_simulation_recording_hotkey(
+    keys: Res<Input<KeyCode>>,
+    mut recorder: ResMut<SimulationRecorder>
+) {
+    if keys.just_pressed(KeyCode::F10) {
+        recorder.toggle_requested = true;
+    }
+}
+
+fn start_recording(recorder: &mut SimulationRecorder, header: serde_json::Value, now: f32) -> std::io::Result<()> {
+    fs::create_dir_all(&recorder.directory)?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let mode = header["mode"].as_str().unwrap_or("unknown").to_lowercase();
+    let path = recorder.directory.join(format!("replay_{mode}_{timestamp}.boidrec"));
+
+    let mut writer = BufWriter::new(File::create(&path)?);
+    write_header(&mut writer, &header)?;
+
+    info!("Recording simulation to {}", path.display());
+    recorder.writer = Some(writer);
+    recorder.path = Some(path);
+    recorder.frames = 0;
+    recorder.started_at = now;
+    recorder.last_obstacles = None;
+    recorder.last_targets = None;
+    Ok(())
+}
+
+pub fn stop_simulation_recording(mut recorder: ResMut<SimulationRecorder>) {
+    if let Some(mut writer) = recorder.writer.take() {
+        if let Err(error) = writer.flush() {
+            recorder.error = Some(error.to_string());
+        }
+        info!("Stopped recording after {} frames", recorder.frames);
+    }
+}
+
+pub fn handle_simulation_recording_toggle(
+    mut recorder: ResMut<SimulationRecorder>,
+    state: Res<State<SimulationState>>,
+    boid_settings_2d: Res<BoidSettings2D>,
+    boid_settings_3d: Res<BoidSettings3D>,
+    sky_boid_settings: Res<SkyBoidSettings>,
+    species_settings: Res<SpeciesSettings>,
+    time: Res<Time>
+) {
+    if !recorder.toggle_requested {
+        return;
+    }
+    recorder.toggle_requested = false;
+
+    if recorder.is_recording() {
+        stop_simulation_recording(recorder);
+        return;
+    }
+    let header = settings_header(state.get(), &boid_settings_2d, &boid_settings_3d, &sky_boid_settings, &species_settings);
+    recorder.error = start_recording(&mut recorder, header, time.elapsed_seconds()).err().map(|error| error.to_string());
+}
+
+/// Returns the points only when they differ from the last written ones.
+fn changed_points<T: Clone + PartialEq>(last: &mut Option<Vec<T>>, points: Vec<T>) -> Option<Vec<T>> {
+    if last.as_ref() == Some(&points) {
+        return None;
+    }
+    *last = Some(points.clone());
+    Some(points)
+}
+
+fn write_recorded_frame(
+    recorder: &mut SimulationRecorder,
+    agents: Vec<AgentSample>,
+    obstacles: Vec<ObstacleSample>,
+    targets: Vec<Vec3>,
+    now: f32
+) {
+    let frame = RecordedFrame {
+        time: now - recorder.started_at,
+        agents,
+        obstacles: changed_points(&mut recorder.last_obstacles, obstacles),
+        targets: changed_points(&mut recorder.last_targets, targets)
+    };
+    let Some(writer) = recorder.writer.as_mut() else { return };
+    if let Err(error) = write_frame(writer, &frame) {
+        error!("Simulation recording failed: {error}");
+        recorder.error = Some(error.to_string());
+        recorder.writer = None;
+        return;
+    }
+    recorder.frames += 1;
+}
+
+/// Frames are only recorded while the simulation runs, so pauses and replays leave no gap.
+fn should_record(recorder: &SimulationRecorder, time: &Time) -> bool {
+    recorder.is_recording() && time.delta_seconds() > 0.0
+}
+
+pub fn record_frame_2d(
+    boid_query: Query<(&BoidId, &Transform, &boids_2d::Boid)>,
+    obstacle_query: Query<(&Transform, &PlacedObstacle)>,
+    groups_targets: Res<GroupsTargets2D>,
+    mut recorder: ResMut<SimulationRecorder>,
+    time: Res<Time>
+) {
+    if !should_record(&recorder, &time) {
+        return;
+    }
+    let agents = boid_query.iter().map(|(boid_id, transform, boid)| AgentSample {
+        id: boid_id.id as u32,
+        kind: AgentKind::Boid2D,
+        group: boid.group,
+        position: transform.translation,
+        rotation: transform.rotation,
+        scale: transform.scale.x
+    }).collect();
+    let obstacles = obstacle_query.iter()
+        .map(|(transform, obstacle)| ObstacleSample { position: transform.translation, radius: obstacle.radius })
+        .collect();
+    let targets = groups_targets.targets.iter().map(|target| target.extend(0.0)).collect();
+    write_recorded_frame(&mut recorder, agents, obstacles, targets, time.elapsed_seconds());
+}
+
+/// Used by the 3D, sky and underwater modes. Terrain, islands and the submarine are part of the
+/// scene, so only placed obstacles are recorded, with the radius they were placed with.
+#[allow(clippy::type_complexity)]
+pub fn record_frame_3d(
+    boid_query: Query<(&BoidId, &Transform, &boids_3d::Boid, Option<&FishType>, Has<SkySceneMarker>)>,
+    obstacle_query: Query<(&Transform, &PlacedObstacle)>,
+    groups_targets: Res<GroupsTargets3D>,
+    state: Res<State<SimulationState>>,
+    mut recorder: ResMut<SimulationRecorder>,
+    time: Res<Time>
+) {
+    if !should_record(&recorder, &time) {
+        return;
+    }
+    let agents = boid_query.iter().map(|(boid_id, transform, boid, fish_type, bird)| AgentSample {
+        id: boid_id.id as u32,
+        kind: match fish_type.map(|fish_type| fish_type.species) {
+            Some(Species::SmallFish) => AgentKind::SmallFish,
+            Some(Species::MediumFish) => AgentKind::MediumFish,
+            Some(Species::LargeFish) => AgentKind::LargeFish,
+            None if bird => AgentKind::Bird,
+            None => AgentKind::Boid3D
+        },
+        group: boid.group,
+        position: transform.translation,
+        rotation: transform.rotation,
+        scale: transform.scale.x
+    }).collect();
+    let obstacles = obstacle_query.iter()
+        .map(|(transform, obstacle)| ObstacleSample { position: transform.translation, radius: obstacle.radius })
+        .collect();
+    let targets = match state.get() {
+        SimulationState::Underwater => Vec::new(),
+        _ => groups_targets.targets.clone()
+    };
+    write_recorded_frame(&mut recorder, agents, obstacles, targets, time.elapsed_seconds());
+}
+
+/// Loads the requested file, switching to the mode it was recorded in if needed.
+pub fn load_replay(
+    mut replay: ResMut<Replay>,
+    state: Res<State<SimulationState>>,
+    mut next_state: ResMut<NextState<SimulationState>>
+) {
+    let Some(path) = replay.load_requested.take() else { return };
+    let recording = File::open(&path).and_then(|file| read_recording(&mut BufReader::new(file)));
+    match recording {
+        Ok(recording) if recording.frames.is_empty() => {
+            replay.error = Some(format!("{} holds no frames", path.display()));
+        }
+        Ok(recording) => {
+            info!("Loaded {} frames from {}", recording.frames.len(), path.display());
+            if &recording.mode != state.get() {
+                next_state.set(recording.mode.clone());
+            }
+            replay.pending = Some(recording);
+            replay.error = None;
+        }
+        Err(error) => replay.error = Some(format!("{}: {error}", path.display()))
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn start_replay(
+    mut replay: ResMut<Replay>,
+    state: Res<State<SimulationState>>,
+    mut live_boids: Query<&mut Visibility, Or<(With<boids_2d::Boid>, With<boids_3d::Boid>)>>,
+    mut virtual_time: ResMut<Time<Virtual>>
+) {
+    if replay.pending.as_ref().map_or(true, |recording| &recording.mode != state.get()) {
+        return;
+    }
+    replay.recording = replay.pending.take();
+    replay.cursor = 0.0;
+    replay.frame = 0;
+    replay.playing = true;
+    virtual_time.pause();
+    for mut visibility in live_boids.iter_mut() {
+        *visibility = Visibility::Hidden;
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn exit_replay(
+    mut commands: Commands,
+    mut replay: ResMut<Replay>,
+    mut live_boids: Query<&mut Visibility, Or<(With<boids_2d::Boid>, With<boids_3d::Boid>)>>,
+    mut virtual_time: ResMut<Time<Virtual>>
+) {
+    replay.exit_requested = false;
+    if replay.recording.take().is_none() {
+        return;
+    }
+    for (_, proxy) in replay.proxies.drain() {
+        commands.entity(proxy).despawn_recursive();
+    }
+    for mut visibility in live_boids.iter_mut() {
+        *visibility = Visibility::Inherited;
+    }
+    virtual_time.unpause();
+}
+
+pub fn exit_requested(replay: Res<Replay>) -> bool {
+    replay.exit_requested
+}
+
+/// Playback runs on real time, since the simulation's virtual clock is paused during a replay.
+pub fn advance_replay(
+    mut replay: ResMut<Replay>,
+    real_time: Res<Time<Real>>
+) {
+    let replay = &mut *replay;
+    let Some(recording) = replay.recording.as_ref() else { return };
+    let last_frame = recording.frames.len() - 1;
+
+    if replay.step_requested != 0 {
+        replay.playing = false;
+        let frame = (replay.frame as i64 + replay.step_requested as i64).clamp(0, last_frame as i64) as usize;
+        replay.frame = frame;
+        replay.cursor = recording.frames[frame].time;
+        replay.step_requested = 0;
+        return;
+    }
+    if replay.playing {
+        replay.cursor += real_time.delta_seconds() * replay.speed;
+        if replay.cursor >= recording.duration() {
+            replay.cursor = recording.duration();
+            replay.playing = false;
+        }
+    }
+    replay.cursor = replay.cursor.clamp(0.0, recording.duration());
+    replay.frame = recording.frame_at(replay.cursor);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_proxy(
+    commands: &mut Commands,
+    replay: &mut Replay,
+    agent: &AgentSample,
+    transform: Transform,
+    asset_server: &AssetServer,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    bird_model: &BirdModel,
+    fish_models: &FishModels
+) -> Entity {
+    let scene = match agent.kind {
+        AgentKind::Boid2D => {
+            return commands.spawn((
+                SpriteBundle {
+                    transform,
+                    texture: asset_server.load("../assets/fish.png"),
+                    ..default()
+                },
+                ReplayProxy
+            )).id();
+        }
+        AgentKind::Boid3D => {
+            let mesh = replay.cone_mesh.get_or_insert_with(|| meshes.add(Mesh::from(Cone {
+                radius: agent.scale / 2.0,
+                ..default()
+            }))).clone();
+            let group = agent.group as usize % GROUP_COLORS.len();
+            return commands.spawn((
+                PbrBundle {
+                    mesh,
+                    material: materials.add(StandardMaterial {
+                        base_color: GROUP_COLORS[group],
+                        emissive: GROUP_EMISSIVE[group],
+                        ..default()
+                    }),
+                    transform,
+                    ..default()
+                },
+                ReplayProxy
+            )).id();
+        }
+        AgentKind::Bird => bird_model.0.clone(),
+        AgentKind::SmallFish => fish_models.small_fish.clone(),
+        AgentKind::MediumFish => fish_models.medium_fish.clone(),
+        AgentKind::LargeFish => fish_models.large_fish.clone()
+    };
+    commands.spawn((SpatialBundle::from_transform(transform), ReplayProxy))
+        .with_children(|parent| {
+            parent.spawn(SceneBundle {
+                scene,
+                transform: Transform::from_rotation(Quat::from_rotation_y(std::f32::consts::PI)),
+                ..default()
+            });
+        })
+        .id()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn apply_replay_frame(
+    mut commands: Commands,
+    mut replay: ResMut<Replay>,
+    mut proxy_query: Query<(&mut Transform, &mut Visibility), With<ReplayProxy>>,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    bird_model: Res<BirdModel>,
+    fish_models: Res<FishModels>
+) {
+    let Some(recording) = replay.recording.take() else { return };
+    let frame = &recording.frames[replay.frame];
+
+    for (_, mut visibility) in proxy_query.iter_mut() {
+        *visibility = Visibility::Hidden;
+    }
+    for agent in frame.agents.iter() {
+        let transform = Transform {
+            translation: agent.position,
+            rotation: agent.rotation,
+            scale: Vec3::splat(agent.scale)
+        };
+        match replay.proxies.get(&agent.id).and_then(|proxy| proxy_query.get_mut(*proxy).ok()) {
+            Some((mut proxy_transform, mut visibility)) => {
+                *proxy_transform = transform;
+                *visibility = Visibility::Inherited;
+            }
+            None if !replay.proxies.contains_key(&agent.id) => {
+                let proxy = spawn_proxy(&mut commands, &mut replay, agent, transform, &asset_server, &mut meshes, &mut materials, &bird_model, &fish_models);
+                replay.proxies.insert(agent.id, proxy);
+            }
+            // Spawned this frame, its commands have not been applied yet.
+            None => {}
+        }
+    }
+    replay.recording = Some(recording);
+}
+
+pub fn draw_replay_markers(
+    mut gizmos: Gizmos,
+    replay: Res<Replay>
+) {
+    let Some(recording) = replay.recording.as_ref() else { return };
+    let obstacle_color = Color::rgba(1.0, 0.5, 0.0, 0.8);
+    let target_color = Color::rgba(0.2, 1.0, 0.4, 0.8);
+    match recording.mode {
+        SimulationState::Mode2D => {
+            for obstacle in recording.obstacles_at(replay.frame) {
+                gizmos.circle_2d(obstacle.position.truncate(), obstacle.radius, obstacle_color);
+            }
+            for target in recording.targets_at(replay.frame) {
+                gizmos.circle_2d(target.truncate(), 8.0, target_color);
+            }
+        }
+        _ => {
+            for obstacle in recording.obstacles_at(replay.frame) {
+                gizmos.sphere(obstacle.position, Quat::IDENTITY, obstacle.radius, obstacle_color);
+            }
+            for target in recording.targets_at(replay.frame) {
+                gizmos.sphere(*target, Quat::IDENTITY, 5.0, target_color);
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use bevy::prelude::*;
+use crate::boids_3d::systems::{alignment, attraction_to_target, cohesion, roost_attraction, separation};
+use super::rule::*;
+
+/// Steers towards the centre of the cohesion neighbours.
+pub struct Cohesion;
+
+impl FlockingRule for Cohesion {
+    fn name(&self) -> &str {
+        "Cohesion"
+    }
+
+    fn force(&self, context: &NeighbourContext) -> Vec3 {
+        cohesion(&context.position, &context.cohesion_neighbours, &context.coefficients.cohesion)
+    }
+}
+
+/// Matches the mean velocity of the alignment neighbours.
+pub struct Alignment;
+
+impl FlockingRule for Alignment {
+    fn name(&self) -> &str {
+        "Alignment"
+    }
+
+    fn force(&self, context: &NeighbourContext) -> Vec3 {
+        alignment(&context.velocity, &context.alignment_neighbours, &context.coefficients.alignment)
+    }
+}
+
+/// Pushes away from the separation neighbours, harder the closer they are.
+pub struct Separation;
+
+impl FlockingRule for Separation {
+    fn name(&self) -> &str {
+        "Separation"
+    }
+
+    fn force(&self, context: &NeighbourContext) -> Vec3 {
+        separation(&context.position, &context.separation_neighbours, &context.coefficients.separation)
+    }
+}
+
+/// Separation of the 2D mode: linear in the offset, boosted by the collision coefficient when
+/// neighbours come closer than the minimum distance.
+pub struct Avoidance;
+
+impl FlockingRule for Avoidance {
+    fn name(&self) -> &str {
+        "Avoidance"
+    }
+
+    fn force(&self, context: &NeighbourContext) -> Vec3 {
+        let coefficients = &context.coefficients;
+        let min_distance = coefficients.min_distance_between_boids;
+        let mut avoidance_force = Vec3::ZERO;
+        for (other_position, distance) in context.separation_neighbours.iter() {
+            if *distance < min_distance {
+                let interpolation_factor = (min_distance - distance) / min_distance;
+                avoidance_force += (context.position - *other_position) * coefficients.collision * interpolation_factor;
+            } else {
+                avoidance_force += context.position - *other_position;
+            }
+        }
+        avoidance_force * coefficients.separation
+    }
+}
+
+/// Pulls towards the target, in proportion to the distance beyond its radius.
+pub struct Attraction;
+
+impl FlockingRule for Attraction {
+    fn name(&self) -> &str {
+        "Attraction"
+    }
+
+    fn force(&self, context: &NeighbourContext) -> Vec3 {
+        let Some(target) = context.target else { return Vec3::ZERO };
+        if context.target_radius > 0.0 {
+            roost_attraction(&context.position, &target, &context.target_radius, &context.coefficients.attraction)
+        } else {
+            attraction_to_target(&context.position, &target, &context.coefficients.attraction)
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use bevy::prelude::*;
+
+pub mod builtin;
+pub mod resources;
+pub mod rule;
+pub mod source;
+
+pub use resources::*;
+pub use rule::*;
+pub use source::*;
+
+pub struct RulesPlugin;
+
+impl Plugin for RulesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FlockingRules>();
+    }
+}
@@ -0,0 +1,221 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::sync::Mutex;
+use crate::ui::resources::SimulationState;
+use super::builtin::*;
+use super::rule::*;
+
+/// A rule in a mode's list, with its weight and whether it runs.
+pub struct RuleEntry {
+    pub rule: Box<dyn FlockingRule>,
+    pub weight: f32,
+    pub enabled: bool
+}
+
+/// Flocking rules of every mode, by priority. The weighted forces of the enabled rules of a mode
+/// are accumulated in list order until their sum reaches the boid's `max_force`: the rule that
+/// crosses it only adds what is left, and the rules below it add nothing.
+#[derive(Resource)]
+pub struct FlockingRules {
+    modes: HashMap<SimulationState, Vec<RuleEntry>>,
+    /// Boid whose context the flocking systems keep, for the boid inspector.
+    traced: Option<Entity>,
+    trace: Mutex<Option<NeighbourContext>>
+}
+
+impl Default for FlockingRules {
+    fn default() -> Self {
+        let mut rules = Self { modes: HashMap::default(), traced: None, trace: Mutex::new(None) };
+        // Same order as the forces were summed in before rules could be configured.
+        rules.add(SimulationState::Mode2D, Cohesion, 1.0)
+             .add(SimulationState::Mode2D, Avoidance, 1.0)
+             .add(SimulationState::Mode2D, Alignment, 1.0)
+             .add(SimulationState::Mode2D, Attraction, 1.0);
+        for mode in [SimulationState::Mode3D, SimulationState::Sky] {
+            rules.add(mode.clone(), Cohesion, 1.0)
+                 .add(mode.clone(), Separation, 1.0)
+                 .add(mode.clone(), Alignment, 1.0)
+                 .add(mode, Attraction, 1.0);
+        }
+        // Fish have no group targets, their ecology forces are added on top of the rules.
+        rules.add(SimulationState::Underwater, Cohesion, 1.0)
+             .add(SimulationState::Underwater, Separation, 1.0)
+             .add(SimulationState::Underwater, Alignment, 1.0);
+        rules
+    }
+}
+
+impl FlockingRules {
+    /// Appends a rule to the mode's list, below the others in priority.
+    pub fn add(&mut self, mode: SimulationState, rule: impl FlockingRule, weight: f32) -> &mut Self {
+        self.modes.entry(mode).or_default().push(RuleEntry { rule: Box::new(rule), weight, enabled: true });
+        self
+    }
+
+    pub fn rules_mut(&mut self, mode: &SimulationState) -> &mut Vec<RuleEntry> {
+        self.modes.entry(mode.clone()).or_default()
+    }
+
+    /// Moves a rule one place up the mode's list, so it takes its share of the force first.
+    pub fn raise(&mut self, mode: &SimulationState, index: usize) {
+        let rules = self.rules_mut(mode);
+        if index > 0 && index < rules.len() {
+            rules.swap(index - 1, index);
+        }
+    }
+
+    /// Force of the mode's enabled rules, accumulated by priority.
+    pub fn force(&self, mode: &SimulationState, context: &NeighbourContext) -> Vec3 {
+        let mut force = Vec3::ZERO;
+        self.accumulate(mode, context, |_, contribution| force += contribution);
+        force
+    }
+
+    /// What each of the mode's enabled rules adds to the force, by rule name. Rules past the
+    /// force budget add zero.
+    pub fn forces(&self, mode: &SimulationState, context: &NeighbourContext) -> Vec<(String, Vec3)> {
+        let mut forces = Vec::new();
+        self.accumulate(mode, context, |entry, contribution| forces.push((entry.rule.name().to_string(), contribution)));
+        forces
+    }
+
+    fn accumulate(&self, mode: &SimulationState, context: &NeighbourContext, mut add: impl FnMut(&RuleEntry, Vec3)) {
+        let Some(rules) = self.modes.get(mode) else { return };
+        let mut remaining = context.coefficients.max_force;
+        for entry in rules.iter().filter(|entry| entry.enabled) {
+            if remaining <= 0.0 {
+                add(entry, Vec3::ZERO);
+                continue;
+            }
+            let force = entry.rule.force(context) * entry.weight;
+            let magnitude = force.length();
+            if magnitude <= remaining {
+                add(entry, force);
+                remaining -= magnitude;
+            } else {
+                add(entry, force * (remaining / magnitude));
+                remaining = 0.0;
+            }
+        }
+    }
+
+    pub fn traced(&self) -> Option<Entity> {
+        self.traced
+    }
+
+    pub fn set_traced(&mut self, entity: Option<Entity>) {
+        self.traced = entity;
+        *self.trace.get_mut().unwrap() = None;
+    }
+
+    /// Called by the flocking systems with the context of every boid, keeps the traced one's.
+    pub fn trace(&self, entity: Entity, context: &NeighbourContext) {
+        if self.traced == Some(entity) {
+            *self.trace.lock().unwrap() = Some(context.clone());
+        }
+    }
+
+    /// Context of the traced boid from the last flocking step, if it has run since.
+    pub fn take_trace(&self) -> Option<NeighbourContext> {
+        self.trace.lock().unwrap().take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boids_2d::resources::BoidSettings2D;
+
+    /// Pushes every boid the same way.
+    struct Constant(&'static str, Vec3);
+
+    impl FlockingRule for Constant {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn force(&self, _context: &NeighbourContext) -> Vec3 {
+            self.1
+        }
+    }
+
+    fn rules() -> FlockingRules {
+        let mut rules = FlockingRules { modes: HashMap::default(), traced: None, trace: Mutex::new(None) };
+        rules.add(SimulationState::Mode3D, Constant("Up", Vec3::Y), 1.0)
+             .add(SimulationState::Mode3D, Constant("Right", Vec3::X), 2.0)
+             .add(SimulationState::Mode3D, Constant("Back", Vec3::Z), 0.5);
+        rules
+    }
+
+    fn context() -> NeighbourContext {
+        NeighbourContext::new(Vec3::ZERO, Vec3::X, 0, RuleCoefficients::from(&BoidSettings2D::default()))
+    }
+
+    #[test]
+    fn force_is_the_weighted_sum_of_the_enabled_rules() {
+        assert_eq!(rules().force(&SimulationState::Mode3D, &context()), Vec3::new(2.0, 1.0, 0.5));
+    }
+
+    #[test]
+    fn disabled_rules_add_nothing() {
+        let mut rules = rules();
+        rules.rules_mut(&SimulationState::Mode3D)[1].enabled = false;
+        assert_eq!(rules.force(&SimulationState::Mode3D, &context()), Vec3::new(0.0, 1.0, 0.5));
+        rules.rules_mut(&SimulationState::Mode3D).iter_mut().for_each(|entry| entry.enabled = false);
+        assert_eq!(rules.force(&SimulationState::Mode3D, &context()), Vec3::ZERO);
+    }
+
+    #[test]
+    fn weights_scale_each_rule() {
+        let mut rules = rules();
+        rules.rules_mut(&SimulationState::Mode3D)[0].weight = -3.0;
+        rules.rules_mut(&SimulationState::Mode3D)[2].weight = 0.0;
+        assert_eq!(rules.force(&SimulationState::Mode3D, &context()), Vec3::new(2.0, -3.0, 0.0));
+    }
+
+    #[test]
+    fn modes_have_their_own_rules() {
+        assert_eq!(rules().force(&SimulationState::Sky, &context()), Vec3::ZERO);
+    }
+
+    #[test]
+    fn rules_share_the_max_force_in_list_order() {
+        let mut context = context();
+        context.coefficients.max_force = 2.0;
+        let mut rules = rules();
+        assert_eq!(rules.force(&SimulationState::Mode3D, &context), Vec3::new(1.0, 1.0, 0.0));
+        rules.raise(&SimulationState::Mode3D, 1);
+        assert_eq!(rules.force(&SimulationState::Mode3D, &context), Vec3::new(2.0, 0.0, 0.0));
+        let forces = rules.forces(&SimulationState::Mode3D, &context);
+        assert_eq!(forces[0], ("Right".to_string(), Vec3::new(2.0, 0.0, 0.0)));
+        assert_eq!(forces[2], ("Back".to_string(), Vec3::ZERO));
+    }
+
+    #[test]
+    fn raising_the_first_rule_keeps_the_order() {
+        let mut rules = rules();
+        rules.raise(&SimulationState::Mode3D, 0);
+        let names: Vec<_> = rules.rules_mut(&SimulationState::Mode3D).iter().map(|entry| entry.rule.name().to_string()).collect();
+        assert_eq!(names, ["Up", "Right", "Back"]);
+    }
+
+    #[test]
+    fn forces_are_listed_by_enabled_rule() {
+        let mut rules = rules();
+        rules.rules_mut(&SimulationState::Mode3D)[0].enabled = false;
+        let forces = rules.forces(&SimulationState::Mode3D, &context());
+        assert_eq!(forces, vec![("Right".to_string(), Vec3::new(2.0, 0.0, 0.0)), ("Back".to_string(), Vec3::new(0.0, 0.0, 0.5))]);
+    }
+
+    #[test]
+    fn only_the_traced_boid_is_kept() {
+        let mut rules = rules();
+        let (traced, other) = (Entity::from_raw(1), Entity::from_raw(2));
+        rules.set_traced(Some(traced));
+        rules.trace(other, &context());
+        assert!(rules.take_trace().is_none());
+        rules.trace(traced, &context());
+        assert!(rules.take_trace().is_some());
+        assert!(rules.take_trace().is_none());
+    }
+}
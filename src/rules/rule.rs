@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+use crate::boids_2d::resources::BoidSettings2D;
+use crate::boids_3d::resources::BoidSettings3D;
+
+/// Colours each zone of a `NeighbourContext` is drawn in, by the debug overlay and the boid
+/// inspector alike.
+pub const SEPARATION_COLOR: Color = Color::rgb(1.0, 0.3, 0.3);
+pub const ALIGNMENT_COLOR: Color = Color::rgb(0.3, 0.6, 1.0);
+pub const COHESION_COLOR: Color = Color::rgb(0.3, 1.0, 0.4);
+
+/// Steering behaviour of a boid, given what it perceives of its neighbourhood. Rules of a mode
+/// are listed in `FlockingRules`, which weighs their forces and adds them in priority order.
+pub trait FlockingRule: Send + Sync + 'static {
+    fn name(&self) -> &str;
+
+    fn force(&self, context: &NeighbourContext) -> Vec3;
+}
+
+/// Coefficients of the built-in rules, taken from the settings of the mode.
+#[derive(Clone, Copy)]
+pub struct RuleCoefficients {
+    pub cohesion: f32,
+    pub alignment: f32,
+    pub separation: f32,
+    pub attraction: f32,
+    pub min_distance_between_boids: f32,
+    pub collision: f32,
+    /// Steering force the rules share, in list order. 2D boids have no limit.
+    pub max_force: f32
+}
+
+impl From<&BoidSettings2D> for RuleCoefficients {
+    fn from(settings: &BoidSettings2D) -> Self {
+        Self {
+            cohesion: settings.cohesion_coeff,
+            alignment: settings.alignment_coeff,
+            separation: settings.separation_coeff,
+            attraction: settings.attraction_coeff,
+            min_distance_between_boids: settings.min_distance_between_boids,
+            collision: settings.collision_coeff,
+            max_force: f32::INFINITY
+        }
+    }
+}
+
+impl From<&BoidSettings3D> for RuleCoefficients {
+    fn from(settings: &BoidSettings3D) -> Self {
+        Self {
+            cohesion: settings.cohesion_coeff,
+            alignment: settings.alignment_coeff,
+            separation: settings.separation_coeff,
+            attraction: settings.attraction_coeff,
+            min_distance_between_boids: settings.min_distance_between_boids,
+            collision: settings.collision_coeff,
+            max_force: settings.max_force
+        }
+    }
+}
+
+/// A neighbour in the field of view.
+#[derive(Clone, Copy)]
+pub struct Neighbour {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub group: u8,
+    pub distance: f32
+}
+
+/// One boid and the neighbours in its field of view, sorted into the zone they fall in. 2D boids
+/// have a zero `z`.
+#[derive(Clone)]
+pub struct NeighbourContext {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub group: u8,
+    /// Every neighbour in the field of view whatever its zone, for rules that need more than
+    /// the zones keep.
+    pub neighbours: Vec<Neighbour>,
+    /// Positions of the neighbours in the cohesion zone.
+    pub cohesion_neighbours: Vec<Vec3>,
+    /// Positions and velocities of the neighbours in the alignment zone.
+    pub alignment_neighbours: Vec<(Vec3, Vec3)>,
+    /// Positions and distances of the neighbours in the separation zone.
+    pub separation_neighbours: Vec<(Vec3, f32)>,
+    /// Where the boid is drawn to: its group's target, or the roost of a murmuration.
+    pub target: Option<Vec3>,
+    /// Distance to the target under which there is no attraction.
+    pub target_radius: f32,
+    pub coefficients: RuleCoefficients
+}
+
+impl NeighbourContext {
+    pub fn new(position: Vec3, velocity: Vec3, group: u8, coefficients: RuleCoefficients) -> Self {
+        Self {
+            position,
+            velocity,
+            group,
+            neighbours: Vec::new(),
+            cohesion_neighbours: Vec::new(),
+            alignment_neighbours: Vec::new(),
+            separation_neighbours: Vec::new(),
+            target: None,
+            target_radius: 0.0,
+            coefficients
+        }
+    }
+}
@@ -0,0 +1,22 @@
+/// What a force on a boid comes from, so that it can be told apart from the others once summed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ForceSource {
+    /// Weighted sum of the flocking rules.
+    Flocking,
+    Obstacle,
+    /// Fleeing: the cursor in 2D, a disturbance wave in the sky or larger fish underwater.
+    Fear,
+    /// Hunting and keeping clear of other species, underwater.
+    Ecology
+}
+
+impl ForceSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ForceSource::Flocking => "Flocking",
+            ForceSource::Obstacle => "Obstacle",
+            ForceSource::Fear => "Fear",
+            ForceSource::Ecology => "Ecology"
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+use rhai::{Array, Dynamic, Engine, Map};
+use crate::rules::Neighbour;
+
+/// A boid as seen by scripts, either the one the force is computed for or one of its neighbours.
+#[derive(Clone)]
+pub struct ScriptBoid {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub group: u8,
+    /// Distance to the boid the force is computed for, zero for that boid itself.
+    pub distance: f32
+}
+
+impl From<&Neighbour> for ScriptBoid {
+    fn from(neighbour: &Neighbour) -> Self {
+        Self {
+            position: neighbour.position,
+            velocity: neighbour.velocity,
+            group: neighbour.group,
+            distance: neighbour.distance
+        }
+    }
+}
+
+/// What the script sees besides the boids, updated every frame.
+#[derive(Default)]
+pub struct ScriptEnvironment {
+    pub time: f32,
+    pub delta_seconds: f32,
+    pub obstacles: Vec<Vec3>
+}
+
+impl ScriptEnvironment {
+    /// `target` is where the boid is drawn to, unit when it is drawn nowhere.
+    pub fn to_map(&self, mode: &str, target: Option<Vec3>) -> Map {
+        let mut map = Map::new();
+        map.insert("time".into(), Dynamic::from_float(self.time));
+        map.insert("dt".into(), Dynamic::from_float(self.delta_seconds));
+        map.insert("mode".into(), mode.into());
+        map.insert("target".into(), target.map_or(Dynamic::UNIT, Dynamic::from));
+        map.insert("obstacles".into(), Dynamic::from_array(self.obstacles.iter().copied().map(Dynamic::from).collect::<Array>()));
+        map
+    }
+}
+
+/// Engine with vectors and boids registered. Script numbers are `f32`, as in the simulation.
+pub fn script_engine() -> Engine {
+    let mut engine = Engine::new();
+
+    engine.register_type_with_name::<Vec3>("Vec3")
+        .register_fn("vec3", |x: f32, y: f32, z: f32| Vec3::new(x, y, z))
+        .register_get_set("x", |vector: &mut Vec3| vector.x, |vector: &mut Vec3, x: f32| vector.x = x)
+        .register_get_set("y", |vector: &mut Vec3| vector.y, |vector: &mut Vec3, y: f32| vector.y = y)
+        .register_get_set("z", |vector: &mut Vec3| vector.z, |vector: &mut Vec3, z: f32| vector.z = z)
+        .register_fn("+", |a: Vec3, b: Vec3| a + b)
+        .register_fn("-", |a: Vec3, b: Vec3| a - b)
+        .register_fn("-", |a: Vec3| -a)
+        .register_fn("*", |a: Vec3, scale: f32| a * scale)
+        .register_fn("*", |scale: f32, a: Vec3| a * scale)
+        .register_fn("/", |a: Vec3, scale: f32| a / scale)
+        .register_fn("length", |vector: &mut Vec3| vector.length())
+        .register_fn("normalize", |vector: &mut Vec3| vector.normalize_or_zero())
+        .register_fn("dot", |a: &mut Vec3, b: Vec3| a.dot(b))
+        .register_fn("cross", |a: &mut Vec3, b: Vec3| a.cross(b))
+        .register_fn("distance", |a: &mut Vec3, b: Vec3| a.distance(b))
+        .register_fn("to_string", |vector: &mut Vec3| format!("({}, {}, {})", vector.x, vector.y, vector.z))
+        .register_fn("to_debug", |vector: &mut Vec3| format!("{vector:?}"));
+
+    engine.register_type_with_name::<ScriptBoid>("Boid")
+        .register_get("position", |boid: &mut ScriptBoid| boid.position)
+        .register_get("velocity", |boid: &mut ScriptBoid| boid.velocity)
+        .register_get("group", |boid: &mut ScriptBoid| boid.group as rhai::INT)
+        .register_get("distance", |boid: &mut ScriptBoid| boid.distance);
+    engine
+}
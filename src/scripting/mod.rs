@@ -0,0 +1,20 @@
+use bevy::prelude::*;
+
+pub mod api;
+pub mod resources;
+pub mod rule;
+pub mod systems;
+
+pub use resources::*;
+pub use rule::*;
+pub use systems::*;
+
+pub struct ScriptingPlugin;
+
+impl Plugin for ScriptingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ScriptFile>()
+           .add_systems(Startup, register_scripted_rules)
+           .add_systems(PreUpdate, (reload_script, update_script_environment).chain());
+    }
+}
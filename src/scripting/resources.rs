@@ -0,0 +1,63 @@
+use bevy::prelude::*;
+use rhai::{Engine, AST};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use crate::ui::resources::SimulationState;
+use super::api::{script_engine, ScriptEnvironment};
+use super::rule::{mode_name, ScriptedRule};
+
+/// Compiled script and what it reads besides the boids, shared by the custom rule of every mode.
+pub struct Script {
+    pub(super) engine: Engine,
+    pub(super) ast: Option<AST>,
+    pub(super) environment: ScriptEnvironment,
+    pub(super) error: Option<String>
+}
+
+/// File the custom rule's script is loaded from. The rule itself is listed in `FlockingRules`
+/// with the built-in ones, which keep its weight and whether it runs.
+#[derive(Resource)]
+pub struct ScriptFile {
+    pub path: PathBuf,
+    pub reload_requested: bool,
+    pub(super) script: Arc<RwLock<Script>>,
+    pub(super) modified: Option<SystemTime>,
+    pub(super) poll_timer: Timer
+}
+
+impl Default for ScriptFile {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("scripts/custom_force.rhai"),
+            reload_requested: true,
+            script: Arc::new(RwLock::new(Script {
+                engine: script_engine(),
+                ast: None,
+                environment: ScriptEnvironment::default(),
+                error: None
+            })),
+            modified: None,
+            poll_timer: Timer::from_seconds(0.5, TimerMode::Repeating)
+        }
+    }
+}
+
+impl ScriptFile {
+    pub fn is_loaded(&self) -> bool {
+        self.script.read().unwrap().ast.is_some()
+    }
+
+    /// Why the script last failed to compile or run.
+    pub fn error(&self) -> Option<String> {
+        self.script.read().unwrap().error.clone()
+    }
+
+    /// Custom rule of a mode, running this file's script.
+    pub fn rule(&self, mode: &SimulationState) -> ScriptedRule {
+        ScriptedRule {
+            mode: mode_name(mode),
+            script: self.script.clone()
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use bevy::prelude::*;
+use rhai::{Array, Dynamic, Scope};
+use std::sync::{Arc, RwLock};
+use crate::rules::{FlockingRule, NeighbourContext};
+use crate::ui::resources::SimulationState;
+use super::api::ScriptBoid;
+use super::resources::Script;
+
+pub(super) fn mode_name(state: &SimulationState) -> &'static str {
+    match state {
+        SimulationState::Mode2D => "2d",
+        SimulationState::Mode3D => "3d",
+        SimulationState::Underwater => "underwater",
+        SimulationState::Sky => "sky"
+    }
+}
+
+impl Script {
+    fn force(&self, mode: &str, context: &NeighbourContext) -> Result<Vec3, String> {
+        let Some(ast) = self.ast.as_ref() else { return Ok(Vec3::ZERO) };
+        let boid = ScriptBoid { position: context.position, velocity: context.velocity, group: context.group, distance: 0.0 };
+        let neighbours: Array = context.neighbours.iter().map(|neighbour| Dynamic::from(ScriptBoid::from(neighbour))).collect();
+        let environment = self.environment.to_map(mode, context.target);
+        self.engine
+            .call_fn::<Vec3>(&mut Scope::new(), ast, "force", (boid, neighbours, environment))
+            .map_err(|error| error.to_string())
+    }
+}
+
+/// The force returned by the script's `fn force(boid, neighbours, env)`, given the boid's
+/// neighbours in its field of view.
+pub struct ScriptedRule {
+    pub(super) mode: &'static str,
+    pub(super) script: Arc<RwLock<Script>>
+}
+
+impl FlockingRule for ScriptedRule {
+    fn name(&self) -> &str {
+        "Custom rule"
+    }
+
+    fn force(&self, context: &NeighbourContext) -> Vec3 {
+        let result = self.script.read().unwrap().force(self.mode, context);
+        result.unwrap_or_else(|error| {
+            let mut script = self.script.write().unwrap();
+            script.error = Some(error);
+            // Stopped until the next reload, rather than failing the same way for every boid.
+            script.ast = None;
+            Vec3::ZERO
+        })
+    }
+}
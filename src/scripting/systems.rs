@@ -0,0 +1,72 @@
+use bevy::prelude::*;
+use std::fs;
+use crate::boids_2d::components::ObstacleTag;
+use crate::rules::{FlockingRules, RuleEntry};
+use crate::ui::resources::SimulationState;
+use super::resources::*;
+
+fn modified(file: &ScriptFile) -> Option<std::time::SystemTime> {
+    fs::metadata(&file.path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Adds the custom rule to every mode's list, disabled until it is turned on.
+pub fn register_scripted_rules(
+    mut rules: ResMut<FlockingRules>,
+    file: Res<ScriptFile>
+) {
+    for mode in [SimulationState::Mode2D, SimulationState::Mode3D, SimulationState::Underwater, SimulationState::Sky] {
+        let rule = Box::new(file.rule(&mode));
+        rules.rules_mut(&mode).push(RuleEntry { rule, weight: 1.0, enabled: false });
+    }
+}
+
+/// Recompiles the script when its file changes. A script that fails to compile, which is common
+/// halfway through an edit, leaves the last working one running.
+pub fn reload_script(
+    mut file: ResMut<ScriptFile>,
+    time: Res<Time<Real>>
+) {
+    let file = file.bypass_change_detection();
+    let polled = file.poll_timer.tick(time.delta()).just_finished();
+    let modified = modified(file);
+    let changed = polled && modified != file.modified;
+    if !file.reload_requested && !changed {
+        return;
+    }
+    file.reload_requested = false;
+    file.modified = modified;
+
+    let mut script = file.script.write().unwrap();
+    let result = fs::read_to_string(&file.path)
+        .map_err(|error| error.to_string())
+        .and_then(|source| script.engine.compile(source).map_err(|error| error.to_string()));
+    match result {
+        Ok(ast) if ast.iter_functions().any(|function| function.name == "force" && function.params.len() == 3) => {
+            info!("Loaded script {}", file.path.display());
+            script.ast = Some(ast);
+            script.error = None;
+        }
+        Ok(_) => script.error = Some("the script needs a `fn force(boid, neighbours, env)`".to_string()),
+        Err(error) => script.error = Some(format!("{}: {error}", file.path.display()))
+    }
+}
+
+/// Passes the time and the obstacles to the script before the flocking systems run it.
+pub fn update_script_environment(
+    file: Res<ScriptFile>,
+    obstacle_query: Query<&Transform, With<ObstacleTag>>,
+    state: Res<State<SimulationState>>,
+    time: Res<Time>
+) {
+    let mut script = file.script.write().unwrap();
+    if script.ast.is_none() {
+        return;
+    }
+    let environment = &mut script.environment;
+    environment.time = time.elapsed_seconds();
+    environment.delta_seconds = time.delta_seconds();
+    environment.obstacles = match state.get() {
+        SimulationState::Mode2D => obstacle_query.iter().map(|transform| transform.translation.truncate().extend(0.0)).collect(),
+        _ => obstacle_query.iter().map(|transform| transform.translation).collect()
+    };
+}
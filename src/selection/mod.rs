@@ -0,0 +1,28 @@
+use bevy::prelude::*;
+use crate::ui::resources::SimulationState;
+
+pub mod resources;
+pub mod systems;
+
+pub use resources::*;
+pub use systems::*;
+
+/// Middle click selects a boid, whose neighbours and forces are then reported every frame for
+/// the boid inspector.
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SelectedBoid>()
+           .add_systems(Update, (
+                select_boid_2d.run_if(in_state(SimulationState::Mode2D)),
+                select_boid_3d.run_if(not(in_state(SimulationState::Mode2D)))
+           ))
+           // After every force of the frame has been sent.
+           .add_systems(PostUpdate, (
+                inspect_boid_2d.run_if(in_state(SimulationState::Mode2D)),
+                inspect_boid_3d.run_if(not(in_state(SimulationState::Mode2D))),
+                draw_selected_boid
+           ).chain());
+    }
+}
@@ -0,0 +1,34 @@
+use bevy::prelude::*;
+use crate::rules::ForceSource;
+use crate::rules::NeighbourContext;
+
+/// What acted on the selected boid in the last frame.
+pub struct BoidReport {
+    pub group: String,
+    pub position: Vec3,
+    pub velocity: Vec3,
+    /// Its neighbours by zone, `None` when it did not flock, such as a perched bird or a paused
+    /// simulation.
+    pub context: Option<NeighbourContext>,
+    /// Weighted force of each enabled flocking rule, then the other forces by source.
+    pub forces: Vec<(String, Vec3)>
+}
+
+/// The boid picked with the middle mouse button.
+#[derive(Resource, Default)]
+pub struct SelectedBoid {
+    pub entity: Option<Entity>,
+    pub report: Option<BoidReport>
+}
+
+impl SelectedBoid {
+    pub fn select(&mut self, entity: Option<Entity>) {
+        if self.entity != entity {
+            self.entity = entity;
+            self.report = None;
+        }
+    }
+}
+
+/// Sources listed even when they did not push the boid this frame.
+pub(super) const ALWAYS_LISTED: [ForceSource; 2] = [ForceSource::Obstacle, ForceSource::Fear];
@@ -0,0 +1,209 @@
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_egui::EguiContexts;
+use crate::boids_2d::components as boids_2d;
+use crate::boids_2d::events::ApplyForceEvent as ApplyForceEvent2D;
+use crate::boids_2d::systems::SPRITE_SIZE;
+use crate::boids_3d::components as boids_3d;
+use crate::boids_3d::events::ApplyForceEvent as ApplyForceEvent3D;
+use crate::boids_3d::resources::CameraControlState;
+use crate::input::systems::cursor_position;
+use crate::metrics::group_name;
+use crate::rules::{FlockingRules, ForceSource, ALIGNMENT_COLOR, COHESION_COLOR, SEPARATION_COLOR};
+use crate::ui::resources::SimulationState;
+use crate::underwater::fish::components::FishType;
+use super::resources::*;
+
+/// Largest angle between the cursor's ray and a boid for it to be picked, as its tangent.
+const PICK_ANGLE: f32 = 0.03;
+
+const HIGHLIGHT_COLOR: Color = Color::rgb(1.0, 1.0, 0.2);
+
+/// Picks the boid under the cursor, or clears the selection when there is none.
+pub fn select_boid_2d(
+    mut selected: ResMut<SelectedBoid>,
+    mut egui_contexts: EguiContexts,
+    mouse_buttons: Res<Input<MouseButton>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    boid_query: Query<(Entity, &Transform), With<boids_2d::Boid>>
+) {
+    if !mouse_buttons.just_pressed(MouseButton::Middle) || egui_contexts.ctx_mut().is_pointer_over_area() {
+        return;
+    }
+    let Some(cursor) = cursor_position(&window_query) else { return };
+    let nearest = boid_query.iter()
+        .map(|(entity, transform)| (entity, transform.translation.truncate().distance(cursor)))
+        .filter(|(_, distance)| *distance < SPRITE_SIZE)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+    selected.select(nearest.map(|(entity, _)| entity));
+}
+
+/// Picks the boid closest to the ray under the cursor, or under the centre of the screen while
+/// the camera is grabbed.
+pub fn select_boid_3d(
+    mut selected: ResMut<SelectedBoid>,
+    mut egui_contexts: EguiContexts,
+    mouse_buttons: Res<Input<MouseButton>>,
+    camera_control: Res<CameraControlState>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    boid_query: Query<(Entity, &Transform), With<boids_3d::Boid>>
+) {
+    if !mouse_buttons.just_pressed(MouseButton::Middle) {
+        return;
+    }
+    if !camera_control.is_active && egui_contexts.ctx_mut().is_pointer_over_area() {
+        return;
+    }
+    let Ok(window) = window_query.get_single() else { return };
+    let Ok((camera, camera_transform)) = camera_query.get_single() else { return };
+    let cursor = if camera_control.is_active {
+        Some(Vec2::new(window.width(), window.height()) / 2.0)
+    } else {
+        window.cursor_position()
+    };
+    let Some(ray) = cursor.and_then(|cursor| camera.viewport_to_world(camera_transform, cursor)) else { return };
+
+    let nearest = boid_query.iter()
+        .filter_map(|(entity, transform)| {
+            let offset = transform.translation - ray.origin;
+            let along = offset.dot(ray.direction);
+            if along <= 0.0 {
+                return None;
+            }
+            let angle = (offset - ray.direction * along).length() / along;
+            (angle < PICK_ANGLE).then_some((entity, angle))
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+    selected.select(nearest.map(|(entity, _)| entity));
+}
+
+/// Keeps the traced boid of the flocking rules in step with the selection. Returns whether it
+/// was already traced, so that the last flocking step has its context.
+fn sync_trace(selected: &SelectedBoid, rules: &mut ResMut<FlockingRules>) -> bool {
+    if rules.traced() == selected.entity {
+        return true;
+    }
+    rules.set_traced(selected.entity);
+    false
+}
+
+fn report(
+    rules: &FlockingRules,
+    mode: &SimulationState,
+    group: String,
+    position: Vec3,
+    velocity: Vec3,
+    pushes: Vec<(ForceSource, Vec3)>
+) -> BoidReport {
+    let context = rules.take_trace();
+    let mut forces = context.as_ref().map_or_else(Vec::new, |context| rules.forces(mode, context));
+    // The flocking push is the sum of the rules above.
+    let mut others: Vec<(ForceSource, Vec3)> = ALWAYS_LISTED.iter().map(|source| (*source, Vec3::ZERO)).collect();
+    for (source, force) in pushes.into_iter().filter(|(source, _)| *source != ForceSource::Flocking) {
+        match others.iter_mut().find(|(other, _)| *other == source) {
+            Some((_, total)) => *total += force,
+            None => others.push((source, force))
+        }
+    }
+    forces.extend(others.into_iter().map(|(source, force)| (source.name().to_string(), force)));
+    BoidReport { group, position, velocity, context, forces }
+}
+
+pub fn inspect_boid_2d(
+    mut selected: ResMut<SelectedBoid>,
+    mut rules: ResMut<FlockingRules>,
+    mut force_events: EventReader<ApplyForceEvent2D>,
+    boid_query: Query<(&Transform, &boids_2d::Velocity, &boids_2d::Boid)>
+) {
+    let traced = sync_trace(&selected, &mut rules);
+    let Some(entity) = selected.entity else {
+        force_events.clear();
+        return;
+    };
+    let Ok((transform, velocity, boid)) = boid_query.get(entity) else {
+        selected.select(None);
+        return;
+    };
+    let pushes: Vec<(ForceSource, Vec3)> = force_events.read()
+        .filter(|event| event.entity == entity)
+        .map(|event| (event.source, event.force.extend(0.0)))
+        .collect();
+    if traced {
+        selected.report = Some(report(
+            &rules,
+            &SimulationState::Mode2D,
+            group_name(boid.group, None),
+            transform.translation.truncate().extend(0.0),
+            velocity.velocity.extend(0.0),
+            pushes
+        ));
+    }
+}
+
+pub fn inspect_boid_3d(
+    mut selected: ResMut<SelectedBoid>,
+    mut rules: ResMut<FlockingRules>,
+    mut force_events: EventReader<ApplyForceEvent3D>,
+    boid_query: Query<(&Transform, &boids_3d::Velocity, &boids_3d::Boid, Option<&FishType>)>,
+    state: Res<State<SimulationState>>
+) {
+    let traced = sync_trace(&selected, &mut rules);
+    let Some(entity) = selected.entity else {
+        force_events.clear();
+        return;
+    };
+    let Ok((transform, velocity, boid, fish_type)) = boid_query.get(entity) else {
+        selected.select(None);
+        return;
+    };
+    let pushes: Vec<(ForceSource, Vec3)> = force_events.read()
+        .filter(|event| event.entity == entity)
+        .map(|event| (event.source, event.force))
+        .collect();
+    if traced {
+        selected.report = Some(report(
+            &rules,
+            state.get(),
+            group_name(boid.group, fish_type),
+            transform.translation,
+            velocity.velocity,
+            pushes
+        ));
+    }
+}
+
+/// Rings the selected boid and draws a line to each of its neighbours, coloured by zone.
+pub fn draw_selected_boid(
+    mut gizmos: Gizmos,
+    selected: Res<SelectedBoid>,
+    transform_query: Query<&Transform>,
+    state: Res<State<SimulationState>>
+) {
+    let Some(report) = selected.report.as_ref() else { return };
+    let Some(transform) = selected.entity.and_then(|entity| transform_query.get(entity).ok()) else { return };
+    let neighbours: Vec<(Vec3, Color)> = report.context.as_ref().map_or_else(Vec::new, |context| {
+        context.separation_neighbours.iter().map(|(position, _)| (*position, SEPARATION_COLOR))
+            .chain(context.alignment_neighbours.iter().map(|(position, _)| (*position, ALIGNMENT_COLOR)))
+            .chain(context.cohesion_neighbours.iter().map(|position| (*position, COHESION_COLOR)))
+            .collect()
+    });
+
+    match state.get() {
+        SimulationState::Mode2D => {
+            let position = transform.translation.truncate();
+            gizmos.circle_2d(position, SPRITE_SIZE * 0.75, HIGHLIGHT_COLOR);
+            for (neighbour, color) in neighbours {
+                gizmos.line_2d(position, neighbour.truncate(), color);
+            }
+        }
+        _ => {
+            let position = transform.translation;
+            let radius = (transform.scale.max_element() * 2.0).max(2.0);
+            gizmos.sphere(position, Quat::IDENTITY, radius, HIGHLIGHT_COLOR);
+            for (neighbour, color) in neighbours {
+                gizmos.line(position, neighbour, color);
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! How settings types are shown to the user. Settings implement `SettingsHints` and register it
+//! with `#[reflect(SettingsHints)]`, and the UI's inspector lays their fields out from it.
+
+use bevy::reflect::FromType;
+use crate::ui::resources::SimulationState;
+
+/// How the inspector shows a settings field: its label, the bounds of its slider, the unit
+/// after the value and the text shown on hover.
+#[derive(Clone, Copy)]
+pub struct FieldHints {
+    pub label: &'static str,
+    pub range: Option<(f64, f64)>,
+    pub unit: &'static str,
+    pub tooltip: &'static str,
+    /// Field whose value is the upper bound of this one, such as the alignment range which
+    /// cannot exceed the cohesion range.
+    pub capped_by: Option<&'static str>,
+    pub hidden: bool
+}
+
+impl FieldHints {
+    pub const fn new(label: &'static str) -> Self {
+        Self { label, range: None, unit: "", tooltip: "", capped_by: None, hidden: false }
+    }
+
+    /// Not shown, for bookkeeping fields such as `previous_count`.
+    pub const fn hidden() -> Self {
+        Self { hidden: true, ..Self::new("") }
+    }
+
+    pub const fn range(self, min: f64, max: f64) -> Self {
+        Self { range: Some((min, max)), ..self }
+    }
+
+    pub const fn unit(self, unit: &'static str) -> Self {
+        Self { unit, ..self }
+    }
+
+    pub const fn tooltip(self, tooltip: &'static str) -> Self {
+        Self { tooltip, ..self }
+    }
+
+    pub const fn capped_by(self, field: &'static str) -> Self {
+        Self { capped_by: Some(field), ..self }
+    }
+
+    pub const fn label(self, label: &'static str) -> Self {
+        Self { label, ..self }
+    }
+}
+
+/// Metadata of a settings type for the inspector. Fields without hints are shown with a label
+/// made from their name and a drag value instead of a slider.
+pub trait SettingsHints {
+    fn field_hints(field: &str) -> Option<FieldHints>;
+
+    /// Mode whose settings window shows the resource. Settings that are not resources of their
+    /// own, such as those of a fish species, are shown by their owner.
+    fn mode() -> Option<SimulationState> {
+        None
+    }
+}
+
+pub(crate) type HintsFn = fn(&str) -> Option<FieldHints>;
+
+/// Type data of `SettingsHints`, registered with `#[reflect(SettingsHints)]`.
+#[derive(Clone)]
+pub struct ReflectSettingsHints {
+    field_hints: HintsFn,
+    mode: fn() -> Option<SimulationState>
+}
+
+impl<T: SettingsHints> FromType<T> for ReflectSettingsHints {
+    fn from_type() -> Self {
+        Self { field_hints: T::field_hints, mode: T::mode }
+    }
+}
+
+impl ReflectSettingsHints {
+    pub fn field_hints(&self) -> HintsFn {
+        self.field_hints
+    }
+
+    pub fn mode(&self) -> Option<SimulationState> {
+        (self.mode)()
+    }
+}
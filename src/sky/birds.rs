@@ -2,7 +2,8 @@ use bevy::prelude::*;
 use bevy::window::PrimaryWindow;
 use bevy_spatial::SpatialAccess;
 use rand::Rng;
-use crate::boids_2d::components::ObstacleTag;
+use serde::{Deserialize, Serialize};
+use crate::boids_2d::components::{ObstacleTag, PlacedObstacle};
 use crate::boids_3d::events::ApplyForceEvent;
 use crate::boids_3d::resources::GroupsTargets;
 use crate::boids_3d::{
@@ -12,14 +13,80 @@ use crate::boids_3d::{
     systems::*,
 };
 use crate::kd_tree_3d::components::{NNTree3D, TrackedByKDTree3D};
+use crate::rules::{FlockingRules, ForceSource, Neighbour, NeighbourContext, RuleCoefficients};
+use crate::random::SystemRng;
+use crate::settings::{FieldHints, ReflectSettingsHints, SettingsHints};
 use crate::ui::resources::SimulationState;
 use super::SkySceneMarker;
+use super::perching::{InFlight, Perched, Stamina};
+use super::murmuration::MurmurationSettings;
 use std::ops::{Deref, DerefMut};
 
 #[derive(Resource)]
-pub struct BirdModel(Handle<Scene>);
+pub struct BirdModel(pub Handle<Scene>);
 
 #[derive(Resource)]
+pub struct BirdAnimation(Handle<AnimationClip>);
+
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub struct FlightModelSettings {
+    pub enabled: bool,
+    pub gravity: f32,
+    pub lift_coeff: f32,
+    pub drag_coeff: f32,
+    pub stall_speed: f32,
+    pub cruise_speed: f32,
+    pub flap_thrust: f32,
+    pub flap_lift: f32,
+    pub max_energy: f32,
+    pub flap_energy_cost: f32,
+    pub glide_energy_recovery: f32,
+    pub flap_resume_energy: f32
+}
+
+impl Default for FlightModelSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            gravity: 98.0,
+            lift_coeff: 0.008,
+            drag_coeff: 0.002,
+            stall_speed: 40.0,
+            cruise_speed: 90.0,
+            flap_thrust: 60.0,
+            flap_lift: 40.0,
+            max_energy: 100.0,
+            flap_energy_cost: 20.0,
+            glide_energy_recovery: 10.0,
+            flap_resume_energy: 40.0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FlightState {
+    Flapping,
+    Gliding
+}
+
+#[derive(Component, Debug)]
+pub struct Flight {
+    pub state: FlightState,
+    pub energy: f32
+}
+
+impl Default for Flight {
+    fn default() -> Self {
+        Self {
+            state: FlightState::Gliding,
+            energy: FlightModelSettings::default().max_energy
+        }
+    }
+}
+
+#[derive(Resource, Clone, Serialize, Deserialize, Reflect)]
+#[reflect(Resource, SettingsHints)]
+#[serde(transparent)]
 pub struct SkyBoidSettings(BoidSettings3D);
 
 impl Default for SkyBoidSettings {
@@ -41,10 +108,32 @@ impl Default for SkyBoidSettings {
             bounce_against_walls: true,
             attraction_coeff: 1.0,
             field_of_view: 90.0,
+            max_force: 1000.0,
+            max_angular_velocity: 180.0,
+            banking_coeff: 0.004,
+            max_bank_angle: 70.0,
         })
     }
 }
 
+/// Birds are kept in the sky by their own confinement, which has no walls to bounce on.
+impl SettingsHints for SkyBoidSettings {
+    fn field_hints(field: &str) -> Option<FieldHints> {
+        let hints = BoidSettings3D::field_hints(field)?;
+        Some(match field {
+            "bounce_against_walls" => FieldHints::hidden(),
+            "count" => hints.label("Birds count"),
+            "size" => hints.label("Birds size"),
+            "min_distance_between_boids" => hints.label("Minimum distance between birds"),
+            _ => hints
+        })
+    }
+
+    fn mode() -> Option<SimulationState> {
+        Some(SimulationState::Sky)
+    }
+}
+
 impl Deref for SkyBoidSettings {
     type Target = BoidSettings3D;
 
@@ -64,6 +153,8 @@ pub struct BirdsPlugin;
 impl Plugin for BirdsPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<SkyBoidSettings>()
+           .register_type::<SkyBoidSettings>()
+           .init_resource::<FlightModelSettings>()
            .add_systems(Startup, load_bird_model)
            .add_systems(OnEnter(SimulationState::Sky), spawn_sky_birds)
            .add_systems(Update, (
@@ -75,100 +166,223 @@ impl Plugin for BirdsPlugin {
                 resize_boids,
                 avoid_obstacles,
                 confine_birds_movement,
-                handle_mouse_input
+                handle_mouse_input,
+                animate_wing_beats
            ).run_if(in_state(SimulationState::Sky)));
     }
 }
 
-fn apply_sky_flocking(
-    boid_query: Query<(Entity, &Transform, &Velocity, &Boid), With<SkySceneMarker>>,
+#[allow(clippy::type_complexity)]
+pub fn apply_sky_flocking(
+    boid_query: Query<(Entity, &Transform, &Velocity, &Boid), (With<SkySceneMarker>, InFlight)>,
     event_writer: EventWriter<ApplyForceEvent>,
     boid_settings: Res<SkyBoidSettings>,
     groups_targets: Res<GroupsTargets>,
+    murmuration: Res<MurmurationSettings>,
+    rules: Res<FlockingRules>,
     kd_tree: Res<NNTree3D>,
 ) {
     let cohesion_range = boid_settings.cohesion_range;
     let alignment_range = boid_settings.alignment_range;
     let separation_range = boid_settings.separation_range;
+    let mut coefficients = RuleCoefficients::from(&**boid_settings);
+    if murmuration.enabled {
+        coefficients.attraction = murmuration.roost_coeff;
+    }
 
     let event_writer = std::sync::Mutex::new(event_writer);
 
     boid_query.par_iter().for_each(|(entity, transform, velocity, boid)| {
         let position = transform.translation;
-        let mut cohesion_neighbors: Vec<Vec3> = Vec::new();
-        let mut repulsion_neighbors: Vec<(Vec3, f32)> = Vec::new();
-        let mut alignment_neighbors: Vec<Vec3> = Vec::new();
+        let mut context = NeighbourContext::new(position, velocity.velocity, boid.group, coefficients);
+
+        // Murmurations interact with a fixed number of nearest neighbours whatever their distance.
+        let neighbors = if murmuration.enabled {
+            kd_tree.k_nearest_neighbour(position, murmuration.topological_neighbours + 1)
+        } else {
+            kd_tree.within_distance(position, cohesion_range)
+        };
 
-        for (_, neighbor_entity) in kd_tree.within_distance(position, cohesion_range) {
+        for (_, neighbor_entity) in neighbors {
             let neighbor_entity = neighbor_entity.unwrap();
             if neighbor_entity == entity { continue; }
 
-            if let Ok((_, neighbor_transform, neighbor_velocity, _)) = boid_query.get(neighbor_entity) {
+            if let Ok((_, neighbor_transform, neighbor_velocity, neighbor_boid)) = boid_query.get(neighbor_entity) {
                 let neighbor_pos = neighbor_transform.translation;
                 if let Some(distance) = is_in_field_of_view(&position, &velocity.velocity, &neighbor_pos, &boid_settings.field_of_view) {
+                    context.neighbours.push(Neighbour {
+                        position: neighbor_pos,
+                        velocity: neighbor_velocity.velocity,
+                        group: neighbor_boid.group,
+                        distance
+                    });
                     if distance < separation_range {
-                        repulsion_neighbors.push((neighbor_pos, distance));
+                        context.separation_neighbours.push((neighbor_pos, distance));
+                    } else if murmuration.enabled {
+                        context.alignment_neighbours.push((neighbor_pos, neighbor_velocity.velocity));
+                        context.cohesion_neighbours.push(neighbor_pos);
                     } else if distance < alignment_range {
-                        alignment_neighbors.push(neighbor_velocity.velocity);
+                        context.alignment_neighbours.push((neighbor_pos, neighbor_velocity.velocity));
                     } else if distance < cohesion_range {
-                        cohesion_neighbors.push(neighbor_pos);
+                        context.cohesion_neighbours.push(neighbor_pos);
                     }
                 }
             }
         }
 
-        let cohesion_force = cohesion(&position, &cohesion_neighbors, &boid_settings.cohesion_coeff);
-        let separation_force = separation(&position, &repulsion_neighbors, &boid_settings.separation_coeff);
-        let alignment_force = alignment(&velocity.velocity, &alignment_neighbors, &boid_settings.alignment_coeff);
-        let target = groups_targets.targets[boid.group as usize];
-        let attraction_force = attraction_to_target(&position, &target, &boid_settings.attraction_coeff);
-        let total_force = cohesion_force + separation_force + alignment_force + attraction_force;
+        // Murmurations are drawn to their roost rather than to the group targets.
+        if murmuration.enabled {
+            context.target = Some(murmuration.roost);
+            context.target_radius = murmuration.roost_radius;
+        } else {
+            context.target = Some(groups_targets.targets[boid.group as usize]);
+        }
+        rules.trace(entity, &context);
+        let total_force = rules.force(&SimulationState::Sky, &context);
 
         let mut event_writer = event_writer.lock().unwrap();
         event_writer.send(ApplyForceEvent {
             entity,
-            force: total_force 
+            force: total_force,
+            source: ForceSource::Flocking
         });
     });
 }
 
+#[allow(clippy::type_complexity)]
 pub fn update_birds_position(
-    mut boid_query: Query<(&mut Transform, &mut Velocity, &mut Acceleration), With<Boid>>,
+    mut boid_query: Query<(&mut Transform, &mut Velocity, &mut Acceleration, Option<&mut Flight>), (With<Boid>, InFlight)>,
     boid_settings: Res<SkyBoidSettings>,
+    flight_settings: Res<FlightModelSettings>,
     time: Res<Time>
 ) {
-    for (mut transform, mut velocity, mut acceleration) in boid_query.iter_mut() {
-        velocity.velocity += acceleration.acceleration * time.delta_seconds();
-        
-        let speed = velocity.velocity.length();
-        if speed < boid_settings.min_speed {
-            velocity.velocity = velocity.velocity.normalize() * boid_settings.min_speed;
-        } else if speed > boid_settings.max_speed {
-            velocity.velocity = velocity.velocity.normalize() * boid_settings.max_speed;
+    for (mut transform, mut velocity, mut acceleration, flight) in boid_query.iter_mut() {
+        match flight {
+            Some(mut flight) if flight_settings.enabled => {
+                integrate_flight(&mut transform, &mut velocity, &mut acceleration, &mut flight, &boid_settings, &flight_settings, time.delta_seconds());
+            }
+            _ => {
+                integrate_boid(&mut transform, &mut velocity, &mut acceleration, &boid_settings, time.delta_seconds());
+            }
         }
+    }
+}
+
+/// Point-mass flight: lift grows with the square of the speed and acts perpendicular to the
+/// velocity, drag opposes it and gravity pulls down. Flapping adds thrust and lift at the cost of
+/// energy, gliding recovers it. There is no speed clamp, so slow birds stall and fast ones dive.
+pub fn integrate_flight(
+    transform: &mut Transform,
+    velocity: &mut Velocity,
+    acceleration: &mut Acceleration,
+    flight: &mut Flight,
+    boid_settings: &BoidSettings3D,
+    flight_settings: &FlightModelSettings,
+    delta_seconds: f32
+) {
+    if delta_seconds <= 0.0 {
+        acceleration.acceleration = Vec3::ZERO;
+        return;
+    }
+
+    let previous_velocity = velocity.velocity;
+    let speed = previous_velocity.length();
+    let forward = previous_velocity.try_normalize().unwrap_or(Vec3::X);
+    let mut steering = acceleration.acceleration.clamp_length_max(boid_settings.max_force);
+
+    update_flight_state(flight, speed, steering.y, flight_settings, delta_seconds);
+
+    if flight.state == FlightState::Gliding {
+        steering.y = steering.y.min(0.0);
+    }
 
-        transform.translation += velocity.velocity * time.delta_seconds();
-        
-        if velocity.velocity.length_squared() > 0.0 {
-            let forward = -velocity.velocity.normalize();
-            transform.rotation = Quat::from_rotation_arc(Vec3::Z, forward);
+    let up = (Vec3::Y - forward * forward.y).normalize_or_zero();
+    let mut lift_coeff = flight_settings.lift_coeff;
+    if speed < flight_settings.stall_speed {
+        lift_coeff *= (speed / flight_settings.stall_speed).powi(2);
+    }
+    let lift = up * lift_coeff * speed * speed;
+    let drag = -forward * flight_settings.drag_coeff * speed * speed;
+    let gravity = Vec3::NEG_Y * flight_settings.gravity;
+    let thrust = match flight.state {
+        FlightState::Flapping => forward * flight_settings.flap_thrust + up * flight_settings.flap_lift,
+        FlightState::Gliding => Vec3::ZERO
+    };
+
+    velocity.velocity += (steering + lift + drag + gravity + thrust) * delta_seconds;
+    let max_turn = boid_settings.max_angular_velocity.to_radians() * delta_seconds;
+    velocity.velocity = limit_turn(previous_velocity, velocity.velocity, max_turn);
+
+    transform.translation += velocity.velocity * delta_seconds;
+    orient_along_velocity(transform, previous_velocity, velocity.velocity, boid_settings, delta_seconds);
+
+    acceleration.acceleration = Vec3::ZERO;
+}
+
+fn update_flight_state(
+    flight: &mut Flight,
+    speed: f32,
+    vertical_steering: f32,
+    flight_settings: &FlightModelSettings,
+    delta_seconds: f32
+) {
+    match flight.state {
+        FlightState::Flapping => {
+            flight.energy -= flight_settings.flap_energy_cost * delta_seconds;
+            let fast_enough = speed > flight_settings.cruise_speed && vertical_steering <= 0.0;
+            if flight.energy <= 0.0 || fast_enough {
+                flight.state = FlightState::Gliding;
+            }
         }
+        FlightState::Gliding => {
+            flight.energy += flight_settings.glide_energy_recovery * delta_seconds;
+            let needs_power = speed < flight_settings.cruise_speed || vertical_steering > 0.0;
+            if needs_power && flight.energy >= flight_settings.flap_resume_energy {
+                flight.state = FlightState::Flapping;
+            }
+        }
+    }
+    flight.energy = flight.energy.clamp(0.0, flight_settings.max_energy);
+}
 
-        acceleration.acceleration = Vec3::ZERO;
+fn animate_wing_beats(
+    flight_settings: Res<FlightModelSettings>,
+    bird_animation: Res<BirdAnimation>,
+    flight_query: Query<(&Flight, Has<Perched>)>,
+    parent_query: Query<&Parent>,
+    mut player_query: Query<(Entity, &mut AnimationPlayer)>
+) {
+    for (entity, mut player) in player_query.iter_mut() {
+        let Some((flight, perched)) = parent_query.iter_ancestors(entity).find_map(|ancestor| flight_query.get(ancestor).ok()) else {
+            continue;
+        };
+        if player.animation_clip() != &bird_animation.0 {
+            player.play(bird_animation.0.clone()).repeat();
+        }
+        if perched {
+            player.pause();
+        } else if !flight_settings.enabled || flight.state == FlightState::Flapping {
+            player.resume();
+        } else {
+            player.pause();
+        }
     }
 }
 
 fn load_bird_model(mut commands: Commands, asset_server: Res<AssetServer>) {
     let model = asset_server.load("models/bird/scene.gltf#Scene0");
     commands.insert_resource(BirdModel(model));
+    let animation = asset_server.load("models/bird/scene.gltf#Animation0");
+    commands.insert_resource(BirdAnimation(animation));
 }
 
 fn spawn_sky_birds(
     mut commands: Commands,
     bird_model: Res<BirdModel>,
     boid_settings: Res<SkyBoidSettings>,
+    mut rng: Local<SystemRng>,
 ) {
-    let mut rng = rand::thread_rng();
+    let rng = rng.get("spawn_sky_birds");
     
     for _ in 0..boid_settings.count {
         let group = rng.gen_range(0..2);
@@ -178,40 +392,59 @@ fn spawn_sky_birds(
             rng.gen_range(-50.0..50.0)
         );
 
-        commands.spawn((
-            BoidBundle {
-                boid: Boid { group },
-                velocity: Velocity { velocity: Vec3::new(1.0, 0.0, 0.0) },
-                acceleration: Acceleration { acceleration: Vec3::ZERO },
-                pbr_bundle: PbrBundle {
-                    transform: Transform::from_translation(random_pos)
-                        .with_scale(Vec3::splat(boid_settings.size * 0.3)),
-                    ..default()
-                },
-                tracked_by_kdtree: TrackedByKDTree3D,
-            },
-            SkySceneMarker,
-        ))
-        .with_children(|parent| {
-            parent.spawn(SceneBundle {
-                scene: bird_model.0.clone(),
-                transform: Transform::from_rotation(Quat::from_rotation_y(std::f32::consts::PI)),
+        let transform = Transform::from_translation(random_pos)
+            .with_scale(Vec3::splat(boid_settings.size * 0.3));
+        spawn_bird(&mut commands, &bird_model, group, transform, Vec3::new(1.0, 0.0, 0.0));
+    }
+}
+
+pub fn spawn_bird(
+    commands: &mut Commands,
+    bird_model: &BirdModel,
+    group: u8,
+    transform: Transform,
+    velocity: Vec3
+) -> Entity {
+    commands.spawn((
+        BoidBundle {
+            boid: Boid { group },
+            velocity: Velocity { velocity },
+            acceleration: Acceleration { acceleration: Vec3::ZERO },
+            pbr_bundle: PbrBundle {
+                transform,
                 ..default()
-            });
+            },
+            tracked_by_kdtree: TrackedByKDTree3D,
+        },
+        Flight::default(),
+        Stamina::default(),
+        SkySceneMarker,
+    ))
+    .with_children(|parent| {
+        parent.spawn(SceneBundle {
+            scene: bird_model.0.clone(),
+            transform: Transform::from_rotation(Quat::from_rotation_y(std::f32::consts::PI)),
+            ..default()
         });
+    })
+    .id()
+}
+
+pub fn sky_confinement() -> Confinement {
+    let margin = BOUNDS_SIZE * 0.2;
+    Confinement {
+        min: Vec3::new(-BOUNDS_SIZE + margin, margin, -BOUNDS_SIZE + margin),
+        max: Vec3::new(BOUNDS_SIZE - margin, 75.0 - margin, BOUNDS_SIZE - margin),
+        margin
     }
 }
 
 pub fn confine_birds_movement (
     mut boid_query: Query<(&mut Transform, &mut Velocity, &mut Acceleration), With<Boid>>
 ) {
-    let margin = BOUNDS_SIZE * 0.2;
-    let x_min = -BOUNDS_SIZE + margin;
-    let y_min = margin;
-    let z_min = -BOUNDS_SIZE + margin;
-    let x_max = BOUNDS_SIZE - margin;
-    let y_max = 75.0 - margin;
-    let z_max = BOUNDS_SIZE - margin;
+    let Confinement { min, max, margin } = sky_confinement();
+    let (x_min, y_min, z_min) = min.into();
+    let (x_max, y_max, z_max) = max.into();
     for (transform, mut velocity, _) in boid_query.iter_mut() {
         let turn_factor: f32 = 10.0;
         if transform.translation.x > x_max - margin {
@@ -240,13 +473,14 @@ fn adjust_population_birds(
     mut boid_settings: ResMut<SkyBoidSettings>,
     query: Query<Entity, (With<Boid>, With<SkySceneMarker>)>,
     bird_model: Res<BirdModel>,
+    mut rng: Local<SystemRng>,
 ) {
     let current = boid_settings.count;
     let previous = boid_settings.previous_count;
 
     if current > previous {
+        let rng = rng.get("adjust_population_birds");
         for _ in 0..(current - previous) {
-            let mut rng = rand::thread_rng();
             let pos = Vec3::new(
                 rng.gen_range(-BOUNDS_SIZE..BOUNDS_SIZE),
                 rng.gen_range(-50.0..50.0),
@@ -265,6 +499,8 @@ fn adjust_population_birds(
                     },
                     tracked_by_kdtree: TrackedByKDTree3D,
                 },
+                Flight::default(),
+                Stamina::default(),
                 SkySceneMarker,
             ))
             .with_children(|parent| {
@@ -284,19 +520,21 @@ fn adjust_population_birds(
     boid_settings.previous_count = current;
 }
 
+/// Obstacles of the scene are avoided as if they had the default obstacle radius.
 pub fn avoid_obstacles(
-    mut boid_query: Query<(Entity, &Transform, &mut Velocity), With<Boid>>,
+    mut boid_query: Query<(Entity, &Transform, &mut Velocity), (With<Boid>, InFlight)>,
     mut event_writer: EventWriter<ApplyForceEvent>,
-    obstacles_query: Query<(&Transform, &ObstacleTag)>) {
+    obstacles_query: Query<(&Transform, Option<&PlacedObstacle>), With<ObstacleTag>>) {
     for (entity, transform, mut velocity) in boid_query.iter_mut() {
         let position = transform.translation;
         let mut avoidance_force: Vec3 = Vec3::ZERO;
-        let obstacle_avoidance_range = 50.0;
+        let obstacle_avoidance_range = OBSTACLE_AVOIDANCE_RANGE;
         let obstacle_avoidance_coeff: f32 = 10.0;
         let turn_factor: f32 = 20.0;
-        for (obstacle_transform, _) in obstacles_query.iter() {
+        for (obstacle_transform, placed) in obstacles_query.iter() {
             let obstacle_position = obstacle_transform.translation;
-            let distance = position.distance(obstacle_position) - 10.0;
+            let radius = placed.map_or(OBSTACLE_RADIUS, |placed| placed.radius);
+            let distance = position.distance(obstacle_position) - radius;
             if distance < obstacle_avoidance_range {
                 let direction = (position - obstacle_position).normalize();
                 let interpolation_factor = (obstacle_avoidance_range - distance) / obstacle_avoidance_range;
@@ -307,7 +545,8 @@ pub fn avoid_obstacles(
         }
         event_writer.send(ApplyForceEvent {
             entity: entity,
-            force: avoidance_force
+            force: avoidance_force,
+            source: ForceSource::Obstacle
         });
     }
 }
@@ -322,27 +561,38 @@ fn handle_mouse_input(
 ) {
     if mouse_buttons.just_pressed(MouseButton::Left) {
         if let Some(position) = get_mouse_world_position(&windows, &camera_query) {
-            let obstacle_material = materials.add(StandardMaterial {
-                base_color: Color::rgb(0.8, 0.2, 0.2),
-                metallic: 0.0,
-                perceptual_roughness: 0.9,
-                ..default()
-            });
-
-            commands.spawn((
-                PbrBundle {
-                    mesh: meshes.add(Mesh::from(shape::Cube { size: 50.0 })),
-                    material: obstacle_material,
-                    transform: Transform::from_translation(position),
-                    ..default()
-                },
-                ObstacleTag,
-                SkySceneMarker
-            ));
+            spawn_sky_obstacle(&mut commands, position, OBSTACLE_RADIUS, &mut meshes, &mut materials);
         }
     }
 }
 
+pub fn spawn_sky_obstacle(
+    commands: &mut Commands,
+    position: Vec3,
+    radius: f32,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>
+) {
+    let obstacle_material = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.8, 0.2, 0.2),
+        metallic: 0.0,
+        perceptual_roughness: 0.9,
+        ..default()
+    });
+
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Cube { size: radius * 5.0 })),
+            material: obstacle_material,
+            transform: Transform::from_translation(position),
+            ..default()
+        },
+        ObstacleTag,
+        PlacedObstacle { radius },
+        SkySceneMarker
+    ));
+}
+
 fn get_mouse_world_position(
     windows: &Query<&Window, With<PrimaryWindow>>,
     camera_query: &Query<(&Camera, &GlobalTransform), With<Camera3d>>,
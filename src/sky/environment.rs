@@ -4,13 +4,22 @@ use super::SkySceneMarker;
 
 pub struct EnvironmentPlugin;
 
+#[derive(Component)]
+pub struct Island {
+    pub radius: f32,
+    pub height: f32
+}
+
+#[derive(Component)]
+pub struct Sun;
+
 impl Plugin for EnvironmentPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(SimulationState::Sky), setup_environment);
     }
 }
 
-fn setup_environment(
+pub fn setup_environment(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
@@ -59,11 +68,13 @@ fn setup_environment(
         if height < -50.0 {
             continue;
         }
+        let island_radius = 8.0 + (i as f32 * 0.8).sin() * 4.0;
+        let island_height = 5.0;
         commands.spawn((
             PbrBundle {
                 mesh: meshes.add(Mesh::from(shape::Cylinder {
-                    radius: 8.0 + (i as f32 * 0.8).sin() * 4.0,
-                    height: 5.0,
+                    radius: island_radius,
+                    height: island_height,
                     resolution: 20,
                     segments: 20,
                 })),
@@ -76,6 +87,10 @@ fn setup_environment(
                 .with_rotation(Quat::from_rotation_z(angle * 0.2)),
                 ..default()
             },
+            Island {
+                radius: island_radius,
+                height: island_height
+            },
             SkySceneMarker,
             ObstacleTag
         ));
@@ -93,6 +108,7 @@ fn setup_environment(
                 .looking_at(Vec3::ZERO, Vec3::Y),
             ..default()
         },
+        Sun,
         SkySceneMarker,
     ));
 
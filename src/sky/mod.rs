@@ -7,6 +7,12 @@ pub use environment::*;
 pub mod birds;
 use birds::BirdsPlugin;
 
+pub mod perching;
+use perching::PerchingPlugin;
+
+pub mod murmuration;
+use murmuration::MurmurationPlugin;
+
 #[derive(Component)]
 pub struct SkySceneMarker;
 
@@ -17,6 +23,8 @@ impl Plugin for SkyPlugin {
         app.add_plugins((
             EnvironmentPlugin,
             BirdsPlugin,
+            PerchingPlugin,
+            MurmurationPlugin,
         ))
         .add_systems(Update, handle_camera_movement)
         .add_systems(OnEnter(SimulationState::Sky), setup_sky_scene)
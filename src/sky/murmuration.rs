@@ -0,0 +1,321 @@
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy_spatial::SpatialAccess;
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::boids_3d::components::*;
+use crate::boids_3d::events::ApplyForceEvent;
+use crate::rules::ForceSource;
+use crate::kd_tree_3d::components::NNTree3D;
+use crate::random::SystemRng;
+use crate::ui::resources::SimulationState;
+use super::birds::SkyBoidSettings;
+use super::perching::{Perched, SkyPredator};
+use super::SkySceneMarker;
+
+const MAX_RECORDED_WAVES: usize = 20;
+
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub struct MurmurationSettings {
+    pub enabled: bool,
+    pub topological_neighbours: usize,
+    pub roost: Vec3,
+    pub roost_radius: f32,
+    pub roost_coeff: f32,
+    pub predator_trigger_radius: f32,
+    pub escape_coeff: f32,
+    pub reaction_time: f32,
+    pub agitation_duration: f32
+}
+
+impl Default for MurmurationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            topological_neighbours: 7,
+            roost: Vec3::new(0.0, 40.0, 0.0),
+            roost_radius: 120.0,
+            roost_coeff: 2.0,
+            predator_trigger_radius: 40.0,
+            escape_coeff: 600.0,
+            reaction_time: 0.1,
+            agitation_duration: 1.0
+        }
+    }
+}
+
+/// Starling-like parameters: a large flock, short-range separation and strong alignment over
+/// the seven nearest neighbours, held together by the roost rather than by group targets.
+pub fn apply_murmuration_preset(sky_settings: &mut SkyBoidSettings, murmuration: &mut MurmurationSettings) {
+    sky_settings.count = 20000;
+    sky_settings.size = 1.0;
+    sky_settings.cohesion_range = 50.0;
+    sky_settings.alignment_range = 30.0;
+    sky_settings.separation_range = 6.0;
+    sky_settings.cohesion_coeff = 8.0;
+    sky_settings.alignment_coeff = 10.0;
+    sky_settings.separation_coeff = 25.0;
+    sky_settings.min_speed = 60.0;
+    sky_settings.max_speed = 140.0;
+    sky_settings.field_of_view = 300.0;
+    sky_settings.max_force = 800.0;
+    sky_settings.max_angular_velocity = 240.0;
+    sky_settings.banking_coeff = 0.006;
+    *murmuration = MurmurationSettings {
+        enabled: true,
+        ..default()
+    };
+}
+
+#[derive(Component)]
+pub struct Agitation {
+    pub wave: u32,
+    pub escape: Vec3,
+    pub reaction: Timer,
+    pub remaining: Timer,
+    pub spread: bool
+}
+
+#[derive(Component)]
+pub struct WaveMemory {
+    pub last_wave: u32
+}
+
+/// Filter of the flying birds a disturbance can still reach.
+type Calm = (With<SkySceneMarker>, With<Boid>, Without<Agitation>, Without<Perched>);
+
+pub struct DisturbanceWave {
+    pub id: u32,
+    pub origin: Vec3,
+    pub started_at: f32,
+    pub active: bool,
+    /// (seconds since the wave started, distance from its origin) for every bird it reached.
+    pub samples: Vec<[f64; 2]>
+}
+
+impl DisturbanceWave {
+    /// Propagation speed in units per second, as the least-squares slope of distance over time.
+    pub fn speed(&self) -> f64 {
+        self.fit().map_or(0.0, |(slope, _)| slope)
+    }
+
+    pub fn reach(&self) -> f64 {
+        self.samples.iter().map(|[_, distance]| *distance).fold(0.0, f64::max)
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.samples.iter().map(|[time, _]| *time).fold(0.0, f64::max)
+    }
+
+    /// Returns (slope, intercept) of the distance-over-time regression line.
+    pub fn fit(&self) -> Option<(f64, f64)> {
+        let n = self.samples.len() as f64;
+        if n < 2.0 {
+            return None;
+        }
+        let mean_time = self.samples.iter().map(|[time, _]| time).sum::<f64>() / n;
+        let mean_distance = self.samples.iter().map(|[_, distance]| distance).sum::<f64>() / n;
+        let mut covariance = 0.0;
+        let mut variance = 0.0;
+        for [time, distance] in self.samples.iter() {
+            covariance += (time - mean_time) * (distance - mean_distance);
+            variance += (time - mean_time) * (time - mean_time);
+        }
+        if variance <= f64::EPSILON {
+            return None;
+        }
+        let slope = covariance / variance;
+        Some((slope, mean_distance - slope * mean_time))
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct DisturbanceWaves {
+    pub waves: Vec<DisturbanceWave>,
+    pub trigger_requested: bool,
+    next_id: u32
+}
+
+impl DisturbanceWaves {
+    fn active_wave(&self) -> Option<&DisturbanceWave> {
+        self.waves.iter().rev().find(|wave| wave.active)
+    }
+
+    fn start_wave(&mut self, origin: Vec3, now: f32) -> u32 {
+        self.next_id += 1;
+        self.waves.push(DisturbanceWave {
+            id: self.next_id,
+            origin,
+            started_at: now,
+            active: true,
+            samples: vec![[0.0, 0.0]]
+        });
+        if self.waves.len() > MAX_RECORDED_WAVES {
+            self.waves.remove(0);
+        }
+        self.next_id
+    }
+
+    fn record(&mut self, wave_id: u32, position: Vec3, now: f32) {
+        if let Some(wave) = self.waves.iter_mut().find(|wave| wave.id == wave_id) {
+            let elapsed = (now - wave.started_at) as f64;
+            let distance = position.distance(wave.origin) as f64;
+            wave.samples.push([elapsed, distance]);
+        }
+    }
+}
+
+pub struct MurmurationPlugin;
+
+impl Plugin for MurmurationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MurmurationSettings>()
+           .init_resource::<DisturbanceWaves>()
+           .add_systems(Update, (
+                trigger_agitation,
+                apply_deferred,
+                propagate_agitation,
+                agitation_escape,
+                apply_deferred,
+                close_finished_waves
+           ).chain().run_if(in_state(SimulationState::Sky)));
+    }
+}
+
+fn agitate(commands: &mut Commands, entity: Entity, wave: u32, escape: Vec3, delay: f32, duration: f32) {
+    commands.entity(entity).insert((
+        Agitation {
+            wave,
+            escape,
+            reaction: Timer::from_seconds(delay, TimerMode::Once),
+            remaining: Timer::from_seconds(duration, TimerMode::Once),
+            spread: false
+        },
+        WaveMemory { last_wave: wave }
+    ));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn trigger_agitation(
+    mut commands: Commands,
+    mut waves: ResMut<DisturbanceWaves>,
+    bird_query: Query<(Entity, &Transform, Option<&WaveMemory>), Calm>,
+    predator_query: Query<&Transform, With<SkyPredator>>,
+    kd_tree: Res<NNTree3D>,
+    murmuration: Res<MurmurationSettings>,
+    time: Res<Time>,
+    mut rng: Local<SystemRng>
+) {
+    let now = time.elapsed_seconds();
+    let rng = rng.get("trigger_agitation");
+
+    // Stays requested until a bird is calm enough to start the wave.
+    if waves.trigger_requested {
+        if let Some((entity, transform, _)) = bird_query.iter().choose(rng) {
+            waves.trigger_requested = false;
+            let escape = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-0.3..0.3), rng.gen_range(-1.0..1.0)).normalize_or_zero();
+            let wave = waves.start_wave(transform.translation, now);
+            agitate(&mut commands, entity, wave, escape, 0.0, murmuration.agitation_duration);
+        }
+    }
+
+    if !murmuration.enabled {
+        return;
+    }
+    for predator_transform in predator_query.iter() {
+        let predator_position = predator_transform.translation;
+        for (_, entity) in kd_tree.within_distance(predator_position, murmuration.predator_trigger_radius) {
+            let Some(entity) = entity else { continue };
+            let Ok((_, transform, memory)) = bird_query.get(entity) else { continue };
+            let active_wave = waves.active_wave().map(|wave| wave.id);
+            if active_wave.is_some() && memory.map(|memory| memory.last_wave) == active_wave {
+                continue;
+            }
+            let wave = match active_wave {
+                Some(wave) => {
+                    waves.record(wave, transform.translation, now);
+                    wave
+                }
+                None => waves.start_wave(transform.translation, now)
+            };
+            let escape = (transform.translation - predator_position).normalize_or_zero();
+            agitate(&mut commands, entity, wave, escape, 0.0, murmuration.agitation_duration);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn propagate_agitation(
+    mut commands: Commands,
+    mut agitated_query: Query<(&Transform, &mut Agitation)>,
+    candidate_query: Query<(&Transform, Option<&WaveMemory>), Calm>,
+    mut waves: ResMut<DisturbanceWaves>,
+    kd_tree: Res<NNTree3D>,
+    murmuration: Res<MurmurationSettings>,
+    time: Res<Time>,
+    mut rng: Local<SystemRng>
+) {
+    let now = time.elapsed_seconds();
+    let rng = rng.get("propagate_agitation");
+    let mut newly_agitated: HashSet<Entity> = HashSet::new();
+
+    for (transform, mut agitation) in agitated_query.iter_mut() {
+        if agitation.spread || !agitation.reaction.tick(time.delta()).finished() {
+            continue;
+        }
+        agitation.spread = true;
+        let neighbours = kd_tree.k_nearest_neighbour(transform.translation, murmuration.topological_neighbours + 1);
+        for (_, neighbour) in neighbours {
+            let Some(neighbour) = neighbour else { continue };
+            if newly_agitated.contains(&neighbour) {
+                continue;
+            }
+            let Ok((neighbour_transform, memory)) = candidate_query.get(neighbour) else { continue };
+            if memory.is_some_and(|memory| memory.last_wave >= agitation.wave) {
+                continue;
+            }
+            newly_agitated.insert(neighbour);
+            waves.record(agitation.wave, neighbour_transform.translation, now);
+            let delay = murmuration.reaction_time * rng.gen_range(0.5..1.5);
+            agitate(&mut commands, neighbour, agitation.wave, agitation.escape, delay, murmuration.agitation_duration);
+        }
+    }
+}
+
+fn agitation_escape(
+    mut commands: Commands,
+    mut agitated_query: Query<(Entity, &mut Agitation)>,
+    mut event_writer: EventWriter<ApplyForceEvent>,
+    murmuration: Res<MurmurationSettings>,
+    time: Res<Time>
+) {
+    for (entity, mut agitation) in agitated_query.iter_mut() {
+        if agitation.remaining.tick(time.delta()).finished() {
+            commands.entity(entity).remove::<Agitation>();
+            continue;
+        }
+        if agitation.spread {
+            event_writer.send(ApplyForceEvent {
+                entity,
+                force: agitation.escape * murmuration.escape_coeff,
+                source: ForceSource::Fear
+            });
+        }
+    }
+}
+
+fn close_finished_waves(
+    mut waves: ResMut<DisturbanceWaves>,
+    agitated_query: Query<&Agitation>
+) {
+    let mut running: HashSet<u32> = HashSet::new();
+    for agitation in agitated_query.iter() {
+        running.insert(agitation.wave);
+    }
+    for wave in waves.waves.iter_mut() {
+        if wave.active && !running.contains(&wave.id) {
+            wave.active = false;
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use bevy::prelude::*;
+use bevy_spatial::SpatialAccess;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::{PI, TAU};
+use crate::boids_3d::components::*;
+use crate::boids_3d::cone::Cone;
+use crate::boids_3d::systems::orient_along_velocity;
+use crate::kd_tree_3d::components::{NNTree3D, TrackedByKDTree3D};
+use crate::random::SystemRng;
+use crate::ui::resources::SimulationState;
+use super::birds::{update_birds_position, SkyBoidSettings};
+use super::environment::{setup_environment, Island, Sun};
+use super::SkySceneMarker;
+
+const DAY_CLEAR_COLOR: Color = Color::rgb(0.3, 0.1, 0.6);
+const NIGHT_CLEAR_COLOR: Color = Color::rgb(0.03, 0.02, 0.08);
+
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub struct RoostSettings {
+    pub enabled: bool,
+    pub perches_per_island: usize,
+    pub max_stamina: f32,
+    pub fatigue_rate: f32,
+    pub rest_rate: f32,
+    pub tired_threshold: f32,
+    pub landing_chance: f32,
+    pub landing_speed: f32,
+    pub spontaneous_take_off_chance: f32,
+    pub disturbance_radius: f32,
+    pub cascade_probability: f32,
+    pub reaction_time: f32,
+    pub predator_alarm_radius: f32,
+    pub predator_speed: f32
+}
+
+impl Default for RoostSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            perches_per_island: 6,
+            max_stamina: 100.0,
+            fatigue_rate: 2.0,
+            rest_rate: 10.0,
+            tired_threshold: 20.0,
+            landing_chance: 0.5,
+            landing_speed: 60.0,
+            spontaneous_take_off_chance: 0.05,
+            disturbance_radius: 25.0,
+            cascade_probability: 0.6,
+            reaction_time: 0.3,
+            predator_alarm_radius: 60.0,
+            predator_speed: 180.0
+        }
+    }
+}
+
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub struct DayNightCycle {
+    pub time_of_day: f32,
+    pub day_length: f32
+}
+
+impl Default for DayNightCycle {
+    fn default() -> Self {
+        Self {
+            time_of_day: 0.35,
+            day_length: 180.0
+        }
+    }
+}
+
+impl DayNightCycle {
+    /// Height of the sun in [-1, 1]: 0 at sunrise (0.25) and sunset (0.75), 1 at noon.
+    pub fn sun_elevation(&self) -> f32 {
+        ((self.time_of_day - 0.25) * TAU).sin()
+    }
+
+    pub fn is_night(&self) -> bool {
+        self.sun_elevation() < 0.0
+    }
+}
+
+pub struct PerchPoint {
+    pub position: Vec3,
+    pub occupant: Option<Entity>
+}
+
+#[derive(Resource, Default)]
+pub struct PerchPoints {
+    pub points: Vec<PerchPoint>
+}
+
+#[derive(Component, Debug)]
+pub struct Stamina {
+    pub stamina: f32
+}
+
+impl Default for Stamina {
+    fn default() -> Self {
+        Self {
+            stamina: RoostSettings::default().max_stamina
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Landing {
+    pub perch: usize
+}
+
+#[derive(Component)]
+pub struct Perched {
+    pub perch: usize,
+    pub heading: f32,
+    pub idle_timer: Timer,
+    pub alarm: Option<Timer>
+}
+
+#[derive(Component)]
+pub struct TakingOff {
+    pub timer: Timer
+}
+
+/// Filter of the birds that are neither landing nor perched.
+pub type InFlight = (Without<Landing>, Without<Perched>);
+/// Filter of the birds that are landing or perched.
+type OnPerch = Or<(With<Landing>, With<Perched>)>;
+
+#[derive(Component)]
+pub struct SkyPredator {
+    pub velocity: Vec3
+}
+
+#[derive(Event)]
+pub struct TakeOffEvent {
+    pub position: Vec3
+}
+
+pub struct PerchingPlugin;
+
+impl Plugin for PerchingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RoostSettings>()
+           .init_resource::<DayNightCycle>()
+           .init_resource::<PerchPoints>()
+           .add_event::<TakeOffEvent>()
+           .add_systems(OnEnter(SimulationState::Sky), (apply_deferred, sample_perch_points).chain().after(setup_environment))
+           .add_systems(Update, (
+                advance_day_night,
+                release_abandoned_perches,
+                drain_stamina,
+                choose_perch,
+                approach_perch,
+                idle_on_perch,
+                hear_disturbances,
+                take_off,
+                finish_take_off,
+                release_predator,
+                chase_birds
+           ).chain().after(update_birds_position).run_if(in_state(SimulationState::Sky)));
+    }
+}
+
+fn sample_perch_points(
+    mut perch_points: ResMut<PerchPoints>,
+    roost_settings: Res<RoostSettings>,
+    island_query: Query<(&Transform, &Island)>,
+    mut rng: Local<SystemRng>
+) {
+    let rng = rng.get("sample_perch_points");
+    perch_points.points.clear();
+
+    for (transform, island) in island_query.iter() {
+        let normal = transform.rotation * Vec3::Y;
+        if normal.y.abs() < 0.7 {
+            continue;
+        }
+        let top = normal.y.signum() * island.height / 2.0;
+        for _ in 0..roost_settings.perches_per_island {
+            let radius = island.radius * 0.8 * rng.gen::<f32>().sqrt();
+            let angle = rng.gen_range(0.0..TAU);
+            let local = Vec3::new(radius * angle.cos(), top, radius * angle.sin());
+            perch_points.points.push(PerchPoint {
+                position: transform.transform_point(local),
+                occupant: None
+            });
+        }
+    }
+}
+
+fn advance_day_night(
+    mut cycle: ResMut<DayNightCycle>,
+    mut sun_query: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+    mut camera_query: Query<&mut Camera3d, With<SkySceneMarker>>,
+    mut ambient_light: ResMut<AmbientLight>,
+    time: Res<Time>
+) {
+    if cycle.day_length > 0.0 {
+        cycle.time_of_day = (cycle.time_of_day + time.delta_seconds() / cycle.day_length).fract();
+    }
+    let elevation = cycle.sun_elevation();
+    let daylight = elevation.max(0.0);
+
+    for (mut transform, mut light) in sun_query.iter_mut() {
+        let angle = (cycle.time_of_day - 0.25) * TAU;
+        let direction = Vec3::new(angle.cos(), angle.sin(), 0.3).normalize();
+        *transform = Transform::from_translation(direction * 200.0).looking_at(Vec3::ZERO, Vec3::Y);
+        light.illuminance = 20000.0 * daylight + 200.0;
+    }
+    for mut camera_3d in camera_query.iter_mut() {
+        let color = Vec4::from(NIGHT_CLEAR_COLOR).lerp(Vec4::from(DAY_CLEAR_COLOR), daylight.sqrt());
+        camera_3d.clear_color = bevy::core_pipeline::clear_color::ClearColorConfig::Custom(color.into());
+    }
+    ambient_light.brightness = 0.05 + 0.25 * daylight;
+}
+
+fn release_abandoned_perches(
+    mut perch_points: ResMut<PerchPoints>,
+    occupant_query: Query<(), OnPerch>
+) {
+    for point in perch_points.points.iter_mut() {
+        if let Some(occupant) = point.occupant {
+            if occupant_query.get(occupant).is_err() {
+                point.occupant = None;
+            }
+        }
+    }
+}
+
+fn drain_stamina(
+    mut bird_query: Query<&mut Stamina, (With<SkySceneMarker>, Without<Perched>)>,
+    roost_settings: Res<RoostSettings>,
+    time: Res<Time>
+) {
+    for mut stamina in bird_query.iter_mut() {
+        stamina.stamina = (stamina.stamina - roost_settings.fatigue_rate * time.delta_seconds()).max(0.0);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn choose_perch(
+    mut commands: Commands,
+    bird_query: Query<(Entity, &Transform, &Stamina), (With<SkySceneMarker>, InFlight, Without<TakingOff>)>,
+    mut perch_points: ResMut<PerchPoints>,
+    roost_settings: Res<RoostSettings>,
+    cycle: Res<DayNightCycle>,
+    time: Res<Time>,
+    mut rng: Local<SystemRng>
+) {
+    if !roost_settings.enabled {
+        return;
+    }
+    let rng = rng.get("choose_perch");
+    let night = cycle.is_night();
+    let chance = roost_settings.landing_chance * time.delta_seconds();
+
+    for (entity, transform, stamina) in bird_query.iter() {
+        let wants_to_land = night || stamina.stamina < roost_settings.tired_threshold;
+        if !wants_to_land || rng.gen::<f32>() >= chance {
+            continue;
+        }
+        let position = transform.translation;
+        let nearest_free = perch_points.points.iter()
+            .enumerate()
+            .filter(|(_, point)| point.occupant.is_none())
+            .min_by(|(_, a), (_, b)| {
+                a.position.distance_squared(position).total_cmp(&b.position.distance_squared(position))
+            })
+            .map(|(index, _)| index);
+        if let Some(perch) = nearest_free {
+            perch_points.points[perch].occupant = Some(entity);
+            commands.entity(entity).insert(Landing { perch });
+        }
+    }
+}
+
+/// Landing birds leave the flight model and ignore steering: they slow down to the landing speed
+/// on a straight approach to their perch.
+#[allow(clippy::too_many_arguments)]
+fn approach_perch(
+    mut commands: Commands,
+    mut bird_query: Query<(Entity, &mut Transform, &mut Velocity, &mut Acceleration, &Landing)>,
+    perch_points: Res<PerchPoints>,
+    roost_settings: Res<RoostSettings>,
+    boid_settings: Res<SkyBoidSettings>,
+    time: Res<Time>,
+    mut rng: Local<SystemRng>
+) {
+    let rng = rng.get("approach_perch");
+    let delta_seconds = time.delta_seconds();
+
+    for (entity, mut transform, mut velocity, mut acceleration, landing) in bird_query.iter_mut() {
+        acceleration.acceleration = Vec3::ZERO;
+        let Some(point) = perch_points.points.get(landing.perch) else {
+            commands.entity(entity).remove::<Landing>();
+            continue;
+        };
+        let to_perch = point.position - transform.translation;
+        let distance = to_perch.length();
+        let arrival_distance = (velocity.velocity.length() * delta_seconds * 1.5).max(3.0);
+
+        if distance < arrival_distance {
+            let heading = velocity.velocity.x.atan2(velocity.velocity.z) + PI;
+            transform.translation = point.position;
+            transform.rotation = Quat::from_rotation_y(heading);
+            velocity.velocity = Vec3::ZERO;
+            // Perched birds are out of the neighbour searches of the flying ones.
+            commands.entity(entity)
+                .remove::<(Landing, TrackedByKDTree3D)>()
+                .insert(Perched {
+                    perch: landing.perch,
+                    heading,
+                    idle_timer: Timer::from_seconds(rng.gen_range(1.0..4.0), TimerMode::Once),
+                    alarm: None
+                });
+        } else {
+            let previous_velocity = velocity.velocity;
+            let desired = to_perch / distance * roost_settings.landing_speed;
+            velocity.velocity = velocity.velocity.lerp(desired, (4.0 * delta_seconds).min(1.0));
+            transform.translation += velocity.velocity * delta_seconds;
+            orient_along_velocity(&mut transform, previous_velocity, velocity.velocity, &boid_settings, delta_seconds);
+        }
+    }
+}
+
+fn idle_on_perch(
+    mut bird_query: Query<(&mut Transform, &mut Perched, &mut Stamina)>,
+    roost_settings: Res<RoostSettings>,
+    time: Res<Time>,
+    mut rng: Local<SystemRng>
+) {
+    let rng = rng.get("idle_on_perch");
+
+    for (mut transform, mut perched, mut stamina) in bird_query.iter_mut() {
+        stamina.stamina = (stamina.stamina + roost_settings.rest_rate * time.delta_seconds()).min(roost_settings.max_stamina);
+
+        perched.idle_timer.tick(time.delta());
+        if perched.idle_timer.finished() {
+            perched.heading += rng.gen_range(-0.8..0.8);
+            perched.idle_timer = Timer::from_seconds(rng.gen_range(1.0..4.0), TimerMode::Once);
+        }
+
+        let fraction = perched.idle_timer.percent();
+        let peck = if fraction < 0.2 { (fraction / 0.2 * PI).sin() * 0.4 } else { 0.0 };
+        let target = Quat::from_rotation_y(perched.heading) * Quat::from_rotation_x(-peck);
+        transform.rotation = transform.rotation.slerp(target, (5.0 * time.delta_seconds()).min(1.0));
+    }
+}
+
+/// Perched birds are not in the KD-tree, so they are searched directly. There are few take-offs
+/// and predators in a frame.
+fn hear_disturbances(
+    mut take_off_events: EventReader<TakeOffEvent>,
+    mut perched_query: Query<(&Transform, &mut Perched)>,
+    predator_query: Query<&Transform, With<SkyPredator>>,
+    roost_settings: Res<RoostSettings>,
+    mut rng: Local<SystemRng>
+) {
+    let rng = rng.get("hear_disturbances");
+
+    for TakeOffEvent { position } in take_off_events.read() {
+        for (transform, mut perched) in perched_query.iter_mut() {
+            if transform.translation.distance(*position) > roost_settings.disturbance_radius {
+                continue;
+            }
+            if perched.alarm.is_none() && rng.gen::<f32>() < roost_settings.cascade_probability {
+                let delay = roost_settings.reaction_time * rng.gen_range(0.5..1.5);
+                perched.alarm = Some(Timer::from_seconds(delay, TimerMode::Once));
+            }
+        }
+    }
+
+    for predator_transform in predator_query.iter() {
+        for (transform, mut perched) in perched_query.iter_mut() {
+            if transform.translation.distance(predator_transform.translation) > roost_settings.predator_alarm_radius {
+                continue;
+            }
+            if perched.alarm.is_none() {
+                let delay = roost_settings.reaction_time * rng.gen_range(0.2..0.6);
+                perched.alarm = Some(Timer::from_seconds(delay, TimerMode::Once));
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn take_off(
+    mut commands: Commands,
+    mut bird_query: Query<(Entity, &Transform, &mut Velocity, &mut Perched, &Stamina)>,
+    mut take_off_writer: EventWriter<TakeOffEvent>,
+    mut perch_points: ResMut<PerchPoints>,
+    roost_settings: Res<RoostSettings>,
+    boid_settings: Res<SkyBoidSettings>,
+    cycle: Res<DayNightCycle>,
+    time: Res<Time>,
+    mut rng: Local<SystemRng>
+) {
+    let rng = rng.get("take_off");
+    let rested_chance = roost_settings.spontaneous_take_off_chance * time.delta_seconds();
+
+    for (entity, transform, mut velocity, mut perched, stamina) in bird_query.iter_mut() {
+        let alarmed = match perched.alarm.as_mut() {
+            Some(alarm) => alarm.tick(time.delta()).finished(),
+            None => false
+        };
+        let rested = roost_settings.enabled
+            && !cycle.is_night()
+            && stamina.stamina >= roost_settings.max_stamina
+            && rng.gen::<f32>() < rested_chance;
+        if !alarmed && !rested {
+            continue;
+        }
+
+        if let Some(point) = perch_points.points.get_mut(perched.perch) {
+            point.occupant = None;
+        }
+        let heading = Vec3::new(rng.gen_range(-1.0..1.0), 0.0, rng.gen_range(-1.0..1.0)).normalize_or_zero();
+        let speed = boid_settings.min_speed.max(roost_settings.landing_speed);
+        velocity.velocity = (Vec3::Y + heading * 0.6).normalize() * speed;
+        commands.entity(entity)
+            .remove::<Perched>()
+            .insert((TakingOff { timer: Timer::from_seconds(1.0, TimerMode::Once) }, TrackedByKDTree3D));
+        take_off_writer.send(TakeOffEvent { position: transform.translation });
+    }
+}
+
+fn finish_take_off(
+    mut commands: Commands,
+    mut bird_query: Query<(Entity, &mut Velocity, &mut TakingOff)>,
+    roost_settings: Res<RoostSettings>,
+    time: Res<Time>
+) {
+    for (entity, mut velocity, mut taking_off) in bird_query.iter_mut() {
+        velocity.velocity.y = velocity.velocity.y.max(roost_settings.landing_speed * 0.5);
+        if taking_off.timer.tick(time.delta()).finished() {
+            commands.entity(entity).remove::<TakingOff>();
+        }
+    }
+}
+
+fn release_predator(
+    mut commands: Commands,
+    keyboard: Res<Input<KeyCode>>,
+    predator_query: Query<Entity, With<SkyPredator>>,
+    camera_query: Query<&Transform, (With<Camera3d>, With<SkySceneMarker>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>
+) {
+    if !keyboard.just_pressed(KeyCode::P) {
+        return;
+    }
+    if !predator_query.is_empty() {
+        for predator in predator_query.iter() {
+            commands.entity(predator).despawn_recursive();
+        }
+        return;
+    }
+    let Ok(camera_transform) = camera_query.get_single() else { return };
+
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(Mesh::from(Cone {
+                radius: 2.0,
+                height: 8.0,
+                ..default()
+            })),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.1, 0.1, 0.1),
+                emissive: Color::rgb(0.6, 0.0, 0.0),
+                ..default()
+            }),
+            transform: Transform::from_translation(camera_transform.translation + camera_transform.forward() * 20.0),
+            ..default()
+        },
+        SkyPredator {
+            velocity: camera_transform.forward() * 50.0
+        },
+        SkySceneMarker
+    ));
+}
+
+fn chase_birds(
+    mut predator_query: Query<(&mut Transform, &mut SkyPredator)>,
+    bird_query: Query<&Transform, (With<Boid>, Without<SkyPredator>)>,
+    kd_tree: Res<NNTree3D>,
+    roost_settings: Res<RoostSettings>,
+    time: Res<Time>
+) {
+    let delta_seconds = time.delta_seconds();
+
+    for (mut transform, mut predator) in predator_query.iter_mut() {
+        let position = transform.translation;
+        let prey = kd_tree.nearest_neighbour(position)
+            .and_then(|(_, entity)| entity)
+            .and_then(|entity| bird_query.get(entity).ok());
+        if let Some(prey_transform) = prey {
+            let desired = (prey_transform.translation - position).normalize_or_zero() * roost_settings.predator_speed;
+            predator.velocity = predator.velocity.lerp(desired, (2.0 * delta_seconds).min(1.0));
+        }
+        transform.translation += predator.velocity * delta_seconds;
+        if let Some(forward) = predator.velocity.try_normalize() {
+            transform.look_to(forward, Vec3::Y);
+        }
+    }
+}
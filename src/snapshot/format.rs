@@ -0,0 +1,143 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use crate::boids_2d::resources::BoidSettings2D;
+use crate::boids_3d::resources::BoidSettings3D;
+use crate::sky::birds::{FlightModelSettings, FlightState, SkyBoidSettings};
+use crate::sky::murmuration::MurmurationSettings;
+use crate::sky::perching::{DayNightCycle, RoostSettings};
+use crate::ui::resources::SimulationState;
+use crate::underwater::fish::components::{Species, SpeciesSettings};
+use crate::underwater::fish::ecology::EcologySettings;
+
+/// Settings resources of the saved mode. Group targets live here too since their type differs
+/// between 2D and 3D.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ModeSettings {
+    Mode2D {
+        boids: BoidSettings2D,
+        targets: Vec<Vec2>,
+        obstacle_radius: f32
+    },
+    Mode3D {
+        boids: BoidSettings3D,
+        targets: Vec<Vec3>,
+        obstacle_radius: f32
+    },
+    Underwater {
+        species: SpeciesSettings,
+        ecology: EcologySettings
+    },
+    Sky {
+        boids: SkyBoidSettings,
+        targets: Vec<Vec3>,
+        flight_model: FlightModelSettings,
+        murmuration: MurmurationSettings,
+        roost: RoostSettings,
+        day_night: DayNightCycle,
+        /// Perches are sampled at random on the islands, and birds refer to them by index.
+        perches: Vec<Vec3>
+    }
+}
+
+impl ModeSettings {
+    pub fn mode(&self) -> SimulationState {
+        match self {
+            ModeSettings::Mode2D { .. } => SimulationState::Mode2D,
+            ModeSettings::Mode3D { .. } => SimulationState::Mode3D,
+            ModeSettings::Underwater { .. } => SimulationState::Underwater,
+            ModeSettings::Sky { .. } => SimulationState::Sky
+        }
+    }
+}
+
+/// 2D boids keep their velocity in `x` and `y`. Fish use `group` as their school ID.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BoidSnapshot {
+    pub group: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub species: Option<Species>,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+    pub velocity: Vec3,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bird: Option<BirdSnapshot>
+}
+
+impl BoidSnapshot {
+    pub fn transform(&self) -> Transform {
+        Transform {
+            translation: self.translation,
+            rotation: self.rotation,
+            scale: self.scale
+        }
+    }
+}
+
+/// Saved as durations since `Timer` is only serialisable with Bevy's `serialize` feature.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TimerSnapshot {
+    pub duration: f32,
+    pub elapsed: f32
+}
+
+impl From<&Timer> for TimerSnapshot {
+    fn from(timer: &Timer) -> Self {
+        Self {
+            duration: timer.duration().as_secs_f32(),
+            elapsed: timer.elapsed_secs()
+        }
+    }
+}
+
+impl TimerSnapshot {
+    pub fn timer(&self) -> Timer {
+        let mut timer = Timer::from_seconds(self.duration, TimerMode::Once);
+        timer.set_elapsed(Duration::from_secs_f32(self.elapsed));
+        timer
+    }
+}
+
+/// Where a bird is between choosing a perch and flying off it again.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum PerchSnapshot {
+    Landing {
+        perch: usize
+    },
+    Perched {
+        perch: usize,
+        heading: f32,
+        idle_timer: TimerSnapshot,
+        alarm: Option<TimerSnapshot>
+    },
+    TakingOff {
+        timer: TimerSnapshot
+    }
+}
+
+/// State of a bird besides its motion.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BirdSnapshot {
+    pub flight_state: FlightState,
+    pub energy: f32,
+    pub stamina: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub perch: Option<PerchSnapshot>
+}
+
+/// Only obstacles placed by the user are saved, scene obstacles come back with the scene.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ObstacleSnapshot {
+    pub position: Vec3,
+    pub radius: f32,
+    pub color: Vec3
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub version: String,
+    pub settings: ModeSettings,
+    pub boids: Vec<BoidSnapshot>,
+    pub obstacles: Vec<ObstacleSnapshot>
+}
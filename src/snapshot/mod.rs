@@ -0,0 +1,27 @@
+use bevy::prelude::*;
+
+pub mod format;
+pub mod resources;
+pub mod systems;
+
+pub use resources::*;
+pub use systems::*;
+
+pub struct SnapshotPlugin;
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Snapshots>()
+           .add_systems(Startup, refresh_snapshot_files)
+           .add_systems(Update, (
+                save_snapshot_hotkey,
+                save_snapshot,
+                load_snapshot,
+                restore_snapshot
+           ).chain());
+    }
+}
+
+fn refresh_snapshot_files(mut snapshots: ResMut<Snapshots>) {
+    snapshots.refresh_files();
+}
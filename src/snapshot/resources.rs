@@ -0,0 +1,48 @@
+use bevy::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use super::format::WorldSnapshot;
+
+#[derive(Resource)]
+pub struct Snapshots {
+    pub directory: PathBuf,
+    /// Snapshot files found in the directory, listed by the UI.
+    pub files: Vec<PathBuf>,
+    pub save_requested: bool,
+    /// Set by the UI, restored once the snapshot's mode is active.
+    pub load_requested: Option<PathBuf>,
+    pub last_saved: Option<PathBuf>,
+    pub error: Option<String>,
+    pub(super) pending: Option<WorldSnapshot>
+}
+
+impl Default for Snapshots {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("snapshots"),
+            files: Vec::new(),
+            save_requested: false,
+            load_requested: None,
+            last_saved: None,
+            error: None,
+            pending: None
+        }
+    }
+}
+
+impl Snapshots {
+    pub fn refresh_files(&mut self) {
+        self.files = list_files(&self.directory, "json");
+    }
+}
+
+fn list_files(directory: &Path, extension: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(directory)
+        .map(|entries| entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|found| found == extension))
+            .collect())
+        .unwrap_or_default();
+    files.sort();
+    files
+}
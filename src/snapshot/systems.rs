@@ -0,0 +1,366 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::boids_2d::components::{self as boids_2d, PlacedObstacle};
+use crate::boids_2d::resources::{BoidSettings2D, GroupsTargets as GroupsTargets2D};
+use crate::boids_2d::systems::{spawn_boid_at as spawn_boid_2d, spawn_obstacle_2d};
+use crate::boids_3d::components as boids_3d;
+use crate::boids_3d::resources::{BoidSettings3D, GroupsTargets as GroupsTargets3D};
+use crate::boids_3d::systems::{spawn_boid_at as spawn_boid_3d, spawn_obstacle_3d};
+use crate::input::resources::ShapeSettings;
+use crate::kd_tree_3d::components::TrackedByKDTree3D;
+use crate::replay::Replay;
+use crate::sky::birds::{spawn_bird, spawn_sky_obstacle, BirdModel, Flight, FlightModelSettings, SkyBoidSettings};
+use crate::sky::murmuration::MurmurationSettings;
+use crate::sky::perching::{DayNightCycle, Landing, PerchPoint, PerchPoints, Perched, RoostSettings, Stamina, TakingOff};
+use crate::sky::SkySceneMarker;
+use crate::ui::resources::SimulationState;
+use crate::underwater::fish::components::{FishModels, FishType, SpeciesSettings};
+use crate::underwater::fish::ecology::{EcologySettings, ALL_SPECIES};
+use crate::underwater::fish::spawn_fish;
+use super::format::*;
+use super::resources::*;
+
+/// Every resource a snapshot saves and restores.
+#[derive(SystemParam)]
+pub struct SnapshotSettings<'w> {
+    boids_2d: ResMut<'w, BoidSettings2D>,
+    targets_2d: ResMut<'w, GroupsTargets2D>,
+    boids_3d: ResMut<'w, BoidSettings3D>,
+    targets_3d: ResMut<'w, GroupsTargets3D>,
+    shape: ResMut<'w, ShapeSettings>,
+    sky_boids: ResMut<'w, SkyBoidSettings>,
+    flight_model: ResMut<'w, FlightModelSettings>,
+    murmuration: ResMut<'w, MurmurationSettings>,
+    roost: ResMut<'w, RoostSettings>,
+    day_night: ResMut<'w, DayNightCycle>,
+    perch_points: ResMut<'w, PerchPoints>,
+    species: ResMut<'w, SpeciesSettings>,
+    ecology: ResMut<'w, EcologySettings>
+}
+
+impl SnapshotSettings<'_> {
+    fn capture(&self, state: &SimulationState) -> ModeSettings {
+        match state {
+            SimulationState::Mode2D => ModeSettings::Mode2D {
+                boids: self.boids_2d.clone(),
+                targets: self.targets_2d.targets.clone(),
+                obstacle_radius: self.shape.radius
+            },
+            SimulationState::Mode3D => ModeSettings::Mode3D {
+                boids: self.boids_3d.clone(),
+                targets: self.targets_3d.targets.clone(),
+                obstacle_radius: self.shape.radius
+            },
+            SimulationState::Underwater => ModeSettings::Underwater {
+                species: self.species.clone(),
+                ecology: self.ecology.clone()
+            },
+            SimulationState::Sky => ModeSettings::Sky {
+                boids: self.sky_boids.clone(),
+                targets: self.targets_3d.targets.clone(),
+                flight_model: self.flight_model.clone(),
+                murmuration: self.murmuration.clone(),
+                roost: self.roost.clone(),
+                day_night: self.day_night.clone(),
+                perches: self.perch_points.points.iter().map(|point| point.position).collect()
+            }
+        }
+    }
+
+    /// Population counts are synced so that nothing gets spawned or despawned on top of the
+    /// restored boids.
+    fn restore(&mut self, settings: ModeSettings) {
+        match settings {
+            ModeSettings::Mode2D { mut boids, targets, obstacle_radius } => {
+                boids.previous_count = boids.count;
+                *self.boids_2d = boids;
+                self.targets_2d.targets = targets;
+                self.shape.radius = obstacle_radius;
+            }
+            ModeSettings::Mode3D { mut boids, targets, obstacle_radius } => {
+                boids.previous_count = boids.count;
+                *self.boids_3d = boids;
+                self.targets_3d.targets = targets;
+                self.shape.radius = obstacle_radius;
+            }
+            ModeSettings::Underwater { mut species, ecology } => {
+                for kind in ALL_SPECIES {
+                    let school_settings = species.get_mut(kind);
+                    school_settings.previous_schools = school_settings.schools;
+                    school_settings.boids.previous_count = school_settings.boids.count;
+                }
+                *self.species = species;
+                *self.ecology = ecology;
+            }
+            ModeSettings::Sky { mut boids, targets, flight_model, murmuration, roost, day_night, perches } => {
+                boids.previous_count = boids.count;
+                *self.sky_boids = boids;
+                self.targets_3d.targets = targets;
+                *self.flight_model = flight_model;
+                *self.murmuration = murmuration;
+                *self.roost = roost;
+                *self.day_night = day_night;
+                // Occupied again as the birds on them are restored.
+                self.perch_points.points = perches.into_iter()
+                    .map(|position| PerchPoint { position, occupant: None })
+                    .collect();
+            }
+        }
+    }
+}
+
+pub fn save_snapshot_hotkey(
+    keys: Res<Input<KeyCode>>,
+    mut snapshots: ResMut<Snapshots>
+) {
+    if keys.just_pressed(KeyCode::F5) {
+        snapshots.save_requested = true;
+    }
+}
+
+fn color_to_vec3(color: Color) -> Vec3 {
+    Vec3::new(color.r(), color.g(), color.b())
+}
+
+/// Placed obstacles outlive the mode they were placed in, so they are told apart by their
+/// material and scene.
+fn placed_in(state: &SimulationState, is_2d: bool, in_sky: bool) -> bool {
+    match state {
+        SimulationState::Mode2D => is_2d,
+        SimulationState::Mode3D => !is_2d && !in_sky,
+        SimulationState::Sky => in_sky,
+        SimulationState::Underwater => false
+    }
+}
+
+fn capture_bird(flight: &Flight, stamina: &Stamina, landing: Option<&Landing>, perched: Option<&Perched>, taking_off: Option<&TakingOff>) -> BirdSnapshot {
+    let perch = match (landing, perched, taking_off) {
+        (Some(landing), _, _) => Some(PerchSnapshot::Landing { perch: landing.perch }),
+        (_, Some(perched), _) => Some(PerchSnapshot::Perched {
+            perch: perched.perch,
+            heading: perched.heading,
+            idle_timer: TimerSnapshot::from(&perched.idle_timer),
+            alarm: perched.alarm.as_ref().map(TimerSnapshot::from)
+        }),
+        (_, _, Some(taking_off)) => Some(PerchSnapshot::TakingOff { timer: TimerSnapshot::from(&taking_off.timer) }),
+        _ => None
+    };
+    BirdSnapshot {
+        flight_state: flight.state,
+        energy: flight.energy,
+        stamina: stamina.stamina,
+        perch
+    }
+}
+
+/// Puts back what `spawn_bird` started afresh, and takes the bird's perch again.
+fn restore_bird(commands: &mut Commands, entity: Entity, bird: &BirdSnapshot, perch_points: &mut PerchPoints) {
+    let mut entity_commands = commands.entity(entity);
+    entity_commands.insert((
+        Flight { state: bird.flight_state, energy: bird.energy },
+        Stamina { stamina: bird.stamina }
+    ));
+    let perch = match bird.perch.as_ref() {
+        Some(PerchSnapshot::Landing { perch }) => {
+            entity_commands.insert(Landing { perch: *perch });
+            *perch
+        }
+        Some(PerchSnapshot::Perched { perch, heading, idle_timer, alarm }) => {
+            entity_commands.remove::<TrackedByKDTree3D>().insert(Perched {
+                perch: *perch,
+                heading: *heading,
+                idle_timer: idle_timer.timer(),
+                alarm: alarm.as_ref().map(TimerSnapshot::timer)
+            });
+            *perch
+        }
+        Some(PerchSnapshot::TakingOff { timer }) => {
+            entity_commands.insert(TakingOff { timer: timer.timer() });
+            return;
+        }
+        None => return
+    };
+    if let Some(point) = perch_points.points.get_mut(perch) {
+        point.occupant = Some(entity);
+    }
+}
+
+fn write_snapshot(snapshots: &mut Snapshots, snapshot: &WorldSnapshot) -> std::io::Result<()> {
+    fs::create_dir_all(&snapshots.directory)?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let mode = format!("{:?}", snapshot.settings.mode()).to_lowercase();
+    let path = snapshots.directory.join(format!("snapshot_{mode}_{timestamp}.json"));
+
+    serde_json::to_writer_pretty(BufWriter::new(File::create(&path)?), snapshot)?;
+    info!("Saved {} boids to {}", snapshot.boids.len(), path.display());
+    snapshots.last_saved = Some(path);
+    snapshots.refresh_files();
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn save_snapshot(
+    mut snapshots: ResMut<Snapshots>,
+    state: Res<State<SimulationState>>,
+    settings: SnapshotSettings,
+    boid_query_2d: Query<(&Transform, &boids_2d::Velocity, &boids_2d::Boid)>,
+    boid_query_3d: Query<(Entity, &Transform, &boids_3d::Velocity, &boids_3d::Boid, Option<&FishType>)>,
+    bird_query: Query<(&Flight, &Stamina, Option<&Landing>, Option<&Perched>, Option<&TakingOff>)>,
+    obstacle_query: Query<(&Transform, &PlacedObstacle, Option<&Handle<ColorMaterial>>, Option<&Handle<StandardMaterial>>, Has<SkySceneMarker>)>,
+    color_materials: Res<Assets<ColorMaterial>>,
+    standard_materials: Res<Assets<StandardMaterial>>,
+    replay: Res<Replay>
+) {
+    if !snapshots.save_requested {
+        return;
+    }
+    snapshots.save_requested = false;
+    if replay.is_active() {
+        snapshots.error = Some("Exit the replay before saving a snapshot".to_string());
+        return;
+    }
+
+    let boids = match state.get() {
+        SimulationState::Mode2D => boid_query_2d.iter().map(|(transform, velocity, boid)| BoidSnapshot {
+            group: boid.group,
+            species: None,
+            translation: transform.translation,
+            rotation: transform.rotation,
+            scale: transform.scale,
+            velocity: velocity.velocity.extend(0.0),
+            bird: None
+        }).collect(),
+        _ => boid_query_3d.iter().map(|(entity, transform, velocity, boid, fish_type)| BoidSnapshot {
+            group: boid.group,
+            species: fish_type.map(|fish_type| fish_type.species),
+            translation: transform.translation,
+            rotation: transform.rotation,
+            scale: transform.scale,
+            velocity: velocity.velocity,
+            bird: bird_query.get(entity).ok().map(|(flight, stamina, landing, perched, taking_off)| {
+                capture_bird(flight, stamina, landing, perched, taking_off)
+            })
+        }).collect()
+    };
+    let obstacles = obstacle_query.iter().filter_map(|(transform, obstacle, color_material, standard_material, in_sky)| {
+        if !placed_in(state.get(), color_material.is_some(), in_sky) {
+            return None;
+        }
+        let color = match state.get() {
+            SimulationState::Mode2D => color_materials.get(color_material?)?.color,
+            _ => standard_materials.get(standard_material?)?.base_color
+        };
+        Some(ObstacleSnapshot { position: transform.translation, radius: obstacle.radius, color: color_to_vec3(color) })
+    }).collect();
+
+    let snapshot = WorldSnapshot {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        settings: settings.capture(state.get()),
+        boids,
+        obstacles
+    };
+    snapshots.error = write_snapshot(&mut snapshots, &snapshot).err().map(|error| error.to_string());
+}
+
+/// Reads the requested file, switching to the mode it was saved in if needed.
+pub fn load_snapshot(
+    mut snapshots: ResMut<Snapshots>,
+    state: Res<State<SimulationState>>,
+    mut next_state: ResMut<NextState<SimulationState>>,
+    replay: Res<Replay>
+) {
+    let Some(path) = snapshots.load_requested.take() else { return };
+    if replay.is_active() {
+        snapshots.error = Some("Exit the replay before loading a snapshot".to_string());
+        return;
+    }
+    let snapshot = File::open(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|file| serde_json::from_reader::<_, WorldSnapshot>(BufReader::new(file)).map_err(|error| error.to_string()));
+    match snapshot {
+        Ok(snapshot) => {
+            if snapshot.version != env!("CARGO_PKG_VERSION") {
+                warn!("{} was saved by boidsy {}", path.display(), snapshot.version);
+            }
+            let mode = snapshot.settings.mode();
+            if &mode != state.get() {
+                next_state.set(mode);
+            }
+            snapshots.pending = Some(snapshot);
+            snapshots.error = None;
+        }
+        Err(error) => snapshots.error = Some(format!("{}: {error}", path.display()))
+    }
+}
+
+/// Replaces the boids spawned by the mode and the obstacles placed in it with the snapshot's.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn restore_snapshot(
+    mut commands: Commands,
+    mut snapshots: ResMut<Snapshots>,
+    state: Res<State<SimulationState>>,
+    mut settings: SnapshotSettings,
+    boid_query: Query<Entity, Or<(With<boids_2d::Boid>, With<boids_3d::Boid>)>>,
+    obstacle_query: Query<(Entity, Has<Handle<ColorMaterial>>, Has<SkySceneMarker>), With<PlacedObstacle>>,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    mut standard_materials: ResMut<Assets<StandardMaterial>>,
+    bird_model: Res<BirdModel>,
+    fish_models: Res<FishModels>
+) {
+    if snapshots.pending.as_ref().map_or(true, |snapshot| &snapshot.settings.mode() != state.get()) {
+        return;
+    }
+    let Some(snapshot) = snapshots.pending.take() else { return };
+
+    let placed_obstacles = obstacle_query.iter()
+        .filter(|(_, is_2d, in_sky)| placed_in(state.get(), *is_2d, *in_sky))
+        .map(|(entity, _, _)| entity);
+    for entity in boid_query.iter().chain(placed_obstacles) {
+        commands.entity(entity).despawn_recursive();
+    }
+    settings.restore(snapshot.settings);
+
+    let state = state.get();
+    for boid in snapshot.boids.iter() {
+        match state {
+            SimulationState::Mode2D => {
+                spawn_boid_2d(&mut commands, &asset_server, boid.group, boid.transform(), boid.velocity.truncate());
+            }
+            SimulationState::Mode3D => {
+                spawn_boid_3d(&mut commands, &mut meshes, &mut standard_materials, &settings.boids_3d, boid.group, boid.transform(), boid.velocity);
+            }
+            SimulationState::Sky => {
+                let bird = spawn_bird(&mut commands, &bird_model, boid.group, boid.transform(), boid.velocity);
+                if let Some(snapshot) = boid.bird.as_ref() {
+                    restore_bird(&mut commands, bird, snapshot, &mut settings.perch_points);
+                }
+            }
+            SimulationState::Underwater => {
+                let Some(species) = boid.species else { continue };
+                let school_settings = &settings.species.get(species).boids;
+                let fish = spawn_fish(&mut commands, &fish_models, &species, boid.group as usize, boid.translation, boid.velocity, school_settings);
+                commands.entity(fish).insert(boid.transform());
+            }
+        }
+    }
+    for obstacle in snapshot.obstacles.iter() {
+        match state {
+            SimulationState::Mode2D => {
+                spawn_obstacle_2d(&mut commands, obstacle.position.truncate(), obstacle.color, obstacle.radius, &mut meshes, &mut color_materials);
+            }
+            SimulationState::Mode3D => {
+                spawn_obstacle_3d(&mut commands, obstacle.position, obstacle.color, obstacle.radius, &mut meshes, &mut standard_materials);
+            }
+            SimulationState::Sky => {
+                spawn_sky_obstacle(&mut commands, obstacle.position, obstacle.radius, &mut meshes, &mut standard_materials);
+            }
+            SimulationState::Underwater => {}
+        }
+    }
+    info!("Restored {} boids and {} obstacles", snapshot.boids.len(), snapshot.obstacles.len());
+}
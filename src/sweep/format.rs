@@ -0,0 +1,181 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::cli::CliMode;
+
+/// Values taken by one swept parameter. Ranges include their end.
+#[derive(Deserialize)]
+pub enum ParameterValues {
+    Range { from: f64, to: f64, step: f64 },
+    List(Vec<f64>)
+}
+
+impl ParameterValues {
+    pub fn values(&self) -> Result<Vec<f64>, String> {
+        match self {
+            ParameterValues::Range { from, to, step } => {
+                if *step <= 0.0 || to < from {
+                    return Err(format!("range {from}..{to} with step {step} is empty"));
+                }
+                // Rounded so that float steps still reach the end of the range.
+                let count = ((to - from) / step + 1e-9).floor() as usize + 1;
+                Ok((0..count).map(|index| from + index as f64 * step).collect())
+            }
+            ParameterValues::List(values) => Ok(values.clone())
+        }
+    }
+}
+
+/// Reads an experiment file. Optional fields can be written without `Some(..)`.
+pub fn read_ron<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let text = fs::read_to_string(path).map_err(|error| format!("{}: {error}", path.display()))?;
+    ron::Options::default()
+        .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+        .from_str(&text)
+        .map_err(|error| format!("{}: {error}", path.display()))
+}
+
+/// Modes are written as on the command line, "2d", "3d", "underwater" or "sky".
+pub fn mode_from_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CliMode, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    CliMode::from_str(&name, true).map_err(serde::de::Error::custom)
+}
+
+pub fn default_seeds() -> Vec<u64> {
+    vec![0]
+}
+
+pub fn default_steps() -> u32 {
+    600
+}
+
+/// Experiment description, read from a RON file. `count` sets the total number of boids like
+/// `--count`, every other parameter is a boid settings field of the mode like `--set`.
+#[derive(Deserialize)]
+pub struct Manifest {
+    #[serde(deserialize_with = "mode_from_name")]
+    pub mode: CliMode,
+    #[serde(default = "default_seeds")]
+    pub seeds: Vec<u64>,
+    #[serde(default)]
+    pub warmup: u32,
+    #[serde(default = "default_steps")]
+    pub steps: u32,
+    pub parameters: BTreeMap<String, ParameterValues>,
+    /// Metric keys to report, all of them when empty.
+    #[serde(default)]
+    pub metrics: Vec<String>,
+    /// Runs in parallel, the number of cores by default.
+    #[serde(default)]
+    pub jobs: Option<usize>,
+    /// Results table, `sweeps/<manifest name>.csv` by default.
+    #[serde(default)]
+    pub output: Option<PathBuf>
+}
+
+/// One combination of parameter values and seed.
+pub struct SweepRun {
+    pub parameters: Vec<(String, f64)>,
+    pub seed: u64
+}
+
+impl Manifest {
+    /// Every combination of the parameter values, each repeated for every seed.
+    pub fn runs(&self) -> Result<Vec<SweepRun>, String> {
+        let mut combinations: Vec<Vec<(String, f64)>> = vec![Vec::new()];
+        for (name, values) in self.parameters.iter() {
+            let values = values.values().map_err(|error| format!("{name}: {error}"))?;
+            combinations = combinations.into_iter()
+                .flat_map(|combination| values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((name.clone(), *value));
+                    combination
+                }))
+                .collect();
+        }
+        Ok(combinations.into_iter()
+            .flat_map(|parameters| self.seeds.iter().map(move |seed| SweepRun { parameters: parameters.clone(), seed: *seed }))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(parameters: Vec<(&str, ParameterValues)>, seeds: Vec<u64>) -> Manifest {
+        Manifest {
+            mode: CliMode::Mode2D,
+            seeds,
+            warmup: 0,
+            steps: default_steps(),
+            parameters: parameters.into_iter().map(|(name, values)| (name.to_string(), values)).collect(),
+            metrics: Vec::new(),
+            jobs: None,
+            output: None
+        }
+    }
+
+    #[test]
+    fn range_includes_its_end() {
+        let values = ParameterValues::Range { from: 0.0, to: 1.0, step: 0.25 }.values().unwrap();
+        assert_eq!(values, vec![0.0, 0.25, 0.5, 0.75, 1.0]);
+    }
+
+    #[test]
+    fn range_reaches_its_end_despite_float_steps() {
+        let values = ParameterValues::Range { from: 0.0, to: 0.3, step: 0.1 }.values().unwrap();
+        assert_eq!(values.len(), 4);
+    }
+
+    #[test]
+    fn range_stops_before_a_partial_step() {
+        let values = ParameterValues::Range { from: 1.0, to: 2.5, step: 1.0 }.values().unwrap();
+        assert_eq!(values, vec![1.0, 2.0]);
+    }
+
+    #[test]
+    fn empty_ranges_are_errors() {
+        assert!(ParameterValues::Range { from: 0.0, to: 1.0, step: 0.0 }.values().is_err());
+        assert!(ParameterValues::Range { from: 2.0, to: 1.0, step: 1.0 }.values().is_err());
+    }
+
+    #[test]
+    fn list_is_taken_as_it_is() {
+        assert_eq!(ParameterValues::List(vec![3.0, 1.0]).values().unwrap(), vec![3.0, 1.0]);
+    }
+
+    #[test]
+    fn runs_cover_every_combination_for_every_seed() {
+        let manifest = manifest(vec![
+            ("alignment_coeff", ParameterValues::List(vec![1.0, 2.0])),
+            ("cohesion_coeff", ParameterValues::Range { from: 0.0, to: 20.0, step: 10.0 })
+        ], vec![1, 2]);
+        let runs = manifest.runs().unwrap();
+        assert_eq!(runs.len(), 2 * 3 * 2);
+        for alignment in [1.0, 2.0] {
+            for cohesion in [0.0, 10.0, 20.0] {
+                for seed in [1, 2] {
+                    let parameters = vec![("alignment_coeff".to_string(), alignment), ("cohesion_coeff".to_string(), cohesion)];
+                    assert_eq!(runs.iter().filter(|run| run.seed == seed && run.parameters == parameters).count(), 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn runs_without_parameters_are_one_per_seed() {
+        let runs = manifest(Vec::new(), vec![4, 5, 6]).runs().unwrap();
+        assert_eq!(runs.iter().map(|run| run.seed).collect::<Vec<_>>(), vec![4, 5, 6]);
+        assert!(runs.iter().all(|run| run.parameters.is_empty()));
+    }
+
+    #[test]
+    fn runs_name_the_parameter_with_an_empty_range() {
+        let manifest = manifest(vec![("max_speed", ParameterValues::Range { from: 1.0, to: 0.0, step: 1.0 })], default_seeds());
+        let error = manifest.runs().err().unwrap();
+        assert!(error.starts_with("max_speed: "));
+    }
+}
@@ -0,0 +1,4 @@
+pub mod format;
+pub mod runner;
+
+pub use runner::*;
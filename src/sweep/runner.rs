@@ -0,0 +1,193 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use bevy::prelude::default;
+use crate::bench::BenchResult;
+use crate::cli::{override_mode_settings, CliMode};
+use crate::metrics::Metric;
+use super::format::*;
+
+/// Whole numbers are written as integers so that they also fit integer fields such as `count`.
+pub fn parameter_value(value: f64) -> serde_json::Value {
+    if value.fract() == 0.0 && value.abs() < 2f64.powi(53) {
+        serde_json::Value::from(value as i64)
+    } else {
+        serde_json::Value::from(value)
+    }
+}
+
+fn overrides(run: &SweepRun) -> Vec<(String, serde_json::Value)> {
+    run.parameters.iter()
+        .filter(|(name, _)| name != "count")
+        .map(|(name, value)| (name.clone(), parameter_value(*value)))
+        .collect()
+}
+
+/// Command line of a headless run that prints its result as JSON.
+pub fn headless_arguments(
+    mode: CliMode,
+    seed: u64,
+    warmup: u32,
+    steps: u32,
+    count: Option<usize>,
+    overrides: &[(String, serde_json::Value)]
+) -> Vec<String> {
+    let mut arguments: Vec<String> = vec![
+        "--headless".into(), "--json".into(),
+        "--mode".into(), mode.name(),
+        "--seed".into(), seed.to_string(),
+        "--warmup".into(), warmup.to_string(),
+        "--steps".into(), steps.to_string()
+    ];
+    if let Some(count) = count {
+        arguments.extend(["--count".to_string(), count.to_string()]);
+    }
+    for (name, value) in overrides {
+        arguments.extend(["--set".to_string(), format!("{name}={value}")]);
+    }
+    arguments
+}
+
+fn arguments(manifest: &Manifest, run: &SweepRun) -> Vec<String> {
+    let count = run.parameters.iter().find(|(name, _)| name == "count").map(|(_, count)| count.max(0.0) as usize);
+    headless_arguments(manifest.mode, run.seed, manifest.warmup, manifest.steps, count, &overrides(run))
+}
+
+fn execute(executable: &Path, arguments: &[String]) -> Result<BenchResult, String> {
+    let output = Command::new(executable).args(arguments).output().map_err(|error| error.to_string())?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.lines().last().unwrap_or("run failed").to_string());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().last().unwrap_or_default();
+    serde_json::from_str(line).map_err(|error| error.to_string())
+}
+
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |jobs| jobs.get())
+}
+
+/// Runs each command line as a child process of this executable, `jobs` at a time. Each run is
+/// its own process, which keeps the seeded random generator of one run away from the others.
+/// `finished` is called as runs complete, in any order.
+pub fn run_headless(
+    runs: &[Vec<String>],
+    jobs: usize,
+    finished: impl Fn(usize, &Result<BenchResult, String>) + Sync
+) -> Vec<Result<BenchResult, String>> {
+    let executable = match std::env::current_exe() {
+        Ok(executable) => executable,
+        Err(error) => return runs.iter().map(|_| Err(error.to_string())).collect()
+    };
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<BenchResult, String>>>> = Mutex::new(runs.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, runs.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(arguments) = runs.get(index) else { break };
+                let result = execute(&executable, arguments);
+                finished(index, &result);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results.into_inner().unwrap().into_iter()
+        .map(|result| result.unwrap_or_else(|| Err("not run".to_string())))
+        .collect()
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn write_results(
+    path: &Path,
+    manifest_path: &Path,
+    manifest: &Manifest,
+    metrics: &[Metric],
+    runs: &[SweepRun],
+    results: &[Result<BenchResult, String>]
+) -> std::io::Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "# boidsy parameter sweep")?;
+    writeln!(writer, "# manifest: {}", manifest_path.display())?;
+    writeln!(writer, "# warmup: {}, steps: {}", manifest.warmup, manifest.steps)?;
+
+    let mut columns: Vec<&str> = vec!["run", "seed", "version", "mode"];
+    columns.extend(manifest.parameters.keys().map(String::as_str));
+    columns.extend(["boids", "steps_per_second"]);
+    columns.extend(metrics.iter().map(|metric| metric.key()));
+    columns.extend(["settings", "error"]);
+    writeln!(writer, "{}", columns.join(","))?;
+
+    for (index, (run, result)) in runs.iter().zip(results.iter()).enumerate() {
+        let mut row = vec![index.to_string(), run.seed.to_string()];
+        match result {
+            Ok(result) => {
+                row.extend([result.version.clone(), result.mode.name()]);
+                row.extend(run.parameters.iter().map(|(_, value)| value.to_string()));
+                row.extend([result.boids.to_string(), format!("{:.2}", result.steps_per_second)]);
+                row.extend(metrics.iter().map(|metric| result.metrics.get(metric.key()).map_or(String::new(), |value| format!("{value:.6}"))));
+                row.extend([csv_field(&result.settings.to_string()), String::new()]);
+            }
+            Err(error) => {
+                row.extend([env!("CARGO_PKG_VERSION").to_string(), String::new()]);
+                row.extend(run.parameters.iter().map(|(_, value)| value.to_string()));
+                row.extend(std::iter::repeat(String::new()).take(2 + metrics.len() + 1));
+                row.push(csv_field(error));
+            }
+        }
+        writeln!(writer, "{}", row.join(","))?;
+    }
+    writer.flush()
+}
+
+/// Runs every combination of the manifest, in parallel, and writes the results table.
+pub fn run(manifest_path: &Path) -> Result<(), String> {
+    let manifest: Manifest = read_ron(manifest_path)?;
+    let runs = manifest.runs()?;
+    let metrics = if manifest.metrics.is_empty() {
+        Metric::ALL.to_vec()
+    } else {
+        manifest.metrics.iter()
+            .map(|key| Metric::from_key(key).ok_or_else(|| format!("unknown metric \"{key}\"")))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    if let Some(run) = runs.first() {
+        override_mode_settings(manifest.mode, &overrides(run), &mut default(), &mut default(), &mut default(), &mut default())?;
+    }
+    let output = manifest.output.clone().unwrap_or_else(|| {
+        let name = manifest_path.file_stem().map_or("sweep".into(), |stem| stem.to_string_lossy());
+        PathBuf::from("sweeps").join(format!("{name}.csv"))
+    });
+    let jobs = manifest.jobs.unwrap_or_else(default_jobs);
+    eprintln!("Sweeping {} runs of {} mode on {jobs} jobs", runs.len(), manifest.mode.name());
+
+    let finished = AtomicUsize::new(0);
+    let arguments: Vec<_> = runs.iter().map(|run| arguments(&manifest, run)).collect();
+    let results = run_headless(&arguments, jobs, |index, result| {
+        let run = &runs[index];
+        let parameters: Vec<String> = run.parameters.iter().map(|(name, value)| format!("{name}={value}")).collect();
+        let status = result.as_ref().map_or_else(|error| format!("failed: {error}"), |_| "done".to_string());
+        let finished = finished.fetch_add(1, Ordering::Relaxed) + 1;
+        eprintln!("[{finished}/{}] {} seed={} {status}", runs.len(), parameters.join(" "), run.seed);
+    });
+
+    write_results(&output, manifest_path, &manifest, &metrics, &runs, &results).map_err(|error| format!("{}: {error}", output.display()))?;
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    eprintln!("Wrote {} ({failed} failed)", output.display());
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+use bevy::reflect::{ReflectMut, TypeRegistry};
+use bevy_egui::egui;
+use egui::emath::Numeric;
+use crate::settings::{FieldHints, HintsFn, ReflectSettingsHints};
+
+/// "max_bank_angle" becomes "Max bank angle".
+fn label_from_name(name: &str) -> String {
+    let label = name.replace('_', " ");
+    let mut characters = label.chars();
+    characters.next().map_or(String::new(), |first| first.to_uppercase().chain(characters).collect())
+}
+
+fn number_widget<T: Numeric>(ui: &mut egui::Ui, value: &mut T, label: &str, hints: Option<&FieldHints>, cap: Option<f64>) -> egui::Response {
+    let unit = hints.map_or("", |hints| hints.unit);
+    let suffix = if unit.is_empty() { String::new() } else { format!(" {unit}") };
+    match hints.and_then(|hints| hints.range) {
+        Some((min, max)) => {
+            let max = cap.unwrap_or(max);
+            ui.add(egui::Slider::new(value, T::from_f64(min)..=T::from_f64(max)).text(label).suffix(suffix))
+        }
+        None => ui.horizontal(|ui| {
+            let response = ui.add(egui::DragValue::new(value).suffix(suffix));
+            ui.label(label);
+            response
+        }).inner
+    }
+}
+
+fn field_widget(ui: &mut egui::Ui, value: &mut dyn Reflect, label: &str, hints: Option<&FieldHints>, cap: Option<f64>, registry: &TypeRegistry) -> bool {
+    let any = value.as_any_mut();
+    let response = if let Some(value) = any.downcast_mut::<f32>() {
+        number_widget(ui, value, label, hints, cap)
+    } else if let Some(value) = any.downcast_mut::<f64>() {
+        number_widget(ui, value, label, hints, cap)
+    } else if let Some(value) = any.downcast_mut::<usize>() {
+        number_widget(ui, value, label, hints, cap)
+    } else if let Some(value) = any.downcast_mut::<u32>() {
+        number_widget(ui, value, label, hints, cap)
+    } else if let Some(value) = any.downcast_mut::<bool>() {
+        ui.checkbox(value, label)
+    } else {
+        let mut changed = false;
+        egui::CollapsingHeader::new(label).show(ui, |ui| changed = inspect_value(ui, value, None, registry));
+        return changed;
+    };
+    let response = match hints.map(|hints| hints.tooltip).filter(|tooltip| !tooltip.is_empty()) {
+        Some(tooltip) => response.on_hover_text(tooltip),
+        None => response
+    };
+    response.changed()
+}
+
+fn inspect_value(ui: &mut egui::Ui, value: &mut dyn Reflect, hints: Option<HintsFn>, registry: &TypeRegistry) -> bool {
+    // Hints of a wrapper, such as the sky's settings around the 3D ones, win over the inner type's.
+    let hints = hints.or_else(|| {
+        registry.get_type_data::<ReflectSettingsHints>(value.as_any().type_id()).map(ReflectSettingsHints::field_hints)
+    });
+    match value.reflect_mut() {
+        ReflectMut::Struct(settings) => {
+            let mut changed = false;
+            for index in 0..settings.field_len() {
+                let Some(name) = settings.name_at(index).map(str::to_string) else { continue };
+                let field_hints = hints.and_then(|hints| hints(&name));
+                if field_hints.is_some_and(|field_hints| field_hints.hidden) {
+                    continue;
+                }
+                let cap = field_hints
+                    .and_then(|field_hints| field_hints.capped_by)
+                    .and_then(|other| settings.field(other))
+                    .and_then(|other| other.as_any().downcast_ref::<f32>().map(|other| *other as f64));
+                let label = field_hints.map_or_else(|| label_from_name(&name), |field_hints| field_hints.label.to_string());
+                let Some(field) = settings.field_at_mut(index) else { continue };
+                changed |= field_widget(ui, field, &label, field_hints.as_ref(), cap, registry);
+            }
+            changed
+        }
+        ReflectMut::TupleStruct(wrapper) if wrapper.field_len() == 1 => {
+            wrapper.field_mut(0).is_some_and(|inner| inspect_value(ui, inner, hints, registry))
+        }
+        _ => false
+    }
+}
+
+/// Widgets for every field of a reflected settings value, laid out from its `SettingsHints`.
+/// Returns whether a field was changed.
+pub fn inspect_settings(ui: &mut egui::Ui, settings: &mut dyn Reflect, registry: &TypeRegistry) -> bool {
+    inspect_value(ui, settings, None, registry)
+}
@@ -7,6 +7,9 @@ pub mod events;
 pub use events::*;
 
 pub mod resources;
+use resources::SimulationState;
+
+pub mod inspector;
 
 pub struct UiPlugin;
 
@@ -18,7 +21,10 @@ impl Plugin for UiPlugin {
 		.add_event::<CursorVisibilityEvent>()
     	.add_systems(Startup, setup_fps_counter)
 		.add_systems(Update, handle_cursor_visibility)
-        .add_systems(Update, setup_ui)
+        .add_systems(Update, (simulation_mode_ui, setup_ui))
+        .add_systems(Update, (metrics_ui, trajectory_export_ui, flocks_ui, replay_ui, snapshots_ui, presets_ui, flocking_rules_ui, boid_inspector_ui, debug_overlay_ui))
+        .add_systems(Update, disturbance_waves_ui.run_if(in_state(SimulationState::Sky)))
+        .add_systems(Update, (school_states_ui, population_ui).run_if(in_state(SimulationState::Underwater)))
         .add_systems(Update, (
 		    fps_text_update_system,
 		    fps_counter_showhide,
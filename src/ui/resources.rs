@@ -7,4 +7,24 @@ pub enum SimulationState {
     Mode3D,
     Underwater,
     Sky
-}
\ No newline at end of file
+}
+
+/// Modes the simulation can be switched to.
+#[derive(Resource)]
+pub struct EnabledModes {
+    pub modes: Vec<SimulationState>
+}
+
+impl Default for EnabledModes {
+    fn default() -> Self {
+        Self {
+            modes: vec![SimulationState::Mode2D, SimulationState::Mode3D, SimulationState::Underwater, SimulationState::Sky]
+        }
+    }
+}
+
+impl EnabledModes {
+    pub fn contains(&self, mode: &SimulationState) -> bool {
+        self.modes.contains(mode)
+    }
+}
@@ -1,14 +1,34 @@
+use bevy::ecs::system::{SystemParam, SystemState};
 use bevy::prelude::*;
+use bevy::reflect::TypeRegistry;
 use bevy::window::PrimaryWindow;
 use bevy_egui::*;
 
-use crate::boids_2d::resources::BoidSettings2D;
 use crate::boids_3d::events::ResizeEvent;
 use crate::boids_3d::resources::BoidSettings3D;
 use crate::input::resources::ShapeSettings;
 use crate::ui::events::CursorVisibilityEvent;
-use crate::ui::resources::SimulationState;
-use crate::sky::birds::SkyBoidSettings;  // Add this import
+use crate::settings::ReflectSettingsHints;
+use crate::ui::inspector::inspect_settings;
+use crate::ui::resources::{EnabledModes, SimulationState};
+use crate::sky::birds::{FlightModelSettings, SkyBoidSettings};
+use crate::sky::perching::{DayNightCycle, RoostSettings};
+use crate::sky::murmuration::{apply_murmuration_preset, DisturbanceWaves, MurmurationSettings};
+use crate::underwater::UnderwaterMarker;
+use crate::underwater::fish::schools::{CollectiveState, SchoolStates};
+use crate::underwater::fish::components::SpeciesSettings;
+use crate::underwater::fish::ecology::{EcologySettings, PopulationHistory, ALL_SPECIES};
+use crate::metrics::{FlockMetrics, Metric, MetricsSettings};
+use crate::export::{ExportFormat, TrajectoryRecorder};
+use crate::clustering::{flock_color, ClusteringSettings, FlockClusters};
+use crate::replay::{Replay, SimulationRecorder};
+use crate::snapshot::Snapshots;
+use crate::presets::{BuiltInPreset, PresetRequest, Presets};
+use crate::scripting::ScriptFile;
+use crate::overlay::{DebugOverlay, OverlayLayer};
+use crate::rules::FlockingRules;
+use crate::selection::SelectedBoid;
+use egui_plot::{Line, Plot, PlotPoints, Points};
 
 //fps
 use bevy::diagnostic::DiagnosticsStore;
@@ -20,149 +40,716 @@ pub struct FpsRoot;
 #[derive(Component)]
 pub struct FpsText;
 
-pub fn setup_ui(
+pub fn simulation_mode_ui(
     mut egui_context: EguiContexts,
-    mut boid_settings_2d: ResMut<BoidSettings2D>,
-    mut boid_settings_3d: ResMut<BoidSettings3D>,
-    mut sky_boid_settings: ResMut<SkyBoidSettings>,
-    mut shape_settings: ResMut<ShapeSettings>,
     mut next_state: ResMut<NextState<SimulationState>>,
-    state: Res<State<SimulationState>>,
-    mut resize_event_writer: EventWriter<ResizeEvent>
+    enabled_modes: Res<EnabledModes>
 ) {
     egui::Window::new("Simulation mode").show(egui_context.ctx_mut(), |ui| {
-        if ui.button("2D mode").clicked() {
-            next_state.set(SimulationState::Mode2D);
+        for (mode, label) in [
+            (SimulationState::Mode2D, "2D mode"),
+            (SimulationState::Mode3D, "3D mode"),
+            (SimulationState::Underwater, "Underwater"),
+            (SimulationState::Sky, "Sky")
+        ] {
+            if enabled_modes.contains(&mode) && ui.button(label).clicked() {
+                next_state.set(mode);
+            }
         }
-        if ui.button("3D mode").clicked() {
-            next_state.set(SimulationState::Mode3D);
+    });
+}
+
+/// Controls of the settings window besides the fields of the mode's settings resources.
+#[derive(SystemParam)]
+pub struct ModeControls<'w> {
+    sky_boid_settings: ResMut<'w, SkyBoidSettings>,
+    flight_settings: ResMut<'w, FlightModelSettings>,
+    roost_settings: ResMut<'w, RoostSettings>,
+    day_night: ResMut<'w, DayNightCycle>,
+    murmuration: ResMut<'w, MurmurationSettings>,
+    species_settings: ResMut<'w, SpeciesSettings>,
+    shape_settings: ResMut<'w, ShapeSettings>,
+    resize_event_writer: EventWriter<'w, ResizeEvent>
+}
+
+/// Size of the 3D boids or of the birds, which are rescaled when it changes.
+fn boids_size(world: &World, state: &SimulationState) -> Option<f32> {
+    match state {
+        SimulationState::Mode3D => Some(world.resource::<BoidSettings3D>().size),
+        SimulationState::Sky => Some(world.resource::<SkyBoidSettings>().size),
+        _ => None
+    }
+}
+
+/// Settings window of the active mode. Every registered resource whose `SettingsHints` name the
+/// mode gets a panel built by the inspector, followed by the mode's other controls.
+pub fn setup_ui(
+    world: &mut World,
+    controls: &mut SystemState<ModeControls>
+) {
+    let Ok(mut egui_context) = world.query_filtered::<&mut EguiContext, With<PrimaryWindow>>().get_single_mut(world) else { return };
+    let ctx = egui_context.get_mut().clone();
+    let state = world.resource::<State<SimulationState>>().get().clone();
+    let type_registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = type_registry.read();
+    let size = boids_size(world, &state);
+
+    egui::Window::new("Boids settings").show(&ctx, |ui| {
+        for registration in registry.iter() {
+            let (Some(hints), Some(resource)) = (registration.data::<ReflectSettingsHints>(), registration.data::<ReflectResource>()) else { continue };
+            if hints.mode().as_ref() != Some(&state) {
+                continue;
+            }
+            if let Some(mut settings) = resource.reflect_mut(world) {
+                inspect_settings(ui, &mut *settings, &registry);
+            }
         }
-        if ui.button("Underwater").clicked() {
-            next_state.set(SimulationState::Underwater);
+        let new_size = boids_size(world, &state);
+        let mut controls = controls.get_mut(world);
+        if let Some(scale) = new_size.filter(|_| new_size != size) {
+            controls.resize_event_writer.send(ResizeEvent { scale });
         }
-        if ui.button("Sky").clicked() {
-            next_state.set(SimulationState::Sky);
+        mode_controls(ui, &state, &mut controls, &registry);
+    });
+}
+
+fn mode_controls(ui: &mut egui::Ui, state: &SimulationState, controls: &mut ModeControls, registry: &TypeRegistry) {
+    match state {
+        SimulationState::Mode2D => {
+            let radius = &mut controls.shape_settings.radius;
+            ui.add(egui::Slider::new(radius,1.0..=100.0).text("Radius of obstacles"));
+            ui.label("R to remove all obstacles");
         }
+        SimulationState::Mode3D => {}
+        SimulationState::Underwater => {
+            let species_settings = &mut controls.species_settings;
+            // Only flag a change when a widget was touched, so schools are not respawned every frame.
+            let mut changed = false;
+            for species in ALL_SPECIES {
+                let school_settings = species_settings.bypass_change_detection().get_mut(species);
+                egui::CollapsingHeader::new(species.name()).show(ui, |ui| {
+                    changed |= ui.add(egui::Slider::new(&mut school_settings.schools, 0..=30).text("Schools")).changed();
+                    changed |= inspect_settings(ui, &mut school_settings.boids, registry);
+                    if ui.button("Reset to defaults").clicked() {
+                        *school_settings = species.default_settings();
+                        changed = true;
+                    }
+                });
+            }
+            if changed {
+                species_settings.set_changed();
+            }
+        }
+        SimulationState::Sky => {
+            let ModeControls { sky_boid_settings, flight_settings, roost_settings, day_night, murmuration, .. } = controls;
+            ui.separator();
+            ui.checkbox(&mut flight_settings.enabled, "Aerodynamic flight model");
+            if flight_settings.enabled {
+                ui.add(egui::Slider::new(&mut flight_settings.gravity, 0.0..=300.0).text("Gravity"));
+                ui.add(egui::Slider::new(&mut flight_settings.lift_coeff, 0.0..=0.05).text("Lift"));
+                ui.add(egui::Slider::new(&mut flight_settings.drag_coeff, 0.0..=0.01).text("Drag"));
+                ui.add(egui::Slider::new(&mut flight_settings.stall_speed, 0.0..=150.0).text("Stall speed"));
+                ui.add(egui::Slider::new(&mut flight_settings.cruise_speed, 0.0..=300.0).text("Cruise speed"));
+                ui.add(egui::Slider::new(&mut flight_settings.flap_thrust, 0.0..=300.0).text("Flap thrust"));
+                ui.add(egui::Slider::new(&mut flight_settings.flap_lift, 0.0..=300.0).text("Flap lift"));
+                ui.add(egui::Slider::new(&mut flight_settings.flap_energy_cost, 0.0..=100.0).text("Flap energy cost"));
+                ui.add(egui::Slider::new(&mut flight_settings.glide_energy_recovery, 0.0..=100.0).text("Glide energy recovery"));
+            }
+            ui.separator();
+            ui.checkbox(&mut roost_settings.enabled, "Birds perch on islands");
+            let phase = if day_night.is_night() { "night" } else { "day" };
+            ui.add(egui::Slider::new(&mut day_night.time_of_day, 0.0..=1.0).text(format!("Time of day ({phase})")));
+            ui.add(egui::Slider::new(&mut day_night.day_length, 10.0..=600.0).text("Day length (s)"));
+            if roost_settings.enabled {
+                ui.add(egui::Slider::new(&mut roost_settings.fatigue_rate, 0.0..=20.0).text("Fatigue rate"));
+                ui.add(egui::Slider::new(&mut roost_settings.rest_rate, 0.0..=50.0).text("Rest rate"));
+                ui.add(egui::Slider::new(&mut roost_settings.tired_threshold, 0.0..=100.0).text("Tired below"));
+                ui.add(egui::Slider::new(&mut roost_settings.disturbance_radius, 0.0..=100.0).text("Take-off disturbance radius"));
+                ui.add(egui::Slider::new(&mut roost_settings.cascade_probability, 0.0..=1.0).text("Take-off cascade probability"));
+                ui.add(egui::Slider::new(&mut roost_settings.reaction_time, 0.0..=2.0).text("Reaction time (s)"));
+                ui.add(egui::Slider::new(&mut roost_settings.predator_alarm_radius, 0.0..=200.0).text("Predator alarm radius"));
+            }
+            ui.label("P to release or recall a predator");
+            ui.separator();
+            if ui.button("Starling murmuration preset").clicked() {
+                apply_murmuration_preset(sky_boid_settings, murmuration);
+            }
+            ui.checkbox(&mut murmuration.enabled, "Murmuration (topological neighbours, roost)");
+            if murmuration.enabled {
+                ui.add(egui::Slider::new(&mut murmuration.topological_neighbours, 1..=20).text("Topological neighbours"));
+                ui.add(egui::Slider::new(&mut murmuration.roost_radius, 0.0..=300.0).text("Roost radius"));
+                ui.add(egui::Slider::new(&mut murmuration.roost_coeff, 0.0..=20.0).text("Roost attraction"));
+                ui.add(egui::Slider::new(&mut murmuration.predator_trigger_radius, 0.0..=200.0).text("Agitation trigger radius"));
+                ui.add(egui::Slider::new(&mut murmuration.escape_coeff, 0.0..=3000.0).text("Escape"));
+                ui.add(egui::Slider::new(&mut murmuration.reaction_time, 0.0..=1.0).text("Agitation reaction time (s)"));
+                ui.add(egui::Slider::new(&mut murmuration.agitation_duration, 0.0..=5.0).text("Agitation duration (s)"));
+            }
+        }
+    }
+}
+
+pub fn disturbance_waves_ui(
+    mut egui_context: EguiContexts,
+    mut waves: ResMut<DisturbanceWaves>
+) {
+    egui::Window::new("Disturbance waves").default_open(false).show(egui_context.ctx_mut(), |ui| {
+        if ui.button("Trigger agitation wave").clicked() {
+            waves.trigger_requested = true;
+        }
+        egui::Grid::new("waves_grid").striped(true).show(ui, |ui| {
+            ui.label("Wave");
+            ui.label("Birds");
+            ui.label("Speed (u/s)");
+            ui.label("Reach (u)");
+            ui.label("Duration (s)");
+            ui.end_row();
+            for wave in waves.waves.iter().rev().take(5) {
+                ui.label(if wave.active { format!("#{} (running)", wave.id) } else { format!("#{}", wave.id) });
+                ui.label(wave.samples.len().to_string());
+                ui.label(format!("{:.1}", wave.speed()));
+                ui.label(format!("{:.1}", wave.reach()));
+                ui.label(format!("{:.2}", wave.duration()));
+                ui.end_row();
+            }
+        });
+        let Some(wave) = waves.waves.last() else {
+            ui.label("No wave recorded yet");
+            return;
+        };
+        Plot::new("wave_plot")
+            .height(200.0)
+            .x_axis_label("Time since trigger (s)")
+            .y_axis_label("Distance from origin")
+            .show(ui, |plot_ui| {
+                plot_ui.points(Points::new(PlotPoints::from(wave.samples.clone())).radius(1.5).name("Reached birds"));
+                if let Some((slope, intercept)) = wave.fit() {
+                    let end = wave.duration();
+                    plot_ui.line(Line::new(PlotPoints::from(vec![[0.0, intercept], [end, intercept + slope * end]])).name("Front"));
+                }
+            });
     });
+}
 
-    if state.get() == &SimulationState::Underwater {
-        return;
+fn collective_state_color(state: CollectiveState) -> egui::Color32 {
+    match state {
+        CollectiveState::Swarm => egui::Color32::from_rgb(230, 200, 60),
+        CollectiveState::Milling => egui::Color32::from_rgb(80, 200, 230),
+        CollectiveState::Polarised => egui::Color32::from_rgb(90, 220, 90),
+        CollectiveState::Transitioning => egui::Color32::from_rgb(200, 200, 200)
     }
+}
+
+pub fn school_states_ui(
+    mut egui_context: EguiContexts,
+    school_states: Res<SchoolStates>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<UnderwaterMarker>>
+) {
+    let ctx = egui_context.ctx_mut();
 
-    egui::Window::new("Boids settings").show(egui_context.ctx_mut(), |ui| {
-        match *state.get() {
-            SimulationState::Mode2D => {
-                let bounce = &mut boid_settings_2d.bounce_against_walls;
-                ui.checkbox(bounce, "Boids bounce against walls");
-                let boids_count = &mut boid_settings_2d.count;
-                ui.add(egui::Slider::new(boids_count, 0..=15000).text("Boids count"));
-                let min_speed = &mut boid_settings_2d.min_speed;
-                ui.add(egui::Slider::new(min_speed, 0.0..=500.0).text("Min speed"));
-                let max_speed = &mut boid_settings_2d.max_speed;
-                ui.add(egui::Slider::new(max_speed, 0.0..=1000.0).text("Max speed"));
-                let field_of_view = &mut boid_settings_2d.field_of_view;
-                ui.add(egui::Slider::new(field_of_view, 0.0..=360.0).text("Field of view"));
-                let cohesion_range = &mut boid_settings_2d.cohesion_range;
-                ui.add(egui::Slider::new(cohesion_range, 0.0..=100.0).text("Cohesion range"));
-                let max_alignment_range = *cohesion_range;
-                let alignment_range = &mut boid_settings_2d.alignment_range;
-                ui.add(egui::Slider::new(alignment_range, 0.0..=max_alignment_range).text("Alignment range"));
-                let max_separation_range = *alignment_range;
-                let separation_range = &mut boid_settings_2d.separation_range;
-                ui.add(egui::Slider::new(separation_range, 0.0..=max_separation_range).text("Separation range"));
-                let cohesion_coeff = &mut boid_settings_2d.cohesion_coeff;
-                ui.add(egui::Slider::new(cohesion_coeff, 0.0..=50.0).text("Cohesion"));
-                let aligment_coeff = &mut boid_settings_2d.alignment_coeff;
-                ui.add(egui::Slider::new(aligment_coeff, 0.0..=50.0).text("Alignment"));
-                let separation_coeff = &mut boid_settings_2d.separation_coeff;
-                ui.add(egui::Slider::new(separation_coeff, 0.0..=50.0).text("Separation"));
-                let min_distance_between_boids = &mut boid_settings_2d.min_distance_between_boids;
-                ui.add(egui::Slider::new(min_distance_between_boids, 0.0..=50.0).text("Minimum distance between boids"));
-                let collision_coeff = &mut boid_settings_2d.collision_coeff;
-                ui.add(egui::Slider::new(collision_coeff, 0.0..=50.0).text("Collision"));
-                let attraction_coeff = &mut boid_settings_2d.attraction_coeff;
-                ui.add(egui::Slider::new(attraction_coeff, 0.0..=100.0).text("Attraction to target"));
-                let radius = &mut shape_settings.radius;
-                ui.add(egui::Slider::new(radius,1.0..=100.0).text("Radius of obstacles"));
-                ui.label("R to remove all obstacles");
+    if let Ok((camera, camera_transform)) = camera_query.get_single() {
+        for school in school_states.schools.iter() {
+            let Some(screen_position) = camera.world_to_viewport(camera_transform, school.centroid) else {
+                continue;
+            };
+            egui::Area::new(format!("school_{:?}_{}", school.species, school.school_id))
+                .fixed_pos(egui::pos2(screen_position.x, screen_position.y))
+                .interactable(false)
+                .show(ctx, |ui| {
+                    ui.label(egui::RichText::new(school.state.label())
+                        .color(collective_state_color(school.state))
+                        .background_color(egui::Color32::from_black_alpha(140)));
+                });
+        }
+    }
+
+    egui::Window::new("School states").default_open(false).show(ctx, |ui| {
+        egui::Grid::new("school_states_grid").striped(true).show(ui, |ui| {
+            ui.label("School");
+            ui.label("Fish");
+            ui.label("Polarisation");
+            ui.label("Milling");
+            ui.label("State");
+            ui.end_row();
+            for school in school_states.schools.iter() {
+                ui.label(format!("{} #{}", school.species.name(), school.school_id));
+                ui.label(school.fish.to_string());
+                ui.label(format!("{:.2}", school.polarisation));
+                ui.label(format!("{:.2}", school.angular_momentum));
+                ui.colored_label(collective_state_color(school.state), school.state.label());
+                ui.end_row();
             }
-            SimulationState::Mode3D => {
-                let bounce = &mut boid_settings_3d.bounce_against_walls;
-                ui.checkbox(bounce, "Boids bounce against walls");
-                let boids_count = &mut boid_settings_3d.count;
-                ui.add(egui::Slider::new(boids_count, 0..=15000).text("Boids count"));
-                let min_speed = &mut boid_settings_3d.min_speed;
-                ui.add(egui::Slider::new(min_speed, 0.0..=500.0).text("Min speed"));
-                let max_speed = &mut boid_settings_3d.max_speed;
-                ui.add(egui::Slider::new(max_speed, 0.0..=1000.0).text("Max speed"));
-                let field_of_view = &mut boid_settings_3d.field_of_view;
-                ui.add(egui::Slider::new(field_of_view, 0.0..=360.0).text("Field of view"));
-                let cohesion_range = &mut boid_settings_3d.cohesion_range;
-                ui.add(egui::Slider::new(cohesion_range, 0.0..=100.0).text("Cohesion range"));
-                let max_alignment_range = *cohesion_range;
-                let alignment_range = &mut boid_settings_3d.alignment_range;
-                ui.add(egui::Slider::new(alignment_range, 0.0..=max_alignment_range).text("Alignment range"));
-                let max_separation_range = *alignment_range;
-                let separation_range = &mut boid_settings_3d.separation_range;
-                ui.add(egui::Slider::new(separation_range, 0.0..=max_separation_range).text("Separation range"));
-                let cohesion_coeff = &mut boid_settings_3d.cohesion_coeff;
-                ui.add(egui::Slider::new(cohesion_coeff, 0.0..=50.0).text("Cohesion"));
-                let aligment_coeff = &mut boid_settings_3d.alignment_coeff;
-                ui.add(egui::Slider::new(aligment_coeff, 0.0..=50.0).text("Alignment"));
-                let separation_coeff = &mut boid_settings_3d.separation_coeff;
-                ui.add(egui::Slider::new(separation_coeff, 0.0..=50.0).text("Separation"));
-                let min_distance_between_boids = &mut boid_settings_3d.min_distance_between_boids;
-                ui.add(egui::Slider::new(min_distance_between_boids, 0.0..=50.0).text("Minimum distance between boids"));
-                let collision_coeff = &mut boid_settings_3d.collision_coeff;
-                ui.add(egui::Slider::new(collision_coeff, 0.0..=50.0).text("Collision"));
-                let attraction_coeff = &mut boid_settings_3d.attraction_coeff;
-                ui.add(egui::Slider::new(attraction_coeff, 0.0..=100.0).text("Attraction to target"));
-                let boids_size = &mut boid_settings_3d.size;
-                if ui.add(egui::Slider::new(boids_size, 0.1..=20.0).text("Boids size")).changed() {
-                    resize_event_writer.send(ResizeEvent {
-                        scale: *boids_size
-                    });
+        });
+        ui.separator();
+        ui.label("Transitions");
+        for transition in school_states.transitions.iter().rev().take(10) {
+            ui.label(format!(
+                "{:.1}s  {} #{}: {} -> {}",
+                transition.time,
+                transition.species.name(),
+                transition.school_id,
+                transition.from.label(),
+                transition.to.label()
+            ));
+        }
+    });
+}
+
+pub fn population_ui(
+    mut egui_context: EguiContexts,
+    mut ecology: ResMut<EcologySettings>,
+    history: Res<PopulationHistory>
+) {
+    egui::Window::new("Population").default_open(false).show(egui_context.ctx_mut(), |ui| {
+        ui.checkbox(&mut ecology.enabled, "Predator-prey interactions");
+        ui.checkbox(&mut ecology.respawn, "Respawn at spawning grounds");
+        ui.add(egui::Slider::new(&mut ecology.hunt_range, 0.0..=150.0).text("Hunt range"));
+        ui.add(egui::Slider::new(&mut ecology.hunt_coeff, 0.0..=2000.0).text("Hunt coeff"));
+        ui.add(egui::Slider::new(&mut ecology.flee_range, 0.0..=150.0).text("Flee range"));
+        ui.add(egui::Slider::new(&mut ecology.flee_coeff, 0.0..=10000.0).text("Flee coeff"));
+        ui.add(egui::Slider::new(&mut ecology.avoid_range, 0.0..=150.0).text("Avoid range"));
+        ui.add(egui::Slider::new(&mut ecology.avoid_coeff, 0.0..=5000.0).text("Avoid coeff"));
+        ui.add(egui::Slider::new(&mut ecology.catch_distance, 0.0..=20.0).text("Catch distance"));
+        ui.separator();
+        for species in ALL_SPECIES {
+            let alive = history.samples.get(&species)
+                .and_then(|samples| samples.last())
+                .map_or(0.0, |[_, count]| *count);
+            let eaten = history.eaten.get(&species).copied().unwrap_or(0);
+            ui.label(format!("{}: {alive:.0} alive, {eaten} eaten", species.name()));
+        }
+        Plot::new("population_plot")
+            .height(200.0)
+            .legend(egui_plot::Legend::default())
+            .x_axis_label("Time (s)")
+            .y_axis_label("Fish")
+            .show(ui, |plot_ui| {
+                for species in ALL_SPECIES {
+                    if let Some(samples) = history.samples.get(&species) {
+                        plot_ui.line(Line::new(PlotPoints::from(samples.clone())).name(species.name()));
+                    }
+                }
+            });
+    });
+}
+
+pub fn metrics_ui(
+    mut egui_context: EguiContexts,
+    mut metrics: ResMut<FlockMetrics>,
+    mut metrics_settings: ResMut<MetricsSettings>
+) {
+    egui::Window::new("Metrics").default_open(false).show(egui_context.ctx_mut(), |ui| {
+        ui.add(egui::Slider::new(&mut metrics_settings.sample_interval, 1..=120).text("Sample every N frames"));
+        ui.add(egui::Slider::new(&mut metrics_settings.neighbour_radius, 1.0..=200.0).text("Neighbour radius"));
+        ui.add(egui::Slider::new(&mut metrics_settings.collision_distance, 0.1..=20.0).text("Collision distance"));
+        ui.add(egui::Slider::new(&mut metrics_settings.capacity, 10..=5000).text("Samples kept"));
+        egui::ComboBox::from_label("Plotted metric")
+            .selected_text(metrics.plotted.label())
+            .show_ui(ui, |ui| {
+                for metric in Metric::ALL {
+                    ui.selectable_value(&mut metrics.plotted, metric, metric.label());
                 }
+            });
+        egui::Grid::new("metrics_grid").striped(true).show(ui, |ui| {
+            ui.label("Group");
+            ui.label("Boids");
+            for metric in Metric::ALL {
+                ui.label(metric.label());
             }
-            SimulationState::Underwater => {
-                return;
+            ui.end_row();
+            for (group, series) in metrics.sorted_groups() {
+                let Some(latest) = series.latest() else { continue };
+                ui.label(group);
+                ui.label(latest.boids.to_string());
+                for metric in Metric::ALL {
+                    ui.label(format!("{:.2}", metric.value(latest)));
+                }
+                ui.end_row();
             }
-            SimulationState::Sky => {
-                let boids_count = &mut sky_boid_settings.count;
-                ui.add(egui::Slider::new(boids_count, 0..=15000).text("Birds count"));
-                let min_speed = &mut sky_boid_settings.min_speed;
-                ui.add(egui::Slider::new(min_speed, 0.0..=500.0).text("Min speed"));
-                let max_speed = &mut sky_boid_settings.max_speed;
-                ui.add(egui::Slider::new(max_speed, 0.0..=1000.0).text("Max speed"));
-                let field_of_view = &mut sky_boid_settings.field_of_view;
-                ui.add(egui::Slider::new(field_of_view, 0.0..=360.0).text("Field of view"));
-                let cohesion_range = &mut sky_boid_settings.cohesion_range;
-                ui.add(egui::Slider::new(cohesion_range, 0.0..=100.0).text("Cohesion range"));
-                let max_alignment_range = *cohesion_range;
-                let alignment_range = &mut sky_boid_settings.alignment_range;
-                ui.add(egui::Slider::new(alignment_range, 0.0..=max_alignment_range).text("Alignment range"));
-                let max_separation_range = *alignment_range;
-                let separation_range = &mut sky_boid_settings.separation_range;
-                ui.add(egui::Slider::new(separation_range, 0.0..=max_separation_range).text("Separation range"));
-                let cohesion_coeff = &mut sky_boid_settings.cohesion_coeff;
-                ui.add(egui::Slider::new(cohesion_coeff, 0.0..=50.0).text("Cohesion"));
-                let aligment_coeff = &mut sky_boid_settings.alignment_coeff;
-                ui.add(egui::Slider::new(aligment_coeff, 0.0..=50.0).text("Alignment"));
-                let separation_coeff = &mut sky_boid_settings.separation_coeff;
-                ui.add(egui::Slider::new(separation_coeff, 0.0..=50.0).text("Separation"));
-                let min_distance_between_boids = &mut sky_boid_settings.min_distance_between_boids;
-                ui.add(egui::Slider::new(min_distance_between_boids, 0.0..=50.0).text("Minimum distance between birds"));
-                let collision_coeff = &mut sky_boid_settings.collision_coeff;
-                ui.add(egui::Slider::new(collision_coeff, 0.0..=50.0).text("Collision"));
-                let attraction_coeff = &mut sky_boid_settings.attraction_coeff;
-                ui.add(egui::Slider::new(attraction_coeff, 0.0..=100.0).text("Attraction to target"));
-                let boids_size = &mut sky_boid_settings.size;
-                if ui.add(egui::Slider::new(boids_size, 0.1..=20.0).text("Birds size")).changed() {
-                    resize_event_writer.send(ResizeEvent {
-                        scale: *boids_size
-                    });
+        });
+        let plotted = metrics.plotted;
+        Plot::new("metrics_plot")
+            .height(200.0)
+            .legend(egui_plot::Legend::default())
+            .x_axis_label("Time (s)")
+            .y_axis_label(plotted.label())
+            .show(ui, |plot_ui| {
+                for (group, series) in metrics.sorted_groups() {
+                    plot_ui.line(Line::new(PlotPoints::from(series.points(plotted))).name(group));
+                }
+            });
+    });
+}
+
+pub fn trajectory_export_ui(
+    mut egui_context: EguiContexts,
+    mut recorder: ResMut<TrajectoryRecorder>
+) {
+    egui::Window::new("Trajectory export").default_open(false).show(egui_context.ctx_mut(), |ui| {
+        let recording = recorder.is_recording();
+        ui.add_enabled_ui(!recording, |ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut recorder.format, ExportFormat::Csv, "CSV");
+                ui.radio_value(&mut recorder.format, ExportFormat::JsonLines, "JSON Lines");
+            });
+            ui.add(egui::Slider::new(&mut recorder.every_k_steps, 1..=120).text("Write every k steps"));
+        });
+        let label = if recording { "Stop recording" } else { "Start recording" };
+        if ui.button(label).clicked() {
+            recorder.toggle_requested = true;
+        }
+        ui.label("F9 to start or stop recording");
+        if let Some(path) = recorder.path.as_ref() {
+            ui.label(format!("{} ({} rows)", path.display(), recorder.rows));
+        }
+        if let Some(error) = recorder.error.as_ref() {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    });
+}
+
+pub fn flocks_ui(
+    mut egui_context: EguiContexts,
+    mut settings: ResMut<ClusteringSettings>,
+    clusters: Res<FlockClusters>,
+    state: Res<State<SimulationState>>
+) {
+    egui::Window::new("Flocks").default_open(false).show(egui_context.ctx_mut(), |ui| {
+        ui.add(egui::Slider::new(&mut settings.link_distance, 1.0..=100.0).text("Link distance"));
+        ui.add(egui::Slider::new(&mut settings.min_flock_size, 1..=50).text("Min flock size"));
+        ui.add(egui::Slider::new(&mut settings.interval, 1..=120).text("Cluster every N frames"));
+        ui.add_enabled(
+            matches!(state.get(), SimulationState::Mode2D | SimulationState::Mode3D),
+            egui::Checkbox::new(&mut settings.tint_by_flock, "Colour boids by flock (2D and 3D modes)")
+        );
+        ui.label(format!("{} flocks", clusters.flocks.len()));
+        egui::Grid::new("flocks_grid").striped(true).show(ui, |ui| {
+            ui.label("Flock");
+            ui.label("Boids");
+            ui.label("Centroid");
+            ui.end_row();
+            for flock in clusters.flocks.iter().take(10) {
+                let [r, g, b, _] = flock_color(flock.id).as_rgba_u8();
+                ui.colored_label(egui::Color32::from_rgb(r, g, b), format!("#{}", flock.id));
+                ui.label(flock.size.to_string());
+                ui.label(format!("({:.0}, {:.0}, {:.0})", flock.centroid.x, flock.centroid.y, flock.centroid.z));
+                ui.end_row();
+            }
+        });
+        ui.separator();
+        ui.label("Splits and merges");
+        for event in clusters.events.iter().rev().take(10) {
+            ui.label(event.describe());
+        }
+    });
+}
+
+pub fn replay_ui(
+    mut egui_context: EguiContexts,
+    mut recorder: ResMut<SimulationRecorder>,
+    mut replay: ResMut<Replay>,
+    mut was_recording: Local<Option<bool>>
+) {
+    // The list of recordings is refreshed on startup and whenever a recording stops.
+    if *was_recording != Some(recorder.is_recording()) {
+        *was_recording = Some(recorder.is_recording());
+        let directory = recorder.directory.clone();
+        replay.refresh_files(&directory);
+    }
+    egui::Window::new("Replay").default_open(false).show(egui_context.ctx_mut(), |ui| {
+        let label = if recorder.is_recording() { "Stop recording" } else { "Record simulation" };
+        if ui.add_enabled(!replay.is_active(), egui::Button::new(label)).clicked() {
+            recorder.toggle_requested = true;
+        }
+        ui.label("F10 to start or stop recording");
+        if let Some(path) = recorder.path.as_ref() {
+            ui.label(format!("{} ({} frames)", path.display(), recorder.frames));
+        }
+        if let Some(error) = recorder.error.as_ref() {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        ui.separator();
+
+        if let Some(duration) = replay.recording.as_ref().map(|recording| recording.duration()) {
+            if let Some(recording) = replay.recording.as_ref() {
+                ui.label(format!(
+                    "{:?} recording, boidsy {}",
+                    recording.mode,
+                    recording.header["version"].as_str().unwrap_or("unknown")
+                ));
+            }
+            let frames = replay.recording.as_ref().map_or(0, |recording| recording.frames.len());
+            ui.horizontal(|ui| {
+                if ui.button("⏮").clicked() {
+                    replay.step_requested = -1;
+                }
+                let label = if replay.playing { "Pause" } else { "Play" };
+                if ui.button(label).clicked() {
+                    if !replay.playing && replay.cursor >= duration {
+                        replay.cursor = 0.0;
+                    }
+                    replay.playing = !replay.playing;
                 }
+                if ui.button("⏭").clicked() {
+                    replay.step_requested = 1;
+                }
+            });
+            ui.add(egui::Slider::new(&mut replay.cursor, 0.0..=duration).text("Time (s)"));
+            ui.add(egui::Slider::new(&mut replay.speed, 0.1..=4.0).logarithmic(true).text("Speed"));
+            ui.label(format!("Frame {} / {}", replay.frame + 1, frames));
+            if ui.button("Exit replay").clicked() {
+                replay.exit_requested = true;
             }
+        } else {
+            if ui.button("Refresh recordings").clicked() {
+                let directory = recorder.directory.clone();
+                replay.refresh_files(&directory);
+            }
+            let mut requested = None;
+            for path in replay.files.iter() {
+                ui.horizontal(|ui| {
+                    ui.label(path.file_name().unwrap_or_default().to_string_lossy());
+                    if ui.add_enabled(!recorder.is_recording(), egui::Button::new("Load")).clicked() {
+                        requested = Some(path.clone());
+                    }
+                });
+            }
+            if requested.is_some() {
+                replay.load_requested = requested;
+            }
+        }
+        if let Some(error) = replay.error.as_ref() {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    });
+}
+
+pub fn presets_ui(
+    mut egui_context: EguiContexts,
+    mut presets: ResMut<Presets>,
+    state: Res<State<SimulationState>>
+) {
+    egui::Window::new("Presets").default_open(false).show(egui_context.ctx_mut(), |ui| {
+        if state.get() == &SimulationState::Underwater {
+            let species = presets.species;
+            egui::ComboBox::from_label("Species")
+                .selected_text(species.name())
+                .show_ui(ui, |ui| {
+                    for species in ALL_SPECIES {
+                        ui.selectable_value(&mut presets.species, species, species.name());
+                    }
+                });
+        }
+        ui.label("Built-in");
+        ui.horizontal(|ui| {
+            for preset in BuiltInPreset::ALL {
+                if ui.button(preset.name()).clicked() {
+                    presets.requests.push(PresetRequest::LoadBuiltIn(preset));
+                }
+            }
+        });
+        if ui.button("Reset to defaults").clicked() {
+            presets.requests.push(PresetRequest::Reset);
+        }
+        ui.separator();
+
+        ui.label("Saved");
+        let mut requests = Vec::new();
+        for path in presets.files.iter() {
+            ui.horizontal(|ui| {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                if presets.is_active(path) {
+                    ui.strong(format!("{name} (live)"));
+                } else {
+                    ui.label(name);
+                }
+                if ui.button("Load").clicked() {
+                    requests.push(PresetRequest::LoadFile(path.clone()));
+                }
+                if ui.button("Delete").clicked() {
+                    requests.push(PresetRequest::Delete(path.clone()));
+                }
+            });
+        }
+        presets.requests.extend(requests);
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut presets.new_name);
+            if ui.button("Save as").clicked() {
+                let name = std::mem::take(&mut presets.new_name);
+                presets.requests.push(PresetRequest::SaveAs(name));
+            }
+        });
+        ui.label("Presets keep the current population. The last loaded or saved preset reloads when its file changes.");
+        if let Some(error) = presets.error.as_ref() {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    });
+}
+
+pub fn flocking_rules_ui(
+    mut egui_context: EguiContexts,
+    mut rules: ResMut<FlockingRules>,
+    mut script_file: ResMut<ScriptFile>,
+    state: Res<State<SimulationState>>
+) {
+    egui::Window::new("Flocking rules").default_open(false).show(egui_context.ctx_mut(), |ui| {
+        let count = rules.rules_mut(state.get()).len();
+        let mut raised = None;
+        for (index, entry) in rules.rules_mut(state.get()).iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut entry.enabled, entry.rule.name());
+                ui.add(egui::Slider::new(&mut entry.weight, 0.0..=5.0).text("Weight"));
+                if ui.add_enabled(index > 0, egui::Button::new("Up")).clicked() {
+                    raised = Some(index);
+                }
+                if ui.add_enabled(index + 1 < count, egui::Button::new("Down")).clicked() {
+                    raised = Some(index + 1);
+                }
+            });
+        }
+        if let Some(index) = raised {
+            rules.raise(state.get(), index);
+        }
+        ui.label("Weighted forces of the enabled rules are added from top to bottom until they reach the max steering force, so the rules at the top win. 2D has no limit.");
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label(format!("Custom rule: {}", script_file.path.display()));
+            if ui.button("Reload").clicked() {
+                script_file.reload_requested = true;
+            }
+        });
+        ui.label(if script_file.is_loaded() { "Script loaded" } else { "No script loaded" });
+        if let Some(error) = script_file.error() {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    });
+}
+
+fn direction_label(force: Vec3, two_d: bool) -> String {
+    let direction = force.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        "-".to_string()
+    } else if two_d {
+        format!("({:.2}, {:.2})", direction.x, direction.y)
+    } else {
+        format!("({:.2}, {:.2}, {:.2})", direction.x, direction.y, direction.z)
+    }
+}
+
+pub fn boid_inspector_ui(
+    mut egui_context: EguiContexts,
+    mut selected: ResMut<SelectedBoid>,
+    state: Res<State<SimulationState>>
+) {
+    let two_d = state.get() == &SimulationState::Mode2D;
+    let mut deselect = false;
+    egui::Window::new("Boid inspector").default_open(false).show(egui_context.ctx_mut(), |ui| {
+        let Some(report) = selected.report.as_ref() else {
+            ui.label("Middle click a boid to inspect it.");
+            return;
+        };
+        ui.label(format!("{}, speed {:.1}", report.group, report.velocity.length()));
+        match report.context.as_ref() {
+            Some(context) => {
+                egui::Grid::new("inspector_zones_grid").striped(true).show(ui, |ui| {
+                    ui.label("Zone");
+                    ui.label("Neighbours");
+                    ui.end_row();
+                    for (zone, count) in [
+                        ("Separation", context.separation_neighbours.len()),
+                        ("Alignment", context.alignment_neighbours.len()),
+                        ("Cohesion", context.cohesion_neighbours.len())
+                    ] {
+                        ui.label(zone);
+                        ui.label(count.to_string());
+                        ui.end_row();
+                    }
+                });
+            }
+            None => {
+                ui.label("Did not flock this frame.");
+            }
+        }
+        ui.separator();
+        egui::Grid::new("inspector_forces_grid").striped(true).show(ui, |ui| {
+            ui.label("Force");
+            ui.label("Magnitude");
+            ui.label("Direction");
+            ui.end_row();
+            for (name, force) in report.forces.iter() {
+                ui.label(name);
+                ui.label(format!("{:.1}", force.length()));
+                ui.label(direction_label(*force, two_d));
+                ui.end_row();
+            }
+        });
+        ui.label("Rule forces are weighted. Lines join the neighbours: red to separate from, blue to align with, green to cohere with.");
+        deselect = ui.button("Deselect").clicked();
+    });
+    if deselect {
+        selected.select(None);
+    }
+}
+
+pub fn debug_overlay_ui(
+    mut egui_context: EguiContexts,
+    mut overlay: ResMut<DebugOverlay>
+) {
+    egui::Window::new("Debug overlay").default_open(false).show(egui_context.ctx_mut(), |ui| {
+        for layer in OverlayLayer::ALL {
+            let mut shown = overlay.shows(layer);
+            if ui.checkbox(&mut shown, layer.name()).changed() {
+                if shown {
+                    overlay.layers.insert(layer);
+                } else {
+                    overlay.layers.remove(&layer);
+                }
+            }
+            ui.indent(layer.name(), |ui| {
+                for (color, meaning) in layer.legend() {
+                    let [r, g, b, _] = color.as_rgba_u8();
+                    ui.horizontal(|ui| {
+                        ui.colored_label(egui::Color32::from_rgb(r, g, b), "■");
+                        ui.label(meaning);
+                    });
+                }
+            });
+        }
+        ui.separator();
+        ui.checkbox(&mut overlay.selected_only, "Only the selected boid");
+        ui.add(egui::Slider::new(&mut overlay.vector_scale, 0.01..=1.0).logarithmic(true).text("Vector scale"));
+    });
+}
+
+pub fn snapshots_ui(
+    mut egui_context: EguiContexts,
+    mut snapshots: ResMut<Snapshots>
+) {
+    egui::Window::new("World snapshots").default_open(false).show(egui_context.ctx_mut(), |ui| {
+        if ui.button("Save snapshot").clicked() {
+            snapshots.save_requested = true;
+        }
+        ui.label("F5 to save a snapshot");
+        if let Some(path) = snapshots.last_saved.as_ref() {
+            ui.label(format!("Saved {}", path.display()));
+        }
+        ui.separator();
+        if ui.button("Refresh snapshots").clicked() {
+            snapshots.refresh_files();
+        }
+        let mut requested = None;
+        for path in snapshots.files.iter() {
+            ui.horizontal(|ui| {
+                ui.label(path.file_name().unwrap_or_default().to_string_lossy());
+                if ui.button("Load").clicked() {
+                    requested = Some(path.clone());
+                }
+            });
+        }
+        if requested.is_some() {
+            snapshots.load_requested = requested;
+        }
+        if let Some(error) = snapshots.error.as_ref() {
+            ui.colored_label(egui::Color32::RED, error);
         }
     });
 }
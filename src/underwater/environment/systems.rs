@@ -1,6 +1,7 @@
 use bevy::prelude::*;
 use rand::prelude::*;
 use super::components::*;
+use crate::random::SystemRng;
 use crate::underwater::{UnderwaterMarker, submarine::components::Submarine};
 
 pub fn setup_environment(
@@ -21,12 +22,13 @@ pub fn spawn_particles(
     mut effect: ResMut<UnderwaterEffect>,
     time: Res<Time>,
     submarine_query: Query<&Transform, With<Submarine>>,
+    mut rng: Local<SystemRng>,
 ) {
     effect.particle_spawn_timer.tick(time.delta());
 
     if effect.particle_spawn_timer.just_finished() {
         if let Ok(submarine_transform) = submarine_query.get_single() {
-            let mut rng = rand::thread_rng();
+            let rng = rng.get("spawn_particles");
             
             let spawn_position = submarine_transform.translation + Vec3::new(
                 rng.gen_range(-5.0..5.0),
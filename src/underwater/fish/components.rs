@@ -1,8 +1,12 @@
 use bevy::prelude::*;
 use crate::boids_3d::resources::BoidSettings3D;
+use crate::settings::{FieldHints, ReflectSettingsHints, SettingsHints};
+use serde::{Deserialize, Serialize};
 use std::ops::{Deref, DerefMut};
 
-#[derive(Resource)]
+#[derive(Resource, Clone, Serialize, Deserialize, Reflect)]
+#[reflect(Resource, SettingsHints)]
+#[serde(transparent)]
 pub struct UnderwaterBoidSettings(BoidSettings3D);
 
 impl Default for UnderwaterBoidSettings {
@@ -23,7 +27,11 @@ impl Default for UnderwaterBoidSettings {
             max_speed: 100.0,
             bounce_against_walls: true,
             attraction_coeff: 1.0,
-            field_of_view: 270.0
+            field_of_view: 270.0,
+            max_force: 1500.0,
+            max_angular_velocity: 270.0,
+            banking_coeff: 0.001,
+            max_bank_angle: 30.0
         })
     }
 }
@@ -37,6 +45,68 @@ impl DerefMut for UnderwaterBoidSettings {
     fn deref_mut(&mut self) -> &mut Self::Target { &mut self.0 }
 }
 
+/// Fish stay in the water volume and have no group targets, so walls, attraction and the
+/// collision boost are not shown.
+impl SettingsHints for UnderwaterBoidSettings {
+    fn field_hints(field: &str) -> Option<FieldHints> {
+        Some(match field {
+            "count" => FieldHints::new("Fish per school").range(0.0, 500.0),
+            "size" => FieldHints::new("Fish size").range(0.1, 20.0),
+            "bounce_against_walls" | "attraction_coeff" | "min_distance_between_boids" | "collision_coeff" => FieldHints::hidden(),
+            _ => return BoidSettings3D::field_hints(field)
+        })
+    }
+}
+
+/// `boids.count` is the number of fish in each school.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SchoolSettings {
+    pub schools: usize,
+    pub previous_schools: usize,
+    pub boids: UnderwaterBoidSettings
+}
+
+impl SchoolSettings {
+    pub fn population(&self) -> usize {
+        self.schools * self.boids.count
+    }
+}
+
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub struct SpeciesSettings {
+    pub small_fish: SchoolSettings,
+    pub medium_fish: SchoolSettings,
+    pub large_fish: SchoolSettings
+}
+
+impl Default for SpeciesSettings {
+    fn default() -> Self {
+        Self {
+            small_fish: Species::SmallFish.default_settings(),
+            medium_fish: Species::MediumFish.default_settings(),
+            large_fish: Species::LargeFish.default_settings()
+        }
+    }
+}
+
+impl SpeciesSettings {
+    pub fn get(&self, species: Species) -> &SchoolSettings {
+        match species {
+            Species::SmallFish => &self.small_fish,
+            Species::MediumFish => &self.medium_fish,
+            Species::LargeFish => &self.large_fish
+        }
+    }
+
+    pub fn get_mut(&mut self, species: Species) -> &mut SchoolSettings {
+        match species {
+            Species::SmallFish => &mut self.small_fish,
+            Species::MediumFish => &mut self.medium_fish,
+            Species::LargeFish => &mut self.large_fish
+        }
+    }
+}
+
 #[derive(Resource)]
 pub struct FishModel(pub Handle<Scene>);
 
@@ -46,7 +116,7 @@ pub struct FishType {
     pub school_id: usize,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Species {
     SmallFish,
     MediumFish,
@@ -54,29 +124,45 @@ pub enum Species {
 }
 
 impl Species {
-    pub fn get_settings(&self) -> UnderwaterBoidSettings {
+    pub fn name(&self) -> &'static str {
         match self {
-            Species::SmallFish => UnderwaterBoidSettings(BoidSettings3D {
+            Species::SmallFish => "Small fish",
+            Species::MediumFish => "Medium fish",
+            Species::LargeFish => "Large fish"
+        }
+    }
+
+    pub fn default_settings(&self) -> SchoolSettings {
+        let (schools, boids) = match self {
+            Species::SmallFish => (10, BoidSettings3D {
                 count: 150,
+                previous_count: 150,
                 size: 10.0,
                 min_speed: 30.0,
                 max_speed: 150.0,
                 ..default()
             }),
-            Species::MediumFish => UnderwaterBoidSettings(BoidSettings3D {
+            Species::MediumFish => (5, BoidSettings3D {
                 count: 30,
+                previous_count: 30,
                 size: 5.0,
                 min_speed: 20.0,
                 max_speed: 75.0,
                 ..default()
             }),
-            Species::LargeFish => UnderwaterBoidSettings(BoidSettings3D {
+            Species::LargeFish => (3, BoidSettings3D {
                 count: 10,
+                previous_count: 10,
                 size: 1.0,
                 min_speed: 20.0,
                 max_speed: 50.0,
                 ..default()
             })
+        };
+        SchoolSettings {
+            schools,
+            previous_schools: schools,
+            boids: UnderwaterBoidSettings(boids)
         }
     }
 }
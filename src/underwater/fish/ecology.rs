@@ -0,0 +1,229 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_spatial::SpatialAccess;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::kd_tree_3d::components::NNTree3D;
+use crate::random::SystemRng;
+use crate::underwater::terrain::{GROUND_Y_POSITION, TERRAIN_SIZE};
+use super::components::*;
+use super::systems::spawn_fish;
+
+pub const ALL_SPECIES: [Species; 3] = [Species::SmallFish, Species::MediumFish, Species::LargeFish];
+const MAX_POPULATION_SAMPLES: usize = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reaction {
+    Ignore,
+    Hunt,
+    Flee,
+    Avoid
+}
+
+/// How a fish of the first species reacts to a neighbour of the second one.
+#[derive(Resource)]
+pub struct SpeciesInteractions {
+    pub table: HashMap<(Species, Species), Reaction>
+}
+
+impl Default for SpeciesInteractions {
+    fn default() -> Self {
+        let mut table = HashMap::new();
+        table.insert((Species::LargeFish, Species::SmallFish), Reaction::Hunt);
+        table.insert((Species::SmallFish, Species::LargeFish), Reaction::Flee);
+        table.insert((Species::MediumFish, Species::LargeFish), Reaction::Avoid);
+        Self { table }
+    }
+}
+
+impl SpeciesInteractions {
+    pub fn get(&self, species: Species, other: Species) -> Reaction {
+        self.table.get(&(species, other)).copied().unwrap_or(Reaction::Ignore)
+    }
+}
+
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub struct EcologySettings {
+    pub enabled: bool,
+    pub hunt_range: f32,
+    pub hunt_coeff: f32,
+    pub flee_range: f32,
+    pub flee_coeff: f32,
+    pub avoid_range: f32,
+    pub avoid_coeff: f32,
+    pub catch_distance: f32,
+    pub threatened_cohesion_factor: f32,
+    pub threatened_separation_factor: f32,
+    pub respawn: bool
+}
+
+impl Default for EcologySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            hunt_range: 60.0,
+            hunt_coeff: 400.0,
+            flee_range: 40.0,
+            flee_coeff: 3000.0,
+            avoid_range: 30.0,
+            avoid_coeff: 1000.0,
+            catch_distance: 4.0,
+            threatened_cohesion_factor: 2.5,
+            threatened_separation_factor: 0.5,
+            respawn: true
+        }
+    }
+}
+
+impl EcologySettings {
+    pub fn max_range(&self) -> f32 {
+        if self.enabled {
+            self.hunt_range.max(self.flee_range).max(self.avoid_range)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Where each species respawns after being eaten.
+#[derive(Resource, Default)]
+pub struct SpawningGrounds {
+    pub grounds: HashMap<Species, Vec<Vec3>>
+}
+
+#[derive(Resource, Default)]
+pub struct PopulationHistory {
+    pub samples: HashMap<Species, Vec<[f64; 2]>>,
+    pub eaten: HashMap<Species, usize>,
+    pub timer: Timer
+}
+
+pub fn reset_ecology(
+    mut spawning_grounds: ResMut<SpawningGrounds>,
+    mut history: ResMut<PopulationHistory>,
+    mut rng: Local<SystemRng>
+) {
+    let rng = rng.get("reset_ecology");
+    spawning_grounds.grounds.clear();
+    for species in ALL_SPECIES {
+        let grounds = (0..3).map(|_| Vec3::new(
+            rng.gen_range(-TERRAIN_SIZE / 2.0..TERRAIN_SIZE / 2.0) * 0.6,
+            rng.gen_range(GROUND_Y_POSITION..-10.0),
+            rng.gen_range(-TERRAIN_SIZE / 2.0..TERRAIN_SIZE / 2.0) * 0.6,
+        )).collect();
+        spawning_grounds.grounds.insert(species, grounds);
+    }
+    *history = PopulationHistory {
+        samples: HashMap::new(),
+        eaten: HashMap::new(),
+        timer: Timer::from_seconds(1.0, TimerMode::Repeating)
+    };
+}
+
+pub fn eat_prey(
+    mut commands: Commands,
+    fish_query: Query<(Entity, &Transform, &FishType)>,
+    mut history: ResMut<PopulationHistory>,
+    interactions: Res<SpeciesInteractions>,
+    ecology: Res<EcologySettings>,
+    kd_tree: Res<NNTree3D>
+) {
+    if !ecology.enabled {
+        return;
+    }
+    let mut eaten: HashSet<Entity> = HashSet::new();
+
+    for (entity, transform, fish_type) in fish_query.iter() {
+        if eaten.contains(&entity) {
+            continue;
+        }
+        for (_, neighbor) in kd_tree.within_distance(transform.translation, ecology.catch_distance) {
+            let Some(neighbor) = neighbor else { continue };
+            if neighbor == entity || eaten.contains(&neighbor) {
+                continue;
+            }
+            let Ok((_, _, prey_type)) = fish_query.get(neighbor) else { continue };
+            if interactions.get(fish_type.species, prey_type.species) == Reaction::Hunt {
+                eaten.insert(neighbor);
+                commands.entity(neighbor).despawn_recursive();
+                *history.eaten.entry(prey_type.species).or_default() += 1;
+                break;
+            }
+        }
+    }
+}
+
+/// Time between two respawns, kept apart from the settings so ticking it does not mark them changed.
+pub struct RespawnTimer(Timer);
+
+impl Default for RespawnTimer {
+    fn default() -> Self {
+        Self(Timer::from_seconds(2.0, TimerMode::Repeating))
+    }
+}
+
+/// Adds a fish to the smallest school, empty ones included, of every species below its population.
+#[allow(clippy::too_many_arguments)]
+pub fn respawn_fish(
+    mut commands: Commands,
+    fish_query: Query<&FishType>,
+    fish_models: Res<FishModels>,
+    spawning_grounds: Res<SpawningGrounds>,
+    species_settings: Res<SpeciesSettings>,
+    ecology: Res<EcologySettings>,
+    time: Res<Time>,
+    mut respawn_timer: Local<RespawnTimer>,
+    mut rng: Local<SystemRng>
+) {
+    if !ecology.respawn || !respawn_timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    let rng = rng.get("respawn_fish");
+    let mut school_sizes: HashMap<(Species, usize), usize> = HashMap::new();
+    for fish_type in fish_query.iter() {
+        *school_sizes.entry((fish_type.species, fish_type.school_id)).or_default() += 1;
+    }
+
+    for species in ALL_SPECIES {
+        let population: usize = school_sizes.iter()
+            .filter(|((school_species, _), _)| *school_species == species)
+            .map(|(_, size)| *size)
+            .sum();
+        let school_settings = species_settings.get(species);
+        let Some(ground) = spawning_grounds.grounds.get(&species).and_then(|grounds| grounds.choose(rng)) else {
+            continue;
+        };
+        if population >= school_settings.population() {
+            continue;
+        }
+        let school_id = (0..school_settings.schools)
+            .min_by_key(|school_id| school_sizes.get(&(species, *school_id)).copied().unwrap_or(0))
+            .unwrap_or(0);
+        let settings = &school_settings.boids;
+        let offset = Vec3::new(rng.gen_range(-5.0..5.0), rng.gen_range(-2.0..2.0), rng.gen_range(-5.0..5.0));
+        let velocity = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(0.0..0.5), rng.gen_range(-1.0..1.0))
+            .normalize_or_zero() * settings.min_speed;
+        spawn_fish(&mut commands, &fish_models, &species, school_id, *ground + offset, velocity, settings);
+    }
+}
+
+pub fn record_population(
+    fish_query: Query<&FishType>,
+    mut history: ResMut<PopulationHistory>,
+    time: Res<Time>
+) {
+    if !history.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let mut counts: HashMap<Species, usize> = HashMap::new();
+    for fish_type in fish_query.iter() {
+        *counts.entry(fish_type.species).or_default() += 1;
+    }
+    let now = time.elapsed_seconds_f64();
+    for species in ALL_SPECIES {
+        let samples = history.samples.entry(species).or_default();
+        samples.push([now, counts.get(&species).copied().unwrap_or(0) as f64]);
+        let overflow = samples.len().saturating_sub(MAX_POPULATION_SAMPLES);
+        samples.drain(..overflow);
+    }
+}
@@ -1,25 +1,40 @@
 use bevy::prelude::*;
-use components::UnderwaterBoidSettings;
+use components::{SpeciesSettings, UnderwaterBoidSettings};
 use crate::ui::resources::SimulationState;
 use crate::boids_3d::systems::*;
 
-mod components;
+pub mod components;
 mod systems;
+pub mod schools;
+pub mod ecology;
 
 pub use systems::*;
+use schools::*;
+use ecology::*;
 pub struct FishPlugin;
 
 impl Plugin for FishPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<UnderwaterBoidSettings>()
+        app.init_resource::<SpeciesSettings>()
+           .register_type::<UnderwaterBoidSettings>()
+           .init_resource::<SchoolStates>()
+           .init_resource::<SpeciesInteractions>()
+           .init_resource::<EcologySettings>()
+           .init_resource::<SpawningGrounds>()
+           .init_resource::<PopulationHistory>()
            .add_systems(Startup, load_fish_models)
-           .add_systems(OnEnter(SimulationState::Underwater), spawn_fish_schools)
+           .add_systems(OnEnter(SimulationState::Underwater), (reset_ecology, spawn_fish_schools, reset_school_states).chain())
            .add_systems(Update, (
+                respawn_changed_schools.run_if(resource_changed::<SpeciesSettings>()),
                 apply_underwater_flocking,
                 apply_forces_system,
                 update_fish_positions,
                 confine_fishes_movement,
-                avoid_obstacles
+                avoid_obstacles,
+                classify_schools,
+                eat_prey,
+                respawn_fish,
+                record_population
            ).run_if(in_state(SimulationState::Underwater)));
     }
 }
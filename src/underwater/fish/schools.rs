@@ -0,0 +1,170 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use crate::boids_3d::components::*;
+use crate::metrics::{angular_momentum, polarisation};
+use super::components::*;
+
+const MAX_LOGGED_TRANSITIONS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectiveState {
+    Swarm,
+    Milling,
+    Polarised,
+    Transitioning
+}
+
+impl CollectiveState {
+    /// Thresholds follow Couzin et al. (2002): a school is polarised when headings agree, milling
+    /// when it rotates around its centre, and a swarm when neither order parameter is high.
+    pub fn classify(polarisation: f32, angular_momentum: f32) -> Self {
+        if polarisation > 0.65 {
+            CollectiveState::Polarised
+        } else if angular_momentum > 0.65 && polarisation < 0.35 {
+            CollectiveState::Milling
+        } else if polarisation < 0.35 && angular_momentum < 0.35 {
+            CollectiveState::Swarm
+        } else {
+            CollectiveState::Transitioning
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CollectiveState::Swarm => "swarm",
+            CollectiveState::Milling => "milling",
+            CollectiveState::Polarised => "polarised",
+            CollectiveState::Transitioning => "transitioning"
+        }
+    }
+}
+
+pub struct SchoolState {
+    pub species: Species,
+    pub school_id: usize,
+    pub fish: usize,
+    pub centroid: Vec3,
+    pub polarisation: f32,
+    pub angular_momentum: f32,
+    pub state: CollectiveState
+}
+
+pub struct SchoolTransition {
+    pub time: f32,
+    pub species: Species,
+    pub school_id: usize,
+    pub from: CollectiveState,
+    pub to: CollectiveState
+}
+
+#[derive(Resource)]
+pub struct SchoolStates {
+    pub schools: Vec<SchoolState>,
+    pub transitions: Vec<SchoolTransition>,
+    pub timer: Timer
+}
+
+impl Default for SchoolStates {
+    fn default() -> Self {
+        Self {
+            schools: Vec::new(),
+            transitions: Vec::new(),
+            timer: Timer::from_seconds(1.0, TimerMode::Repeating)
+        }
+    }
+}
+
+pub fn reset_school_states(mut school_states: ResMut<SchoolStates>) {
+    *school_states = SchoolStates::default();
+}
+
+pub fn classify_schools(
+    fish_query: Query<(&Transform, &Velocity, &FishType)>,
+    mut school_states: ResMut<SchoolStates>,
+    time: Res<Time>
+) {
+    if !school_states.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let mut members: HashMap<(Species, usize), (Vec<Vec3>, Vec<Vec3>)> = HashMap::new();
+    for (transform, velocity, fish_type) in fish_query.iter() {
+        let (positions, velocities) = members.entry((fish_type.species, fish_type.school_id)).or_default();
+        positions.push(transform.translation);
+        velocities.push(velocity.velocity);
+    }
+
+    let now = time.elapsed_seconds();
+    let mut schools = Vec::with_capacity(members.len());
+    for ((species, school_id), (positions, velocities)) in members {
+        let polarisation = polarisation(&velocities);
+        let angular_momentum = angular_momentum(&positions, &velocities);
+        let state = CollectiveState::classify(polarisation, angular_momentum);
+
+        let previous = school_states.schools.iter()
+            .find(|school| school.species == species && school.school_id == school_id)
+            .map(|school| school.state);
+        if let Some(previous) = previous.filter(|previous| *previous != state) {
+            info!("[{now:.1}s] {} school {school_id}: {} -> {}", species.name(), previous.label(), state.label());
+            school_states.transitions.push(SchoolTransition {
+                time: now,
+                species,
+                school_id,
+                from: previous,
+                to: state
+            });
+        }
+
+        schools.push(SchoolState {
+            species,
+            school_id,
+            fish: positions.len(),
+            centroid: positions.iter().copied().sum::<Vec3>() / positions.len() as f32,
+            polarisation,
+            angular_momentum,
+            state
+        });
+    }
+    schools.sort_by_key(|school| (school.species as usize, school.school_id));
+    school_states.schools = schools;
+
+    let overflow = school_states.transitions.len().saturating_sub(MAX_LOGGED_TRANSITIONS);
+    school_states.transitions.drain(..overflow);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligned_schools_are_polarised_however_they_rotate() {
+        assert_eq!(CollectiveState::classify(0.9, 0.0), CollectiveState::Polarised);
+        assert_eq!(CollectiveState::classify(0.7, 0.9), CollectiveState::Polarised);
+    }
+
+    #[test]
+    fn rotating_unaligned_schools_are_milling() {
+        assert_eq!(CollectiveState::classify(0.1, 0.9), CollectiveState::Milling);
+    }
+
+    #[test]
+    fn schools_without_order_are_swarms() {
+        assert_eq!(CollectiveState::classify(0.1, 0.1), CollectiveState::Swarm);
+        assert_eq!(CollectiveState::classify(0.0, 0.0), CollectiveState::Swarm);
+    }
+
+    #[test]
+    fn in_between_values_are_transitions() {
+        assert_eq!(CollectiveState::classify(0.5, 0.1), CollectiveState::Transitioning);
+        assert_eq!(CollectiveState::classify(0.1, 0.5), CollectiveState::Transitioning);
+        assert_eq!(CollectiveState::classify(0.5, 0.9), CollectiveState::Transitioning);
+    }
+
+    #[test]
+    fn thresholds_are_exclusive() {
+        assert_eq!(CollectiveState::classify(0.65, 0.0), CollectiveState::Transitioning);
+        assert_eq!(CollectiveState::classify(0.35, 0.9), CollectiveState::Transitioning);
+        assert_eq!(CollectiveState::classify(0.1, 0.65), CollectiveState::Transitioning);
+        assert_eq!(CollectiveState::classify(0.1, 0.35), CollectiveState::Transitioning);
+    }
+}
@@ -7,7 +7,11 @@ use crate::underwater::UnderwaterMarker;
 use crate::boids_3d::{bundles::BoidBundle, components::*, events::ApplyForceEvent};
 use crate::kd_tree_3d::components::{NNTree3D, TrackedByKDTree3D};
 use super::components::*;
+use super::ecology::*;
 use crate::boids_3d::systems::*;
+use crate::rules::{FlockingRules, ForceSource, Neighbour, NeighbourContext, RuleCoefficients};
+use crate::random::SystemRng;
+use crate::ui::resources::SimulationState;
 
 pub fn load_fish_models(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.insert_resource(FishModels {
@@ -20,170 +24,284 @@ pub fn load_fish_models(mut commands: Commands, asset_server: Res<AssetServer>)
 pub fn spawn_fish_schools(
     mut commands: Commands,
     fish_models: Res<FishModels>,
+    mut species_settings: ResMut<SpeciesSettings>,
+    mut rng: Local<SystemRng>,
 ) {
-    let mut rng = rand::thread_rng();
-    
-    for species in [Species::SmallFish, Species::MediumFish, Species::LargeFish] {
-        let settings = species.get_settings();
-        let num_schools = match species {
-            Species::SmallFish => 10,
-            Species::MediumFish => 5,
-            Species::LargeFish => 3,
-        };
-
-        for school_id in 0..num_schools {
-            let school_center = Vec3::new(
-                rng.gen_range(-TERRAIN_SIZE/2.0..TERRAIN_SIZE/2.0),
-                rng.gen_range(GROUND_Y_POSITION..-10.0),
-                rng.gen_range(-TERRAIN_SIZE/2.0..TERRAIN_SIZE/2.0),
-            );
-
-            spawn_fish_school(
-                &mut commands,
-                &fish_models,
-                &species,
-                school_id,
-                school_center,
-                &settings,
-            );
+    let rng = rng.get("spawn_fish_schools");
+    for species in ALL_SPECIES {
+        let school_settings = species_settings.get_mut(species);
+        school_settings.previous_schools = school_settings.schools;
+        school_settings.boids.previous_count = school_settings.boids.count;
+        spawn_species_schools(&mut commands, &fish_models, &species, school_settings, rng);
+    }
+}
+
+fn random_school_center(rng: &mut impl Rng) -> Vec3 {
+    Vec3::new(
+        rng.gen_range(-TERRAIN_SIZE/2.0..TERRAIN_SIZE/2.0),
+        rng.gen_range(GROUND_Y_POSITION..-10.0),
+        rng.gen_range(-TERRAIN_SIZE/2.0..TERRAIN_SIZE/2.0),
+    )
+}
+
+fn spawn_species_schools(
+    commands: &mut Commands,
+    models: &FishModels,
+    species: &Species,
+    school_settings: &SchoolSettings,
+    rng: &mut impl Rng,
+) {
+    for school_id in 0..school_settings.schools {
+        let school_center = random_school_center(rng);
+
+        spawn_fish_school(
+            commands,
+            models,
+            species,
+            school_id,
+            school_center,
+            school_settings.boids.count,
+            &school_settings.boids,
+            rng,
+        );
+    }
+}
+
+/// Spawns and despawns fish of a species whose number of schools or fish per school was changed,
+/// so that it has as many as its settings say, and rescales the fish of every species.
+pub fn respawn_changed_schools(
+    mut commands: Commands,
+    mut fish_query: Query<(Entity, &mut Transform, &FishType)>,
+    fish_models: Res<FishModels>,
+    mut species_settings: ResMut<SpeciesSettings>,
+    mut rng: Local<SystemRng>,
+) {
+    let rng = rng.get("respawn_changed_schools");
+    for species in ALL_SPECIES {
+        let school_settings = species_settings.bypass_change_detection().get_mut(species);
+        let population_changed = school_settings.schools != school_settings.previous_schools
+            || school_settings.boids.count != school_settings.boids.previous_count;
+
+        for (_, mut transform, fish_type) in fish_query.iter_mut() {
+            if fish_type.species == species {
+                transform.scale = Vec3::splat(school_settings.boids.size);
+            }
+        }
+        if !population_changed {
+            continue;
+        }
+        school_settings.previous_schools = school_settings.schools;
+        school_settings.boids.previous_count = school_settings.boids.count;
+
+        let mut schools: Vec<Vec<(Entity, Vec3)>> = vec![Vec::new(); school_settings.schools];
+        for (entity, transform, fish_type) in fish_query.iter() {
+            if fish_type.species != species {
+                continue;
+            }
+            match schools.get_mut(fish_type.school_id) {
+                Some(school) => school.push((entity, transform.translation)),
+                None => commands.entity(entity).despawn_recursive()
+            }
+        }
+        for (school_id, school) in schools.iter().enumerate() {
+            let count = school_settings.boids.count;
+            if school.len() > count {
+                for (entity, _) in school[count..].iter() {
+                    commands.entity(*entity).despawn_recursive();
+                }
+            } else if school.len() < count {
+                // New fish join their school where it is, or start a new one.
+                let center = match school.len() {
+                    0 => random_school_center(rng),
+                    size => school.iter().map(|(_, position)| *position).sum::<Vec3>() / size as f32
+                };
+                spawn_fish_school(&mut commands, &fish_models, &species, school_id, center, count - school.len(), &school_settings.boids, rng);
+            }
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn spawn_fish_school(
     commands: &mut Commands,
     models: &FishModels,
     species: &Species,
     school_id: usize,
     center: Vec3,
+    count: usize,
     settings: &UnderwaterBoidSettings,
+    rng: &mut impl Rng,
 ) {
-    let mut rng = rand::thread_rng();
-    let model = match species {
-        Species::SmallFish => &models.small_fish,
-        Species::MediumFish => &models.medium_fish,
-        Species::LargeFish => &models.large_fish,
-    };
-
-    for _ in 0..settings.count {
+    for _ in 0..count {
         let offset = Vec3::new(
             rng.gen_range(-20.0..20.0),
             rng.gen_range(-10.0..10.0),
             rng.gen_range(-20.0..20.0),
         );
+        let velocity = Vec3::new(
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-0.5..0.5),
+            rng.gen_range(-1.0..1.0),
+        ).normalize() * settings.min_speed;
 
-        commands.spawn((
-            BoidBundle {
-                boid: Boid { group: school_id as u8 },
-                velocity: Velocity { 
-                    velocity: Vec3::new(
-                        rng.gen_range(-1.0..1.0),
-                        rng.gen_range(-0.5..0.5),
-                        rng.gen_range(-1.0..1.0),
-                    ).normalize() * settings.min_speed
-                },
-                acceleration: Acceleration {
-                    acceleration: Vec3::ZERO
-                },
-                pbr_bundle: PbrBundle {
-                    transform: Transform::from_translation(center + offset)
-                        .with_scale(Vec3::splat(settings.size)),
-                    ..default()
-                },
-                tracked_by_kdtree: TrackedByKDTree3D,
-            },
-            FishType {
-                species: species.clone(),
-                school_id,
+        spawn_fish(commands, models, species, school_id, center + offset, velocity, settings);
+    }
+}
+
+pub fn spawn_fish(
+    commands: &mut Commands,
+    models: &FishModels,
+    species: &Species,
+    school_id: usize,
+    position: Vec3,
+    velocity: Vec3,
+    settings: &UnderwaterBoidSettings,
+) -> Entity {
+    let model = match species {
+        Species::SmallFish => &models.small_fish,
+        Species::MediumFish => &models.medium_fish,
+        Species::LargeFish => &models.large_fish,
+    };
+
+    commands.spawn((
+        BoidBundle {
+            boid: Boid { group: school_id as u8 },
+            velocity: Velocity { velocity },
+            acceleration: Acceleration {
+                acceleration: Vec3::ZERO
             },
-            UnderwaterMarker,
-        ))
-        .with_children(|parent| {
-            parent.spawn(SceneBundle {
-                scene: model.clone(),
-                transform: Transform::from_rotation(Quat::from_rotation_y(std::f32::consts::PI)),
+            pbr_bundle: PbrBundle {
+                transform: Transform::from_translation(position)
+                    .with_scale(Vec3::splat(settings.size)),
                 ..default()
-            });
+            },
+            tracked_by_kdtree: TrackedByKDTree3D,
+        },
+        FishType {
+            species: *species,
+            school_id,
+        },
+        UnderwaterMarker,
+    ))
+    .with_children(|parent| {
+        parent.spawn(SceneBundle {
+            scene: model.clone(),
+            transform: Transform::from_rotation(Quat::from_rotation_y(std::f32::consts::PI)),
+            ..default()
         });
-    }
+    })
+    .id()
 }
 
 pub fn apply_underwater_flocking(
     boid_query: Query<(Entity, &Transform, &Velocity, &Boid, &FishType), With<UnderwaterMarker>>,
     event_writer: EventWriter<ApplyForceEvent>,
+    species_settings: Res<SpeciesSettings>,
+    interactions: Res<SpeciesInteractions>,
+    ecology: Res<EcologySettings>,
+    rules: Res<FlockingRules>,
     kd_tree: Res<NNTree3D>
 ) {
     let event_writer = std::sync::Mutex::new(event_writer);
 
-    boid_query.par_iter().for_each(|(entity, transform, velocity, _, fish_type)| {
+    boid_query.par_iter().for_each(|(entity, transform, velocity, boid, fish_type)| {
         let position = transform.translation;
-        let settings = fish_type.species.get_settings();
+        let settings = &species_settings.get(fish_type.species).boids;
         
-        let mut cohesion_neighbors = Vec::new();
-        let mut repulsion_neighbors = Vec::new();
-        let mut alignment_neighbors = Vec::new();
+        let mut context = NeighbourContext::new(position, velocity.velocity, boid.group, RuleCoefficients::from(&**settings));
+        let mut threats = Vec::new();
+        let mut nearest_prey: Option<(Vec3, f32)> = None;
+        let mut fear_force = Vec3::ZERO;
+        let mut interspecies_force = Vec3::ZERO;
 
-        let max_range = settings.cohesion_range.max(settings.alignment_range).max(settings.separation_range);
+        let max_range = settings.cohesion_range.max(settings.alignment_range).max(settings.separation_range).max(ecology.max_range());
 
         for (_, neighbor_entity) in kd_tree.within_distance(position, max_range) {
             let neighbor_entity = neighbor_entity.unwrap();
             if neighbor_entity == entity { continue; }
 
-            if let Ok((_, neighbor_transform, neighbor_velocity, _, neighbor_fish_type)) = boid_query.get(neighbor_entity) {
-                if std::mem::discriminant(&fish_type.species) != std::mem::discriminant(&neighbor_fish_type.species) {
+            if let Ok((_, neighbor_transform, neighbor_velocity, neighbor_boid, neighbor_fish_type)) = boid_query.get(neighbor_entity) {
+                let neighbor_pos = neighbor_transform.translation;
+                if fish_type.species != neighbor_fish_type.species {
+                    if !ecology.enabled {
+                        continue;
+                    }
+                    let distance = position.distance(neighbor_pos);
+                    match interactions.get(fish_type.species, neighbor_fish_type.species) {
+                        Reaction::Hunt if distance < ecology.hunt_range
+                            && nearest_prey.map_or(true, |(_, nearest)| distance < nearest) => {
+                            nearest_prey = Some((neighbor_pos, distance));
+                        }
+                        Reaction::Flee if distance < ecology.flee_range => {
+                            threats.push((neighbor_pos, distance));
+                        }
+                        Reaction::Avoid if distance < ecology.avoid_range && distance > 0.0 => {
+                            let interpolation_factor = (ecology.avoid_range - distance) / ecology.avoid_range;
+                            interspecies_force += (position - neighbor_pos) / distance * ecology.avoid_coeff * interpolation_factor;
+                        }
+                        _ => {}
+                    }
                     continue;
                 }
 
-                let neighbor_pos = neighbor_transform.translation;
                 if let Some(distance) = is_in_field_of_view(&position, &velocity.velocity, &neighbor_pos, &settings.field_of_view) {
+                    context.neighbours.push(Neighbour {
+                        position: neighbor_pos,
+                        velocity: neighbor_velocity.velocity,
+                        group: neighbor_boid.group,
+                        distance
+                    });
                     if distance < settings.separation_range {
-                        repulsion_neighbors.push((neighbor_pos, distance));
+                        context.separation_neighbours.push((neighbor_pos, distance));
                     } else if distance < settings.alignment_range {
-                        alignment_neighbors.push(neighbor_velocity.velocity);
+                        context.alignment_neighbours.push((neighbor_pos, neighbor_velocity.velocity));
                     } else if distance < settings.cohesion_range {
-                        cohesion_neighbors.push(neighbor_pos);
+                        context.cohesion_neighbours.push(neighbor_pos);
                     }
                 }
             }
         }
 
-        let cohesion_force = cohesion(&position, &cohesion_neighbors, &settings.cohesion_coeff);
-        let separation_force = separation(&position, &repulsion_neighbors, &settings.separation_coeff);
-        let alignment_force = alignment(&velocity.velocity, &alignment_neighbors, &settings.alignment_coeff);
-        
-        let total_force = cohesion_force + separation_force + alignment_force;
+        // Threatened fish pack closer together while they flee.
+        if !threats.is_empty() {
+            context.coefficients.cohesion *= ecology.threatened_cohesion_factor;
+            context.coefficients.separation *= ecology.threatened_separation_factor;
+        }
+        for (threat_pos, distance) in threats.iter() {
+            if *distance > 0.0 {
+                let interpolation_factor = (ecology.flee_range - distance) / ecology.flee_range;
+                fear_force += (position - *threat_pos) / *distance * ecology.flee_coeff * interpolation_factor;
+            }
+        }
+        if let Some((prey_pos, _)) = nearest_prey {
+            interspecies_force += (prey_pos - position).normalize_or_zero() * ecology.hunt_coeff;
+        }
+
+        rules.trace(entity, &context);
+        let total_force = rules.force(&SimulationState::Underwater, &context);
 
         let mut event_writer = event_writer.lock().unwrap();
         event_writer.send(ApplyForceEvent {
             entity,
-            force: total_force 
+            force: total_force,
+            source: ForceSource::Flocking
         });
+        for (force, source) in [(fear_force, ForceSource::Fear), (interspecies_force, ForceSource::Ecology)] {
+            if force != Vec3::ZERO {
+                event_writer.send(ApplyForceEvent { entity, force, source });
+            }
+        }
     });
 }
 
 pub fn update_fish_positions(
-    mut boid_query: Query<(&mut Transform, &mut Velocity, &mut Acceleration), With<Boid>>,
-    boid_settings: Res<UnderwaterBoidSettings>,
+    mut boid_query: Query<(&mut Transform, &mut Velocity, &mut Acceleration, &FishType), With<Boid>>,
+    species_settings: Res<SpeciesSettings>,
     time: Res<Time>
 ) {
-    for (mut transform, mut velocity, mut acceleration) in boid_query.iter_mut() {
-        velocity.velocity += acceleration.acceleration * time.delta_seconds();
-        
-        let speed = velocity.velocity.length();
-        if speed < boid_settings.min_speed {
-            velocity.velocity = velocity.velocity.normalize() * boid_settings.min_speed;
-        } else if speed > boid_settings.max_speed {
-            velocity.velocity = velocity.velocity.normalize() * boid_settings.max_speed;
-        }
-
-        transform.translation += velocity.velocity * time.delta_seconds();
-        
-        if velocity.velocity.length_squared() > 0.0 {
-            let forward = -velocity.velocity.normalize();
-            transform.rotation = Quat::from_rotation_arc(Vec3::Z, forward);
-        }
-
-        acceleration.acceleration = Vec3::ZERO;
+    for (mut transform, mut velocity, mut acceleration, fish_type) in boid_query.iter_mut() {
+        let boid_settings = &species_settings.get(fish_type.species).boids;
+        integrate_boid(&mut transform, &mut velocity, &mut acceleration, boid_settings, time.delta_seconds());
     }
 }
 
@@ -194,12 +312,12 @@ pub fn avoid_obstacles(
     for (entity, transform, mut velocity) in boid_query.iter_mut() {
         let position = transform.translation;
         let mut avoidance_force: Vec3 = Vec3::ZERO;
-        let obstacle_avoidance_range = 50.0;
+        let obstacle_avoidance_range = OBSTACLE_AVOIDANCE_RANGE;
         let obstacle_avoidance_coeff: f32 = 10.0;
         let turn_factor: f32 = 20.0;
         for (obstacle_transform, _) in obstacles_query.iter() {
             let obstacle_position = obstacle_transform.translation;
-            let distance = position.distance(obstacle_position) - 10.0;
+            let distance = position.distance(obstacle_position) - OBSTACLE_RADIUS;
             if distance < obstacle_avoidance_range {
                 let direction = (position - obstacle_position).normalize();
                 let interpolation_factor = (obstacle_avoidance_range - distance) / obstacle_avoidance_range;
@@ -210,21 +328,27 @@ pub fn avoid_obstacles(
         }
         event_writer.send(ApplyForceEvent {
             entity: entity,
-            force: avoidance_force
+            force: avoidance_force,
+            source: ForceSource::Obstacle
         });
     }
 }
 
+pub fn underwater_confinement() -> Confinement {
+    let margin = TERRAIN_SCALE * 0.2;
+    Confinement {
+        min: Vec3::new(-200.0 + margin, GROUND_Y_POSITION, -200.0 + margin),
+        max: Vec3::new(200.0 - margin, 30.0, 200.0 - margin),
+        margin
+    }
+}
+
 pub fn confine_fishes_movement (
     mut boid_query: Query<(&mut Transform, &mut Velocity, &mut Acceleration), With<Boid>>
 ) {
-    let margin = TERRAIN_SCALE * 0.2;
-    let x_min = -200.0 + margin;
-    let y_min = GROUND_Y_POSITION;
-    let z_min = -200.0 + margin;
-    let x_max = 200.0 - margin;
-    let y_max = 30.0;
-    let z_max = 200.0 - margin;
+    let Confinement { min, max, margin } = underwater_confinement();
+    let (x_min, y_min, z_min) = min.into();
+    let (x_max, y_max, z_max) = max.into();
     for (transform, mut velocity, _) in boid_query.iter_mut() {
         let turn_factor: f32 = 100.0;
         if transform.translation.x > x_max - margin {
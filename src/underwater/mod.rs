@@ -1,16 +1,16 @@
 use bevy::prelude::*;
 
 mod submarine;
-mod terrain;
+pub mod terrain;
 mod environment;
-mod fish;
+pub mod fish;
 
 use submarine::SubmarinePlugin;
 use terrain::TerrainPlugin;
 use environment::EnvironmentPlugin;
 use fish::FishPlugin;
 
-use crate::{cleanup_3d_mode, ui::resources::SimulationState};
+use crate::{plugin::cleanup_3d_mode, ui::resources::SimulationState};
 
 pub struct UnderwaterPlugin;
 
@@ -68,7 +68,7 @@ pub fn setup_submarine(
 }
 
 pub fn submarine_movement(
-    time: Res<Time>,
+    time: Res<Time<Real>>,
     keyboard: Res<Input<KeyCode>>,
     mut submarine_query: Query<(&mut Transform, &mut Submarine)>
 ) {
@@ -123,7 +123,7 @@ pub fn submarine_movement(
 pub fn update_camera(
     submarine_query: Query<&Transform, (With<Submarine>, Without<SubmarineCamera>)>,
     mut camera_query: Query<(&mut Transform, &SubmarineCamera), Without<Submarine>>,
-    time: Res<Time>,
+    time: Res<Time<Real>>,
 ) {
     let Ok(submarine_transform) = submarine_query.get_single() else { return };
     